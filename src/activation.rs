@@ -0,0 +1,51 @@
+use std::{env, os::unix::io::RawFd, process};
+
+/// First file descriptor passed by systemd.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Sockets passed with the systemd `LISTEN_FDS` protocol.
+pub struct ListenFds {
+    /// Descriptors with their names, from `LISTEN_FDNAMES`.
+    fds: Vec<(String, RawFd)>,
+}
+
+impl ListenFds {
+    /// Take the sockets passed to this process, if any.
+    ///
+    /// The environment variables are removed, so child processes don't see them. Must be called
+    /// before other threads are started.
+    pub fn from_env() -> Self {
+        let pid = env::var("LISTEN_PID").ok();
+        let count = env::var("LISTEN_FDS").ok();
+        let names = env::var("LISTEN_FDNAMES").ok();
+        for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            env::remove_var(name);
+        }
+
+        let for_us = pid.and_then(|pid| pid.parse().ok()) == Some(process::id());
+        let count: RawFd = count
+            .filter(|_| for_us)
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(0);
+        let mut names = names.as_deref().unwrap_or_default().split(':');
+        let fds = (LISTEN_FDS_START..LISTEN_FDS_START + count)
+            .map(|fd| {
+                // Safety: this only sets a flag on the descriptor.
+                unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+                let name = names.next().filter(|name| !name.is_empty());
+                (name.unwrap_or("unknown").to_owned(), fd)
+            })
+            .collect();
+        ListenFds { fds }
+    }
+
+    /// Take the socket with a name, or the only socket if there is just one.
+    pub fn take(&mut self, name: &str) -> Option<RawFd> {
+        let index = match self.fds.iter().position(|(fd_name, _)| fd_name == name) {
+            Some(index) => index,
+            None if self.fds.len() == 1 => 0,
+            None => return None,
+        };
+        Some(self.fds.remove(index).1)
+    }
+}
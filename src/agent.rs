@@ -0,0 +1,122 @@
+use std::{thread, time::Duration};
+
+use pocketcron::crontab::{parse_crontab_with, ParseError};
+use ring::signature::{UnparsedPublicKey, ED25519};
+
+use crate::{log::error, JobHandle, LoadOptions};
+
+/// Timeout of requests to the server.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Connection to a server that manages the jobs of this host.
+pub struct Agent {
+    /// Base URL, without a trailing slash.
+    url: String,
+    /// Bearer token, if the server requires one.
+    token: Option<String>,
+    /// Ed25519 public key that crontabs must be signed with.
+    key: Vec<u8>,
+    /// Time between fetches.
+    interval: Duration,
+}
+
+impl Agent {
+    /// Create an agent from a base URL, and the hex-encoded public key of the server.
+    pub fn new(
+        url: &str,
+        key: &str,
+        token: Option<String>,
+        interval: Duration,
+    ) -> Result<Self, String> {
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return Err(format!("invalid agent URL: {}", url));
+        }
+        let key = decode_hex(key.trim())
+            .filter(|key| key.len() == 32)
+            .ok_or("invalid agent key, expected 64 hex digits")?;
+        Ok(Agent {
+            url: url.trim_end_matches('/').to_owned(),
+            token,
+            key,
+            interval,
+        })
+    }
+
+    /// URL that run results are posted to.
+    pub fn results_url(&self) -> String {
+        format!("{}/results", self.url)
+    }
+
+    /// Bearer token, if any.
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    /// Fetch the crontab, and verify its signature.
+    pub fn fetch(&self) -> Result<String, String> {
+        let url = format!("{}/crontab", self.url);
+        let mut request = ureq::get(&url)
+            .timeout(TIMEOUT)
+            .set("X-Pocketcron-Host", &crate::hostname());
+        if let Some(token) = &self.token {
+            request = request.set("Authorization", &format!("Bearer {}", token));
+        }
+        let response = request.call().map_err(|err| err.to_string())?;
+        let signature = response
+            .header("X-Pocketcron-Signature")
+            .and_then(decode_hex)
+            .ok_or("missing or invalid signature")?;
+        let crontab = response.into_string().map_err(|err| err.to_string())?;
+        UnparsedPublicKey::new(&ED25519, &self.key)
+            .verify(crontab.as_bytes(), &signature)
+            .map_err(|_| "signature verification failed")?;
+        Ok(crontab)
+    }
+
+    /// Fetch the crontab periodically on a separate thread, and call `apply` when it changed.
+    pub fn watch(
+        self,
+        mut last: Option<String>,
+        apply: impl Fn(&str) -> Result<(), String> + Send + 'static,
+    ) {
+        thread::spawn(move || loop {
+            thread::sleep(self.interval);
+            match self.fetch() {
+                Ok(crontab) if last.as_ref() != Some(&crontab) => {
+                    if let Err(err) = apply(&crontab) {
+                        error!("{}: new crontab rejected: {}", self.url, err);
+                    }
+                    // Don't retry a bad crontab, but wait for the server to change it.
+                    last = Some(crontab);
+                }
+                Ok(_) => {}
+                Err(err) => error!("{}: fetch failed: {}", self.url, err),
+            }
+        });
+    }
+}
+
+/// Replace the jobs from the agent server with those in a new crontab, and return the number of
+/// jobs in it.
+pub fn apply(
+    jobs: &mut Vec<JobHandle>,
+    crontab: &str,
+    options: &LoadOptions,
+) -> Result<usize, String> {
+    let specs = parse_crontab_with(crontab, options.compat).map_err(|errors| {
+        let errors: Vec<String> = errors.iter().map(ParseError::to_string).collect();
+        errors.join("; ")
+    })?;
+    Ok(crate::replace_jobs(jobs, specs, true, options))
+}
+
+/// Decode a string of hex digits.
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
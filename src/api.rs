@@ -0,0 +1,236 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use chrono::{offset::Local, DateTime};
+use serde::Serialize;
+
+use crate::{control, dispatch::Dispatcher, status, JobList, Outcome};
+
+/// The dashboard, which shows the jobs with the API.
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+/// How long to wait for a request to be sent.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Most bytes of the request line and headers that are read, so that a connection can't make its
+/// thread hold on to ever more memory.
+const MAX_REQUEST: u64 = 16 * 1024;
+
+/// How the last run of a job ended.
+#[derive(Clone, Serialize)]
+pub struct LastRun {
+    started: DateTime<Local>,
+    duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signal: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl LastRun {
+    pub fn new(started: DateTime<Local>, duration_ms: u64, outcome: &Outcome) -> Self {
+        let (code, signal, error) = match outcome {
+            Outcome::Code(code) => (Some(*code), None, None),
+            Outcome::Signal(signal) => (None, Some(*signal), None),
+            Outcome::Error(error) => (None, None, Some(error.clone())),
+        };
+        LastRun {
+            started,
+            duration_ms,
+            code,
+            signal,
+            error,
+        }
+    }
+}
+
+/// A job, as listed by `GET /jobs`.
+#[derive(Serialize)]
+struct JobInfo {
+    id: usize,
+    name: String,
+    schedule: String,
+    command: String,
+    state: &'static str,
+    next: Option<DateTime<Local>>,
+    last_run: Option<LastRun>,
+}
+
+/// Serve the JSON API over HTTP, on a separate thread. Every request must have the token in an
+/// `Authorization: Bearer` header.
+///
+/// `GET /jobs` lists the jobs, and `POST /jobs/<name>/run`, `/pause` and `/resume` act on the jobs
+/// with a name, like the requests on the control socket. `GET /` is a dashboard, which needs no
+/// token itself, but asks for it to use the API.
+pub fn listen(listener: TcpListener, token: String, jobs: JobList, dispatcher: Arc<Dispatcher>) {
+    let token: Arc<str> = token.into();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let token = token.clone();
+            let jobs = jobs.clone();
+            let dispatcher = dispatcher.clone();
+            thread::spawn(move || serve(stream, &token, &jobs, &dispatcher));
+        }
+    });
+}
+
+fn serve(stream: TcpStream, token: &str, jobs: &JobList, dispatcher: &Arc<Dispatcher>) {
+    let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+    let mut reader = BufReader::new((&stream).take(MAX_REQUEST));
+    let mut request = String::new();
+    if reader.read_line(&mut request).is_err() {
+        return;
+    }
+    // Only the authorization matters of the headers. Requests have no body.
+    let mut authorized = false;
+    let mut header = String::new();
+    while reader.read_line(&mut header).is_ok_and(|len| len > 2) {
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("authorization") {
+                let given = value.trim().strip_prefix("Bearer ").unwrap_or_default();
+                authorized = equal(given.trim().as_bytes(), token.as_bytes());
+            }
+        }
+        header.clear();
+    }
+    // Requests cut off at the limit are dropped, rather than acted on without all of their headers.
+    if reader.get_ref().limit() == 0 {
+        return;
+    }
+
+    let mut parts = request.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or_default(), parts.next());
+    let segments: Vec<String> = path
+        .unwrap_or_default()
+        .trim_matches('/')
+        .split('/')
+        .map(decode)
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    if let ("GET", [""]) = (method, segments.as_slice()) {
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            DASHBOARD.len(),
+            DASHBOARD
+        );
+        let _ = (&stream).write_all(response.as_bytes());
+        return;
+    }
+    let (status, body) = if !authorized {
+        ("401 Unauthorized", error("missing or invalid token"))
+    } else {
+        match (method, segments.as_slice()) {
+            ("GET", ["jobs"]) => ("200 OK", list(jobs)),
+            ("POST", ["jobs", name, action @ ("run" | "pause" | "resume")]) => {
+                act(jobs, dispatcher, name, action)
+            }
+            (_, ["jobs"]) => ("405 Method Not Allowed", error("expected GET")),
+            (_, ["jobs", _, "run" | "pause" | "resume"]) => {
+                ("405 Method Not Allowed", error("expected POST"))
+            }
+            _ => ("404 Not Found", error("not found")),
+        }
+    };
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n",
+        status,
+        body.len()
+    );
+    if !authorized {
+        response.push_str("WWW-Authenticate: Bearer\r\n");
+    }
+    response.push_str("\r\n");
+    response.push_str(&body);
+    let _ = (&stream).write_all(response.as_bytes());
+}
+
+/// The jobs that are in a crontab, with their next run and how their last run ended.
+fn list(jobs: &JobList) -> String {
+    let jobs: Vec<JobInfo> = (jobs.lock().unwrap().iter())
+        .map(|job_handle| job_handle.lock().unwrap())
+        .filter(|job| !job.retired)
+        .map(|job| JobInfo {
+            id: job.id,
+            name: job.name(),
+            schedule: job.schedule().to_owned(),
+            command: job.command().to_owned(),
+            state: status::state(&job),
+            next: job.next,
+            last_run: job.last_result.as_deref().cloned(),
+        })
+        .collect();
+    #[derive(Serialize)]
+    struct Jobs {
+        jobs: Vec<JobInfo>,
+    }
+    serde_json::to_string(&Jobs { jobs }).unwrap()
+}
+
+fn act(
+    jobs: &JobList,
+    dispatcher: &Arc<Dispatcher>,
+    name: &str,
+    action: &str,
+) -> (&'static str, String) {
+    let jobs = match control::named(jobs, name) {
+        Ok(jobs) => jobs,
+        Err(err) => return ("404 Not Found", error(&err)),
+    };
+    let result = match action {
+        "run" => control::run(dispatcher, jobs),
+        _ => Ok(control::set_enabled(&jobs, action == "resume")),
+    };
+    match result {
+        Ok(message) => (
+            "200 OK",
+            serde_json::json!({ "message": message }).to_string(),
+        ),
+        // Runs are only refused for paused jobs.
+        Err(err) => ("409 Conflict", error(&err)),
+    }
+}
+
+fn error(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
+
+/// Decode `%XX` escapes in a path segment.
+fn decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let hex = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Compare a token in constant time, so its contents can't be guessed from how long it takes.
+fn equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -0,0 +1,162 @@
+use std::{
+    fs,
+    process::exit,
+    sync::atomic::Ordering,
+    time::{Duration as StdDuration, Instant},
+};
+
+use chrono::{offset::Local, Duration};
+use pocketcron::{crontab::parse_crontab, schedule::Compat};
+
+use crate::{dispatch::Dispatcher, dispatch_all, new_job, LoadOptions, USAGE};
+
+/// Entry point of the `bench` command, which measures the scheduler with synthetic jobs.
+pub fn main() -> ! {
+    let mut count = 10000;
+    let mut ticks = 60;
+    let mut max_concurrent = None;
+    let mut args = ::std::env::args().skip(2);
+    while let Some(arg) = args.next() {
+        let value = args.next().and_then(|value| value.parse().ok());
+        match (arg.as_str(), value) {
+            ("--jobs", Some(value)) if value > 0 => count = value,
+            ("--ticks", Some(value)) if value > 0 => ticks = value,
+            ("--max-concurrent", Some(value)) if value > 0 => max_concurrent = Some(value),
+            _ => {
+                eprintln!("{}", USAGE);
+                exit(1);
+            }
+        }
+    }
+
+    let crontab = generate(count);
+    let rss_before = rss();
+    let start = Instant::now();
+    let specs = match parse_crontab(&crontab) {
+        Ok(specs) => specs,
+        Err(errors) => {
+            eprintln!("generated crontab is invalid: {}", errors[0]);
+            exit(1);
+        }
+    };
+    println!("parse: {} jobs in {:?}", specs.len(), start.elapsed());
+
+    let options = LoadOptions {
+        jitter_seed: String::new(),
+        compat: Compat::Default,
+        shard: None,
+        default_timeout: None,
+        shell: None,
+        env_jobs: false,
+        lenient: false,
+        spool: None,
+        only_failures: false,
+    };
+    let now = Local::now();
+    let start = Instant::now();
+    let jobs: Vec<_> = specs
+        .into_iter()
+        .enumerate()
+        .map(|(index, spec)| new_job(spec, index + 1, false, &options, now))
+        .collect();
+    println!("load: {:?}", start.elapsed());
+    match (rss_before, rss()) {
+        (Some(before), Some(after)) => println!(
+            "memory: {:.1} MiB for jobs, {:.1} MiB resident",
+            after.saturating_sub(before) as f64 / 1048576.0,
+            after as f64 / 1048576.0
+        ),
+        _ => println!("memory: unknown"),
+    }
+
+    // Do what the main loop does, for every minute in a simulated period of time.
+    let mut latencies = Vec::with_capacity(ticks);
+    let mut due = 0;
+    for tick in 1..=ticks {
+        let now = now + Duration::minutes(tick as i64);
+        let start = Instant::now();
+        for job_handle in &jobs {
+            let mut job = job_handle.lock().unwrap();
+            if job.next.is_some_and(|next| now >= next) {
+                due += 1;
+                while job.next.filter(|next| now >= *next).is_some() {
+                    job.next = job.upcoming.next();
+                }
+            }
+        }
+        latencies.push(start.elapsed());
+    }
+    latencies.sort();
+    let total: StdDuration = latencies.iter().sum();
+    println!(
+        "ticks: {} with {} due jobs, latency avg {:?}, p99 {:?}, max {:?}",
+        ticks,
+        due,
+        total / ticks as u32,
+        latencies[(ticks * 99 / 100).min(ticks - 1)],
+        latencies[ticks - 1]
+    );
+
+    // Push every job through the dispatcher once, without running processes. Logs are silenced
+    // meanwhile, because they would dominate.
+    crate::NOOP.store(true, Ordering::Relaxed);
+    let dispatcher = Dispatcher::new(max_concurrent);
+    let stderr = silence_stderr();
+    let start = Instant::now();
+    dispatch_all(&dispatcher, jobs.clone());
+    dispatcher.wait_idle();
+    let elapsed = start.elapsed();
+    restore_stderr(stderr);
+    println!(
+        "dispatch: {} jobs in {:?}, {:.0} jobs/s",
+        jobs.len(),
+        elapsed,
+        jobs.len() as f64 / elapsed.as_secs_f64()
+    );
+    exit(0);
+}
+
+/// Generate a crontab with a mix of common schedules.
+fn generate(count: usize) -> String {
+    let mut crontab = String::new();
+    for i in 0..count {
+        let schedule = match i % 5 {
+            0 => format!("*/{} * * * *", 1 + i % 15),
+            1 => format!("{} {} * * *", i % 60, i % 24),
+            2 => format!("{} */{} * * 1-5", i % 60, 1 + i % 6),
+            3 => format!("@every {}m", 1 + i % 90),
+            _ => format!("{} {} {} * *", i % 60, i % 24, 1 + i % 28),
+        };
+        crontab.push_str(&format!("{} true {}\n", schedule, i));
+    }
+    crontab
+}
+
+/// Resident memory of the process in bytes, if known.
+fn rss() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Point stderr to `/dev/null`, and return the original.
+fn silence_stderr() -> libc::c_int {
+    // Safety: these calls only operate on descriptors, and the path is a valid C string.
+    unsafe {
+        let original = libc::dup(libc::STDERR_FILENO);
+        let null = libc::open(c"/dev/null".as_ptr(), libc::O_WRONLY);
+        libc::dup2(null, libc::STDERR_FILENO);
+        libc::close(null);
+        original
+    }
+}
+
+/// Restore stderr after `silence_stderr`.
+fn restore_stderr(original: libc::c_int) {
+    // Safety: the descriptor was returned by `silence_stderr`.
+    unsafe {
+        libc::dup2(original, libc::STDERR_FILENO);
+        libc::close(original);
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    ffi::CString,
+    fs, io,
+    os::unix::{ffi::OsStrExt, process::CommandExt},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::OnceLock,
+    thread,
+    time::Duration,
+};
+
+use pocketcron::crontab::Limits;
+
+use crate::{log::warning, run_id::RunId};
+
+/// Length of the period of `cpu.max`, in microseconds, which is the default of the kernel.
+const CPU_PERIOD: u64 = 100_000;
+
+/// How long to wait for processes left behind by a run to be gone, before giving up on removing
+/// its cgroup.
+const REMOVE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Whether runs can be put in cgroups on this platform.
+pub const SUPPORTED: bool = cfg!(target_os = "linux");
+
+/// The directory that holds the cgroups of runs, set with `--cgroup-root`.
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// Put every run in a cgroup of its own, below a directory in the cgroup v2 hierarchy, which is
+/// created if needed. The memory and cpu controllers are enabled for the cgroups of runs, if they
+/// are available.
+pub fn enable(root: &Path) -> io::Result<()> {
+    fs::create_dir_all(root)?;
+    let available = fs::read_to_string(root.join("cgroup.controllers"))?;
+    let mut enabled = Vec::new();
+    for controller in ["memory", "cpu"] {
+        if available
+            .split_whitespace()
+            .any(|other| other == controller)
+        {
+            enabled.push(format!("+{}", controller));
+        } else {
+            warning!(
+                "{}: {} controller not available",
+                root.display(),
+                controller
+            );
+        }
+    }
+    if !enabled.is_empty() {
+        fs::write(root.join("cgroup.subtree_control"), enabled.join(" "))?;
+    }
+    let _ = ROOT.set(root.to_owned());
+    Ok(())
+}
+
+/// Whether runs are put in cgroups.
+pub fn is_enabled() -> bool {
+    ROOT.get().is_some()
+}
+
+/// The cgroup of a run, which is removed when dropped, along with any processes left in it.
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+/// Create the cgroup of a run, with the memory and CPU caps of the job, if cgroups are enabled.
+/// It's named after the job and the id of the run, like `3.01J9Z3K4QW8N6T2V5X7Y0B1C3D`.
+pub fn create(id: usize, run_id: RunId, limits: Option<&Limits>) -> Option<io::Result<Cgroup>> {
+    let path = ROOT.get()?.join(format!("{}.{}", id, run_id));
+    Some(setup(path, limits))
+}
+
+fn setup(path: PathBuf, limits: Option<&Limits>) -> io::Result<Cgroup> {
+    // One that exists belongs to another run, whose processes must be left alone, so it's an
+    // error rather than taken over.
+    fs::create_dir(&path)?;
+    let cgroup = Cgroup { path };
+    if let Some(size) = limits.and_then(|limits| limits.memory_max) {
+        write_control(&cgroup.path, "memory.max", &size.to_string())?;
+    }
+    if let Some(percent) = limits.and_then(|limits| limits.cpu_max) {
+        let quota = u64::from(percent) * CPU_PERIOD / 100;
+        write_control(
+            &cgroup.path,
+            "cpu.max",
+            &format!("{} {}", quota, CPU_PERIOD),
+        )?;
+    }
+    Ok(cgroup)
+}
+
+/// Write a file of a controller, which is missing if the controller isn't enabled.
+fn write_control(path: &Path, name: &str, value: &str) -> io::Result<()> {
+    let path = path.join(name);
+    if !path.exists() {
+        let controller = name.split('.').next().unwrap();
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} controller not available", controller),
+        ));
+    }
+    fs::write(path, value)
+}
+
+impl Cgroup {
+    /// Make a command start in this cgroup, so that all processes it starts are in it too. Must
+    /// come before switching users, because only root may move processes to it.
+    pub fn apply(&self, command: &mut Command) {
+        let procs = CString::new(self.path.join("cgroup.procs").as_os_str().as_bytes()).unwrap();
+        // Safety: `open`, `write` and `close` are async-signal-safe, and the path was allocated
+        // before forking.
+        unsafe {
+            command.pre_exec(move || {
+                let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                // Writing 0 moves the process that writes it.
+                let res = libc::write(fd, b"0".as_ptr().cast(), 1);
+                let err = io::Error::last_os_error();
+                libc::close(fd);
+                if res < 0 {
+                    return Err(err);
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // Processes left behind by the run are killed, because the cgroup can't be removed
+        // while they're in it. `cgroup.kill` is missing before Linux 5.14.
+        if fs::write(self.path.join("cgroup.kill"), "1").is_err() {
+            if let Ok(procs) = fs::read_to_string(self.path.join("cgroup.procs")) {
+                for pid in procs.lines().filter_map(|pid| pid.parse().ok()) {
+                    // Safety: `kill` has no preconditions.
+                    unsafe { libc::kill(pid, libc::SIGKILL) };
+                }
+            }
+        }
+        let mut waited = Duration::ZERO;
+        loop {
+            match fs::remove_dir(&self.path) {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => return,
+                Err(_) if waited < REMOVE_TIMEOUT => {
+                    thread::sleep(Duration::from_millis(50));
+                    waited += Duration::from_millis(50);
+                }
+                Err(err) => {
+                    warning!("{}: cgroup removal failed: {}", self.path.display(), err);
+                    return;
+                }
+                Ok(()) => return,
+            }
+        }
+    }
+}
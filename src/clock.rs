@@ -0,0 +1,42 @@
+use std::time::Instant;
+
+use chrono::{offset::Local, DateTime, Duration};
+
+/// How far the wall clock may move apart from the monotonic clock between two checks, before it
+/// counts as a jump that is logged.
+const THRESHOLD_SECS: i64 = 10;
+
+/// How far the wall clock may move apart from the monotonic clock between two checks, before
+/// intervals on the monotonic clock follow it. Slewing by NTP stays below this, because checks are
+/// at most a minute apart.
+const STEP_MS: i64 = 100;
+
+/// Watches the wall clock for jumps, like when it's set by hand or corrected at boot, by comparing
+/// it to the monotonic clock.
+pub struct Clock {
+    wall: DateTime<Local>,
+    monotonic: Instant,
+}
+
+impl Clock {
+    pub fn new(now: DateTime<Local>) -> Self {
+        Clock {
+            wall: now,
+            monotonic: Instant::now(),
+        }
+    }
+
+    /// Check whether the wall clock was stepped since the last check. Returns how far, positive if
+    /// it moved forward. A suspend of the machine looks like a step forward.
+    pub fn check(&mut self, now: DateTime<Local>) -> Option<Duration> {
+        let elapsed = Duration::from_std(self.monotonic.elapsed()).unwrap_or_default();
+        let jump = now - (self.wall + elapsed);
+        *self = Clock::new(now);
+        (jump.num_milliseconds().abs() >= STEP_MS).then_some(jump)
+    }
+}
+
+/// Whether a step of the wall clock is large enough to count as a jump.
+pub fn is_jump(step: Duration) -> bool {
+    step.num_seconds().abs() >= THRESHOLD_SECS
+}
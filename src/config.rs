@@ -0,0 +1,557 @@
+use std::{path::Path, rc::Rc};
+
+use pocketcron::crontab::is_setting;
+
+use crate::include::Expanded;
+
+/// Whether a file is a config file in TOML, rather than a crontab, by its extension.
+pub fn is_config(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "toml")
+}
+
+/// A job from a config file, as a crontab of its own.
+pub struct ConfigJob {
+    pub crontab: Expanded,
+    /// Whether the job line has a user, like in system crontabs.
+    pub has_user: bool,
+}
+
+/// Keys of a job that aren't settings, and can't be shared by all jobs.
+const JOB_KEYS: [&str; 4] = ["name", "schedule", "command", "user"];
+
+/// Value in a config file.
+enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Vec<(String, Value)>),
+}
+
+/// A `key = value` line.
+struct Entry {
+    line: usize,
+    key: String,
+    value: Value,
+}
+
+/// Keys of a `[[job]]` table, or of the top of the file, and its `env` table.
+#[derive(Default)]
+struct Table {
+    line: usize,
+    entries: Vec<Entry>,
+    env: Vec<Entry>,
+}
+
+/// Translate a config file into a crontab for every `[[job]]` table in it, so that the settings of
+/// one job don't carry over to the next. Keys at the top and in `[env]` apply to all jobs.
+///
+/// The file is TOML, without multi-line strings and arrays, dotted keys and other tables. Keys
+/// are settings, like `timeout = "1h"` for `TIMEOUT=1h`, and those in `env` are variables.
+///
+/// Also returns all errors, like `jobs.toml:3: error: ...`. Jobs with errors are left out.
+pub fn translate(path: &Path, input: &str) -> (Vec<ConfigJob>, Vec<String>) {
+    let error =
+        |line: usize, message: String| format!("{}:{}: error: {}", path.display(), line, message);
+    let mut errors = Vec::new();
+    let mut global = Table::default();
+    let mut tables: Vec<Table> = Vec::new();
+    // Whether keys go in the `env` table of the current table.
+    let mut in_env = false;
+    for (index, raw) in input.lines().enumerate() {
+        let line_no = index + 1;
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            let header = line
+                .split_once('#')
+                .map_or(line, |(header, _)| header.trim_end());
+            match header {
+                "[[job]]" => {
+                    tables.push(Table {
+                        line: line_no,
+                        ..Table::default()
+                    });
+                    in_env = false;
+                }
+                "[env]" if tables.is_empty() => in_env = true,
+                "[job.env]" if !tables.is_empty() => in_env = true,
+                _ => errors.push(error(line_no, format!("unexpected table: {}", header))),
+            }
+            continue;
+        }
+        let entry = parse_entry(line).map(|(key, value)| Entry {
+            line: line_no,
+            key,
+            value,
+        });
+        let is_global = tables.is_empty();
+        let table = tables.last_mut().unwrap_or(&mut global);
+        match entry {
+            Ok(entry) if in_env => table.env.push(entry),
+            Ok(entry) if is_global && JOB_KEYS.contains(&entry.key.as_str()) => {
+                let message = format!("{} must be in a [[job]] table", entry.key);
+                errors.push(error(line_no, message));
+            }
+            Ok(entry) => table.entries.push(entry),
+            Err(message) => errors.push(error(line_no, message)),
+        }
+    }
+
+    let origin: Rc<Path> = path.into();
+    let mut jobs = Vec::new();
+    let mut names: Vec<String> = Vec::new();
+    for job in &tables {
+        let mut crontab = Expanded::new(false);
+        let mut ok = true;
+        let mut name = None;
+        let mut schedule = None;
+        let mut command = None;
+        let mut user = None;
+        for entry in global.entries.iter().chain(&job.entries) {
+            let result = match entry.key.as_str() {
+                "name" => text(&entry.value).map(|value| name = Some((value, entry.line))),
+                "schedule" => text(&entry.value).map(|value| schedule = Some(value)),
+                "command" => text(&entry.value).map(|value| command = Some(value)),
+                "user" => text(&entry.value).map(|value| user = Some(value)),
+                "env" => match &entry.value {
+                    Value::Table(env) => env.iter().try_for_each(|(name, value)| {
+                        assignment(name, value).map(|line| crontab.push(&line, &origin, entry.line))
+                    }),
+                    _ => Err("env must be a table".to_owned()),
+                },
+                key => {
+                    let name = match key {
+                        "cwd" => "WORKDIR".to_owned(),
+                        _ => key.to_ascii_uppercase(),
+                    };
+                    if is_setting(&name) && !key.contains(|c: char| c.is_ascii_uppercase()) {
+                        text(&entry.value).and_then(|value| {
+                            let line = variable(&name, &value)?;
+                            crontab.push(&line, &origin, entry.line);
+                            Ok(())
+                        })
+                    } else {
+                        Err(format!("unknown key: {}", key))
+                    }
+                }
+            };
+            if let Err(message) = result {
+                errors.push(error(entry.line, message));
+                ok = false;
+            }
+        }
+        for entry in global.env.iter().chain(&job.env) {
+            match assignment(&entry.key, &entry.value) {
+                Ok(line) => crontab.push(&line, &origin, entry.line),
+                Err(message) => {
+                    errors.push(error(entry.line, message));
+                    ok = false;
+                }
+            }
+        }
+        let (Some(schedule), Some(command)) = (schedule, command) else {
+            errors.push(error(
+                job.line,
+                "a job needs a schedule and a command".to_owned(),
+            ));
+            continue;
+        };
+        let fields = [
+            (Some(&schedule), "schedule"),
+            (Some(&command), "command"),
+            (name.as_ref().map(|(name, _)| name), "name"),
+            (user.as_ref(), "user"),
+        ];
+        for (value, what) in fields {
+            if value.is_some_and(|value| value.contains('\n')) {
+                errors.push(error(job.line, format!("{} has a line break", what)));
+                ok = false;
+            }
+        }
+        // Names are unique across jobs, like in a crontab.
+        if let Some((name, line)) = &name {
+            if names.contains(name) {
+                errors.push(error(*line, format!("duplicate job name: {}", name)));
+                ok = false;
+            }
+            names.push(name.clone());
+        }
+        if !ok {
+            continue;
+        }
+        if let Some((name, line)) = name {
+            crontab.push(&format!("# name: {}", name), &origin, line);
+        }
+        // A `%` in a crontab line starts the input of the command, but not in a config file.
+        let command = command.replace('%', "\\%");
+        let line = match &user {
+            Some(user) => format!("{} {} {}", schedule, user, command),
+            None => format!("{} {}", schedule, command),
+        };
+        crontab.push(&line, &origin, job.line);
+        jobs.push(ConfigJob {
+            crontab,
+            has_user: user.is_some(),
+        });
+    }
+    (jobs, errors)
+}
+
+/// A crontab variable assignment. Quotes keep surrounding spaces, and are the only ones removed.
+fn variable(name: &str, value: &str) -> Result<String, String> {
+    if value.contains('\n') {
+        return Err(format!("{} has a line break", name));
+    }
+    Ok(format!("{}=\"{}\"", name, value))
+}
+
+/// A crontab assignment of an environment variable.
+fn assignment(name: &str, value: &Value) -> Result<String, String> {
+    let mut chars = name.chars();
+    let is_identifier = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_identifier {
+        return Err(format!("invalid variable name: {}", name));
+    }
+    if is_setting(name) {
+        return Err(format!(
+            "{} is a setting, use {} outside of env",
+            name,
+            name.to_ascii_lowercase()
+        ));
+    }
+    variable(name, &text(value)?)
+}
+
+/// The text of a value in a crontab. Arrays are comma-separated, like for `ENV_KEEP`.
+fn text(value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Integer(value) => Ok(value.to_string()),
+        Value::Boolean(value) => Ok(value.to_string()),
+        Value::Array(values) => {
+            let values = values
+                .iter()
+                .map(|value| match value {
+                    Value::Array(_) | Value::Table(_) => Err("nested values".to_owned()),
+                    value => text(value),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(values.join(","))
+        }
+        Value::Table(_) => Err("expected a value, not a table".to_owned()),
+    }
+}
+
+/// Parse a `key = value` line, with an optional comment after it.
+fn parse_entry(line: &str) -> Result<(String, Value), String> {
+    let (key, rest) = parse_key(line)?;
+    let rest = rest
+        .trim_start()
+        .strip_prefix('=')
+        .ok_or_else(|| format!("expected = after {}", key))?;
+    let (value, rest) = parse_value(rest.trim_start())?;
+    let rest = rest.trim_start();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return Err(format!("unexpected text after value: {}", rest));
+    }
+    Ok((key, value))
+}
+
+/// Parse a bare or quoted key, and return the rest of the input.
+fn parse_key(input: &str) -> Result<(String, &str), String> {
+    if input.starts_with('"') || input.starts_with('\'') {
+        return match parse_value(input)? {
+            (Value::String(key), rest) => Ok((key, rest)),
+            _ => unreachable!(),
+        };
+    }
+    let end = input
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '-')
+        .unwrap_or(input.len());
+    if end == 0 {
+        return Err(format!("expected a key: {}", input));
+    }
+    if input[end..].starts_with('.') {
+        return Err("dotted keys are not supported".to_owned());
+    }
+    Ok((input[..end].to_owned(), &input[end..]))
+}
+
+/// Parse a value at the start of the input, and return the rest.
+fn parse_value(input: &str) -> Result<(Value, &str), String> {
+    if input.starts_with("\"\"\"") || input.starts_with("'''") {
+        return Err("multi-line strings are not supported".to_owned());
+    }
+    if let Some(rest) = input.strip_prefix('\'') {
+        let (value, rest) = rest
+            .split_once('\'')
+            .ok_or_else(|| "unterminated string".to_owned())?;
+        return Ok((Value::String(value.to_owned()), rest));
+    }
+    if let Some(rest) = input.strip_prefix('"') {
+        return parse_basic_string(rest);
+    }
+    if let Some(mut rest) = input.strip_prefix('[') {
+        let mut values = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::Array(values), after));
+            }
+            if rest.is_empty() {
+                return Err("multi-line arrays are not supported".to_owned());
+            }
+            let (value, after) = parse_value(rest)?;
+            values.push(value);
+            rest = after.trim_start();
+            rest = rest.strip_prefix(',').unwrap_or(rest);
+        }
+    }
+    if let Some(mut rest) = input.strip_prefix('{') {
+        let mut entries = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix('}') {
+                return Ok((Value::Table(entries), after));
+            }
+            let (key, after) = parse_key(rest)?;
+            let after = after
+                .trim_start()
+                .strip_prefix('=')
+                .ok_or_else(|| format!("expected = after {}", key))?;
+            let (value, after) = parse_value(after.trim_start())?;
+            entries.push((key, value));
+            rest = after.trim_start();
+            rest = rest.strip_prefix(',').unwrap_or(rest);
+        }
+    }
+    let end = input
+        .find(|c: char| c.is_whitespace() || matches!(c, ',' | ']' | '}' | '#'))
+        .unwrap_or(input.len());
+    let (token, rest) = input.split_at(end);
+    let value = match token {
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        _ => match token.replace('_', "").parse() {
+            Ok(value) => Value::Integer(value),
+            Err(_) => return Err(format!("invalid value: {}", token)),
+        },
+    };
+    Ok((value, rest))
+}
+
+/// Parse the rest of a string in double quotes, with escapes.
+fn parse_basic_string(input: &str) -> Result<(Value, &str), String> {
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((Value::String(value), &input[index + 1..])),
+            '\\' => {
+                let escaped = match chars.next().map(|(_, c)| c) {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some(kind @ ('u' | 'U')) => {
+                        let len = if kind == 'u' { 4 } else { 8 };
+                        let hex: String = chars.by_ref().take(len).map(|(_, c)| c).collect();
+                        u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| format!("invalid escape: \\{}{}", kind, hex))?
+                    }
+                    Some(c) => return Err(format!("invalid escape: \\{}", c)),
+                    None => break,
+                };
+                value.push(escaped);
+            }
+            c => value.push(c),
+        }
+    }
+    Err("unterminated string".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The crontabs of the jobs in a config file, and its errors without the file name.
+    fn translate_str(input: &str) -> (Vec<String>, Vec<String>) {
+        let (jobs, errors) = translate(Path::new("jobs.toml"), input);
+        let crontabs = jobs.into_iter().map(|job| job.crontab.input).collect();
+        let errors = errors
+            .into_iter()
+            .map(|err| err.strip_prefix("jobs.toml:").unwrap().to_owned())
+            .collect();
+        (crontabs, errors)
+    }
+
+    fn parse_str(input: &str) -> Result<Value, String> {
+        parse_value(input).map(|(value, _)| value)
+    }
+
+    #[test]
+    fn jobs_have_settings_of_their_own() {
+        let (crontabs, errors) = translate_str(
+            r#"
+timeout = "1h"
+[env]
+PGHOST = "db"
+
+[[job]]
+name = "backup"
+schedule = "0 3 * * *"
+command = "pg_dump app"
+cwd = "/backups"
+env = { PGUSER = "backup" }
+
+[[job]]
+schedule = "@hourly"
+user = "nobody"
+command = "cleanup"
+env_keep = ["PATH", "HOME"]
+[job.env]
+TMP = '/tmp'
+"#,
+        );
+        assert_eq!(errors, Vec::<String>::new());
+        assert_eq!(
+            crontabs,
+            [
+                "TIMEOUT=\"1h\"\nWORKDIR=\"/backups\"\nPGUSER=\"backup\"\nPGHOST=\"db\"\n\
+                 # name: backup\n0 3 * * * pg_dump app\n",
+                "TIMEOUT=\"1h\"\nENV_KEEP=\"PATH,HOME\"\nPGHOST=\"db\"\nTMP=\"/tmp\"\n\
+                 @hourly nobody cleanup\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn percent_signs_are_part_of_the_command() {
+        let (crontabs, _) =
+            translate_str("[[job]]\nschedule = \"@daily\"\ncommand = \"date +%F > \\\\%.log\"\n");
+        assert_eq!(crontabs, ["@daily date +\\%F > \\\\%.log\n"]);
+    }
+
+    #[test]
+    fn strings() {
+        let string = |input| match parse_str(input) {
+            Ok(Value::String(value)) => value,
+            _ => panic!("not a string: {}", input),
+        };
+        assert_eq!(
+            string(r#""a \"b\" \\ \t\u00e9\U0001F600""#),
+            "a \"b\" \\ \té😀"
+        );
+        assert_eq!(string(r"'C:\path'"), r"C:\path");
+        assert_eq!(parse_str(r#""\x""#).err().unwrap(), "invalid escape: \\x");
+        assert_eq!(
+            parse_str(r#""\u12""#).err().unwrap(),
+            "invalid escape: \\u12\""
+        );
+        assert_eq!(parse_str("\"open").err().unwrap(), "unterminated string");
+        assert_eq!(parse_str("'open").err().unwrap(), "unterminated string");
+        assert_eq!(
+            parse_str("\"\"\"long").err().unwrap(),
+            "multi-line strings are not supported"
+        );
+    }
+
+    #[test]
+    fn other_values() {
+        assert!(matches!(parse_str("1_000"), Ok(Value::Integer(1000))));
+        assert!(matches!(parse_str("-5"), Ok(Value::Integer(-5))));
+        assert!(matches!(parse_str("false"), Ok(Value::Boolean(false))));
+        assert!(matches!(parse_str("[1, 'a',]"), Ok(Value::Array(values)) if values.len() == 2));
+        assert!(
+            matches!(parse_str("{ a = 1, 'b c' = 2 }"), Ok(Value::Table(entries))
+            if entries.iter().map(|(key, _)| key.as_str()).eq(["a", "b c"]))
+        );
+        assert_eq!(parse_str("yes").err().unwrap(), "invalid value: yes");
+        assert_eq!(
+            parse_str("[1,").err().unwrap(),
+            "multi-line arrays are not supported"
+        );
+    }
+
+    #[test]
+    fn entries() {
+        assert!(matches!(
+            parse_entry("nice = 5 # lower"),
+            Ok((key, Value::Integer(5))) if key == "nice"
+        ));
+        assert_eq!(
+            parse_entry("a.b = 1").err().unwrap(),
+            "dotted keys are not supported"
+        );
+        assert_eq!(parse_entry("a 1").err().unwrap(), "expected = after a");
+        assert_eq!(parse_entry("= 1").err().unwrap(), "expected a key: = 1");
+        assert_eq!(
+            parse_entry("a = 1 2").err().unwrap(),
+            "unexpected text after value: 2"
+        );
+    }
+
+    #[test]
+    fn errors_have_the_line_they_are_on() {
+        let (crontabs, errors) = translate_str(
+            r#"name = "shared"
+[other]
+[[job]]
+schedule = "@daily"
+command = "a"
+color = "blue"
+TIMEOUT = "1h"
+env = { TIMEOUT = "1h" }
+[[job]]
+command = "b"
+"#,
+        );
+        assert!(crontabs.is_empty());
+        assert_eq!(
+            errors,
+            [
+                "1: error: name must be in a [[job]] table",
+                "2: error: unexpected table: [other]",
+                "6: error: unknown key: color",
+                "7: error: unknown key: TIMEOUT",
+                "8: error: TIMEOUT is a setting, use timeout outside of env",
+                "9: error: a job needs a schedule and a command",
+            ]
+        );
+    }
+
+    #[test]
+    fn line_breaks_are_errors() {
+        let (crontabs, errors) = translate_str(
+            "[[job]]\nname = \"a\\nb\"\nschedule = \"@daily\"\ncommand = \"x\"\n\
+             [[job]]\nschedule = \"@daily\"\ncommand = \"x\"\nuser = \"a\\nb\"\n",
+        );
+        assert!(crontabs.is_empty());
+        assert_eq!(
+            errors,
+            [
+                "1: error: name has a line break",
+                "5: error: user has a line break"
+            ]
+        );
+    }
+
+    #[test]
+    fn names_are_unique() {
+        let (crontabs, errors) = translate_str(
+            "[[job]]\nname = \"a\"\nschedule = \"@daily\"\ncommand = \"x\"\n\
+             [[job]]\nname = \"a\"\nschedule = \"@daily\"\ncommand = \"y\"\n",
+        );
+        assert_eq!(crontabs.len(), 1);
+        assert_eq!(errors, ["6: error: duplicate job name: a"]);
+    }
+}
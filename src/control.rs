@@ -0,0 +1,244 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    sync::Arc,
+    thread,
+};
+
+use pocketcron::schedule::Trigger;
+
+#[cfg(feature = "history")]
+use crate::history;
+#[cfg(feature = "metrics")]
+use crate::metrics;
+use crate::{
+    dispatch::Dispatcher, dispatch_all, events, fire, log::info, status, stop, JobHandle, JobList,
+};
+
+/// Number of jobs the `next` request lists by default.
+const DEFAULT_NEXT_COUNT: usize = 10;
+
+/// Create the control socket.
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    // Remove a socket left behind by a previous instance.
+    if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+/// Handle requests on the control socket, on a separate thread.
+///
+/// The protocol is line-based. Every request is a single line, like `trigger <name>`, answered
+/// with a line starting with `ok` or `error`, and possibly more lines of text until the connection
+/// is closed. Requests that stop jobs are answered once the jobs have ended. After a `tail`
+/// request, the connection stays open, and receives events as they happen.
+pub fn listen(listener: UnixListener, jobs: JobList, dispatcher: Arc<Dispatcher>) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let jobs = jobs.clone();
+            let dispatcher = dispatcher.clone();
+            thread::spawn(move || serve(stream, &jobs, &dispatcher));
+        }
+    });
+}
+
+fn serve(stream: UnixStream, jobs: &JobList, dispatcher: &Arc<Dispatcher>) {
+    let mut line = String::new();
+    if BufReader::new(&stream).read_line(&mut line).is_err() {
+        return;
+    }
+    let request = line.trim();
+    if let Some(name) = request
+        .strip_prefix("tail")
+        .filter(|rest| rest.is_empty() || rest.starts_with(' '))
+    {
+        return tail(stream, name.trim(), jobs);
+    }
+    let response = match handle(request, jobs, dispatcher) {
+        Ok(message) => format!("ok {}\n", message),
+        Err(message) => format!("error {}\n", message),
+    };
+    let _ = (&stream).write_all(response.as_bytes());
+}
+
+/// Send events to the client from now on, about the jobs with a name, or all events.
+fn tail(stream: UnixStream, name: &str, jobs: &JobList) {
+    let filter = if name.is_empty() {
+        None
+    } else {
+        let ids: Vec<usize> = (jobs.lock().unwrap().iter())
+            .map(|job_handle| job_handle.lock().unwrap())
+            .filter(|job| !job.retired && job.is_named(name))
+            .map(|job| job.id)
+            .collect();
+        if ids.is_empty() {
+            let _ = (&stream).write_all(format!("error unknown job: {}\n", name).as_bytes());
+            return;
+        }
+        Some(ids)
+    };
+    let response = match &filter {
+        Some(ids) => format!("ok following {} job(s)\n", ids.len()),
+        None => "ok following all jobs\n".to_owned(),
+    };
+    if (&stream).write_all(response.as_bytes()).is_ok() {
+        events::subscribe(stream, filter);
+    }
+}
+
+fn handle(request: &str, jobs: &JobList, dispatcher: &Arc<Dispatcher>) -> Result<String, String> {
+    let (verb, arg) = request.split_once(' ').unwrap_or((request, ""));
+    match verb {
+        "trigger" if !arg.is_empty() => {
+            match fire(jobs, dispatcher, &Trigger::Named(arg.to_owned())) {
+                0 => Err(format!("unknown trigger: {}", arg)),
+                count => Ok(format!("triggered {} job(s)", count)),
+            }
+        }
+        "kill" if !arg.is_empty() => {
+            let (name, signal) = match arg.split_once(' ') {
+                Some((name, signal)) => (name, Some(signal.parse()?)),
+                None => (arg, None),
+            };
+            stop::kill(dispatcher, name, signal)
+        }
+        "abort" if arg.is_empty() || arg == "pause" => {
+            if arg == "pause" {
+                stop::set_paused(true);
+            }
+            info!("aborting all running jobs");
+            let count = stop::abort(dispatcher);
+            Ok(format!("aborted {} job(s)", count))
+        }
+        "status" if arg.is_empty() => Ok(status::render(&dispatcher.active())),
+        #[cfg(feature = "metrics")]
+        "stats" if arg.is_empty() => Ok(metrics::stats(jobs)),
+        #[cfg(not(feature = "metrics"))]
+        "stats" if arg.is_empty() => Err("requires a build with the `metrics` feature".to_owned()),
+        "resume" if arg.is_empty() => {
+            stop::set_paused(false);
+            Ok("resumed".to_owned())
+        }
+        "list" if arg.is_empty() => Ok(status::list(&jobs.lock().unwrap().clone(), None)),
+        "next" => {
+            let count = match arg {
+                "" => DEFAULT_NEXT_COUNT,
+                _ => arg.parse().map_err(|_| format!("invalid count: {}", arg))?,
+            };
+            Ok(status::list(&jobs.lock().unwrap().clone(), Some(count)))
+        }
+        "run" if !arg.is_empty() => run(dispatcher, named(jobs, arg)?),
+        "restart" if !arg.is_empty() => stop::restart(arg, &named(jobs, arg)?),
+        #[cfg(feature = "history")]
+        "history" if !arg.is_empty() => {
+            // Runs are kept by the name of the job, which also covers jobs that were removed.
+            let name = match named(jobs, arg) {
+                Ok(jobs) => jobs[0].lock().unwrap().name(),
+                Err(err) if !history::contains(arg) => return Err(err),
+                Err(_) => arg.to_owned(),
+            };
+            history::render(&name).ok_or_else(|| "no history file".to_owned())
+        }
+        #[cfg(not(feature = "history"))]
+        "history" if !arg.is_empty() => {
+            Err("requires a build with the `history` feature".to_owned())
+        }
+        "pause" | "resume" if !arg.is_empty() => {
+            Ok(set_enabled(&named(jobs, arg)?, verb == "resume"))
+        }
+        _ => Err(format!("invalid request: {}", request)),
+    }
+}
+
+/// Run jobs right away, unless one of them is paused.
+pub fn run(dispatcher: &Arc<Dispatcher>, due: Vec<JobHandle>) -> Result<String, String> {
+    if let Some(job) = due
+        .iter()
+        .map(|job| job.lock().unwrap())
+        .find(|job| !job.enabled)
+    {
+        return Err(format!("job {} is paused", job.id));
+    }
+    for job_handle in &due {
+        info!([job_handle.lock().unwrap().id] "running on request");
+    }
+    let count = due.len();
+    dispatch_all(dispatcher, due);
+    Ok(format!("dispatched {} job(s)", count))
+}
+
+/// Pause or resume jobs.
+pub fn set_enabled(jobs: &[JobHandle], enabled: bool) -> String {
+    let done = if enabled { "resumed" } else { "paused" };
+    for job_handle in jobs {
+        let mut job = job_handle.lock().unwrap();
+        if job.enabled != enabled {
+            job.enabled = enabled;
+            info!([job.id] "{} on request", done);
+        }
+    }
+    format!("{} {} job(s)", done, jobs.len())
+}
+
+/// Jobs in a crontab with a name, or an error if there are none.
+pub fn named(jobs: &JobList, name: &str) -> Result<Vec<JobHandle>, String> {
+    let jobs: Vec<JobHandle> = (jobs.lock().unwrap().iter())
+        .filter(|job_handle| {
+            let job = job_handle.lock().unwrap();
+            !job.retired && job.is_named(name)
+        })
+        .cloned()
+        .collect();
+    if jobs.is_empty() {
+        return Err(format!("unknown job: {}", name));
+    }
+    Ok(jobs)
+}
+
+/// Send a request to a running pocketcron, and return the response message.
+pub fn request(path: &Path, request: &str) -> Result<String, String> {
+    let stream = UnixStream::connect(path)
+        .map_err(|err| format!("{}: connect failed: {}", path.display(), err))?;
+    let mut response = String::new();
+    writeln!(&stream, "{}", request)
+        .and_then(|_| BufReader::new(&stream).read_to_string(&mut response))
+        .map_err(|err| format!("{}: request failed: {}", path.display(), err))?;
+    let line = response.trim_end();
+    match line.split_once(' ').unwrap_or((line, "")) {
+        ("ok", message) => Ok(message.to_owned()),
+        ("error", message) => Err(message.to_owned()),
+        _ => Err(format!("invalid response: {}", line)),
+    }
+}
+
+/// Send a request to a running pocketcron that is answered with a stream of lines, and pass each
+/// line after the response to a function, until the connection is closed.
+pub fn follow(path: &Path, request: &str, mut f: impl FnMut(&str)) -> Result<(), String> {
+    let stream = UnixStream::connect(path)
+        .map_err(|err| format!("{}: connect failed: {}", path.display(), err))?;
+    writeln!(&stream, "{}", request)
+        .map_err(|err| format!("{}: request failed: {}", path.display(), err))?;
+    let mut lines = BufReader::new(&stream).lines();
+    let line = lines.next().transpose().ok().flatten().unwrap_or_default();
+    match line.split_once(' ').unwrap_or((&line, "")) {
+        ("ok", _) => {}
+        ("error", message) => return Err(message.to_owned()),
+        _ => return Err(format!("invalid response: {}", line)),
+    }
+    for line in lines {
+        match line {
+            Ok(line) => f(&line),
+            Err(err) => return Err(format!("{}: read failed: {}", path.display(), err)),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,607 @@
+use chrono::{Datelike, Months, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+
+use crate::{hash, schedule::ScheduleError};
+
+/// Flavor of cron expression syntax and semantics.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    /// Vixie cron. Days of the week are numbered 0 to 7, where both 0 and 7 are Sunday.
+    Vixie,
+    /// BusyBox crond. Like Vixie, but ranges may wrap around, names are matched by their first
+    /// three letters, and there are no extensions like `L`, `W` and `#`.
+    Busybox,
+    /// Quartz. Days of the week are numbered 1 (Sunday) to 7 (Saturday), and both day fields must
+    /// match.
+    Quartz,
+}
+
+/// A parsed cron expression.
+#[derive(Debug)]
+pub struct Expression {
+    seconds: u64,
+    minutes: u64,
+    hours: u64,
+    days: Days,
+    months: u64,
+    weekdays: Weekdays,
+    /// Allowed years in ascending order, or `None` for any year.
+    years: Option<Vec<i32>>,
+    /// Whether a match of either day field is enough, instead of both.
+    either_day: bool,
+    /// Whether days of the month past the end of a month match on the last day instead.
+    clamp: bool,
+}
+
+/// Matching days of the month.
+#[derive(Debug, Default)]
+struct Days {
+    /// Plain days, as bits.
+    values: u64,
+    /// Days counted back from the last day of the month, from `L` and `L-n`, as bits.
+    from_last: u64,
+    /// Days for which the nearest weekday matches, from `nW`, as bits.
+    nearest_weekday: u64,
+    /// Whether the last weekday of the month matches, from `LW`.
+    last_weekday: bool,
+}
+
+/// Matching days of the week, numbered from Sunday as 0.
+#[derive(Debug, Default)]
+struct Weekdays {
+    /// Plain days, as bits.
+    values: u64,
+    /// Days for which the last in the month matches, from `nL`, as bits.
+    last: u64,
+    /// Days and occurrences in the month, from `n#k`.
+    nth: Vec<(u32, u32)>,
+}
+
+const MONTHS: [&str; 12] = [
+    "january",
+    "february",
+    "march",
+    "april",
+    "may",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+];
+
+const WEEKDAYS: [&str; 7] = [
+    "sunday",
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+];
+
+/// Years after the start of a search before an expression without years is considered to never
+/// match. The Gregorian calendar repeats every 400 years.
+const SEARCH_YEARS: i32 = 400;
+
+/// Description of a field, for parsing.
+struct Field {
+    name: &'static str,
+    min: u32,
+    max: u32,
+    /// Names for values, starting at `first_name`.
+    names: &'static [&'static str],
+    first_name: u32,
+}
+
+impl Expression {
+    /// Parse the fields of a cron expression.
+    ///
+    /// With 5 fields, these are minute, hour, day of month, month and day of week. A sixth field
+    /// is the year. With 7 fields, the first is seconds. Quartz expressions always start with
+    /// seconds, and have an optional year. With `seconds`, other expressions do too.
+    ///
+    /// `H` in a field stands for a value derived from `hash`, like in Jenkins. With `clamp`, days
+    /// of the month past the end of a month match on its last day instead.
+    pub fn parse(
+        fields: &[&str],
+        dialect: Dialect,
+        seconds: bool,
+        hash: u64,
+        clamp: bool,
+    ) -> Result<Self, ScheduleError> {
+        let has_seconds = match (dialect, fields.len()) {
+            (Dialect::Busybox, 5) => false,
+            (Dialect::Busybox, _) => return Err("expected 5 fields".to_owned().into()),
+            (Dialect::Quartz, 6 | 7) => true,
+            (Dialect::Quartz, _) => return Err("expected 6 or 7 fields".to_owned().into()),
+            (_, 6 | 7) if seconds => true,
+            (_, _) if seconds => return Err("expected 6 or 7 fields".to_owned().into()),
+            (_, 5 | 6) => false,
+            (_, 7) => true,
+            _ => return Err("expected 5 to 7 fields".to_owned().into()),
+        };
+        let (seconds, first) = if has_seconds {
+            (fields[0], 1)
+        } else {
+            ("0", 0)
+        };
+        let [minutes, hours, days, months, weekdays] = fields[first..first + 5] else {
+            unreachable!();
+        };
+        let year = fields.get(first + 5).copied();
+
+        // Errors refer to the field by index.
+        let at = |index: usize| {
+            move |message| ScheduleError {
+                field: Some(index),
+                message,
+            }
+        };
+
+        // Replace `H` with values before parsing, a different one in every field. Days of the
+        // month stop at 28, so that every month has them.
+        let weekday_min = if dialect == Dialect::Quartz { 1 } else { 0 };
+        let resolve = |index: usize, text: &str, name, min, max| match dialect {
+            Dialect::Busybox => Ok(text.to_owned()),
+            _ => resolve_hashes(text, name, min, max, hash::mix(hash ^ index as u64))
+                .map_err(at(index)),
+        };
+        let seconds = match has_seconds {
+            true => resolve(0, seconds, "second", 0, 59)?,
+            false => seconds.to_owned(),
+        };
+        let minutes = &resolve(first, minutes, "minute", 0, 59)?;
+        let hours = &resolve(first + 1, hours, "hour", 0, 23)?;
+        let days = &resolve(first + 2, days, "day of month", 1, 28)?;
+        let months = &resolve(first + 3, months, "month", 1, 12)?;
+        let weekdays = &resolve(
+            first + 4,
+            weekdays,
+            "day of week",
+            weekday_min,
+            weekday_min + 6,
+        )?;
+        let time_field = |name, max| Field {
+            name,
+            min: 0,
+            max,
+            names: &[],
+            first_name: 0,
+        };
+        let month_field = Field {
+            name: "month",
+            min: 1,
+            max: 12,
+            names: &MONTHS,
+            first_name: 1,
+        };
+        // Vixie cron considers a day field restricted unless it starts with `*`.
+        let is_star = |field: &str| field.starts_with('*') || field.starts_with('?');
+        let expression = Expression {
+            seconds: parse_values(&seconds, &time_field("second", 59), dialect).map_err(at(0))?,
+            minutes: parse_values(minutes, &time_field("minute", 59), dialect)
+                .map_err(at(first))?,
+            hours: parse_values(hours, &time_field("hour", 23), dialect).map_err(at(first + 1))?,
+            days: parse_days(days, dialect).map_err(at(first + 2))?,
+            months: parse_values(months, &month_field, dialect).map_err(at(first + 3))?,
+            weekdays: parse_weekdays(weekdays, dialect).map_err(at(first + 4))?,
+            years: year
+                .map(parse_years)
+                .transpose()
+                .map_err(at(first + 5))?
+                .flatten(),
+            either_day: dialect != Dialect::Quartz && !is_star(days) && !is_star(weekdays),
+            clamp,
+        };
+        // Otherwise the schedule never runs, which is found only after searching for centuries.
+        if !expression.has_days() {
+            return Err(at(first + 2)(format!(
+                "invalid day of month '{}': no such day in the months and years of the schedule",
+                days
+            )));
+        }
+        Ok(expression)
+    }
+
+    /// Whether the day of month field can match in one of the months and years, leaving aside
+    /// the day of the week.
+    fn has_days(&self) -> bool {
+        let days = &self.days;
+        if self.either_day || self.clamp || days.from_last != 0 || days.last_weekday {
+            return true;
+        }
+        let leap = self.years.as_ref().is_none_or(|years| {
+            years
+                .iter()
+                .any(|year| NaiveDate::from_ymd_opt(*year, 2, 29).is_some())
+        });
+        let first = (days.values | days.nearest_weekday).trailing_zeros();
+        (1..=12)
+            .filter(|month| has_bit(self.months, *month))
+            .any(|month| {
+                NaiveDate::from_ymd_opt(if leap { 2000 } else { 2001 }, month, 1)
+                    .is_some_and(|date| first <= last_day(date))
+            })
+    }
+
+    /// Find the first matching time after `time`.
+    ///
+    /// Years, months and days that don't match are skipped as a whole, so that the search takes
+    /// at most a few steps for every month.
+    pub fn next_after(&self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = time.with_nanosecond(0)? + chrono::Duration::seconds(1);
+        let last_year = match &self.years {
+            Some(years) => *years.last()?,
+            None => start.year() + SEARCH_YEARS,
+        };
+
+        let mut date = start.date();
+        let mut from = start.time();
+        loop {
+            let year = self
+                .next_year(date.year())
+                .filter(|year| *year <= last_year)?;
+            if year != date.year() {
+                date = NaiveDate::from_ymd_opt(year, 1, 1)?;
+                from = NaiveTime::MIN;
+            }
+            let Some(month) = bits_from(self.months, date.month()).find(|month| *month <= 12)
+            else {
+                date = NaiveDate::from_ymd_opt(year + 1, 1, 1)?;
+                from = NaiveTime::MIN;
+                continue;
+            };
+            if month != date.month() {
+                date = NaiveDate::from_ymd_opt(year, month, 1)?;
+                from = NaiveTime::MIN;
+            }
+            let Some(day) = (date.day()..=last_day(date))
+                .filter_map(|day| date.with_day(day))
+                .find(|date| self.matches_day(*date))
+            else {
+                date = date.with_day(1)?.checked_add_months(Months::new(1))?;
+                from = NaiveTime::MIN;
+                continue;
+            };
+            if day != date {
+                date = day;
+                from = NaiveTime::MIN;
+            }
+            match self.next_time(from) {
+                Some(time) => return Some(date.and_time(time)),
+                None => {
+                    date = date.succ_opt()?;
+                    from = NaiveTime::MIN;
+                }
+            }
+        }
+    }
+
+    /// The first allowed year at or after `year`.
+    fn next_year(&self, year: i32) -> Option<i32> {
+        match &self.years {
+            Some(years) => years
+                .get(years.partition_point(|other| *other < year))
+                .copied(),
+            None => Some(year),
+        }
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let last = last_day(date);
+        let day = date.day();
+        let days = &self.days;
+        let by_day = has_bit(days.values, day)
+            || (self.clamp && day == last && days.values >> (last + 1) != 0)
+            || has_bit(days.from_last, last - day)
+            || (1..=31).any(|n| {
+                has_bit(days.nearest_weekday, n)
+                    && (n <= last || self.clamp)
+                    && nearest_weekday(date, n) == day
+            })
+            || (days.last_weekday && nearest_weekday(date, last) == day);
+
+        let weekday = date.weekday().num_days_from_sunday();
+        let weekdays = &self.weekdays;
+        let by_weekday = has_bit(weekdays.values, weekday)
+            || (has_bit(weekdays.last, weekday) && day + 7 > last)
+            || weekdays.nth.contains(&(weekday, (day - 1) / 7 + 1));
+
+        if self.either_day {
+            by_day || by_weekday
+        } else {
+            by_day && by_weekday
+        }
+    }
+
+    /// Find the first matching time of day at or after `from`.
+    fn next_time(&self, from: NaiveTime) -> Option<NaiveTime> {
+        let (hour, minute, second) = (from.hour(), from.minute(), from.second());
+        for h in bits_from(self.hours, hour) {
+            let first_minute = if h == hour { minute } else { 0 };
+            for m in bits_from(self.minutes, first_minute) {
+                let first_second = if (h, m) == (hour, minute) { second } else { 0 };
+                if let Some(s) = bits_from(self.seconds, first_second).next() {
+                    return NaiveTime::from_hms_opt(h, m, s);
+                }
+            }
+        }
+        None
+    }
+}
+
+fn has_bit(bits: u64, index: u32) -> bool {
+    index < 64 && bits >> index & 1 == 1
+}
+
+fn bits_from(bits: u64, from: u32) -> impl Iterator<Item = u32> {
+    (from..64).filter(move |index| has_bit(bits, *index))
+}
+
+/// Last day of the month of `date`.
+fn last_day(date: NaiveDate) -> u32 {
+    date.with_day(1)
+        .and_then(|first| first.checked_add_months(Months::new(1)))
+        .and_then(|next_month| next_month.pred_opt())
+        .map_or(31, |last| last.day())
+}
+
+/// Weekday nearest to day `day` within the month of `date`, like Quartz `W`.
+fn nearest_weekday(date: NaiveDate, day: u32) -> u32 {
+    let last = last_day(date);
+    let day = day.min(last);
+    let Some(weekday) = date
+        .with_day(day)
+        .map(|date| date.weekday().num_days_from_sunday())
+    else {
+        return day;
+    };
+    match weekday {
+        // Saturday goes to Friday, unless that is in the previous month.
+        6 if day == 1 => day + 2,
+        6 => day - 1,
+        // Sunday goes to Monday, unless that is in the next month.
+        0 if day == last => day - 2,
+        0 => day + 1,
+        _ => day,
+    }
+}
+
+/// Parse a field that only contains plain values, as bits.
+fn parse_values(text: &str, field: &Field, dialect: Dialect) -> Result<u64, String> {
+    let mut bits = 0;
+    for item in text.split(',') {
+        for value in
+            parse_item(item, field, dialect).map_err(|err| field_error(field, text, err))?
+        {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+fn parse_days(text: &str, dialect: Dialect) -> Result<Days, String> {
+    let field = Field {
+        name: "day of month",
+        min: 1,
+        max: 31,
+        names: &[],
+        first_name: 0,
+    };
+    let mut days = Days::default();
+    for item in text.split(',') {
+        let extended = dialect != Dialect::Busybox;
+        let result = if extended && item == "L" {
+            days.from_last |= 1;
+            Ok(())
+        } else if extended && item == "LW" {
+            days.last_weekday = true;
+            Ok(())
+        } else if let Some(offset) = item.strip_prefix("L-").filter(|_| extended) {
+            parse_number(offset, 0, 30).map(|offset| days.from_last |= 1 << offset)
+        } else if let Some(day) = item.strip_suffix('W').filter(|_| extended) {
+            parse_number(day, 1, 31).map(|day| days.nearest_weekday |= 1 << day)
+        } else {
+            parse_item(item, &field, dialect)
+                .map(|values| values.into_iter().for_each(|day| days.values |= 1 << day))
+        };
+        result.map_err(|err| field_error(&field, text, err))?;
+    }
+    Ok(days)
+}
+
+fn parse_weekdays(text: &str, dialect: Dialect) -> Result<Weekdays, String> {
+    // Values are converted to count from Sunday as 0.
+    let (field, to_index): (_, fn(u32) -> u32) = match dialect {
+        Dialect::Quartz => (
+            Field {
+                name: "day of week",
+                min: 1,
+                max: 7,
+                names: &WEEKDAYS,
+                first_name: 1,
+            },
+            |day| day - 1,
+        ),
+        _ => (
+            Field {
+                name: "day of week",
+                min: 0,
+                max: 7,
+                names: &WEEKDAYS,
+                first_name: 0,
+            },
+            |day| day % 7,
+        ),
+    };
+    let mut weekdays = Weekdays::default();
+    for item in text.split(',') {
+        let extended = dialect != Dialect::Busybox;
+        let result = if let Some((day, nth)) = item.split_once('#').filter(|_| extended) {
+            parse_value(day, &field, dialect).and_then(|day| {
+                let nth = parse_number(nth, 1, 5)?;
+                weekdays.nth.push((to_index(day), nth));
+                Ok(())
+            })
+        } else if extended && item == "L" {
+            Err("expected a day of the week before 'L'".to_owned())
+        } else if let Some(day) = item.strip_suffix('L').filter(|_| extended) {
+            parse_value(day, &field, dialect).map(|day| weekdays.last |= 1 << to_index(day))
+        } else {
+            parse_item(item, &field, dialect).map(|values| {
+                for day in values {
+                    weekdays.values |= 1 << to_index(day);
+                }
+            })
+        };
+        result.map_err(|err| field_error(&field, text, err))?;
+    }
+    Ok(weekdays)
+}
+
+/// Parse a year field, returning `None` for any year.
+fn parse_years(text: &str) -> Result<Option<Vec<i32>>, String> {
+    let field = Field {
+        name: "year",
+        min: 1970,
+        max: 2099,
+        names: &[],
+        first_name: 0,
+    };
+    if text == "*" {
+        return Ok(None);
+    }
+    let mut years = Vec::new();
+    for item in text.split(',') {
+        let values = parse_item(item, &field, Dialect::Vixie)
+            .map_err(|err| field_error(&field, text, err))?;
+        years.extend(values.into_iter().map(|year| year as i32));
+    }
+    years.sort_unstable();
+    years.dedup();
+    Ok(Some(years))
+}
+
+/// Replace items like `H`, `H(2-4)`, `H/15` and `H(0-29)/10` in a field with a value or a range
+/// that is derived from `hash`. Plain `H` is a value from `min` to `max`, and ranges must be
+/// within them.
+fn resolve_hashes(text: &str, name: &str, min: u32, max: u32, hash: u64) -> Result<String, String> {
+    let error = |err: String| format!("invalid {} '{}': {}", name, text, err);
+    let mut items = Vec::new();
+    for item in text.split(',') {
+        let Some(spec) = item.strip_prefix('H') else {
+            items.push(item.to_owned());
+            continue;
+        };
+        let (range, step) = match spec.split_once('/') {
+            Some((range, step)) => (range, Some(parse_number(step, 1, max).map_err(error)?)),
+            None => (spec, None),
+        };
+        let (start, end) = match range {
+            "" => (min, max),
+            _ => {
+                let (start, end) = range
+                    .strip_prefix('(')
+                    .and_then(|range| range.strip_suffix(')'))
+                    .and_then(|range| range.split_once('-'))
+                    .ok_or_else(|| error("expected H(<start>-<end>)".to_owned()))?;
+                let start = parse_number(start, min, max).map_err(error)?;
+                let end = parse_number(end, min, max).map_err(error)?;
+                if start > end {
+                    return Err(error(format!("{} is after {}", start, end)));
+                }
+                (start, end)
+            }
+        };
+        let count = u64::from(end - start + 1);
+        items.push(match step {
+            Some(step) => {
+                let first = start + (hash % count.min(step.into())) as u32;
+                format!("{}-{}/{}", first, end, step)
+            }
+            None => (start + (hash % count) as u32).to_string(),
+        });
+    }
+    Ok(items.join(","))
+}
+
+fn field_error(field: &Field, text: &str, err: String) -> String {
+    format!("invalid {} '{}': {}", field.name, text, err)
+}
+
+/// Parse a list item containing a value, a range or `*`, with an optional step.
+fn parse_item(item: &str, field: &Field, dialect: Dialect) -> Result<Vec<u32>, String> {
+    let (range, step) = match item.split_once('/') {
+        Some((range, step)) => (range, parse_number(step, 1, field.max)?),
+        None => (item, 1),
+    };
+    let is_day = field.name.starts_with("day");
+    let (start, end) = match range.split_once('-') {
+        _ if range == "*" || (is_day && range == "?" && dialect != Dialect::Busybox) => {
+            (field.min, field.max)
+        }
+        Some((start, end)) => (
+            parse_value(start, field, dialect)?,
+            parse_value(end, field, dialect)?,
+        ),
+        // A single value with a step runs until the maximum.
+        None if item.contains('/') => (parse_value(range, field, dialect)?, field.max),
+        None => {
+            let value = parse_value(range, field, dialect)?;
+            (value, value)
+        }
+    };
+
+    // Only BusyBox allows ranges that wrap around.
+    let len = if end >= start {
+        end - start + 1
+    } else if dialect == Dialect::Busybox {
+        field.max - start + 1 + end - field.min + 1
+    } else {
+        return Err(format!("range '{}' ends before it starts", range));
+    };
+    Ok((0..len)
+        .step_by(step as usize)
+        .map(|offset| {
+            let value = start + offset;
+            if value > field.max {
+                value - field.max - 1 + field.min
+            } else {
+                value
+            }
+        })
+        .collect())
+}
+
+/// Parse a single value, either a number or a name.
+fn parse_value(text: &str, field: &Field, dialect: Dialect) -> Result<u32, String> {
+    if text.chars().all(|c| c.is_ascii_digit()) {
+        return parse_number(text, field.min, field.max);
+    }
+
+    // Names are case-insensitive, and may be abbreviated to three letters. BusyBox only looks at
+    // the first three letters.
+    let lower = text.to_ascii_lowercase();
+    let index = field.names.iter().position(|name| match dialect {
+        Dialect::Busybox => lower
+            .get(..3)
+            .is_some_and(|prefix| name.starts_with(prefix)),
+        _ => *name == lower || (lower.len() == 3 && name.starts_with(&lower)),
+    });
+    match index {
+        Some(index) => Ok(index as u32 + field.first_name),
+        None => Err(format!("unknown value '{}'", text)),
+    }
+}
+
+fn parse_number(text: &str, min: u32, max: u32) -> Result<u32, String> {
+    if text.is_empty() || !text.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("'{}' is not a number", text));
+    }
+    match text.parse() {
+        Ok(value) if value >= min && value <= max => Ok(value),
+        _ => Err(format!("{} is out of range {}-{}", text, min, max)),
+    }
+}
@@ -0,0 +1,1476 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    hash::Hasher,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    offset::Local,
+    DateTime, Duration,
+};
+
+use crate::{
+    duration::parse_duration,
+    hash::Fnv1a,
+    schedule::{Align, Compat, Dst, ParseOptions, Schedule, Trigger},
+    tz::Tz,
+};
+
+/// A job, as described by a crontab line.
+#[derive(Debug)]
+pub struct JobSpec {
+    /// Line number, starting at 1.
+    pub line: usize,
+    /// The line itself, without surrounding whitespace. This identifies the job for the random
+    /// delay, so that it doesn't change when other lines are added.
+    pub source: String,
+    pub schedule: Schedule,
+    /// Fixed offset of every occurrence.
+    pub offset: Duration,
+    /// Maximum random delay of every occurrence.
+    pub random_delay: Duration,
+    /// Whether the interval is measured on the monotonic clock, from `CLOCK=monotonic`. Only set
+    /// for intervals counted from the start or from completion.
+    pub monotonic: bool,
+    /// Shell command.
+    pub command: String,
+    /// Name from a `# name:` comment above the line, if any.
+    pub name: Option<String>,
+    /// Shell that runs the command, with arguments, or `None` for the default.
+    pub shell: Option<String>,
+    /// Whether the command runs directly, split into arguments, without a shell, from
+    /// `SHELL=none`.
+    pub exec: bool,
+    /// User to run as, from the user field of system crontabs.
+    pub user: Option<String>,
+    /// Directory the command runs in, from `WORKDIR`, or `None` for that of pocketcron.
+    pub workdir: Option<PathBuf>,
+    /// What to do when the concurrency limit is reached.
+    pub queue: QueuePolicy,
+    /// What to do while a previous run is in progress.
+    pub overlap: Overlap,
+    /// Dispatch priority, higher goes first.
+    pub priority: i32,
+    /// Where to lock occurrences, if the job is shared between hosts.
+    pub lock: Option<LockBackend>,
+    /// File that is locked with `flock` while the job runs, if any, from `FLOCK`.
+    pub flock: Option<PathBuf>,
+    /// Shell command that reads the output of the job, if any.
+    pub pipe_to: Option<String>,
+    /// Time between asking the job to stop and killing it.
+    pub grace: Duration,
+    /// How long a run may take before it is stopped, zero for no limit, or `None` for the default.
+    pub timeout: Option<Duration>,
+    /// Shortest time between the starts of two runs, zero for none.
+    pub min_interval: Duration,
+    /// Longest time without a successful run before the job is overdue, if watched.
+    pub expect_within: Option<Duration>,
+    /// Signal that asks the job to stop.
+    pub kill_signal: Signal,
+    /// Names of the environment variables passed to the job, or all if `None`.
+    pub env_keep: Option<Vec<String>>,
+    /// Where to mail the output of runs, if anywhere.
+    pub mail_to: Option<String>,
+    /// Path of the file to write the output of runs to, with placeholders, if any.
+    pub log_file: Option<String>,
+    /// When the log file is rotated, if ever.
+    pub log_rotation: Option<LogRotation>,
+    /// URL to ping when runs start and end, if any.
+    pub ping: Option<String>,
+    /// Niceness and resource limits of runs.
+    pub limits: Limits,
+    /// What the processes of runs are kept from doing.
+    pub sandbox: Sandbox,
+    /// Shell command to run after a run succeeded, if any.
+    pub on_success: Option<String>,
+    /// Shell command to run after a run failed, if any.
+    pub on_failure: Option<String>,
+    /// Number of failures in a row before `on_failure` runs and failures are mailed, if set, in
+    /// which case the first success after an alert is a recovery.
+    pub alert_after: Option<u32>,
+    /// Environment variables set for the job, in addition to those passed.
+    pub env: Vec<(String, String)>,
+    /// Whether an occurrence missed while pocketcron wasn't running runs when it starts.
+    pub catch_up: bool,
+    /// Whether the job also runs once when pocketcron starts.
+    pub run_on_start: bool,
+    /// Whether the output of runs is only passed on when they fail.
+    pub quiet_success: bool,
+    /// Hash of the variables in effect for the job, which decide its settings.
+    pub settings: u64,
+}
+
+impl JobSpec {
+    /// What identifies the job when a crontab is reloaded.
+    pub fn key(&self) -> JobKey<'_> {
+        JobKey {
+            source: &self.source,
+            command: &self.command,
+            state_key: self.state_key(),
+            settings: self.settings,
+        }
+    }
+
+    /// Key of the job in the state file, if runs are recorded: the id of a period, or the line
+    /// itself for jobs that catch up.
+    pub fn state_key(&self) -> Option<&str> {
+        self.schedule
+            .state_key()
+            .or_else(|| self.catch_up.then_some(self.source.as_str()))
+    }
+}
+
+/// What identifies a job when a crontab is reloaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JobKey<'a> {
+    /// The crontab line.
+    pub source: &'a str,
+    /// Shell command.
+    pub command: &'a str,
+    /// Key of the job in the state file, if any.
+    pub state_key: Option<&'a str>,
+    /// Hash of the variables in effect for the job.
+    pub settings: u64,
+}
+
+/// How a job in a reloaded crontab relates to the jobs loaded before.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Match {
+    /// The same line as the old job at this index, with the same settings.
+    Same(usize),
+    /// A changed version of the old job at this index.
+    Changed(usize),
+    /// A job that didn't exist before.
+    New,
+}
+
+/// What to do with a due job when the concurrency limit is reached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueuePolicy {
+    /// Skip this occurrence of the job.
+    Drop,
+    /// Queue the job, and start it as soon as a slot frees up.
+    Defer,
+    /// Queue the job ahead of deferred ones, and start it in the next slot that frees up.
+    Block,
+}
+
+impl FromStr for QueuePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(QueuePolicy::Drop),
+            "defer" => Ok(QueuePolicy::Defer),
+            "block" => Ok(QueuePolicy::Block),
+            _ => Err(format!("invalid queue policy: {}", s)),
+        }
+    }
+}
+
+/// What to do with a due job while a previous run is still in progress.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overlap {
+    /// Skip this occurrence of the job.
+    Skip,
+    /// Run the job again as soon as the previous run finishes.
+    Queue,
+    /// Stop the previous run, and start a new one.
+    Kill,
+    /// Start another run alongside the previous one.
+    Allow,
+}
+
+impl FromStr for Overlap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Overlap::Skip),
+            "queue" => Ok(Overlap::Queue),
+            "kill" => Ok(Overlap::Kill),
+            "allow" => Ok(Overlap::Allow),
+            _ => Err(format!("invalid overlap policy: {}", s)),
+        }
+    }
+}
+
+/// Where to take per-occurrence locks, so that a job shared between hosts runs on only one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LockBackend {
+    /// Lock files in a directory on a shared filesystem.
+    File(PathBuf),
+    /// Keys in a Redis server, as `host:port`.
+    Redis(String),
+}
+
+impl FromStr for LockBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(dir) = s.strip_prefix("file:").filter(|dir| !dir.is_empty()) {
+            return Ok(LockBackend::File(PathBuf::from(dir)));
+        }
+        if let Some(addr) = s.strip_prefix("redis://") {
+            let addr = addr.strip_suffix('/').unwrap_or(addr);
+            if !addr.is_empty() && !addr.contains('/') {
+                return Ok(LockBackend::Redis(if addr.contains(':') {
+                    addr.to_owned()
+                } else {
+                    format!("{}:6379", addr)
+                }));
+            }
+        }
+        Err(format!("invalid lock backend: {}", s))
+    }
+}
+
+/// Signals by their name, without the `SIG` prefix.
+#[cfg(unix)]
+const SIGNALS: [(&str, i32); 12] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("WINCH", libc::SIGWINCH),
+];
+
+/// Without signals, the names are only parsed, so crontabs from Unix can be read as they are. The
+/// numbers are those of Linux.
+#[cfg(not(unix))]
+const SIGNALS: [(&str, i32); 12] = [
+    ("HUP", 1),
+    ("INT", 2),
+    ("QUIT", 3),
+    ("KILL", 9),
+    ("USR1", 10),
+    ("USR2", 12),
+    ("ALRM", 14),
+    ("TERM", 15),
+    ("CONT", 18),
+    ("STOP", 19),
+    ("TSTP", 20),
+    ("WINCH", 28),
+];
+
+/// A signal to send to jobs, like `SIGINT`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Signal(pub i32);
+
+impl FromStr for Signal {
+    type Err = String;
+
+    /// Parse a signal name, with or without the `SIG` prefix.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.strip_prefix("SIG").unwrap_or(s);
+        SIGNALS
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, signal)| Signal(*signal))
+            .ok_or_else(|| format!("invalid signal: {}", s))
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match SIGNALS.iter().find(|(_, signal)| *signal == self.0) {
+            Some((name, _)) => write!(f, "SIG{}", name),
+            None => write!(f, "signal {}", self.0),
+        }
+    }
+}
+
+/// When and how the log file of a job is rotated, from `LOG_MAX_SIZE`, `LOG_KEEP` and
+/// `LOG_COMPRESS`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LogRotation {
+    /// Size in bytes from which the log file is rotated.
+    pub max_size: u64,
+    /// Number of rotated files kept, like `job.log.1` to `job.log.5`.
+    pub keep: usize,
+    /// Whether rotated files are compressed with gzip, like `job.log.1.gz`.
+    pub compress: bool,
+}
+
+/// Niceness, resource limits and umask of a job, from `NICE`, `RLIMIT_CPU`, `RLIMIT_AS`,
+/// `MEMORY_MAX`, `CPU_MAX` and `UMASK`. Unset values are inherited from pocketcron.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Nice level, from -20 to 19.
+    pub nice: Option<i32>,
+    /// CPU time a process may use.
+    pub cpu: Option<Duration>,
+    /// Size of the address space of a process, in bytes.
+    pub address_space: Option<u64>,
+    /// Memory all processes of a run may use together, in bytes, with `--cgroup-root`.
+    pub memory_max: Option<u64>,
+    /// CPU time all processes of a run may use together, in percent of one CPU, with
+    /// `--cgroup-root`.
+    pub cpu_max: Option<u32>,
+    /// Permission bits that are cleared from files and directories that processes create.
+    pub umask: Option<u32>,
+}
+
+impl Limits {
+    /// Whether nothing is changed from pocketcron.
+    pub fn is_empty(&self) -> bool {
+        *self == Limits::default()
+    }
+}
+
+/// What the processes of a job are kept from doing on Linux, from `SANDBOX`, `ALLOW_PATHS` and
+/// `NO_NETWORK`. Processes can't get out of it, and neither can those they start.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sandbox {
+    /// Whether files can only be changed below `allow_paths`, with Landlock.
+    pub read_only: bool,
+    /// Directories and files that can still be changed.
+    pub allow_paths: Vec<PathBuf>,
+    /// Whether network sockets can't be opened, with seccomp.
+    pub no_network: bool,
+}
+
+impl Sandbox {
+    /// Whether processes can do everything pocketcron can.
+    pub fn is_empty(&self) -> bool {
+        !self.read_only && !self.no_network
+    }
+}
+
+/// An error in a crontab, with its position.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    /// Line number, starting at 1.
+    pub line: usize,
+    /// Byte column, starting at 1.
+    pub column: usize,
+    pub kind: ParseErrorKind,
+    pub message: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// Assignment of an invalid value to a variable.
+    InvalidValue,
+    /// Invalid schedule, including cron expressions and intervals.
+    InvalidSchedule,
+    /// Invalid offset after the schedule.
+    InvalidOffset,
+    /// A schedule without a command.
+    MissingCommand,
+    /// Invalid or duplicate name in a `# name:` comment.
+    InvalidName,
+    /// A command that can't be split into arguments, with `SHELL=none`.
+    InvalidCommand,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Settings from crontab variables, which apply to subsequent jobs in the file.
+#[derive(Clone)]
+struct Settings {
+    /// Value of `QUEUE`.
+    queue: QueuePolicy,
+    /// Value of `OVERLAP`.
+    overlap: Overlap,
+    /// Value of `CRON_TZ`.
+    zone: Option<Arc<Tz>>,
+    /// Value of `PRIORITY`.
+    priority: i32,
+    /// Value of `RANDOM_DELAY`.
+    random_delay: Duration,
+    /// Value of `OFFSET`.
+    offset: Duration,
+    /// Value of `ALIGN`.
+    align: Align,
+    /// Value of `DST`.
+    dst: Dst,
+    /// Whether `CLOCK` is `monotonic`.
+    monotonic: bool,
+    /// Whether `END_OF_MONTH` is `clamp`.
+    clamp: bool,
+    /// Value of `CRON_SECONDS`.
+    seconds: bool,
+    /// Value of `DEBOUNCE`.
+    debounce: Duration,
+    /// Value of `LOCK`.
+    lock: Option<LockBackend>,
+    /// Value of `FLOCK`.
+    flock: Option<PathBuf>,
+    /// Value of `PIPE_TO`.
+    pipe_to: Option<String>,
+    /// Value of `SHELL`.
+    shell: Option<String>,
+    /// Value of `WORKDIR`.
+    workdir: Option<PathBuf>,
+    /// Whether `SHELL` is `none`.
+    exec: bool,
+    /// Value of `GRACE`.
+    grace: Duration,
+    /// Value of `TIMEOUT`.
+    timeout: Option<Duration>,
+    /// Value of `MIN_INTERVAL`.
+    min_interval: Duration,
+    /// Value of `EXPECT_WITHIN`.
+    expect_within: Option<Duration>,
+    /// Value of `KILL_SIGNAL`.
+    kill_signal: Signal,
+    /// Value of `ENV_KEEP`.
+    env_keep: Option<Vec<String>>,
+    /// Value of `MAILTO`.
+    mail_to: Option<String>,
+    /// Value of `LOG_FILE`.
+    log_file: Option<String>,
+    /// Value of `LOG_MAX_SIZE`.
+    log_max_size: Option<u64>,
+    /// Value of `LOG_KEEP`.
+    log_keep: usize,
+    /// Whether `LOG_COMPRESS` is `gzip`.
+    log_compress: bool,
+    /// Value of `PING`.
+    ping: Option<String>,
+    /// Values of `NICE`, `RLIMIT_CPU`, `RLIMIT_AS`, `MEMORY_MAX`, `CPU_MAX` and `UMASK`.
+    limits: Limits,
+    /// Values of `SANDBOX`, `ALLOW_PATHS` and `NO_NETWORK`.
+    sandbox: Sandbox,
+    /// Value of `ON_SUCCESS`.
+    on_success: Option<String>,
+    /// Value of `ON_FAILURE`.
+    on_failure: Option<String>,
+    /// Value of `ALERT_AFTER`.
+    alert_after: Option<u32>,
+    /// Value of `CATCHUP`.
+    catch_up: bool,
+    /// Value of `RUN_ON_START`.
+    run_on_start: bool,
+    /// Value of `QUIET_SUCCESS`.
+    quiet_success: bool,
+    /// Other variables, which are set in the environment of jobs.
+    env: Vec<(String, String)>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            queue: QueuePolicy::Defer,
+            overlap: Overlap::Skip,
+            zone: None,
+            priority: 0,
+            random_delay: Duration::zero(),
+            offset: Duration::zero(),
+            align: Align::Start,
+            dst: Dst::Once,
+            monotonic: false,
+            clamp: false,
+            seconds: false,
+            debounce: Duration::seconds(1),
+            lock: None,
+            flock: None,
+            pipe_to: None,
+            shell: None,
+            workdir: None,
+            exec: false,
+            grace: Duration::seconds(30),
+            timeout: None,
+            min_interval: Duration::zero(),
+            expect_within: None,
+            kill_signal: Signal(libc::SIGTERM),
+            env_keep: None,
+            mail_to: None,
+            log_file: None,
+            log_max_size: None,
+            log_keep: 5,
+            log_compress: false,
+            ping: None,
+            limits: Limits::default(),
+            sandbox: Sandbox::default(),
+            on_success: None,
+            on_failure: None,
+            alert_after: None,
+            catch_up: false,
+            run_on_start: false,
+            quiet_success: false,
+            env: Vec::new(),
+        }
+    }
+}
+
+/// Parse a crontab, the way pocketcron does.
+pub fn parse_crontab(input: &str) -> Result<Vec<JobSpec>, Vec<ParseError>> {
+    parse_crontab_with(input, Compat::Default)
+}
+
+/// Parse a crontab in a compatibility mode.
+///
+/// All lines are parsed, and either all jobs or all errors are returned.
+pub fn parse_crontab_with(input: &str, compat: Compat) -> Result<Vec<JobSpec>, Vec<ParseError>> {
+    let (jobs, errors) = parse_crontab_lenient(input, compat);
+    if errors.is_empty() {
+        Ok(jobs)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parse a crontab in a compatibility mode, leaving out lines with errors. Returns the jobs on
+/// the other lines, and all errors.
+///
+/// Jobs that follow a variable with an invalid value are left out too, until it's set to a valid
+/// value again.
+pub fn parse_crontab_lenient(input: &str, compat: Compat) -> (Vec<JobSpec>, Vec<ParseError>) {
+    let mut jobs = Vec::new();
+    let mut errors = Vec::new();
+    let mut settings = Settings::default();
+    // Large crontabs tend to repeat expressions, which are then shared.
+    let mut expressions = HashMap::new();
+    // Name for the next job, from a comment above it, and the names taken so far.
+    let mut name: Option<String> = None;
+    let mut names: HashSet<String> = HashSet::new();
+    // Last values of the variables so far, which decide the settings of jobs, and their hash.
+    let mut variables: BTreeMap<&str, &str> = BTreeMap::new();
+    let mut settings_hash = Fnv1a::default().finish();
+    // Variables whose last value is invalid. Jobs below them are skipped rather than run with
+    // the previous value, which may give them more access than intended.
+    let mut invalid: Vec<&str> = Vec::new();
+    for (index, raw) in input.lines().enumerate() {
+        let line_no = index + 1;
+        let error = |start: &str, kind, message| ParseError {
+            line: line_no,
+            column: start.as_ptr() as usize - raw.as_ptr() as usize + 1,
+            kind,
+            message,
+        };
+
+        // Ignore empty lines and comments, except for names of jobs.
+        let line = raw.trim();
+        if line.is_empty() {
+            name = None;
+            continue;
+        }
+        if let Some(comment) = line.strip_prefix('#') {
+            if let Some(value) = comment.trim_start().strip_prefix("name:") {
+                let value = value.trim();
+                match parse_name(value) {
+                    Ok(()) if names.contains(value) => errors.push(error(
+                        value,
+                        ParseErrorKind::InvalidName,
+                        format!("duplicate job name: {}", value),
+                    )),
+                    Ok(()) => name = Some(value.to_owned()),
+                    Err(message) => errors.push(error(value, ParseErrorKind::InvalidName, message)),
+                }
+            }
+            continue;
+        }
+
+        // Variable assignments change settings for subsequent jobs.
+        if let Some((name, value)) = parse_variable(line) {
+            match set_variable(&mut settings, name, value) {
+                Ok(()) => {
+                    invalid.retain(|other| *other != name);
+                    variables.insert(name, value);
+                    let mut hasher = Fnv1a::default();
+                    for (name, value) in &variables {
+                        hasher.write(name.as_bytes());
+                        hasher.write_u8(b'=');
+                        hasher.write(value.as_bytes());
+                        hasher.write_u8(0);
+                    }
+                    settings_hash = hasher.finish();
+                }
+                Err(message) => {
+                    if !invalid.contains(&name) {
+                        invalid.push(name);
+                    }
+                    errors.push(error(value, ParseErrorKind::InvalidValue, message));
+                }
+            }
+            continue;
+        }
+        let name = name.take();
+        if let Some(variable) = invalid.first() {
+            errors.push(error(
+                line,
+                ParseErrorKind::InvalidValue,
+                format!(
+                    "skipped, because the value of {} above is invalid",
+                    variable
+                ),
+            ));
+            continue;
+        }
+        if let Some(name) = &name {
+            names.insert(name.clone());
+        }
+
+        // Use `str::split_whitespace` only to find the end of the schedule. We don't want to split the
+        // command that way, because it could break spaces in quoted strings. Would prefer using
+        // `SplitWhitespace::remainder`, but that is nightly-only at the moment.
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let schedule_len = Schedule::len(&tokens, compat, settings.seconds);
+        let mut rest = tokens.iter().copied().skip(schedule_len);
+        let token_start = |token: &str| token.as_ptr() as usize - line.as_ptr() as usize;
+        let mut command_token = rest.next();
+        let schedule_end = command_token.map_or(line.len(), token_start);
+
+        // The schedule may be followed by an offset, like `+30s`.
+        let mut offset = settings.offset;
+        if let Some(token) = command_token.filter(|token| token.starts_with('+')) {
+            match parse_offset(&token[1..]) {
+                Ok(value) => offset = value,
+                Err(message) => {
+                    errors.push(error(token, ParseErrorKind::InvalidOffset, message));
+                    continue;
+                }
+            }
+            command_token = rest.next();
+        }
+
+        // Then by a maximum random delay, like `~15m`. Commands may start with `~` too, like
+        // `~/bin/backup.sh`, so it's only a delay if it is one, and something follows it.
+        let mut random_delay = settings.random_delay;
+        let delay = (command_token.filter(|token| token.starts_with('~')))
+            .filter(|_| rest.clone().next().is_some())
+            .and_then(|token| Some((token, parse_delay(&token[1..]).ok()?)));
+        // Delays that are too long are still delays, rather than the start of the command.
+        if let Some((token, value)) = delay {
+            match limit_random_delay(value, &token[1..]) {
+                Ok(value) => random_delay = value,
+                Err(message) => {
+                    errors.push(error(token, ParseErrorKind::InvalidOffset, message));
+                    continue;
+                }
+            }
+            command_token = rest.next();
+        }
+
+        // System crontabs have a user before the command.
+        let mut user = None;
+        if compat == Compat::System {
+            user = command_token;
+            command_token = rest.next();
+        }
+
+        let Some(command_token) = command_token else {
+            errors.push(error(
+                &line[line.len()..],
+                ParseErrorKind::MissingCommand,
+                "not enough elements".to_owned(),
+            ));
+            continue;
+        };
+
+        // Parse the schedule. `H` is derived from the name of the job, or else its command.
+        let schedule = line[..schedule_end].trim_end();
+        let command = &line[token_start(command_token)..];
+        let mut hasher = Fnv1a::default();
+        hasher.write(name.as_deref().unwrap_or(command).as_bytes());
+        let hash = hasher.finish();
+        let options = ParseOptions {
+            align: settings.align,
+            dst: settings.dst,
+            zone: settings.zone.clone(),
+            clamp: settings.clamp,
+            compat,
+            debounce: settings.debounce,
+            seconds: settings.seconds,
+            hash,
+        };
+        let schedule = match Schedule::parse(schedule, &options) {
+            Ok(Schedule::Cron(expression, dst, zone)) => {
+                let expression = expressions
+                    .entry((
+                        schedule,
+                        settings.clamp,
+                        settings.seconds,
+                        schedule.contains('H').then_some(hash),
+                    ))
+                    .or_insert(expression);
+                Schedule::Cron(Arc::clone(expression), dst, zone)
+            }
+            Ok(schedule) => schedule,
+            Err(err) => {
+                let start = err
+                    .field
+                    .and_then(|field| tokens.get(field))
+                    .map_or(line, |token| *token);
+                errors.push(error(start, ParseErrorKind::InvalidSchedule, err.message));
+                continue;
+            }
+        };
+        let dates = check_dates(&split_input(command).0)
+            .and(settings.pipe_to.as_deref().map_or(Ok(()), check_dates));
+        if let Err(message) = dates {
+            errors.push(error(
+                command_token,
+                ParseErrorKind::InvalidCommand,
+                message,
+            ));
+            continue;
+        }
+        if settings.exec {
+            let words = check_words(&split_input(command).0)
+                .and(settings.pipe_to.as_deref().map_or(Ok(()), check_words));
+            if let Err(message) = words {
+                errors.push(error(
+                    command_token,
+                    ParseErrorKind::InvalidCommand,
+                    message,
+                ));
+                continue;
+            }
+        }
+        // Periods catch up already, and triggered jobs have no occurrences to miss.
+        let catch_up =
+            settings.catch_up && matches!(schedule, Schedule::Cron(..) | Schedule::Every(..));
+        // `@reboot` jobs run when pocketcron starts already.
+        let run_on_start =
+            settings.run_on_start && !matches!(schedule, Schedule::Trigger(Trigger::Reboot));
+        // Boundaries and timestamps are points on the wall clock.
+        let monotonic = settings.monotonic
+            && matches!(
+                schedule,
+                Schedule::Every(_, Align::Start | Align::Completion, ..)
+            );
+
+        jobs.push(JobSpec {
+            line: line_no,
+            source: line.to_owned(),
+            schedule,
+            offset,
+            random_delay,
+            monotonic,
+            command: command.to_owned(),
+            name,
+            user: user.map(str::to_owned),
+            queue: settings.queue,
+            overlap: settings.overlap,
+            priority: settings.priority,
+            lock: settings.lock.clone(),
+            flock: settings.flock.clone(),
+            pipe_to: settings.pipe_to.clone(),
+            shell: settings.shell.clone(),
+            workdir: settings.workdir.clone(),
+            exec: settings.exec,
+            grace: settings.grace,
+            timeout: settings.timeout,
+            min_interval: settings.min_interval,
+            expect_within: settings.expect_within,
+            kill_signal: settings.kill_signal,
+            env_keep: settings.env_keep.clone(),
+            mail_to: settings.mail_to.clone(),
+            log_file: settings.log_file.clone(),
+            log_rotation: settings.log_max_size.map(|max_size| LogRotation {
+                max_size,
+                keep: settings.log_keep,
+                compress: settings.log_compress,
+            }),
+            ping: settings.ping.clone(),
+            limits: settings.limits,
+            sandbox: settings.sandbox.clone(),
+            on_success: settings.on_success.clone(),
+            on_failure: settings.on_failure.clone(),
+            alert_after: settings.alert_after,
+            env: settings.env.clone(),
+            catch_up,
+            run_on_start,
+            quiet_success: settings.quiet_success,
+            settings: settings_hash,
+        });
+    }
+
+    (jobs, errors)
+}
+
+/// Match the jobs in a reloaded crontab to the jobs loaded before, so that their state carries
+/// over. Old jobs that aren't matched were removed.
+///
+/// Lines that didn't change, with the same variables in effect, are matched first. Of the
+/// remaining jobs, one is a changed version of an old job if it has the same command, or else the
+/// same key in the state file. Every old job is matched at most once, in order, so duplicate lines
+/// are matched one by one.
+pub fn match_jobs(old: &[JobKey<'_>], new: &[JobSpec]) -> Vec<Match> {
+    let mut unmatched: HashMap<(&str, u64), Vec<usize>> = HashMap::new();
+    for (index, key) in old.iter().enumerate().rev() {
+        unmatched
+            .entry((key.source, key.settings))
+            .or_default()
+            .push(index);
+    }
+    let mut taken = vec![false; old.len()];
+    let mut matches: Vec<Match> = new
+        .iter()
+        .map(|spec| {
+            let same =
+                (unmatched.get_mut(&(spec.source.as_str(), spec.settings))).and_then(Vec::pop);
+            match same {
+                Some(index) => {
+                    taken[index] = true;
+                    Match::Same(index)
+                }
+                None => Match::New,
+            }
+        })
+        .collect();
+
+    // Usually few jobs change, so a linear search is fine.
+    let passes: [fn(&JobKey<'_>, &JobSpec) -> bool; 2] = [
+        |old, new| old.command == new.command,
+        |old, new| old.state_key.is_some() && old.state_key == new.state_key(),
+    ];
+    for same in passes {
+        for (spec, result) in new.iter().zip(&mut matches) {
+            if *result != Match::New {
+                continue;
+            }
+            let found = (0..old.len()).find(|index| !taken[*index] && same(&old[*index], spec));
+            if let Some(index) = found {
+                taken[index] = true;
+                *result = Match::Changed(index);
+            }
+        }
+    }
+    matches
+}
+
+/// Split a job command into the shell command and its input, like traditional cron: the first
+/// `%` ends the command, and the text after it is passed on stdin, with every other `%` as a
+/// newline. `\%` is a literal `%` anywhere.
+pub fn split_input(command: &str) -> (String, Option<String>) {
+    let mut script = String::with_capacity(command.len());
+    let mut input: Option<String> = None;
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' if chars.peek() == Some(&'%') => chars.next().unwrap(),
+            '%' if input.is_none() => {
+                input = Some(String::new());
+                continue;
+            }
+            '%' => '\n',
+            c => c,
+        };
+        input.as_mut().unwrap_or(&mut script).push(c);
+    }
+    // The input ends with a newline, like a file would.
+    if let Some(input) = &mut input {
+        if !input.is_empty() && !input.ends_with('\n') {
+            input.push('\n');
+        }
+    }
+    (script, input)
+}
+
+/// Expand `${NAME}` and `${DATE:<format>}` in a command, before it runs.
+///
+/// `${DATE}` is the date of `now`, and `${DATE:<format>}` formats it like `strftime`. Other names
+/// are looked up, and left as they are if unknown, for the shell to expand. So are other forms of
+/// shell expansion, like `${NAME:-default}`, and `${` after a backslash.
+pub fn expand(
+    command: &str,
+    now: DateTime<Local>,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> String {
+    let mut output = String::with_capacity(command.len());
+    let mut rest = command;
+    while let Some(start) = rest.find("${") {
+        let escaped = rest[..start].ends_with('\\');
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').filter(|_| !escaped).and_then(|end| {
+            let value = match &rest[2..end] {
+                "DATE" => Some(now.format("%Y-%m-%d").to_string()),
+                name => match name.strip_prefix("DATE:") {
+                    Some(format) if is_date_format(format) => Some(now.format(format).to_string()),
+                    Some(_) => None,
+                    None if is_variable_name(name) => lookup(name),
+                    None => None,
+                },
+            };
+            Some((value?, end))
+        });
+        match value {
+            Some((value, end)) => {
+                output.push_str(&value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                output.push_str("${");
+                rest = &rest[2..];
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Expand `${JOB_NAME}`-like names of `lookup`, `${DATE}` and `${DATE:<format>}` in a shell
+/// command, before it runs.
+///
+/// Values are quoted for where they are in the command, so they stay what they are, and nothing
+/// is expanded in single quotes, like the shell does. Unknown names are left for the shell, which
+/// expands variables from its environment, and so are other forms of expansion, and `${` after a
+/// backslash.
+pub fn expand_shell(
+    command: &str,
+    now: DateTime<Local>,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> String {
+    let mut output = String::with_capacity(command.len());
+    let (mut single, mut double) = (false, false);
+    let mut chars = command.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' if !single => {
+                output.push(c);
+                if let Some((_, c)) = chars.next() {
+                    output.push(c);
+                }
+                continue;
+            }
+            '\'' if !double => single = !single,
+            '"' if !single => double = !double,
+            '$' if !single && command[index..].starts_with("${") => {
+                let rest = &command[index..];
+                let value = rest.find('}').and_then(|end| {
+                    let value = match &rest[2..end] {
+                        "DATE" => Some(now.format("%Y-%m-%d").to_string()),
+                        name => match name.strip_prefix("DATE:") {
+                            Some(format) if is_date_format(format) => {
+                                Some(now.format(format).to_string())
+                            }
+                            Some(_) => None,
+                            None if is_variable_name(name) => lookup(name),
+                            None => None,
+                        },
+                    };
+                    Some((value?, end))
+                });
+                if let Some((value, end)) = value {
+                    match double {
+                        true => output.extend(value.chars().flat_map(|c| {
+                            let escape = matches!(c, '\\' | '"' | '$' | '`');
+                            escape.then_some('\\').into_iter().chain([c])
+                        })),
+                        false => {
+                            output.push('\'');
+                            output.push_str(&value.replace('\'', "'\\''"));
+                            output.push('\'');
+                        }
+                    }
+                    // Skip to the end of the name.
+                    let end = index + end;
+                    while chars.next().is_some_and(|(index, _)| index < end) {}
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        output.push(c);
+    }
+    output
+}
+
+/// Check the formats of `${DATE:<format>}` in a command.
+fn check_dates(command: &str) -> Result<(), String> {
+    for (start, _) in command.match_indices("${DATE:") {
+        let format = &command[start + 7..];
+        let format = &format[..format.find('}').unwrap_or(format.len())];
+        if !is_date_format(format) {
+            return Err(format!("invalid date format: {}", format));
+        }
+    }
+    Ok(())
+}
+
+fn is_date_format(format: &str) -> bool {
+    !StrftimeItems::new(format).any(|item| item == Item::Error)
+}
+
+fn is_variable_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Split a command into arguments, for `SHELL=none`. Single and double quotes and backslashes
+/// work like in a shell, but nothing is expanded.
+pub fn split_words(command: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unclosed single quote".to_owned()),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("unclosed double quote".to_owned()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("unclosed double quote".to_owned()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err("trailing backslash".to_owned()),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    if words.is_empty() {
+        return Err("empty command".to_owned());
+    }
+    Ok(words)
+}
+
+/// A `NAME=value` word at the start of a command for `SHELL=none`, which sets a variable for the
+/// command only, like in a shell. Returns the name and the value.
+pub fn split_assignment(word: &str) -> Option<(&str, &str)> {
+    word.split_once('=')
+        .filter(|(name, _)| is_variable_name(name))
+}
+
+/// Check that a command for `SHELL=none` splits into words, and has a program after its variables.
+fn check_words(command: &str) -> Result<(), String> {
+    let words = split_words(command)?;
+    if words.iter().all(|word| split_assignment(word).is_some()) {
+        return Err("missing command after variables".to_owned());
+    }
+    Ok(())
+}
+
+/// Check a name from a `# name:` comment, which may be used on the command line instead of the
+/// job number.
+fn parse_name(value: &str) -> Result<(), String> {
+    if value.is_empty() || value.contains(char::is_whitespace) {
+        Err(format!("invalid job name: {}", value))
+    } else if value.parse::<usize>().is_ok() {
+        Err(format!("job name can't be a number: {}", value))
+    } else {
+        Ok(())
+    }
+}
+
+/// Parse a `NAME=value` line, stripping optional quotes around the value.
+pub fn parse_variable(line: &str) -> Option<(&str, &str)> {
+    let (name, value) = line.split_once('=')?;
+    let name = name.trim_end();
+    let mut chars = name.chars();
+    let is_identifier = chars
+        .next()
+        .filter(|c| c.is_ascii_alphabetic() || *c == '_')
+        .is_some()
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_identifier {
+        return None;
+    }
+
+    let value = value.trim_start();
+    for quote in ['"', '\''] {
+        if let Some(value) = value
+            .strip_prefix(quote)
+            .and_then(|value| value.strip_suffix(quote))
+        {
+            return Some((name, value));
+        }
+    }
+    Some((name, value))
+}
+
+/// Parse the value of `ALLOW_PATHS`, a comma-separated list of absolute paths.
+fn parse_allow_paths(value: &str) -> Result<Vec<PathBuf>, String> {
+    (value.split(',').map(str::trim))
+        .filter(|path| !path.is_empty())
+        .map(|path| match Path::new(path).is_absolute() {
+            true => Ok(PathBuf::from(path)),
+            false => Err(format!("not an absolute path: {}", path)),
+        })
+        .collect()
+}
+
+/// Parse a comma-separated list of environment variable names, or an empty value for none.
+fn parse_env_keep(value: &str) -> Result<Option<Vec<String>>, String> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value
+        .split(',')
+        .map(str::trim)
+        .map(|name| {
+            let valid = !name.is_empty()
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if valid {
+                Ok(name.to_owned())
+            } else {
+                Err(format!("invalid environment variable name: {}", name))
+            }
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+/// Parse a size in bytes, optionally with a binary unit, like `512M`.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid size: {}", value);
+    let (digits, shift) = match value.strip_suffix(['K', 'M', 'G', 'T']) {
+        Some(digits) => (
+            digits,
+            10 * (1 + "KMGT".find(&value[digits.len()..]).unwrap()),
+        ),
+        None => (value, 0),
+    };
+    let size: u64 = digits.parse().map_err(|_| invalid())?;
+    size.checked_mul(1 << shift)
+        .filter(|size| *size > 0)
+        .ok_or_else(invalid)
+}
+
+/// Check a path template with `{id}`, `{name}` and `{date}` placeholders, or an empty value for
+/// none.
+fn parse_log_file(value: &str) -> Result<Option<String>, String> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let mut rest = value;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed placeholder: {}", &rest[start..]))?;
+        let placeholder = &rest[start..start + end + 1];
+        if !["{id}", "{name}", "{date}"].contains(&placeholder) {
+            return Err(format!("unknown placeholder: {}", placeholder));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    Ok(Some(value.to_owned()))
+}
+
+/// Check a URL to ping, or an empty value for none.
+fn parse_ping(value: &str) -> Result<Option<String>, String> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let host = value
+        .strip_prefix("https://")
+        .or_else(|| value.strip_prefix("http://"));
+    if !host.is_some_and(|host| !host.is_empty() && !host.starts_with('/')) {
+        return Err(format!("invalid ping URL: {}", value));
+    }
+    if cfg!(feature = "http") {
+        Ok(Some(value.to_owned()))
+    } else {
+        Err(format!(
+            "{}: requires a build with the `http` feature",
+            value
+        ))
+    }
+}
+
+/// Longest offset or random delay of a schedule, which keeps the times they're added to within
+/// what chrono can represent.
+const MAX_OFFSET: Duration = Duration::days(366);
+
+/// Parse a fixed offset, of at most a year.
+fn parse_offset(value: &str) -> Result<Duration, String> {
+    match parse_duration(value)? {
+        offset if offset > MAX_OFFSET => Err(format!("offset must be at most 366d: {}", value)),
+        offset => Ok(offset),
+    }
+}
+
+/// Parse a maximum random delay, of at most a year.
+fn parse_random_delay(value: &str) -> Result<Duration, String> {
+    parse_delay(value).and_then(|delay| limit_random_delay(delay, value))
+}
+
+/// Parse a maximum random delay of any length. A bare number is in minutes, like in cronie.
+fn parse_delay(value: &str) -> Result<Duration, String> {
+    match value.parse() {
+        Ok(minutes) => {
+            Duration::try_minutes(minutes).ok_or_else(|| format!("invalid duration: {}", value))
+        }
+        Err(_) => parse_duration(value),
+    }
+}
+
+/// Reject a maximum random delay that is longer than a year.
+fn limit_random_delay(delay: Duration, value: &str) -> Result<Duration, String> {
+    match delay > MAX_OFFSET {
+        true => Err(format!("random delay must be at most 366d: {}", value)),
+        false => Ok(delay),
+    }
+}
+
+/// Whether a crontab variable is a setting, like `TIMEOUT`, rather than one that is set in the
+/// environment of jobs.
+pub fn is_setting(name: &str) -> bool {
+    let mut settings = Settings::default();
+    let _ = set_variable(&mut settings, name, "");
+    settings.env.is_empty()
+}
+
+/// Apply a variable assignment to the settings. Variables that aren't settings are set in the
+/// environment of jobs, like in Vixie cron.
+fn set_variable(settings: &mut Settings, name: &str, value: &str) -> Result<(), String> {
+    match name {
+        "QUEUE" => value.parse().map(|value| settings.queue = value),
+        "OVERLAP" => value.parse().map(|value| settings.overlap = value),
+        "PRIORITY" => value
+            .parse()
+            .map(|value| settings.priority = value)
+            .map_err(|_| format!("invalid priority: {}", value)),
+        "RANDOM_DELAY" => parse_random_delay(value).map(|value| settings.random_delay = value),
+        "OFFSET" => parse_offset(value).map(|value| settings.offset = value),
+        "ALIGN" => value.parse().map(|value| settings.align = value),
+        "DST" => value.parse().map(|value| settings.dst = value),
+        "CLOCK" => match value {
+            "" | "wall" => Ok(false),
+            "monotonic" => Ok(true),
+            _ => Err(format!("invalid clock: {}", value)),
+        }
+        .map(|value| settings.monotonic = value),
+        "CRON_TZ" => match value {
+            "" => Ok(None),
+            _ => Tz::load(value).map(|zone| Some(Arc::new(zone))),
+        }
+        .map(|value| settings.zone = value),
+        "CRON_SECONDS" => match value {
+            "true" => Ok(true),
+            "" | "false" => Ok(false),
+            _ => Err(format!("invalid seconds setting: {}", value)),
+        }
+        .map(|value| settings.seconds = value),
+        "END_OF_MONTH" => match value {
+            "skip" => Ok(false),
+            "clamp" => Ok(true),
+            _ => Err(format!("invalid end of month policy: {}", value)),
+        }
+        .map(|value| settings.clamp = value),
+        "DEBOUNCE" => parse_duration(value).map(|value| settings.debounce = value),
+        "LOCK" => match value {
+            "" | "none" => Ok(None),
+            _ => value.parse().map(Some),
+        }
+        .map(|value| settings.lock = value),
+        "FLOCK" => match value {
+            "" => Ok(None),
+            _ if PathBuf::from(value).is_absolute() => Ok(Some(PathBuf::from(value))),
+            _ => Err(format!("not an absolute path: {}", value)),
+        }
+        .map(|value| settings.flock = value),
+        "GRACE" => parse_duration(value).map(|value| settings.grace = value),
+        "MIN_INTERVAL" => match value {
+            "" => Ok(Duration::zero()),
+            _ => parse_duration(value),
+        }
+        .map(|value| settings.min_interval = value),
+        "EXPECT_WITHIN" => match value {
+            "" => Ok(None),
+            _ => parse_duration(value).and_then(|within| match within > Duration::zero() {
+                true => Ok(Some(within)),
+                false => Err(format!("invalid duration: {}", value)),
+            }),
+        }
+        .map(|value| settings.expect_within = value),
+        "TIMEOUT" => match value {
+            "" => Ok(None),
+            "none" => Ok(Some(Duration::zero())),
+            _ => parse_duration(value).map(Some),
+        }
+        .map(|value| settings.timeout = value),
+        "KILL_SIGNAL" => value.parse().map(|value| settings.kill_signal = value),
+        "PIPE_TO" => {
+            settings.pipe_to = Some(value.to_owned()).filter(|value| !value.is_empty());
+            Ok(())
+        }
+        "NICE" => match value {
+            "" => Ok(None),
+            _ => match value.parse() {
+                Ok(nice) if (-20..=19).contains(&nice) => Ok(Some(nice)),
+                _ => Err(format!("invalid nice level: {}", value)),
+            },
+        }
+        .map(|value| settings.limits.nice = value),
+        "UMASK" => match value {
+            "" => Ok(None),
+            _ => match u32::from_str_radix(value, 8) {
+                Ok(umask) if umask <= 0o777 => Ok(Some(umask)),
+                _ => Err(format!("invalid umask: {}", value)),
+            },
+        }
+        .map(|value| settings.limits.umask = value),
+        "RLIMIT_CPU" => match value {
+            "" => Ok(None),
+            _ => parse_duration(value).map(Some),
+        }
+        .map(|value| settings.limits.cpu = value),
+        "RLIMIT_AS" => match value {
+            "" => Ok(None),
+            _ => parse_size(value).map(Some),
+        }
+        .map(|value| settings.limits.address_space = value),
+        "MEMORY_MAX" => match value {
+            "" => Ok(None),
+            _ => parse_size(value).map(Some),
+        }
+        .map(|value| settings.limits.memory_max = value),
+        "CPU_MAX" => match value {
+            "" => Ok(None),
+            _ => match value.strip_suffix('%').map(str::parse) {
+                Some(Ok(percent)) if percent > 0 => Ok(Some(percent)),
+                _ => Err(format!("invalid CPU percentage: {}", value)),
+            },
+        }
+        .map(|value| settings.limits.cpu_max = value),
+        "SANDBOX" => match value {
+            "readonly-fs" if cfg!(feature = "sandbox") => Ok(true),
+            "readonly-fs" => Err(format!(
+                "{}: requires a build with the `sandbox` feature",
+                value
+            )),
+            "" | "none" => Ok(false),
+            _ => Err(format!("invalid sandbox: {}", value)),
+        }
+        .map(|value| settings.sandbox.read_only = value),
+        "ALLOW_PATHS" => parse_allow_paths(value).map(|value| settings.sandbox.allow_paths = value),
+        "NO_NETWORK" => match value {
+            "true" if cfg!(feature = "sandbox") => Ok(true),
+            "true" => Err(format!(
+                "{}: requires a build with the `sandbox` feature",
+                value
+            )),
+            "" | "false" => Ok(false),
+            _ => Err(format!("invalid no network setting: {}", value)),
+        }
+        .map(|value| settings.sandbox.no_network = value),
+        "ON_SUCCESS" => {
+            settings.on_success = Some(value.to_owned()).filter(|value| !value.is_empty());
+            Ok(())
+        }
+        "ON_FAILURE" => {
+            settings.on_failure = Some(value.to_owned()).filter(|value| !value.is_empty());
+            Ok(())
+        }
+        "ALERT_AFTER" => match value {
+            "" => Ok(None),
+            _ => (value.parse().ok())
+                .filter(|value| *value > 0)
+                .map(Some)
+                .ok_or_else(|| format!("invalid number of failures: {}", value)),
+        }
+        .map(|value| settings.alert_after = value),
+        "SHELL" if value.trim() == "none" => {
+            settings.shell = None;
+            settings.exec = true;
+            settings.env.retain(|(other, _)| other != name);
+            Ok(())
+        }
+        "SHELL" => {
+            settings.exec = false;
+            settings.shell = Some(value.trim().to_owned()).filter(|value| !value.is_empty());
+            // Like in traditional cron, jobs also see it in their environment.
+            settings.env.retain(|(other, _)| other != name);
+            if let Some(shell) = &settings.shell {
+                settings.env.push((name.to_owned(), shell.clone()));
+            }
+            Ok(())
+        }
+        "WORKDIR" => match value {
+            "" => Ok(None),
+            _ if PathBuf::from(value).is_absolute() => Ok(Some(PathBuf::from(value))),
+            _ => Err(format!("not an absolute path: {}", value)),
+        }
+        .map(|value| settings.workdir = value),
+        "ENV_KEEP" => parse_env_keep(value).map(|value| settings.env_keep = value),
+        "LOG_FILE" => parse_log_file(value).map(|value| settings.log_file = value),
+        "LOG_MAX_SIZE" => match value {
+            "" => Ok(None),
+            _ => parse_size(value).map(Some),
+        }
+        .map(|value| settings.log_max_size = value),
+        "LOG_KEEP" => match value {
+            "" => Ok(5),
+            _ => value
+                .parse()
+                .map_err(|_| format!("invalid number of log files: {}", value)),
+        }
+        .map(|value| settings.log_keep = value),
+        "LOG_COMPRESS" => match value {
+            "gzip" if cfg!(feature = "gzip") => Ok(true),
+            "gzip" => Err(format!(
+                "{}: requires a build with the `gzip` feature",
+                value
+            )),
+            "" | "none" => Ok(false),
+            _ => Err(format!("invalid log compression: {}", value)),
+        }
+        .map(|value| settings.log_compress = value),
+        "PING" => parse_ping(value).map(|value| settings.ping = value),
+        "CATCHUP" => match value {
+            "true" => Ok(true),
+            "" | "false" => Ok(false),
+            _ => Err(format!("invalid catch-up setting: {}", value)),
+        }
+        .map(|value| settings.catch_up = value),
+        "RUN_ON_START" => match value {
+            "true" => Ok(true),
+            "" | "false" => Ok(false),
+            _ => Err(format!("invalid run on start setting: {}", value)),
+        }
+        .map(|value| settings.run_on_start = value),
+        "QUIET_SUCCESS" => match value {
+            "true" => Ok(true),
+            "" | "false" => Ok(false),
+            _ => Err(format!("invalid quiet success setting: {}", value)),
+        }
+        .map(|value| settings.quiet_success = value),
+        "MAILTO" => {
+            settings.mail_to = Some(value.to_owned()).filter(|value| !value.is_empty());
+            Ok(())
+        }
+        _ => {
+            settings.env.retain(|(other, _)| other != name);
+            settings.env.push((name.to_owned(), value.to_owned()));
+            Ok(())
+        }
+    }
+}
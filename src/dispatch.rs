@@ -0,0 +1,351 @@
+use std::{
+    collections::VecDeque,
+    mem,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use chrono::offset::Local;
+use pocketcron::crontab::{Overlap, QueuePolicy};
+
+use crate::{
+    events::Event,
+    log::{info, warning},
+    run_job, stop, JobHandle,
+};
+
+/// How long a deferred job waits before its priority is raised by one.
+const AGING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long an idle worker waits for another run before it exits.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Stack size of workers. Runs mostly wait for their process, so they need little.
+const WORKER_STACK_SIZE: usize = 256 * 1024;
+
+/// Starts job runs, subject to the concurrency limit.
+///
+/// Every slot is a worker thread. When a worker finishes a run, it picks up the next deferred job
+/// from the queue, if any. Otherwise it stays around for a while, so that the next run can reuse
+/// it instead of starting a thread.
+///
+/// To prevent starvation, a job is queued at most once, and the priority of queued jobs rises the
+/// longer they wait. This way frequent jobs can't push a less frequent one back indefinitely.
+/// Blocked jobs wait in the same queue, but ahead of the others, so that the scheduler goes on
+/// with other jobs meanwhile.
+pub struct Dispatcher {
+    /// Maximum number of simultaneously running jobs, if limited.
+    max_concurrent: Option<usize>,
+    state: Mutex<DispatchState>,
+    /// Signalled whenever a worker finishes its runs.
+    slot_freed: Condvar,
+    /// Signalled when a run is handed to idle workers.
+    work: Condvar,
+    /// Set when a run finished or jobs changed, and the scheduler should look at jobs again.
+    woken: Mutex<bool>,
+    /// Signalled when `woken` is set.
+    wake: Condvar,
+}
+
+struct DispatchState {
+    /// Jobs that workers are running, one per worker.
+    active: Vec<JobHandle>,
+    /// Deferred jobs, in the order they were queued.
+    queue: VecDeque<QueueEntry>,
+    /// Number of job occurrences dropped so far.
+    drops: u64,
+    /// Number of idle workers that no run was handed to yet.
+    idle: usize,
+    /// Runs handed to idle workers, which they didn't pick up yet.
+    handoff: VecDeque<JobHandle>,
+}
+
+struct QueueEntry {
+    job_handle: JobHandle,
+    /// Job identifier, for coalescing.
+    id: usize,
+    /// Job priority at the time it was queued.
+    priority: i32,
+    /// When the job was queued.
+    since: Instant,
+    /// Whether the job takes the next free slot, with `QUEUE=block`.
+    blocked: bool,
+}
+
+impl QueueEntry {
+    /// Priority including aging.
+    fn effective_priority(&self, now: Instant) -> i64 {
+        let waited = now.duration_since(self.since).as_secs() / AGING_INTERVAL.as_secs();
+        i64::from(self.priority).saturating_add(waited as i64)
+    }
+}
+
+impl DispatchState {
+    /// Take the oldest blocked job, or the queued job with the highest effective priority, oldest
+    /// first.
+    fn pop_queue(&mut self) -> Option<QueueEntry> {
+        if let Some(index) = self.queue.iter().position(|entry| entry.blocked) {
+            return self.queue.remove(index);
+        }
+        let now = Instant::now();
+        let mut best: Option<(usize, i64)> = None;
+        for (index, entry) in self.queue.iter().enumerate() {
+            let priority = entry.effective_priority(now);
+            if best.filter(|(_, best)| *best >= priority).is_none() {
+                best = Some((index, priority));
+            }
+        }
+        let (index, _) = best?;
+        self.queue.remove(index)
+    }
+}
+
+impl Dispatcher {
+    pub fn new(max_concurrent: Option<usize>) -> Arc<Self> {
+        Arc::new(Dispatcher {
+            max_concurrent,
+            state: Mutex::new(DispatchState {
+                active: Vec::new(),
+                queue: VecDeque::new(),
+                drops: 0,
+                idle: 0,
+                handoff: VecDeque::new(),
+            }),
+            slot_freed: Condvar::new(),
+            work: Condvar::new(),
+            woken: Mutex::new(false),
+            wake: Condvar::new(),
+        })
+    }
+
+    /// Start a job, or apply its queue policy if all slots are taken.
+    pub fn dispatch(self: &Arc<Self>, job_handle: JobHandle) {
+        let (id, policy, priority) = {
+            let mut job = job_handle.lock().unwrap();
+
+            // Overlapping runs don't take a slot, unless they are allowed.
+            if job.is_running() {
+                match job.overlap {
+                    Overlap::Skip => {
+                        Event::new("job_skipped")
+                            .num("job", job.id as i64)
+                            .str("reason", "running")
+                            .emit();
+                        return;
+                    }
+                    // The worker of the previous run runs the job again, once only.
+                    Overlap::Queue | Overlap::Kill if job.rerun => {
+                        Event::new("job_skipped")
+                            .num("job", job.id as i64)
+                            .str("reason", "queued")
+                            .emit();
+                        return;
+                    }
+                    Overlap::Queue => {
+                        info!([job.id] "queued until the previous run finishes");
+                        job.rerun = true;
+                        return;
+                    }
+                    Overlap::Kill => {
+                        info!([job.id] "stopping the previous run");
+                        job.rerun = true;
+                        let job_handle = job_handle.clone();
+                        thread::spawn(move || stop::terminate(&job_handle));
+                        return;
+                    }
+                    Overlap::Allow => {}
+                }
+            }
+            (job.id, job.queue, job.priority)
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if !self.is_full(&state) {
+            self.spawn_worker(&mut state, job_handle);
+            return;
+        }
+
+        match policy {
+            QueuePolicy::Drop => {
+                reschedule(&job_handle);
+                state.drops += 1;
+                warning!(
+                    [id] "dropped: concurrency limit reached (total dropped: {})", state.drops
+                );
+                Event::new("job_skipped")
+                    .num("job", id as i64)
+                    .str("reason", "dropped")
+                    .emit();
+            }
+            QueuePolicy::Defer | QueuePolicy::Block => {
+                if state.queue.iter().any(|entry| entry.id == id) {
+                    warning!([id] "skipped: already queued");
+                    Event::new("job_skipped")
+                        .num("job", id as i64)
+                        .str("reason", "queued")
+                        .emit();
+                    return;
+                }
+                let blocked = policy == QueuePolicy::Block;
+                state.queue.push_back(QueueEntry {
+                    job_handle,
+                    id,
+                    priority,
+                    since: Instant::now(),
+                    blocked,
+                });
+                match blocked {
+                    true => warning!(
+                        [id] "blocked: concurrency limit reached (queue depth: {})",
+                        state.queue.len()
+                    ),
+                    false => info!(
+                        [id] "deferred: concurrency limit reached (queue depth: {})",
+                        state.queue.len()
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Sleep until the timeout, or until a run finishes.
+    pub fn sleep(&self, timeout: Duration) {
+        let woken = self.woken.lock().unwrap();
+        let (mut woken, _) = self
+            .wake
+            .wait_timeout_while(woken, timeout, |woken| !*woken)
+            .unwrap();
+        *woken = false;
+    }
+
+    /// Wait until no runs are in progress or queued.
+    pub fn wait_idle(&self) {
+        let state = self.state.lock().unwrap();
+        let _state = self
+            .slot_freed
+            .wait_while(state, |state| !state.active.is_empty())
+            .unwrap();
+    }
+
+    /// Wait until no runs are in progress or queued, for at most a while. Returns whether that
+    /// happened in time.
+    pub fn wait_idle_timeout(&self, timeout: Duration) -> bool {
+        let state = self.state.lock().unwrap();
+        let (_state, result) = self
+            .slot_freed
+            .wait_timeout_while(state, timeout, |state| !state.active.is_empty())
+            .unwrap();
+        !result.timed_out()
+    }
+
+    /// Jobs that are running, including those that were removed in the meantime. Jobs with
+    /// several runs in progress are only listed once.
+    pub fn active(&self) -> Vec<JobHandle> {
+        let mut active: Vec<JobHandle> = Vec::new();
+        for job_handle in &self.state.lock().unwrap().active {
+            if !active.iter().any(|other| Arc::ptr_eq(other, job_handle)) {
+                active.push(job_handle.clone());
+            }
+        }
+        active
+    }
+
+    /// Number of jobs queued until a slot is free.
+    #[cfg(feature = "metrics")]
+    pub fn queue_depth(&self) -> usize {
+        self.state.lock().unwrap().queue.len()
+    }
+
+    /// Number of job occurrences dropped so far, because all slots were taken.
+    #[cfg(feature = "metrics")]
+    pub fn drops(&self) -> u64 {
+        self.state.lock().unwrap().drops
+    }
+
+    /// Make the scheduler look at jobs again.
+    pub fn wake(&self) {
+        *self.woken.lock().unwrap() = true;
+        self.wake.notify_all();
+    }
+
+    fn is_full(&self, state: &DispatchState) -> bool {
+        self.max_concurrent
+            .filter(|max| state.active.len() >= *max)
+            .is_some()
+    }
+
+    fn spawn_worker(self: &Arc<Self>, state: &mut DispatchState, job_handle: JobHandle) {
+        state.active.push(job_handle.clone());
+        if state.idle > 0 {
+            state.idle -= 1;
+            state.handoff.push_back(job_handle);
+            self.work.notify_one();
+            return;
+        }
+        let dispatcher = self.clone();
+        let result = thread::Builder::new()
+            .stack_size(WORKER_STACK_SIZE)
+            .spawn(move || dispatcher.work(job_handle));
+        if let Err(err) = result {
+            let job_handle = state.active.pop().unwrap();
+            warning!([job_handle.lock().unwrap().id] "skipped: worker failed: {}", err);
+            reschedule(&job_handle);
+        }
+    }
+
+    /// Run jobs on a worker thread, until it was idle for a while.
+    fn work(&self, mut job_handle: JobHandle) {
+        loop {
+            run_job(&job_handle);
+            self.wake();
+            if mem::take(&mut job_handle.lock().unwrap().rerun) {
+                continue;
+            }
+
+            // Note that the state lock is released before running the next job.
+            let mut state = self.state.lock().unwrap();
+            let index = state
+                .active
+                .iter()
+                .position(|active| Arc::ptr_eq(active, &job_handle));
+            if let Some(index) = index {
+                state.active.swap_remove(index);
+            }
+            if let Some(next) = state.pop_queue() {
+                info!(
+                    [next.id] "dequeued after waiting {:.1}s (queue depth: {})",
+                    next.since.elapsed().as_secs_f64(),
+                    state.queue.len()
+                );
+                state.active.push(next.job_handle.clone());
+                job_handle = next.job_handle;
+                continue;
+            }
+            self.slot_freed.notify_all();
+
+            state.idle += 1;
+            let (mut state, _) = self
+                .work
+                .wait_timeout_while(state, IDLE_TIMEOUT, |state| state.handoff.is_empty())
+                .unwrap();
+            match state.handoff.pop_front() {
+                Some(next) => job_handle = next,
+                // Nothing was handed to this worker, so it still counts as idle.
+                None => {
+                    state.idle -= 1;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Update the schedule of a job that won't run this time.
+pub fn reschedule(job_handle: &JobHandle) {
+    let mut job = job_handle.lock().unwrap();
+    if let Some(next) = job.upcoming.complete(Local::now()) {
+        job.next = Some(next);
+        job.emit_scheduled();
+    }
+}
@@ -0,0 +1,178 @@
+use std::{env, fs, path::Path};
+
+/// Load the variables in an env file into the environment of pocketcron, which jobs inherit.
+///
+/// Must be called before any threads are started.
+pub fn load(path: &Path) -> Result<(), String> {
+    let input = fs::read_to_string(path)
+        .map_err(|err| format!("{}: read failed: {}", path.display(), err))?;
+    let vars =
+        parse(&input).map_err(|(line, err)| format!("{}:{}: {}", path.display(), line, err))?;
+    for (name, value) in vars {
+        env::set_var(name, value);
+    }
+    Ok(())
+}
+
+/// Parse the `NAME=value` lines of an env file, like Docker and most `.env` loaders read them.
+///
+/// Empty lines and lines starting with `#` are skipped, and a leading `export` is ignored. Values
+/// in single quotes are taken as they are, and those in double quotes may have backslash escapes.
+/// Both may span lines. Unquoted values are trimmed, and end at a ` #` comment. Returns the line
+/// number of the first error, if any.
+fn parse(input: &str) -> Result<Vec<(String, String)>, (usize, String)> {
+    let mut vars = Vec::new();
+    let mut line_no = 0;
+    let mut lines = input.lines();
+    while let Some(line) = lines.next() {
+        line_no += 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((name, value)) = line.split_once('=') else {
+            return Err((line_no, format!("expected NAME=value: {}", line)));
+        };
+        let name = name.trim();
+        let valid = !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err((line_no, format!("invalid variable name: {}", name)));
+        }
+
+        let value = value.trim_start();
+        let start = line_no;
+        let value = match value.chars().next() {
+            Some(quote @ ('\'' | '"')) => {
+                // Continue on the next lines until the closing quote.
+                let mut text = value[1..].to_owned();
+                let (value, rest) = loop {
+                    if let Some(result) = unquote(&text, quote) {
+                        break result;
+                    }
+                    let Some(next) = lines.next() else {
+                        return Err((start, format!("unclosed quote in {}", name)));
+                    };
+                    line_no += 1;
+                    text.push('\n');
+                    text.push_str(next);
+                };
+                let rest = rest.trim();
+                if !rest.is_empty() && !rest.starts_with('#') {
+                    return Err((line_no, format!("unexpected text after quote: {}", rest)));
+                }
+                value
+            }
+            _ => match value.find(" #") {
+                Some(end) => value[..end].trim_end().to_owned(),
+                None => value.trim_end().to_owned(),
+            },
+        };
+        vars.push((name.to_owned(), value));
+    }
+    Ok(vars)
+}
+
+/// The value of a quoted string, up to its closing quote, and the text after it. Returns `None` if
+/// the quote isn't closed.
+fn unquote(text: &str, quote: char) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            c if c == quote => return Some((value, &text[index + 1..])),
+            '\\' if quote == '"' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(input: &str) -> Vec<(String, String)> {
+        parse(input).unwrap()
+    }
+
+    fn var(name: &str, value: &str) -> (String, String) {
+        (name.to_owned(), value.to_owned())
+    }
+
+    #[test]
+    fn unquoted_values_are_trimmed_and_end_at_comments() {
+        assert_eq!(
+            vars("# comment\n\nA=1\n  B = two words  \nC=x # note\nD=a#b\nE=\n"),
+            [
+                var("A", "1"),
+                var("B", "two words"),
+                var("C", "x"),
+                var("D", "a#b"),
+                var("E", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn export_prefixes_are_ignored() {
+        assert_eq!(
+            vars("export A=1\n  export B='2'\nexporter=3\n"),
+            [var("A", "1"), var("B", "2"), var("exporter", "3")]
+        );
+    }
+
+    #[test]
+    fn single_quoted_values_are_taken_as_they_are() {
+        assert_eq!(
+            vars("A='  $HOME \\n # x '\nB='one\ntwo' # comment\nC=after\n"),
+            [
+                var("A", "  $HOME \\n # x "),
+                var("B", "one\ntwo"),
+                var("C", "after"),
+            ]
+        );
+    }
+
+    #[test]
+    fn double_quoted_values_have_escapes() {
+        assert_eq!(
+            vars("A=\"a\\tb\\nc \\\"q\\\" \\\\\"\nB=\"one\ntwo\"\nC='x\"y'\n"),
+            [
+                var("A", "a\tb\nc \"q\" \\"),
+                var("B", "one\ntwo"),
+                var("C", "x\"y"),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_lines_are_errors_with_their_line() {
+        let error = |input| parse(input).unwrap_err();
+        assert_eq!(
+            error("A=1\nno equals sign\n"),
+            (2, "expected NAME=value: no equals sign".to_owned())
+        );
+        assert_eq!(error("1A=x\n"), (1, "invalid variable name: 1A".to_owned()));
+        assert_eq!(
+            error("A-B=x\n"),
+            (1, "invalid variable name: A-B".to_owned())
+        );
+        assert_eq!(error("=x\n"), (1, "invalid variable name: ".to_owned()));
+        // Unclosed quotes are reported on the line they start on.
+        assert_eq!(
+            error("A=1\nB='one\ntwo\n"),
+            (2, "unclosed quote in B".to_owned())
+        );
+        assert_eq!(
+            error("A=\"one\ntwo\" three\n"),
+            (2, "unexpected text after quote: three".to_owned())
+        );
+    }
+}
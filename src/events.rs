@@ -0,0 +1,159 @@
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::Write as _,
+    os::unix::{
+        io::{FromRawFd, RawFd},
+        net::UnixStream,
+    },
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use chrono::{offset::Local, DateTime, SecondsFormat};
+
+use crate::forward;
+
+/// Destination of events, if enabled.
+static SINK: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Clients following events live, with `tail`.
+static SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+
+/// How long a write to a subscriber may block, before it is dropped.
+const SUBSCRIBER_TIMEOUT: Duration = Duration::from_secs(1);
+
+struct Subscriber {
+    stream: UnixStream,
+    /// Numbers of the jobs to send events about, or all events if `None`.
+    jobs: Option<Vec<usize>>,
+}
+
+/// Send events to a file descriptor inherited from the parent process.
+///
+/// # Safety
+///
+/// The file descriptor must be open, and not used elsewhere in the process.
+pub unsafe fn open(fd: RawFd) {
+    let _ = SINK.set(Mutex::new(File::from_raw_fd(fd)));
+}
+
+/// Send events to a client as well, until it goes away. With a list of job numbers, only events
+/// about those jobs are sent.
+pub fn subscribe(stream: UnixStream, jobs: Option<Vec<usize>>) {
+    let _ = stream.set_write_timeout(Some(SUBSCRIBER_TIMEOUT));
+    SUBSCRIBERS
+        .lock()
+        .unwrap()
+        .push(Subscriber { stream, jobs });
+}
+
+/// Whether any client is following events.
+pub fn has_subscribers() -> bool {
+    !SUBSCRIBERS.lock().unwrap().is_empty()
+}
+
+/// A machine-readable event, written as a line of JSON.
+pub struct Event {
+    buf: String,
+    /// Whether this is a job run record, which is also forwarded.
+    forward: bool,
+    /// The job the event is about, if any, for subscribers that only follow some jobs.
+    job: Option<usize>,
+}
+
+impl Event {
+    pub fn new(kind: &str) -> Self {
+        let mut event = Event {
+            buf: String::from("{"),
+            forward: kind.starts_with("job_") && kind != "job_scheduled",
+            job: None,
+        };
+        event.buf.push_str("\"event\":");
+        push_string(&mut event.buf, kind);
+        event.time("time", Local::now())
+    }
+
+    /// Add a string field.
+    pub fn str(mut self, name: &str, value: &str) -> Self {
+        self.key(name);
+        push_string(&mut self.buf, value);
+        self
+    }
+
+    /// Add a number field.
+    pub fn num(mut self, name: &str, value: i64) -> Self {
+        if name == "job" {
+            self.job = Some(value as usize);
+        }
+        self.key(name);
+        let _ = write!(self.buf, "{}", value);
+        self
+    }
+
+    /// Add a timestamp field.
+    pub fn time(self, name: &str, value: DateTime<Local>) -> Self {
+        self.str(name, &value.to_rfc3339_opts(SecondsFormat::Millis, false))
+    }
+
+    /// Write the event. Write errors are ignored, so a consumer going away doesn't affect jobs.
+    pub fn emit(mut self) {
+        // The collector receives records from many hosts.
+        let forward = self.forward && forward::is_enabled();
+        if forward {
+            self = self.str("host", &crate::hostname());
+        }
+        self.buf.push_str("}\n");
+        if let Some(sink) = SINK.get() {
+            let _ = sink.lock().unwrap().write_all(self.buf.as_bytes());
+        }
+        self.publish();
+        if forward {
+            forward::push(self.buf);
+        }
+    }
+
+    /// Write the event to subscribers only. For output of jobs, which is only captured while
+    /// there are subscribers.
+    pub fn emit_live(mut self) {
+        self.buf.push_str("}\n");
+        self.publish();
+    }
+
+    fn publish(&self) {
+        let mut subscribers = SUBSCRIBERS.lock().unwrap();
+        subscribers.retain_mut(|subscriber| {
+            let wanted = match (&subscriber.jobs, self.job) {
+                (None, _) => true,
+                (Some(jobs), Some(job)) => jobs.contains(&job),
+                (Some(_), None) => false,
+            };
+            !wanted || subscriber.stream.write_all(self.buf.as_bytes()).is_ok()
+        });
+    }
+
+    fn key(&mut self, name: &str) {
+        self.buf.push(',');
+        push_string(&mut self.buf, name);
+        self.buf.push(':');
+    }
+}
+
+/// Append a JSON string literal.
+fn push_string(buf: &mut String, value: &str) {
+    buf.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(buf, "\\u{:04x}", c as u32);
+            }
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+}
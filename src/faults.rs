@@ -0,0 +1,126 @@
+use std::{
+    fmt,
+    hash::Hasher,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+};
+
+use chrono::Duration;
+use pocketcron::{
+    duration::parse_duration,
+    hash::{self, Fnv1a},
+};
+
+use crate::log::info;
+
+/// Environment variable that enables fault injection, for testing alerting and retries.
+const FAULTS_ENV: &str = "POCKETCRON_FAULTS";
+
+/// Faults to inject, if enabled.
+static FAULTS: OnceLock<Faults> = OnceLock::new();
+
+/// Number of runs so far, which varies the faults picked for the same job.
+static RUNS: AtomicU64 = AtomicU64::new(0);
+
+/// Percentages of runs to inject each fault into.
+#[derive(Default)]
+struct Faults {
+    seed: u64,
+    fail: u64,
+    hang: u64,
+    /// Percentage, and the delay.
+    delay: Option<(u64, Duration)>,
+}
+
+/// A fault injected into a run.
+pub enum Fault {
+    /// Exit with an error, without running the command.
+    Fail,
+    /// Never exit, without running the command.
+    Hang,
+    /// Run the command after a delay.
+    Delay(Duration),
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::Fail => write!(f, "failure"),
+            Fault::Hang => write!(f, "hang"),
+            Fault::Delay(delay) => write!(f, "delay of {}s", delay.num_seconds()),
+        }
+    }
+}
+
+impl Fault {
+    /// Apply the fault to a shell command.
+    pub fn apply(&self, command: &str) -> String {
+        match self {
+            Fault::Fail => "echo 'pocketcron: injected failure' >&2; exit 1".to_owned(),
+            Fault::Hang => "exec sleep 2147483647".to_owned(),
+            Fault::Delay(delay) => format!("sleep {}; {}", delay.num_seconds(), command),
+        }
+    }
+}
+
+/// Enable fault injection, if configured in the environment.
+///
+/// The format is a comma separated list, like `fail:5%,hang:1%,delay:10%:30s,seed:42`.
+pub fn init() -> Result<(), String> {
+    let Some(value) = std::env::var_os(FAULTS_ENV) else {
+        return Ok(());
+    };
+    let value = value.to_string_lossy();
+    let invalid = |part: &str| format!("{}: invalid fault: {}", FAULTS_ENV, part);
+    let percentage = |part: &str, value: &str| {
+        value
+            .strip_suffix('%')
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value <= 100)
+            .ok_or_else(|| invalid(part))
+    };
+
+    let mut faults = Faults::default();
+    for part in value.split(',') {
+        let mut fields = part.split(':');
+        match (fields.next(), fields.next(), fields.next(), fields.next()) {
+            (Some("seed"), Some(seed), None, None) => {
+                faults.seed = seed.parse().map_err(|_| invalid(part))?;
+            }
+            (Some("fail"), Some(value), None, None) => faults.fail = percentage(part, value)?,
+            (Some("hang"), Some(value), None, None) => faults.hang = percentage(part, value)?,
+            (Some("delay"), Some(value), Some(delay), None) => {
+                let delay = parse_duration(delay).map_err(|_| invalid(part))?;
+                faults.delay = Some((percentage(part, value)?, delay));
+            }
+            _ => return Err(invalid(part)),
+        }
+    }
+    let delay = faults.delay.map_or(0, |(percentage, _)| percentage);
+    if faults.fail + faults.hang + delay > 100 {
+        return Err(format!("{}: percentages add up to over 100%", FAULTS_ENV));
+    }
+    info!("fault injection enabled: {}", value);
+    let _ = FAULTS.set(faults);
+    Ok(())
+}
+
+/// Pick the fault to inject into a run of the job on a crontab line, if any.
+pub fn pick(source: &str) -> Option<Fault> {
+    let faults = FAULTS.get()?;
+    let mut hasher = Fnv1a::default();
+    hasher.write_u64(faults.seed);
+    hasher.write(source.as_bytes());
+    hasher.write_u64(RUNS.fetch_add(1, Ordering::Relaxed));
+    let roll = hash::mix(hasher.finish()) % 100;
+    if roll < faults.fail {
+        return Some(Fault::Fail);
+    }
+    if roll < faults.fail + faults.hang {
+        return Some(Fault::Hang);
+    }
+    let (percentage, delay) = faults.delay?;
+    (roll < faults.fail + faults.hang + percentage).then_some(Fault::Delay(delay))
+}
@@ -0,0 +1,238 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Condvar, Mutex, OnceLock},
+    thread,
+    time::Duration,
+};
+
+use crate::log::{error, info, warning};
+
+/// Maximum number of records per request.
+const BATCH_SIZE: usize = 100;
+
+/// How long to wait for more records before sending a batch.
+const BATCH_DELAY: Duration = Duration::from_secs(1);
+
+/// Maximum number of records kept in memory, if there is no backlog file.
+const MAX_PENDING: usize = 10000;
+
+/// Retry delays start at the minimum, and double up to the maximum.
+const MIN_RETRY: Duration = Duration::from_secs(1);
+const MAX_RETRY: Duration = Duration::from_secs(60);
+
+/// Timeout of connections and requests.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Records waiting to be sent, if forwarding is enabled.
+static PENDING: OnceLock<Pending> = OnceLock::new();
+
+struct Pending {
+    records: Mutex<VecDeque<String>>,
+    added: Condvar,
+}
+
+/// Where to send records.
+#[derive(Clone, Debug)]
+pub enum Target {
+    /// Lines of JSON over a TCP connection, to `host:port`.
+    Tcp(String),
+    /// Requests with lines of JSON, to an HTTP or HTTPS URL, with an optional bearer token.
+    #[cfg(feature = "http")]
+    Http { url: String, token: Option<String> },
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid forward target: {}", s);
+        if let Some(addr) = s.strip_prefix("tcp://") {
+            let addr = addr.strip_suffix('/').unwrap_or(addr);
+            if addr.contains(':') && !addr.contains('/') {
+                return Ok(Target::Tcp(addr.to_owned()));
+            }
+            return Err(invalid());
+        }
+        let host = s
+            .strip_prefix("https://")
+            .or_else(|| s.strip_prefix("http://"));
+        if host.is_some_and(|host| !host.is_empty() && !host.starts_with('/')) {
+            #[cfg(feature = "http")]
+            return Ok(Target::Http {
+                url: s.to_owned(),
+                token: None,
+            });
+            #[cfg(not(feature = "http"))]
+            return Err(format!("{}: requires a build with the `http` feature", s));
+        }
+        Err(invalid())
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Tcp(addr) => write!(f, "tcp://{}", addr),
+            #[cfg(feature = "http")]
+            Target::Http { url, .. } => write!(f, "{}", url),
+        }
+    }
+}
+
+impl Target {
+    /// Send a batch of records, each a line of JSON.
+    fn send(&self, records: &[String]) -> io::Result<()> {
+        let body = records.concat();
+        match self {
+            Target::Tcp(addr) => {
+                let mut stream = TcpStream::connect(addr)?;
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                stream.write_all(body.as_bytes())
+            }
+            #[cfg(feature = "http")]
+            Target::Http { url, token } => {
+                let mut request = ureq::post(url)
+                    .timeout(TIMEOUT)
+                    .set("Content-Type", "application/x-ndjson");
+                if let Some(token) = token {
+                    request = request.set("Authorization", &format!("Bearer {}", token));
+                }
+                request
+                    .send_string(&body)
+                    .map(drop)
+                    .map_err(|err| io::Error::other(err.to_string()))
+            }
+        }
+    }
+}
+
+/// Whether records are forwarded.
+pub fn is_enabled() -> bool {
+    PENDING.get().is_some()
+}
+
+/// Queue a record, which is a line of JSON.
+pub fn push(record: String) {
+    let Some(pending) = PENDING.get() else {
+        return;
+    };
+    let mut records = pending.records.lock().unwrap();
+    records.push_back(record);
+    pending.added.notify_one();
+}
+
+/// Send queued records to the target on a separate thread.
+///
+/// Records that can't be sent are kept in the backlog file, if any, and sent before new records
+/// once the target is reachable again. Without a backlog file, a limited number is kept in memory.
+pub fn start(target: Target, backlog: Option<PathBuf>) {
+    let pending = PENDING.get_or_init(|| Pending {
+        records: Mutex::new(VecDeque::new()),
+        added: Condvar::new(),
+    });
+    thread::spawn(move || {
+        let mut retry = MIN_RETRY;
+        let mut failing = false;
+        loop {
+            // Send the backlog first, to keep records in order.
+            let result = send_backlog(&target, backlog.as_deref()).and_then(|()| {
+                let batch = pending.wait_batch();
+                target.send(&batch).inspect_err(|_| pending.unshift(batch))
+            });
+
+            match result {
+                Ok(()) => {
+                    if failing {
+                        info!("forward to {}: recovered", target);
+                    }
+                    failing = false;
+                    retry = MIN_RETRY;
+                }
+                Err(err) => {
+                    error!("forward to {}: {}", target, err);
+                    failing = true;
+                    if let Some(path) = &backlog {
+                        if let Err(err) = pending.spill(path) {
+                            error!("{}: backlog write failed: {}", path.display(), err);
+                        }
+                    }
+                    thread::sleep(retry);
+                    retry = (retry * 2).min(MAX_RETRY);
+                }
+            }
+        }
+    });
+}
+
+impl Pending {
+    /// Wait for records, and take a batch.
+    fn wait_batch(&self) -> Vec<String> {
+        let mut records = self
+            .added
+            .wait_while(self.records.lock().unwrap(), |records| records.is_empty())
+            .unwrap();
+        // Give records that are emitted together a chance to go in the same batch.
+        drop(records);
+        thread::sleep(BATCH_DELAY);
+        records = self.records.lock().unwrap();
+        let len = records.len().min(BATCH_SIZE);
+        records.drain(..len).collect()
+    }
+
+    /// Put back records that couldn't be sent, dropping the oldest if there are too many.
+    fn unshift(&self, batch: Vec<String>) {
+        let mut records = self.records.lock().unwrap();
+        for record in batch.into_iter().rev() {
+            records.push_front(record);
+        }
+        let excess = records.len().saturating_sub(MAX_PENDING);
+        if excess > 0 {
+            records.drain(..excess);
+            warning!("forward: dropped {} record(s), too many pending", excess);
+        }
+    }
+
+    /// Move all records to the backlog file.
+    fn spill(&self, path: &Path) -> io::Result<()> {
+        let mut records = self.records.lock().unwrap();
+        if records.is_empty() {
+            return Ok(());
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        for record in records.iter() {
+            file.write_all(record.as_bytes())?;
+        }
+        file.sync_data()?;
+        records.clear();
+        Ok(())
+    }
+}
+
+/// Send the records in the backlog file, if any, and remove those that were sent.
+fn send_backlog(target: &Target, path: Option<&Path>) -> io::Result<()> {
+    let Some(path) = path else {
+        return Ok(());
+    };
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    let records: Vec<String> = contents.lines().map(|line| format!("{}\n", line)).collect();
+    for (index, batch) in records.chunks(BATCH_SIZE).enumerate() {
+        if let Err(err) = target.send(batch) {
+            let mut temp = path.as_os_str().to_owned();
+            temp.push(".tmp");
+            fs::write(&temp, records[index * BATCH_SIZE..].concat())?;
+            fs::rename(&temp, path)?;
+            return Err(err);
+        }
+    }
+    fs::remove_file(path)
+}
@@ -0,0 +1,50 @@
+/// Match a file name against a shell-style pattern.
+///
+/// Supports `*`, `?` and bracket expressions like `[a-z]` and `[!0-9]`.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    match_from(&pattern, &name)
+}
+
+fn match_from(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| match_from(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && match_from(&pattern[1..], &name[1..]),
+        Some('[') => {
+            let Some((matched, rest)) = name.first().and_then(|c| match_class(&pattern[1..], *c))
+            else {
+                return false;
+            };
+            matched && match_from(rest, &name[1..])
+        }
+        Some(c) => name.first() == Some(c) && match_from(&pattern[1..], &name[1..]),
+    }
+}
+
+/// Match a character against a bracket expression, without the opening bracket. Returns whether
+/// it matches, and the pattern after the closing bracket, or `None` if there is no closing bracket.
+fn match_class(pattern: &[char], c: char) -> Option<(bool, &[char])> {
+    let (negate, mut rest) = match pattern.first() {
+        Some('!' | '^') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match rest {
+            [']', tail @ ..] if !first => return Some((matched != negate, tail)),
+            [start, '-', end, tail @ ..] if *end != ']' => {
+                matched |= (*start..=*end).contains(&c);
+                rest = tail;
+            }
+            [item, tail @ ..] => {
+                matched |= *item == c;
+                rest = tail;
+            }
+            [] => return None,
+        }
+        first = false;
+    }
+}
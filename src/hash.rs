@@ -0,0 +1,37 @@
+use std::hash::Hasher;
+
+/// 64-bit FNV-1a hasher.
+///
+/// Used where hashes must be stable across builds and hosts, unlike the std `DefaultHasher`.
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Mix the bits of a hash, so that every bit depends on every input bit.
+///
+/// FNV-1a barely mixes the last bytes of its input, which matters when a hash is reduced to a
+/// small range.
+pub fn mix(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
@@ -0,0 +1,206 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use chrono::{offset::Local, DateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    log::{error, warning},
+    state, status, Outcome,
+};
+
+/// Number of runs kept per job by default.
+pub const DEFAULT_LIMIT: usize = 100;
+
+/// A finished run, as one line of the history file.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    /// Name of the job, like in log file paths.
+    job: String,
+    started: DateTime<Local>,
+    duration_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signal: Option<i32>,
+    /// Why the run failed to start or finish, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Record {
+    fn succeeded(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+struct History {
+    path: PathBuf,
+    /// Number of runs kept per job.
+    limit: usize,
+    /// The file, opened for appending, the runs kept of every job, and the number of lines in the
+    /// file.
+    inner: Mutex<(File, BTreeMap<String, VecDeque<Record>>, usize)>,
+}
+
+/// The history file, if enabled.
+static HISTORY: OnceLock<History> = OnceLock::new();
+
+/// Load the history file, and append runs to it from now on. If it doesn't exist yet, starts with
+/// an empty history.
+///
+/// Only the last `limit` runs of every job are kept. The file is compacted to those once it holds
+/// twice as many lines.
+pub fn open(path: &Path, limit: usize) -> io::Result<()> {
+    let mut runs: BTreeMap<String, VecDeque<Record>> = BTreeMap::new();
+    let mut lines = 0;
+    match File::open(path) {
+        Ok(file) => {
+            for (index, line) in BufReader::new(file).split(b'\n').enumerate() {
+                let line = line?;
+                lines += 1;
+                // A line may be cut short by a crash while it was written.
+                let record: Record = match serde_json::from_slice(&line) {
+                    Ok(record) => record,
+                    Err(err) => {
+                        warning!(
+                            "{}:{}: skipped history line: {}",
+                            path.display(),
+                            index + 1,
+                            err
+                        );
+                        continue;
+                    }
+                };
+                let runs = runs.entry(record.job.clone()).or_default();
+                runs.push_back(record);
+                if runs.len() > limit {
+                    runs.pop_front();
+                }
+            }
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    let file = append(path)?;
+    let _ = HISTORY.set(History {
+        path: path.to_owned(),
+        limit,
+        inner: Mutex::new((file, runs, lines)),
+    });
+    Ok(())
+}
+
+/// Add a finished run to the history, if enabled.
+pub fn record(job: &str, started: DateTime<Local>, duration_ms: u64, outcome: Outcome) {
+    let Some(history) = HISTORY.get() else {
+        return;
+    };
+    let (code, signal, error) = match outcome {
+        Outcome::Code(code) => (Some(code), None, None),
+        Outcome::Signal(signal) => (None, Some(signal), None),
+        Outcome::Error(error) => (None, None, Some(error)),
+    };
+    let record = Record {
+        job: job.to_owned(),
+        started,
+        duration_ms,
+        code,
+        signal,
+        error,
+    };
+    let mut inner = history.inner.lock().unwrap();
+    let (file, runs, lines) = &mut *inner;
+    let mut line = serde_json::to_string(&record).unwrap();
+    line.push('\n');
+    if let Err(err) = file.write_all(line.as_bytes()) {
+        error!("{}: history write failed: {}", history.path.display(), err);
+    }
+    *lines += 1;
+    let job_runs = runs.entry(record.job.clone()).or_default();
+    job_runs.push_back(record);
+    if job_runs.len() > history.limit {
+        job_runs.pop_front();
+    }
+
+    let kept: usize = runs.values().map(VecDeque::len).sum();
+    if *lines >= 2 * kept.max(history.limit) {
+        match compact(&history.path, runs) {
+            Ok(compacted) => {
+                *file = compacted;
+                *lines = kept;
+            }
+            Err(err) => error!(
+                "{}: history compaction failed: {}",
+                history.path.display(),
+                err
+            ),
+        }
+    }
+}
+
+/// Describe the last runs of a job, oldest first, for the `history` request. Returns `None` if
+/// there is no history file.
+pub fn render(job: &str) -> Option<String> {
+    let history = HISTORY.get()?;
+    let inner = history.inner.lock().unwrap();
+    let runs: Vec<&Record> = inner.1.get(job).into_iter().flatten().collect();
+
+    let mut output = format!("{} run(s) of {}", runs.len(), job);
+    match runs.iter().rev().find(|record| record.succeeded()) {
+        Some(record) => output.push_str(&format!(
+            ", last succeeded at {}",
+            record.started.format("%Y-%m-%d %H:%M:%S")
+        )),
+        None if !runs.is_empty() => output.push_str(", none succeeded"),
+        None => {}
+    }
+    let rows: Vec<[String; 3]> = runs
+        .iter()
+        .map(|record| {
+            [
+                record.started.format("%Y-%m-%d %H:%M:%S").to_string(),
+                format!("{:.1}s", record.duration_ms as f64 / 1000.0),
+                match (record.code, record.signal, &record.error) {
+                    (Some(code), _, _) => format!("exit code {}", code),
+                    (_, Some(signal), _) => format!("killed by signal {}", signal),
+                    (_, _, Some(error)) => format!("failed: {}", error),
+                    _ => "unknown".to_owned(),
+                },
+            ]
+        })
+        .collect();
+    if !rows.is_empty() {
+        status::write_table(&mut output, ["STARTED", "DURATION", "RESULT"], &rows);
+    }
+    Some(output)
+}
+
+/// Whether there are runs of a job with a name in the history.
+pub fn contains(job: &str) -> bool {
+    HISTORY
+        .get()
+        .is_some_and(|history| history.inner.lock().unwrap().1.contains_key(job))
+}
+
+/// Rewrite the history file with only the runs that are kept, and return it opened for appending.
+fn compact(path: &Path, runs: &BTreeMap<String, VecDeque<Record>>) -> io::Result<File> {
+    let mut data = Vec::new();
+    let mut records: Vec<&Record> = runs.values().flatten().collect();
+    records.sort_by_key(|record| record.started);
+    for record in records {
+        serde_json::to_writer(&mut data, record)?;
+        data.push(b'\n');
+    }
+    state::write_atomic(path, &data)?;
+    append(path)
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
@@ -0,0 +1,150 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+/// A crontab, with the crontabs it includes in place of their `@include` and `@include_dir`
+/// lines, as if they had been concatenated.
+pub struct Expanded {
+    pub input: String,
+    /// File and line number of every line of the input.
+    origins: Vec<(Rc<Path>, usize)>,
+    /// Whether columns of the input are those in the files, unless it was translated.
+    columns: bool,
+}
+
+impl Expanded {
+    /// An empty crontab, translated from another format if not `columns`.
+    pub fn new(columns: bool) -> Self {
+        Expanded {
+            input: String::new(),
+            origins: Vec::new(),
+            columns,
+        }
+    }
+
+    /// A crontab as it is, with `@include` lines left in place.
+    pub fn plain(path: &Path, input: &str) -> Self {
+        let mut expanded = Expanded::new(true);
+        let origin: Rc<Path> = path.into();
+        for (index, line) in input.lines().enumerate() {
+            expanded.push(line, &origin, index + 1);
+        }
+        expanded
+    }
+
+    /// Add a line, which came from a line of a file, counting from 1.
+    pub fn push(&mut self, line: &str, path: &Rc<Path>, line_no: usize) {
+        self.input.push_str(line);
+        self.input.push('\n');
+        self.origins.push((path.clone(), line_no));
+    }
+
+    /// Where a position in the input came from, like `common.cron:3:10`, counting from 1. Columns
+    /// are left out when they don't match the file.
+    pub fn location(&self, line: usize, column: usize) -> String {
+        let (path, line) = &self.origins[line - 1];
+        match self.columns {
+            true => format!("{}:{}:{}", path.display(), line, column),
+            false => format!("{}:{}", path.display(), line),
+        }
+    }
+}
+
+/// Replace `@include <path>` and `@include_dir <path>` lines in a crontab with the contents of the
+/// crontab or directory of crontabs they name, recursively. Relative paths are relative to the
+/// directory of the crontab that includes them.
+///
+/// Also returns all errors, like `common.cron:3:10: error: include failed: ...`. Lines with
+/// errors are left out.
+pub fn expand(path: &Path, input: &str) -> (Expanded, Vec<String>) {
+    let mut expanded = Expanded::new(true);
+    let mut errors = Vec::new();
+    let mut stack = Vec::new();
+    if let Ok(canonical) = fs::canonicalize(path) {
+        stack.push(canonical);
+    }
+    add(&mut expanded, &mut errors, &mut stack, path, input);
+    (expanded, errors)
+}
+
+fn add(
+    expanded: &mut Expanded,
+    errors: &mut Vec<String>,
+    stack: &mut Vec<PathBuf>,
+    path: &Path,
+    input: &str,
+) {
+    let origin: Rc<Path> = path.into();
+    for (index, raw) in input.lines().enumerate() {
+        let line_no = index + 1;
+        let mut words = raw.trim_start().splitn(2, char::is_whitespace);
+        let keyword = words.next().unwrap_or_default();
+        if keyword != "@include" && keyword != "@include_dir" {
+            expanded.push(raw, &origin, line_no);
+            continue;
+        }
+        let target = words.next().unwrap_or_default().trim();
+        let column = match target.is_empty() {
+            true => raw.len() + 1,
+            false => target.as_ptr() as usize - raw.as_ptr() as usize + 1,
+        };
+        let error = |message: String| {
+            format!(
+                "{}:{}:{}: error: {}",
+                path.display(),
+                line_no,
+                column,
+                message
+            )
+        };
+        if target.is_empty() {
+            errors.push(error(format!("missing path after {}", keyword)));
+            continue;
+        }
+        let target = match path.parent() {
+            Some(dir) => dir.join(target),
+            None => PathBuf::from(target),
+        };
+        let files = match keyword {
+            "@include" => Ok(vec![target.clone()]),
+            _ => crate::dir_crontabs(&target),
+        };
+        let files = match files {
+            Ok(files) => files,
+            Err(err) => {
+                errors.push(error(format!(
+                    "include failed: {}: {}",
+                    target.display(),
+                    err
+                )));
+                continue;
+            }
+        };
+        for file in files {
+            let result = fs::canonicalize(&file).and_then(|canonical| {
+                let input = fs::read_to_string(&file)?;
+                Ok((canonical, input))
+            });
+            let (canonical, input) = match result {
+                Ok(result) => result,
+                Err(err) => {
+                    errors.push(error(format!(
+                        "include failed: {}: {}",
+                        file.display(),
+                        err
+                    )));
+                    continue;
+                }
+            };
+            if stack.contains(&canonical) {
+                errors.push(error(format!("include cycle back to {}", file.display())));
+                continue;
+            }
+            stack.push(canonical);
+            add(expanded, errors, stack, &file, &input);
+            stack.pop();
+        }
+    }
+}
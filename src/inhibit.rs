@@ -0,0 +1,101 @@
+use std::{
+    io,
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use crate::log::error;
+
+/// Set with `--inhibit-sleep`.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Runs in progress, and the helper process that holds the inhibitor while there are any.
+static HELD: Mutex<(usize, Option<Child>)> = Mutex::new((0, None));
+
+/// Whether sleep can be inhibited on this platform.
+pub const SUPPORTED: bool = cfg!(any(target_os = "linux", target_os = "macos"));
+
+/// Keep the system from sleeping while runs are in progress.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Keeps the system awake until dropped, with other runs in progress.
+pub struct Guard(());
+
+/// Keep the system awake during a run, if enabled.
+pub fn hold() -> Option<Guard> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let mut held = HELD.lock().unwrap();
+    held.0 += 1;
+    if held.0 == 1 {
+        match spawn() {
+            Ok(helper) => held.1 = Some(helper),
+            Err(err) => error!("sleep inhibitor failed: {}", err),
+        }
+    }
+    Some(Guard(()))
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let mut held = HELD.lock().unwrap();
+        held.0 -= 1;
+        if held.0 == 0 {
+            if let Some(helper) = held.1.take() {
+                release(helper);
+            }
+        }
+    }
+}
+
+/// Start a process that holds a systemd-logind inhibitor lock until its stdin is closed, which
+/// also happens if pocketcron dies.
+#[cfg(target_os = "linux")]
+fn spawn() -> io::Result<Child> {
+    let mut command = Command::new("systemd-inhibit");
+    command
+        .args(["--what=sleep", "--who=pocketcron", "--why=Running jobs"])
+        .args(["--mode=block", "cat"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null());
+    command.spawn()
+}
+
+#[cfg(target_os = "linux")]
+fn release(mut helper: Child) {
+    drop(helper.stdin.take());
+    let _ = helper.wait();
+}
+
+/// Start a process that holds an IOKit power assertion against idle sleep, until it is killed or
+/// pocketcron exits.
+#[cfg(target_os = "macos")]
+fn spawn() -> io::Result<Child> {
+    let mut command = Command::new("caffeinate");
+    command
+        .arg("-i")
+        .arg("-w")
+        .arg(std::process::id().to_string())
+        .stdin(Stdio::null());
+    command.spawn()
+}
+
+#[cfg(target_os = "macos")]
+fn release(mut helper: Child) {
+    let _ = helper.kill();
+    let _ = helper.wait();
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn spawn() -> io::Result<Child> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn release(_helper: Child) {}
@@ -0,0 +1,88 @@
+use std::{io, mem, process::exit, ptr};
+
+use crate::log::{error, info};
+
+/// Signals that the init process passes on to pocketcron.
+const FORWARDED: [i32; 8] = [
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTERM,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+    libc::SIGALRM,
+    libc::SIGWINCH,
+];
+
+/// Run as the init process of a container, with `--init`.
+///
+/// This forks, and only returns in the child, which goes on to be pocketcron. The parent stays
+/// behind to reap every process that ends up attached to it, like those left behind by jobs that
+/// double-fork, and passes signals on to pocketcron, which stops the process groups of jobs in
+/// turn. It exits with the status of pocketcron, once that has exited.
+///
+/// Must be called before any threads are started.
+pub fn start() {
+    // Safety: the set is initialized by `sigemptyset` before use.
+    let (set, old) = unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        let mut old: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for signal in FORWARDED.iter().chain(&[libc::SIGCHLD]) {
+            libc::sigaddset(&mut set, *signal);
+        }
+        // Blocked before forking, so that no signal is missed in between.
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, &mut old);
+        (set, old)
+    };
+    // Outside of a container, orphans must be adopted explicitly.
+    #[cfg(target_os = "linux")]
+    if std::process::id() != 1 {
+        // Safety: `prctl` with this option only sets a flag on the process.
+        unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1) };
+    }
+
+    // Safety: no other threads are running, see above.
+    let child = match unsafe { libc::fork() } {
+        -1 => {
+            error!("fork failed: {}", io::Error::last_os_error());
+            exit(1);
+        }
+        0 => {
+            // Safety: `old` was filled in by `pthread_sigmask` above.
+            unsafe { libc::pthread_sigmask(libc::SIG_SETMASK, &old, ptr::null_mut()) };
+            return;
+        }
+        child => child,
+    };
+
+    info!("running as init for pocketcron, pid {}", child);
+    loop {
+        let mut signal = 0;
+        // Safety: both pointers are valid for the duration of the call.
+        if unsafe { libc::sigwait(&set, &mut signal) } != 0 {
+            continue;
+        }
+        if signal != libc::SIGCHLD {
+            // Safety: `kill` has no preconditions.
+            unsafe { libc::kill(child, signal) };
+            continue;
+        }
+        // One SIGCHLD may stand for several processes that ended.
+        loop {
+            let mut status = 0;
+            // Safety: the pointer is valid for the duration of the call.
+            let pid = unsafe { libc::waitpid(-1, &mut status, libc::WNOHANG) };
+            if pid <= 0 {
+                break;
+            }
+            if pid == child {
+                exit(if libc::WIFSIGNALED(status) {
+                    128 + libc::WTERMSIG(status)
+                } else {
+                    libc::WEXITSTATUS(status)
+                });
+            }
+        }
+    }
+}
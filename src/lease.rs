@@ -0,0 +1,90 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+
+use chrono::{offset::Local, Duration};
+
+use crate::{
+    events::Event,
+    lock,
+    log::{error, info},
+};
+
+/// Whether jobs run here. Without a lease, every instance is the leader.
+static LEADER: AtomicBool = AtomicBool::new(true);
+
+/// Whether this instance runs jobs.
+pub fn is_leader() -> bool {
+    LEADER.load(Ordering::Relaxed)
+}
+
+/// Compete for a lease in a file on shared storage, on a separate thread. Only the instance that
+/// holds the lease is the leader.
+///
+/// The holder renews the lease three times per `ttl`. Others take over once it expires, and become
+/// leader when their own lease is still in place at the next renewal, so that only one of several
+/// instances taking over at the same time wins.
+pub fn start(path: PathBuf, ttl: Duration) {
+    LEADER.store(false, Ordering::Relaxed);
+    let interval = (ttl / 3).to_std().unwrap_or_default();
+    thread::spawn(move || {
+        let mut renewed = false;
+        loop {
+            let now = Local::now().timestamp();
+            let leader = match read(&path) {
+                Ok(Some((holder, expiry))) if holder != lock::holder() && expiry > now => {
+                    renewed = false;
+                    false
+                }
+                Ok(lease) => {
+                    let ours = lease.is_some_and(|(holder, _)| holder == lock::holder());
+                    let confirmed = ours && renewed;
+                    renewed = write(&path, now + ttl.num_seconds())
+                        .inspect_err(|err| {
+                            error!("{}: lease write failed: {}", path.display(), err)
+                        })
+                        .is_ok();
+                    confirmed && renewed
+                }
+                Err(err) => {
+                    error!("{}: lease read failed: {}", path.display(), err);
+                    renewed = false;
+                    false
+                }
+            };
+
+            if LEADER.swap(leader, Ordering::Relaxed) != leader {
+                let role = if leader { "leader" } else { "standby" };
+                info!("lease {}: now {}", path.display(), role);
+                Event::new("lease").str("role", role).emit();
+            }
+            thread::sleep(interval);
+        }
+    });
+}
+
+/// Read the holder and expiry time of a lease, if there is one.
+fn read(path: &Path) -> io::Result<Option<(String, i64)>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    // A lease that can't be parsed is treated like an expired one.
+    let lease = contents
+        .trim()
+        .rsplit_once(' ')
+        .and_then(|(holder, expiry)| Some((holder.to_owned(), expiry.parse().ok()?)));
+    Ok(Some(lease.unwrap_or_default()))
+}
+
+/// Replace the lease with one held by this instance.
+fn write(path: &Path, expiry: i64) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".{}.tmp", lock::holder().replace('/', "_")));
+    fs::write(&temp, format!("{} {}\n", lock::holder(), expiry))?;
+    fs::rename(&temp, path)
+}
@@ -0,0 +1,10 @@
+//! Crontab parsing and scheduling, as used by the pocketcron binary.
+
+pub mod cron;
+pub mod crontab;
+pub mod duration;
+mod glob;
+pub mod hash;
+pub mod schedule;
+pub mod scheduler;
+pub mod tz;
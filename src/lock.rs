@@ -0,0 +1,158 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    hash::Hasher,
+    io::{self, BufRead, BufReader, Write},
+    net::TcpStream,
+    os::fd::AsRawFd,
+    path::Path,
+    sync::OnceLock,
+    time::Duration,
+};
+
+use pocketcron::{crontab::LockBackend, hash::Fnv1a};
+
+/// How long a lock is kept, which only needs to outlive random delays and clock differences
+/// between hosts.
+const TTL_SECS: i64 = 86400;
+
+/// Timeout of Redis connections and requests.
+const REDIS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Identity of this process as a lock holder.
+pub fn holder() -> &'static str {
+    static HOLDER: OnceLock<String> = OnceLock::new();
+    HOLDER.get_or_init(|| format!("{}:{}", crate::hostname(), std::process::id()))
+}
+
+/// Per-occurrence lock of a job.
+#[derive(Clone)]
+pub struct Lock {
+    backend: LockBackend,
+    /// Identifies the job, independent of its position in the crontab.
+    key: String,
+}
+
+impl Lock {
+    /// Create a lock for the job on a crontab line.
+    pub fn new(backend: LockBackend, source: &str) -> Self {
+        let mut hasher = Fnv1a::default();
+        hasher.write(source.as_bytes());
+        Lock {
+            backend,
+            key: format!("{:016x}", hasher.finish()),
+        }
+    }
+
+    /// Try to take the lock on an occurrence, identified by its scheduled Unix time.
+    ///
+    /// Returns the holder if another process got there first.
+    pub fn acquire(&self, occurrence: i64) -> io::Result<Option<String>> {
+        match &self.backend {
+            LockBackend::File(dir) => self.acquire_file(dir, occurrence),
+            LockBackend::Redis(addr) => self.acquire_redis(addr, occurrence),
+        }
+    }
+
+    fn acquire_file(&self, dir: &Path, occurrence: i64) -> io::Result<Option<String>> {
+        // Write the holder to a private file, then link it into place. Unlike `O_EXCL`, this is
+        // atomic on network filesystems, and the lock file is never seen without its contents.
+        let name = format!("{}-{}.lock", self.key, occurrence);
+        let path = dir.join(&name);
+        let temp = dir.join(format!("{}.{}.tmp", name, holder().replace('/', "_")));
+        fs::write(&temp, format!("{}\n", holder()))?;
+        let result = fs::hard_link(&temp, &path);
+        let _ = fs::remove_file(&temp);
+        match result {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                return Ok(Some(fs::read_to_string(&path)?.trim().to_owned()));
+            }
+            Err(err) => return Err(err),
+        }
+
+        // Clean up locks on occurrences of the job that are long gone.
+        let prefix = format!("{}-", self.key);
+        for entry in fs::read_dir(dir)?.flatten() {
+            let entry_name = entry.file_name();
+            let expired = entry_name
+                .to_str()
+                .and_then(|entry_name| entry_name.strip_prefix(&prefix))
+                .and_then(|rest| rest.strip_suffix(".lock"))
+                .and_then(|time| time.parse::<i64>().ok())
+                .is_some_and(|time| time < occurrence - TTL_SECS);
+            if expired {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+        Ok(None)
+    }
+
+    fn acquire_redis(&self, addr: &str, occurrence: i64) -> io::Result<Option<String>> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(REDIS_TIMEOUT))?;
+        stream.set_write_timeout(Some(REDIS_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        let key = format!("pocketcron:{}:{}", self.key, occurrence);
+        let ttl = TTL_SECS.to_string();
+        writer.write_all(&redis_command(&["SET", &key, holder(), "NX", "EX", &ttl]))?;
+        if redis_reply(&mut reader)?.is_some() {
+            return Ok(None);
+        }
+        writer.write_all(&redis_command(&["GET", &key]))?;
+        Ok(Some(redis_reply(&mut reader)?.unwrap_or_default()))
+    }
+}
+
+/// Take an exclusive `flock` on a file, creating it if needed, without waiting. The lock is held
+/// until the file is closed.
+///
+/// Returns `None` if another process holds the lock.
+pub fn flock(path: &Path) -> io::Result<Option<File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    // Safety: the descriptor is open for as long as the file.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(Some(file));
+    }
+    match io::Error::last_os_error() {
+        err if err.kind() == io::ErrorKind::WouldBlock => Ok(None),
+        err => Err(err),
+    }
+}
+
+/// Encode a Redis command.
+fn redis_command(args: &[&str]) -> Vec<u8> {
+    let mut buf = format!("*{}\r\n", args.len());
+    for arg in args {
+        buf.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+    }
+    buf.into_bytes()
+}
+
+/// Read a simple or bulk string reply from Redis, which is `None` for a nil reply.
+fn redis_reply(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let line = line.trim_end();
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    match line.split_at_checked(1) {
+        Some(("+", value)) => Ok(Some(value.to_owned())),
+        Some(("-", message)) => Err(invalid(format!("redis: {}", message))),
+        Some(("$", "-1")) => Ok(None),
+        Some(("$", len)) => {
+            let len: usize = len
+                .parse()
+                .map_err(|_| invalid(format!("redis: invalid reply: {}", line)))?;
+            let mut buf = vec![0; len + 2];
+            reader.read_exact(&mut buf)?;
+            buf.truncate(len);
+            Ok(Some(String::from_utf8_lossy(&buf).into_owned()))
+        }
+        _ => Err(invalid(format!("redis: unexpected reply: {}", line))),
+    }
+}
@@ -0,0 +1,397 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, Write},
+    os::unix::net::UnixDatagram,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Mutex, OnceLock,
+    },
+};
+
+use chrono::{offset::Local, SecondsFormat};
+use serde_json::{Map, Value};
+
+use crate::run_id::RunId;
+
+/// Set with `--log-format json`.
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Set with `--prefix-output`.
+static PREFIX: AtomicBool = AtomicBool::new(false);
+
+/// Least severe level that is logged, set with `-q`, `-v` and `-vv`.
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// Socket of the system logger, set with `--log-target`.
+static SINK: OnceLock<(Target, UnixDatagram)> = OnceLock::new();
+
+/// Names of jobs from `# name:` comments, by job number.
+static NAMES: Mutex<BTreeMap<usize, Box<str>>> = Mutex::new(BTreeMap::new());
+
+/// Path of the syslog socket.
+#[cfg(target_os = "macos")]
+const SYSLOG_SOCKET: &str = "/var/run/syslog";
+#[cfg(not(target_os = "macos"))]
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Path of the socket for the native protocol of the systemd journal.
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Syslog facility of messages, `daemon`.
+const FACILITY: u8 = 3;
+
+/// Format of log messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Lines of text, with the job number in brackets.
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("invalid log format: {}", s)),
+        }
+    }
+}
+
+/// Where log messages go.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    Stderr,
+    /// The local syslog socket.
+    Syslog,
+    /// The systemd journal, with the job number in `POCKETCRON_JOB`.
+    Journald,
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stderr" => Ok(Target::Stderr),
+            "syslog" => Ok(Target::Syslog),
+            "journald" => Ok(Target::Journald),
+            _ => Err(format!("invalid log target: {}", s)),
+        }
+    }
+}
+
+/// The job a log message is about, the run of the job, if its runs may overlap, and the unique
+/// id of the run, if it's about one.
+#[derive(Clone, Copy, Debug)]
+pub struct Label {
+    job: usize,
+    run: Option<u64>,
+    run_id: Option<RunId>,
+}
+
+impl Label {
+    pub fn run(job: usize, run: u64) -> Self {
+        Label {
+            job,
+            run: Some(run),
+            run_id: None,
+        }
+    }
+
+    /// The same label, about the run with an id.
+    pub fn with_run_id(self, run_id: RunId) -> Self {
+        Label {
+            run_id: Some(run_id),
+            ..self
+        }
+    }
+
+    pub fn job(&self) -> usize {
+        self.job
+    }
+}
+
+/// Set the name a job is logged with instead of its number, or go back to the number.
+pub fn set_name(job: usize, name: Option<&str>) {
+    let mut names = NAMES.lock().unwrap();
+    match name {
+        Some(name) => names.insert(job, name.into()),
+        None => names.remove(&job),
+    };
+}
+
+/// Name a job is logged with, if any.
+fn name(job: usize) -> Option<Box<str>> {
+    NAMES.lock().unwrap().get(&job).cloned()
+}
+
+impl From<usize> for Label {
+    fn from(job: usize) -> Self {
+        Label {
+            job,
+            run: None,
+            run_id: None,
+        }
+    }
+}
+
+/// Like `3`, or `3#12` for run 12 of job 3. Named jobs have their name instead of the number.
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match name(self.job) {
+            Some(name) => write!(f, "{}", name)?,
+            None => write!(f, "{}", self.job)?,
+        }
+        if let Some(run) = self.run {
+            write!(f, "#{}", run)?;
+        }
+        Ok(())
+    }
+}
+
+/// Severity of a log message, from most to least severe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warning,
+    Info,
+    /// Decisions of the scheduler, like how long it sleeps and when jobs run next, with `-v`.
+    Debug,
+    /// Every job the scheduler looks at, with `-vv`.
+    Trace,
+}
+
+impl Level {
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warning => "warning",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    /// Syslog severity.
+    fn priority(self) -> u8 {
+        match self {
+            Level::Error => 3,
+            Level::Warning => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        }
+    }
+}
+
+/// Log messages up to a level, and drop less severe ones, like `Level::Warning` with `-q`.
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Whether messages of a level are logged.
+pub fn is_enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn set_format(format: Format) {
+    JSON.store(format == Format::Json, Ordering::Relaxed);
+}
+
+/// Send log messages to the system logger, instead of stderr.
+pub fn set_target(target: Target) -> io::Result<()> {
+    let path = match target {
+        Target::Stderr => return Ok(()),
+        Target::Syslog => SYSLOG_SOCKET,
+        Target::Journald => JOURNALD_SOCKET,
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(path)?;
+    let _ = SINK.set((target, socket));
+    Ok(())
+}
+
+/// Whether log messages are JSON.
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Prefix every line of output of jobs with the time and the job, like `12:00:00 [3] line`.
+pub fn set_prefix_output() {
+    PREFIX.store(true, Ordering::Relaxed);
+}
+
+/// Whether output of jobs is prefixed. JSON messages already say which job they are about.
+pub fn is_prefixed() -> bool {
+    PREFIX.load(Ordering::Relaxed) && !is_json()
+}
+
+/// Write a log message to stderr, optionally about a job. Use the macros instead.
+///
+/// Messages that can't be sent to the system logger go to stderr instead.
+pub fn write(level: Level, job: Option<Label>, message: fmt::Arguments<'_>) {
+    if !is_enabled(level) {
+        return;
+    }
+    if let Some((target, socket)) = SINK.get() {
+        let text = match job {
+            Some(job) => format!("[{}] {}", job, message),
+            None => message.to_string(),
+        };
+        let datagram = match target {
+            Target::Syslog => syslog_datagram(level, &text),
+            _ => journald_datagram(level, job, &text),
+        };
+        if socket.send(&datagram).is_ok() {
+            return;
+        }
+    }
+    if is_json() {
+        let mut record = record(level, job);
+        record.insert("message".into(), message.to_string().into());
+        let _ = writeln!(io::stderr(), "{}", Value::Object(record));
+        return;
+    }
+    match job {
+        Some(job) => eprintln!("[{}] {}", job, message),
+        None => eprintln!("{}", message),
+    }
+}
+
+/// Write a line of output of a job as a JSON log message.
+pub fn write_output(mut writer: impl Write, job: Label, stream: &str, line: &[u8]) {
+    let line = String::from_utf8_lossy(line);
+    let mut record = record(Level::Info, Some(job));
+    record.insert("stream".into(), stream.into());
+    record.insert(
+        "message".into(),
+        line.strip_suffix('\n').unwrap_or(&line).into(),
+    );
+    let _ = writeln!(writer, "{}", Value::Object(record));
+}
+
+/// Write a line of output of a job with the time and the job in front of it. The line is written
+/// at once, so lines of jobs that run at the same time don't mix.
+pub fn write_prefixed(mut writer: impl Write, job: Label, line: &[u8]) {
+    let mut text = format!("{} [{}] ", Local::now().format("%H:%M:%S"), job).into_bytes();
+    text.extend_from_slice(line);
+    if !text.ends_with(b"\n") {
+        text.push(b'\n');
+    }
+    let _ = writer.write_all(&text);
+}
+
+/// A message in the format of BSD syslog, like `syslog(3)` sends it.
+fn syslog_datagram(level: Level, text: &str) -> Vec<u8> {
+    format!(
+        "<{}>{} pocketcron[{}]: {}",
+        FACILITY * 8 + level.priority(),
+        Local::now().format("%b %e %H:%M:%S"),
+        std::process::id(),
+        text
+    )
+    .into_bytes()
+}
+
+/// A message in the native protocol of the journal.
+fn journald_datagram(level: Level, job: Option<Label>, text: &str) -> Vec<u8> {
+    let mut datagram = format!(
+        "PRIORITY={}\nSYSLOG_FACILITY={}\nSYSLOG_IDENTIFIER=pocketcron\n",
+        level.priority(),
+        FACILITY
+    );
+    if let Some(label) = job {
+        datagram.push_str(&format!("POCKETCRON_JOB={}\n", label.job));
+        if let Some(name) = name(label.job) {
+            datagram.push_str(&format!("POCKETCRON_JOB_NAME={}\n", name));
+        }
+        if let Some(run) = label.run {
+            datagram.push_str(&format!("POCKETCRON_RUN={}\n", run));
+        }
+        if let Some(run_id) = label.run_id {
+            datagram.push_str(&format!("POCKETCRON_RUN_ID={}\n", run_id));
+        }
+    }
+    let mut datagram = datagram.into_bytes();
+    // Values with newlines are written with their length instead of `=`.
+    datagram.extend_from_slice(b"MESSAGE\n");
+    datagram.extend_from_slice(&(text.len() as u64).to_le_bytes());
+    datagram.extend_from_slice(text.as_bytes());
+    datagram.push(b'\n');
+    datagram
+}
+
+fn record(level: Level, job: Option<Label>) -> Map<String, Value> {
+    let mut record = Map::new();
+    let time = Local::now().to_rfc3339_opts(SecondsFormat::Millis, false);
+    record.insert("time".into(), time.into());
+    record.insert("level".into(), level.as_str().into());
+    if let Some(label) = job {
+        record.insert("job".into(), label.job.into());
+        if let Some(name) = name(label.job) {
+            record.insert("name".into(), name.to_string().into());
+        }
+        if let Some(run) = label.run {
+            record.insert("run".into(), run.into());
+        }
+        if let Some(run_id) = label.run_id {
+            record.insert("run_id".into(), run_id.to_string().into());
+        }
+    }
+    record
+}
+
+/// Log a message, optionally about a job, like `info!([id] "CMD {}", command)`.
+macro_rules! info {
+    ($($arg:tt)+) => {
+        $crate::log::log!(Info, $($arg)+)
+    };
+}
+
+/// Log a warning, like `info!`.
+macro_rules! warning {
+    ($($arg:tt)+) => {
+        $crate::log::log!(Warning, $($arg)+)
+    };
+}
+
+/// Log an error, like `info!`.
+macro_rules! error {
+    ($($arg:tt)+) => {
+        $crate::log::log!(Error, $($arg)+)
+    };
+}
+
+/// Log a message that helps to tell what the scheduler is doing, like `info!`, with `-v`.
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        $crate::log::log!(Debug, $($arg)+)
+    };
+}
+
+/// Log a message about every job the scheduler looks at, like `info!`, with `-vv`.
+macro_rules! trace {
+    ($($arg:tt)+) => {
+        $crate::log::log!(Trace, $($arg)+)
+    };
+}
+
+macro_rules! log {
+    ($level:ident, [$job:expr] $($arg:tt)+) => {
+        $crate::log::write(
+            $crate::log::Level::$level,
+            Some($crate::log::Label::from($job)),
+            format_args!($($arg)+),
+        )
+    };
+    ($level:ident, $($arg:tt)+) => {
+        $crate::log::write($crate::log::Level::$level, None, format_args!($($arg)+))
+    };
+}
+
+pub(crate) use {debug, error, info, log, trace, warning};
@@ -0,0 +1,49 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    thread,
+};
+
+use crate::log::error;
+
+/// Mail the output of a run with `sendmail -t`, like traditional cron, on a separate thread.
+///
+/// `note` describes how the run failed, or that it recovered from failures, and is added after
+/// the output.
+pub fn send(id: usize, to: &str, command: &str, output: Vec<u8>, note: Option<String>) {
+    let mut message = format!(
+        "To: {}\nSubject: pocketcron <{}> {}\nContent-Type: text/plain; charset=UTF-8\n\
+         X-Pocketcron-Job: {}\n\n",
+        to,
+        crate::hostname(),
+        command,
+        id
+    )
+    .into_bytes();
+    message.extend_from_slice(&output);
+    if let Some(note) = note {
+        if !output.is_empty() && !output.ends_with(b"\n") {
+            message.push(b'\n');
+        }
+        message.extend_from_slice(format!("\n[{}]\n", note).as_bytes());
+    }
+
+    let to = to.to_owned();
+    thread::spawn(move || {
+        let mut command = Command::new("sendmail");
+        command
+            .arg("-t")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null());
+        let result = command.spawn().and_then(|mut proc| {
+            let written = proc.stdin.take().unwrap().write_all(&message);
+            let status = proc.wait()?;
+            written.map(|()| status)
+        });
+        match result {
+            Ok(status) if status.success() => {}
+            Ok(status) => error!([id] "mail to {} failed: sendmail {}", to, status),
+            Err(err) => error!([id] "mail to {} failed: {}", to, err),
+        }
+    });
+}
@@ -1,190 +1,3268 @@
+#[cfg(not(unix))]
+compile_error!("the pocketcron binary only runs on Unix, but the library also builds elsewhere");
+
 use std::{
+    cmp::Reverse,
     ffi::OsString,
     fs,
-    io::{BufRead, BufReader},
-    process::{exit, Command, Stdio},
-    sync::{Arc, Mutex},
+    hash::Hasher,
+    io::{self, Write},
+    mem,
+    os::unix::ffi::OsStrExt,
+    os::unix::fs::MetadataExt,
+    os::unix::process::{CommandExt, ExitStatusExt},
+    os::unix::{io::FromRawFd, net::UnixListener},
+    path::{Path, PathBuf},
+    process::{exit, Child, Command, ExitStatus, Stdio},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
     thread,
+    time::Instant,
 };
 
+#[cfg(any(feature = "api", feature = "metrics"))]
+use std::net::{SocketAddr, TcpListener};
+
 use chrono::{offset::Local, DateTime, Duration};
-use cron::OwnedScheduleIterator;
+
+use pocketcron::{
+    crontab::{
+        expand, expand_shell, match_jobs, parse_crontab_lenient, parse_size, parse_variable,
+        split_assignment, split_input, split_words, JobKey, JobSpec, Limits, LogRotation, Match,
+        Overlap, QueuePolicy, Sandbox, Signal,
+    },
+    duration::parse_duration,
+    hash::{self, Fnv1a},
+    schedule::{parse_time, Compat, Trigger, Upcoming, Watch},
+};
+
+#[cfg(feature = "agent")]
+use crate::agent::Agent;
+#[cfg(feature = "api")]
+use crate::api::LastRun;
+use crate::{
+    activation::ListenFds,
+    clock::Clock,
+    dispatch::Dispatcher,
+    events::Event,
+    include::Expanded,
+    lock::Lock,
+    log::{debug, error, info, trace, warning, Label},
+    output::{Capture, LogFile},
+    ping::Ping,
+    run_id::RunId,
+    user::Account,
+    zone::Zone,
+};
+
+mod activation;
+#[cfg(feature = "agent")]
+mod agent;
+#[cfg(feature = "api")]
+mod api;
+mod bench;
+mod cgroup;
+mod clock;
+mod config;
+mod control;
+mod dispatch;
+mod dotenv;
+mod events;
+mod faults;
+mod forward;
+#[cfg(feature = "history")]
+mod history;
+mod include;
+mod inhibit;
+mod init;
+mod lease;
+mod lock;
+mod log;
+mod mail;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "notify")]
+mod notify;
+#[cfg(feature = "otel")]
+mod otel;
+mod output;
+mod ping;
+mod preview;
+#[cfg(feature = "publish")]
+mod publish;
+mod run_id;
+mod sandbox;
+mod signals;
+mod state;
+mod status;
+mod stop;
+mod tick;
+mod user;
+mod watch;
+mod zone;
 
 /// Description and state of a job.
 struct Job {
     /// Job index, used as identifier for logging.
     id: usize,
+    /// Name from a `# name:` comment, used instead of the index for logging.
+    given_name: Option<Box<str>>,
     /// Schedule iterator.
-    upcoming: OwnedScheduleIterator<Local>,
+    upcoming: Upcoming,
     /// Next time this job will run.
     next: Option<DateTime<Local>>,
-    /// Shell command.
-    command: String,
-    /// Whether the process is running.
-    is_running: bool,
+    /// Start of the shell command in `source`.
+    command_start: usize,
+    /// Runs in progress, at most one unless overlap is allowed.
+    runs: Vec<Run>,
+    /// Number of runs started so far, which numbers them.
+    run_count: u64,
+    /// What to do when the concurrency limit is reached.
+    queue: QueuePolicy,
+    /// What to do while a previous run is in progress.
+    overlap: Overlap,
+    /// Whether to run again once the run in progress finishes.
+    rerun: bool,
+    /// Dispatch priority, higher goes first.
+    priority: i32,
+    /// External event that runs the job, if it has no time schedule.
+    trigger: Option<Box<Trigger>>,
+    /// Key of the job in the state file, if runs are recorded.
+    state_key: Option<Box<str>>,
+    /// Whether missed occurrences are caught up, in which case the state key is the crontab line.
+    catch_up: bool,
+    /// Hash of the variables in effect for the job in its crontab, to tell whether its settings
+    /// changed on reload.
+    settings: u64,
+    /// Whether the job also runs once when pocketcron starts.
+    run_on_start: bool,
+    /// Whether the output of runs is held back, and only passed on when they fail.
+    quiet_success: bool,
+    /// Lock on occurrences, if the job is shared between hosts.
+    lock: Option<Box<Lock>>,
+    /// File that is locked with `flock` while the job runs, if any.
+    flock: Option<Box<Path>>,
+    /// Shell command that reads the output of the job, if any.
+    pipe_to: Option<Box<str>>,
+    /// Shell that runs the command and `PIPE_TO`, with arguments.
+    shell: Box<str>,
+    /// Whether the command and `PIPE_TO` run directly, without the shell, from `SHELL=none`.
+    exec: bool,
+    /// Time between asking the job to stop and killing it.
+    grace: Duration,
+    /// Signal that asks the job to stop.
+    kill_signal: Signal,
+    /// How long a run may take before it is stopped, if limited.
+    timeout: Option<Duration>,
+    /// Shortest time between the starts of two runs, from `MIN_INTERVAL`.
+    min_interval: Duration,
+    /// Whether the interval follows the monotonic clock, from `CLOCK=monotonic`.
+    monotonic: bool,
+    /// Longest time without a successful run before the job is overdue, if watched.
+    expect_within: Option<Duration>,
+    /// When the last run succeeded, or when the job was loaded.
+    expected_since: Instant,
+    /// Whether the job was reported as overdue since then.
+    overdue: bool,
+    /// When the last run started, which is kept when the job is changed.
+    last_start: Option<Instant>,
+    /// How the last run ended, which is kept when the job is changed.
+    #[cfg(feature = "api")]
+    last_result: Option<Box<LastRun>>,
+    /// Names of the environment variables passed to the job, or all if `None`.
+    env_keep: Option<Box<[String]>>,
+    /// Where to mail the output of runs, if anywhere.
+    mail_to: Option<Box<str>>,
+    /// Path of the file to write the output of runs to, with placeholders, if any.
+    log_file: Option<Box<str>>,
+    /// When the log file is rotated, from `LOG_MAX_SIZE`.
+    log_rotation: Option<LogRotation>,
+    /// URL to ping when runs start and end, if any.
+    ping: Option<Box<str>>,
+    /// Niceness, resource limits and umask of runs, if any.
+    limits: Option<Box<Limits>>,
+    /// What runs are kept from doing, if sandboxed.
+    sandbox: Option<Box<Sandbox>>,
+    /// Shell command to run after a run succeeded, if any.
+    on_success: Option<Box<str>>,
+    /// Shell command to run after a run failed, if any.
+    on_failure: Option<Box<str>>,
+    /// Number of failures in a row before they alert, if set.
+    alert_after: Option<u32>,
+    /// Number of runs in a row that failed, up to the last one.
+    failures: u32,
+    /// Environment variables set for the job, in addition to those passed.
+    env: Box<[(String, String)]>,
+    /// User to run as, if not the user of pocketcron.
+    user: Option<Box<str>>,
+    /// Directory to run in, from `WORKDIR`, if not that of pocketcron.
+    workdir: Option<Box<Path>>,
+    /// Scheduled time of the due occurrence, which is locked before running.
+    occurrence: Option<DateTime<Local>>,
+    /// The crontab line, which identifies the job when jobs are replaced.
+    source: Box<str>,
+    /// Whether the job is from the agent server, rather than a local crontab.
+    remote: bool,
+    /// Whether the job was removed from its crontab. A run in progress isn't interrupted.
+    retired: bool,
+    /// Whether the job runs when it's due, unless it was paused through the control socket.
+    enabled: bool,
 }
 
-/// Thread-safe job handle.
-type JobHandle = Arc<Mutex<Job>>;
+impl Job {
+    /// Create a job from its description.
+    fn new(
+        spec: JobSpec,
+        id: usize,
+        remote: bool,
+        options: &LoadOptions,
+        now: DateTime<Local>,
+    ) -> Self {
+        let trigger = spec.schedule.trigger().cloned().map(Box::new);
+        let state_key = spec.state_key().map(Box::from);
+        // Only occurrences missed while pocketcron wasn't running are caught up, not those of jobs
+        // that change on reload.
+        let last_run = state_key
+            .as_deref()
+            .filter(|_| !spec.catch_up || !STARTED.load(Ordering::Relaxed))
+            .and_then(|key| {
+                state::get(|state| state.jobs.get(key).and_then(|job| job.last_run)).flatten()
+            });
+        let catch_up = spec.catch_up;
+        let mut upcoming = Upcoming::new(
+            spec.schedule,
+            now,
+            last_run,
+            spec.offset,
+            spec.random_delay,
+            &options.jitter_seed,
+            &spec.source,
+        );
+        let next = upcoming.next();
+        if catch_up && next == Some(now) {
+            if let Some(missed) = upcoming.scheduled() {
+                info!(
+                    [id] "missed the run at {}, catching up",
+                    missed.format("%Y-%m-%d %H:%M:%S %:z")
+                );
+            }
+        }
+        log::set_name(id, spec.name.as_deref());
+        Job {
+            id,
+            given_name: spec.name.map(String::into_boxed_str),
+            upcoming,
+            next,
+            command_start: spec.source.len() - spec.command.len(),
+            runs: Vec::new(),
+            run_count: 0,
+            queue: spec.queue,
+            overlap: spec.overlap,
+            rerun: false,
+            priority: spec.priority,
+            trigger,
+            state_key,
+            catch_up,
+            settings: spec.settings,
+            run_on_start: spec.run_on_start,
+            quiet_success: spec.quiet_success || options.only_failures,
+            flock: spec.flock.map(PathBuf::into_boxed_path),
+            lock: spec
+                .lock
+                .map(|backend| Box::new(Lock::new(backend, &spec.source))),
+            pipe_to: spec.pipe_to.map(String::into_boxed_str),
+            shell: spec
+                .shell
+                .or_else(|| options.shell.clone())
+                .unwrap_or_else(|| DEFAULT_SHELL.to_owned())
+                .into_boxed_str(),
+            exec: spec.exec,
+            grace: spec.grace,
+            kill_signal: spec.kill_signal,
+            timeout: spec
+                .timeout
+                .or(options.default_timeout)
+                .filter(|timeout| *timeout > Duration::zero()),
+            min_interval: spec.min_interval,
+            monotonic: spec.monotonic,
+            last_start: None,
+            expect_within: spec.expect_within,
+            expected_since: Instant::now(),
+            overdue: false,
+            #[cfg(feature = "api")]
+            last_result: None,
+            env_keep: spec.env_keep.map(Vec::into_boxed_slice),
+            mail_to: spec.mail_to.map(String::into_boxed_str),
+            log_file: spec.log_file.map(String::into_boxed_str),
+            log_rotation: spec.log_rotation,
+            ping: spec.ping.map(String::into_boxed_str),
+            limits: Some(spec.limits)
+                .filter(|limits| !limits.is_empty())
+                .map(Box::new),
+            sandbox: Some(spec.sandbox)
+                .filter(|sandbox| !sandbox.is_empty())
+                .map(Box::new),
+            on_success: spec.on_success.map(String::into_boxed_str),
+            on_failure: spec.on_failure.map(String::into_boxed_str),
+            alert_after: spec.alert_after,
+            failures: 0,
+            env: spec.env.into_boxed_slice(),
+            user: spec.user.map(String::into_boxed_str),
+            workdir: spec.workdir.map(PathBuf::into_boxed_path),
+            occurrence: None,
+            source: spec.source.into_boxed_str(),
+            remote,
+            retired: false,
+            enabled: true,
+        }
+    }
 
-fn main() {
-    let args = ::std::env::args_os();
-    if args.len() < 2 {
-        eprintln!("Usage: pocketcron <crontab...>");
-        exit(1);
+    /// What identifies the job when its crontab is reloaded.
+    fn key(&self) -> JobKey<'_> {
+        JobKey {
+            source: &self.source,
+            command: self.command(),
+            state_key: self.state_key.as_deref(),
+            settings: self.settings,
+        }
     }
 
-    let mut jobs = Vec::new();
-    for crontab in args.skip(1) {
-        load_jobs(&mut jobs, crontab);
+    /// Whether a name given on the command line refers to this job: its number, its name, the name
+    /// of its trigger, or its id in the state file.
+    fn is_named(&self, name: &str) -> bool {
+        name.parse() == Ok(self.id)
+            || self.given_name.as_deref() == Some(name)
+            || matches!(self.trigger.as_deref(), Some(Trigger::Named(trigger)) if trigger == name)
+            || self.period_id() == Some(name)
     }
 
-    loop {
-        let now = Local::now();
+    /// Set up the environment of a command for the job: its `WORKDIR`, only the variables in
+    /// `ENV_KEEP`, if any, those that describe the user it runs as, and those set in the crontab.
+    fn apply_env(&self, command: &mut Command, account: Option<&Account>) {
+        if let Some(dir) = &self.workdir {
+            command.current_dir(dir);
+        }
+        if let Some(keep) = &self.env_keep {
+            command.env_clear();
+            for name in keep.iter() {
+                if let Some(value) = ::std::env::var_os(name) {
+                    command.env(name, value);
+                }
+            }
+        }
+        if let Some(account) = account {
+            command.envs(account.env());
+        }
+        command.envs(self.env.iter().map(|(name, value)| (name, value)));
+    }
 
-        // Find the minimum of all jobs' `next` time.
-        // Max sleep is 1 minute, to account for any clock jumps.
-        let mut next_min = now + Duration::minutes(1);
-        for job_handle in &jobs {
-            let mut job = job_handle.lock().unwrap();
+    /// Make a command run with the niceness, resource limits and umask of the job. Must come
+    /// before switching users, because only root may raise them.
+    fn apply_limits(&self, command: &mut Command) {
+        let Some(limits) = self.limits.as_deref().copied() else {
+            return;
+        };
+        let cpu = limits
+            .cpu
+            .map(|cpu| cpu.num_seconds().max(1) as libc::rlim_t);
+        let address_space = limits.address_space.map(|size| size as libc::rlim_t);
+        // Safety: `setpriority`, `setrlimit` and `umask` are async-signal-safe.
+        unsafe {
+            command.pre_exec(move || {
+                let check = |res| match res {
+                    0 => Ok(()),
+                    _ => Err(io::Error::last_os_error()),
+                };
+                if let Some(nice) = limits.nice {
+                    check(libc::setpriority(libc::PRIO_PROCESS, 0, nice))?;
+                }
+                if let Some(cpu) = cpu {
+                    let limit = libc::rlimit {
+                        rlim_cur: cpu,
+                        rlim_max: cpu,
+                    };
+                    check(libc::setrlimit(libc::RLIMIT_CPU, &limit))?;
+                }
+                if let Some(size) = address_space {
+                    let limit = libc::rlimit {
+                        rlim_cur: size,
+                        rlim_max: size,
+                    };
+                    check(libc::setrlimit(libc::RLIMIT_AS, &limit))?;
+                }
+                if let Some(umask) = limits.umask {
+                    libc::umask(umask as libc::mode_t);
+                }
+                Ok(())
+            });
+        }
+    }
 
-            // It's possible a job may not ever run again.
-            let Some(next) = job.next else {
-                continue;
-            };
+    /// Command that runs the command of the job or its `PIPE_TO`, with the environment of the job:
+    /// directly with `SHELL=none`, and otherwise in the shell of the job. Variables are expanded
+    /// for a run.
+    fn exec_command(&self, script: &str, run: u64, account: Option<&Account>) -> Command {
+        if !self.exec {
+            let mut command = self.shell_command(&self.expand_script(script, run));
+            self.apply_env(&mut command, account);
+            return command;
+        }
+        // The words were checked when the crontab was parsed. They're expanded one by one, so
+        // values with spaces stay a single argument.
+        let words = split_words(script).unwrap_or_default();
+        let vars = words
+            .iter()
+            .map_while(|word| split_assignment(word))
+            .count();
+        let (vars, words) = words.split_at(vars);
+        let words: Vec<String> = words
+            .iter()
+            .map(|word| self.expand(word, run, account))
+            .collect();
+        let mut command = Command::new(words.first().map_or("", String::as_str));
+        command.args(words.iter().skip(1));
+        self.apply_env(&mut command, account);
+        // Like in a shell, variables before the command are only set for it.
+        for (name, value) in vars.iter().filter_map(|word| split_assignment(word)) {
+            command.env(name, self.expand(value, run, account));
+        }
+        command
+    }
 
-            // If in the future, aggregate in `next_min` .
-            if now < next {
-                next_min = next.min(next_min);
-                continue;
+    /// Expand `${JOB_NAME}`, `${RUN_ID}`, `${DATE:<format>}` and the variables of the job in a word
+    /// of a command without a shell, for a run. Variables are those of the crontab, those that
+    /// describe the user it runs as, and those of pocketcron, unless `ENV_KEEP` leaves them out.
+    fn expand(&self, text: &str, run: u64, account: Option<&Account>) -> String {
+        expand(text, Local::now(), |name| match name {
+            "JOB_NAME" => Some(self.name()),
+            "RUN_ID" => Some(run.to_string()),
+            _ => {
+                let set = self.env.iter().rev().find(|(other, _)| other == name);
+                let user = account
+                    .map(Account::env)
+                    .into_iter()
+                    .flatten()
+                    .find(|(other, _)| *other == name);
+                let kept = self
+                    .env_keep
+                    .as_deref()
+                    .is_none_or(|keep| keep.iter().any(|other| other == name));
+                set.map(|(_, value)| value.clone())
+                    .or_else(|| user.map(|(_, value)| value.to_string()))
+                    .or_else(|| ::std::env::var(name).ok().filter(|_| kept))
             }
+        })
+    }
 
-            // Otherwise, the job needs to run.
-            run_job(job_handle.clone());
+    /// Expand `${JOB_NAME}`, `${RUN_ID}` and `${DATE:<format>}` in a shell script, for a run. The
+    /// shell expands the variables of the job itself, from its environment.
+    fn expand_script(&self, script: &str, run: u64) -> String {
+        expand_shell(script, Local::now(), |name| match name {
+            "JOB_NAME" => Some(self.name()),
+            "RUN_ID" => Some(run.to_string()),
+            _ => None,
+        })
+    }
 
-            // Iterate the schedule until we find the next time in the future.
-            while job.next.filter(|next| now >= *next).is_some() {
-                job.next = job.upcoming.next();
-            }
-        }
+    /// Command that runs a script in the shell of the job, like `sh -c <script>`.
+    fn shell_command(&self, script: &str) -> Command {
+        let mut args = self.shell.split_whitespace();
+        let mut command = Command::new(args.next().unwrap_or(DEFAULT_SHELL));
+        command.args(args).arg("-c").arg(script);
+        command
+    }
 
-        // Delay until the aggregate `next_min` time.
-        // Safety: conversion can only fail if negative, which should never
-        // happen here.
-        let delay = (next_min - now)
-            .to_std()
-            .expect("unexpected negative sleep");
-        thread::sleep(delay);
+    /// Command that runs the `ON_SUCCESS` or `ON_FAILURE` hook of the job, as its user, with its
+    /// environment and in its sandbox.
+    fn hook_command(&self, hook: &str, account: Option<&Account>) -> io::Result<Command> {
+        let mut command = self.shell_command(hook);
+        command.stdin(Stdio::null()).process_group(0);
+        if let Some(account) = account {
+            account.apply(&mut command);
+        }
+        if let Some(sandbox) = self.sandbox.as_deref() {
+            sandbox::prepare(sandbox)
+                .map_err(|err| {
+                    io::Error::new(err.kind(), format!("sandbox setup failed: {}", err))
+                })?
+                .apply(&mut command);
+        }
+        self.apply_env(&mut command, account);
+        command.env("POCKETCRON_JOB", self.name());
+        Ok(command)
     }
-}
 
-fn load_jobs(jobs: &mut Vec<JobHandle>, path: OsString) {
-    let file = match fs::File::open(&path) {
-        Ok(file) => file,
-        Err(err) => {
-            eprintln!("{}: open failed: {}", path.to_string_lossy(), err);
-            exit(1);
+    /// Name of the job in log file paths and metrics: its name, the name of its trigger, its id in
+    /// the state file, or its number.
+    fn name(&self) -> String {
+        if let Some(name) = &self.given_name {
+            return name.to_string();
         }
-    };
+        match (self.trigger.as_deref(), self.period_id()) {
+            (Some(Trigger::Named(name)), _) => name.clone(),
+            (_, Some(id)) => id.to_owned(),
+            _ => self.id.to_string(),
+        }
+    }
 
-    // Read by line.
-    let now = Local::now();
-    for (line_no, line) in BufReader::new(file).lines().enumerate() {
-        let line = match line {
-            Ok(line) => line,
-            Err(err) => {
-                eprintln!("{}: read failed: {}", path.to_string_lossy(), err);
-                exit(1);
-            }
+    /// Id of the job in the state file, if it has a period.
+    fn period_id(&self) -> Option<&str> {
+        self.state_key.as_deref().filter(|_| !self.catch_up)
+    }
+
+    /// Whether a run is in progress.
+    fn is_running(&self) -> bool {
+        !self.runs.is_empty()
+    }
+
+    /// Start a new run, and return its number and unique id.
+    fn start_run(&mut self) -> (u64, RunId) {
+        self.run_count += 1;
+        self.last_start = Some(Instant::now());
+        let id = RunId::new();
+        self.runs.push(Run {
+            number: self.run_count,
+            id,
+            pid: None,
+            started: None,
+        });
+        (self.run_count, id)
+    }
+
+    /// Time since the last run started, if that is less than `MIN_INTERVAL`.
+    fn too_soon(&self) -> Option<std::time::Duration> {
+        let min_interval = self.min_interval.to_std().ok()?;
+        self.last_start
+            .map(|last_start| last_start.elapsed())
+            .filter(|since| *since < min_interval)
+    }
+
+    /// Remove a run that ended.
+    fn end_run(&mut self, number: u64) {
+        self.runs.retain(|run| run.number != number);
+    }
+
+    /// Label of a run in log messages, which only has the run number if runs may overlap.
+    fn label(&self, number: u64) -> Label {
+        let label = match self.overlap {
+            Overlap::Allow => Label::run(self.id, number),
+            _ => self.id.into(),
         };
+        match self.runs.iter().find(|run| run.number == number) {
+            Some(run) => label.with_run_id(run.id),
+            None => label,
+        }
+    }
 
-        // Ignore empty lines and comments.
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') {
-            continue;
+    /// Shell command.
+    fn command(&self) -> &str {
+        &self.source[self.command_start..]
+    }
+
+    /// The crontab line before the command, which is the schedule, and the user in system
+    /// crontabs.
+    #[cfg(any(feature = "api", feature = "otel"))]
+    fn schedule(&self) -> &str {
+        self.source[..self.command_start].trim_end()
+    }
+
+    /// Emit a `job_scheduled` event for the next run, if any.
+    fn emit_scheduled(&self) {
+        if let Some(next) = self.next {
+            Event::new("job_scheduled")
+                .num("job", self.id as i64)
+                .time("next", next)
+                .emit();
         }
+    }
+}
 
-        // Use `str::split_whitespace` only to find the end of the schedule. We don't want to split the
-        // command that way, because it could break spaces in quoted strings. Would prefer using
-        // `SplitWhitespace::remainder`, but that is nightly-only at the moment.
-        let command_start = if line.starts_with('@') {
-            line.split_whitespace().nth(1)
-        } else {
-            line.split_whitespace().nth(5)
-        };
-        let Some(command_start) = command_start else {
-            eprintln!(
-                "{}:{}: error: not enough elements",
-                path.to_string_lossy(),
-                line_no
-            );
-            exit(1);
-        };
-        let command_start = command_start.as_ptr() as usize - line.as_ptr() as usize;
+/// A run of a job in progress.
+struct Run {
+    /// Number of the run, counting from 1 for every job.
+    number: u64,
+    /// Unique id of the run, in `POCKETCRON_RUN_ID`.
+    id: RunId,
+    /// Process group, once spawned.
+    pid: Option<u32>,
+    /// When the process was spawned.
+    started: Option<DateTime<Local>>,
+}
 
-        // Parse the schedule.
-        let schedule = &line[..command_start];
-        let schedule = if schedule.starts_with('@') {
-            schedule.to_owned()
-        } else {
-            // 'cron'-crate expects additional second and year elements.
-            format!("0 {} *", schedule)
-        };
-        let schedule: ::cron::Schedule = match schedule.parse() {
-            Ok(schedule) => schedule,
-            Err(err) => {
-                eprintln!("{}:{}: error: {}", path.to_string_lossy(), line_no, err);
-                exit(1);
-            }
-        };
+/// Crontab argument that reads standard input.
+const STDIN: &str = "-";
 
-        // Initialize the `Job` structure.
-        let mut upcoming = schedule.after_owned(now);
-        let next = upcoming.next();
-        jobs.push(Arc::new(Mutex::new(Job {
-            id: jobs.len() + 1,
-            upcoming,
-            next,
-            command: line[command_start..].to_owned(),
-            is_running: false,
-        })));
+/// Thread-safe job handle.
+type JobHandle = Arc<Mutex<Job>>;
+
+/// List of all jobs, shared with the control socket.
+type JobList = Arc<Mutex<Vec<JobHandle>>>;
+
+const USAGE: &str = "Usage: pocketcron [-q|-v|-vv] [--noop] [--max-concurrent <n>] \
+    [--jitter-seed <seed>] [--compat busybox|system] [--user <name> [--group <name>]] [--init] \
+    [--inhibit-sleep] [--cgroup-root <path>] [--shutdown-timeout <duration>] [--until <time>] \
+    [--max-runs <n>] [--default-timeout <duration>] [--max-output <size>] [--shell <command>] \
+    [--env-file <path>...] [--log-format text|json] [--log-target stderr|syslog|journald] \
+    [--prefix-output] [--events-fd <fd>] [--control-socket <path>] [--metrics-addr <addr>] \
+    [--api-addr <addr> --api-token-file <path>] [--watch-crontabs] [--lenient] [--run-on-start] \
+    [--only-failures] [--state-file <path>] [--state-format json|binary] \
+    [--history-file <path> [--history-limit <n>]] [--lease-file <path> [--lease-time <duration>]] \
+    [--shard <k>/<n>] [--forward <url> [--forward-backlog <path>]] \
+    [--otel-endpoint <url>] [--events <url>] \
+    [--agent <url> --agent-key <path> [--agent-token-file <path>] [--agent-interval <duration>]] \
+    [--stdin] [--config <path>...] [--env-jobs] [--spool <dir>] <crontab...>
+       pocketcron tick --state-file <path> [<options>] <crontab...>
+       pocketcron trigger [--control-socket <path>] <name>
+       pocketcron kill [--control-socket <path>] [-s <signal>] <job>
+       pocketcron restart [--control-socket <path>] <job>
+       pocketcron abort [--control-socket <path>] [--pause]
+       pocketcron resume [--control-socket <path>] [<job>]
+       pocketcron pause [--control-socket <path>] <job>
+       pocketcron run [--control-socket <path>] <job>
+       pocketcron list [--control-socket <path>]
+       pocketcron next [--control-socket <path>] [-n <count>]
+       pocketcron status [--control-socket <path>]
+       pocketcron stats [--control-socket <path>]
+       pocketcron tail [--control-socket <path>] [--job <name>]
+       pocketcron history [--control-socket <path>] <job>
+       pocketcron check [--compat busybox|system] [--env-jobs] <crontab...>
+       pocketcron preview [--count <n>] [--compat busybox|system] \
+    [--jitter-seed <seed>] <crontab...>
+       pocketcron bench [--jobs <n>] [--ticks <n>] [--max-concurrent <n>]";
+
+/// Part of the jobs to run, so that several hosts can share a crontab.
+#[derive(Clone, Copy)]
+struct Shard {
+    /// Shard number, starting at 0.
+    index: u64,
+    /// Number of shards.
+    count: u64,
+}
+
+impl Shard {
+    /// Whether the job on a crontab line is in this shard.
+    fn contains(&self, source: &str) -> bool {
+        let mut hasher = Fnv1a::default();
+        hasher.write(source.as_bytes());
+        hash::mix(hasher.finish()) % self.count == self.index
     }
 }
 
-fn run_job(job_handle: JobHandle) {
-    thread::spawn(move || {
-        let (id, mut command) = {
-            let mut job = job_handle.lock().unwrap();
+impl FromStr for Shard {
+    type Err = String;
 
-            // Prevent overlap.
-            if job.is_running {
-                return;
-            }
-            job.is_running = true;
+    /// Parse a shard like `2/4`, numbered from 1.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let shard = s.split_once('/').and_then(|(index, count)| {
+            let index: u64 = index.parse().ok()?;
+            let count: u64 = count.parse().ok()?;
+            (1..=count).contains(&index).then(|| Shard {
+                index: index - 1,
+                count,
+            })
+        });
+        shard.ok_or_else(|| format!("invalid shard: {}", s))
+    }
+}
 
-            eprintln!("[{}] CMD {}", job.id, job.command);
+/// Settings that apply to all jobs as they are loaded.
+struct LoadOptions {
+    jitter_seed: String,
+    compat: Compat,
+    shard: Option<Shard>,
+    /// Timeout of jobs without `TIMEOUT`.
+    default_timeout: Option<Duration>,
+    /// Shell of jobs without `SHELL`.
+    shell: Option<String>,
+    /// Whether jobs are also read from `POCKETCRON_JOB_*` variables, with `--env-jobs`.
+    env_jobs: bool,
+    /// Whether crontabs and lines with errors are skipped, instead of failing, with `--lenient`.
+    lenient: bool,
+    /// Directory of crontabs of users, named after them, with `--spool`.
+    spool: Option<PathBuf>,
+    /// Whether the output of all jobs is only passed on when runs fail, with `--only-failures`.
+    only_failures: bool,
+}
 
-            let mut command = Command::new("sh");
-            command.arg("-c").arg(&job.command).stdin(Stdio::null());
-            (job.id, command)
-        };
+/// Shell of jobs, unless set otherwise.
+const DEFAULT_SHELL: &str = "sh";
 
-        match command.spawn() {
-            Err(err) => {
-                eprintln!("[{}] spawn failed: {}", id, err);
+impl LoadOptions {
+    /// Whether a job runs here. Jobs in other shards are left to other hosts.
+    fn includes(&self, spec: &JobSpec) -> bool {
+        self.shard.is_none_or(|shard| shard.contains(&spec.source))
+    }
+}
+
+/// Whether due jobs are only logged, and never run, with `--noop`.
+static NOOP: AtomicBool = AtomicBool::new(false);
+
+/// Set once the crontabs are loaded at startup.
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Number of runs after which pocketcron exits, with `--max-runs`.
+static MAX_RUNS: OnceLock<u64> = OnceLock::new();
+
+/// Number of runs that started, or are about to, for `--max-runs`.
+static CLAIMED_RUNS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of runs that finished, for `--max-runs`.
+static FINISHED_RUNS: AtomicU64 = AtomicU64::new(0);
+
+/// The jobs and the dispatcher, for runs to start the jobs that run `@after` them.
+static CHAINED: OnceLock<(JobList, Arc<Dispatcher>)> = OnceLock::new();
+
+/// Environment variable with the default control socket path for commands.
+const CONTROL_SOCKET_ENV: &str = "POCKETCRON_CONTROL_SOCKET";
+
+fn main() {
+    let command = ::std::env::args_os().nth(1);
+    if let Some(
+        command @ ("trigger" | "kill" | "restart" | "abort" | "resume" | "pause" | "run" | "list"
+        | "next" | "status" | "stats" | "tail" | "history"),
+    ) = command.as_ref().and_then(|arg| arg.to_str())
+    {
+        control_main(command);
+    }
+    if command.as_ref().is_some_and(|arg| arg == "check") {
+        check_main();
+    }
+    if command.as_ref().is_some_and(|arg| arg == "preview") {
+        preview::main();
+    }
+    if command.as_ref().is_some_and(|arg| arg == "bench") {
+        bench::main();
+    }
+    // `tick` takes the same options as the scheduler itself.
+    let tick = command.is_some_and(|arg| arg == "tick");
+
+    if let Err(err) = faults::init() {
+        eprintln!("{}", err);
+        exit(1);
+    }
+
+    // Sockets passed by systemd, for socket activation.
+    let mut listen_fds = ListenFds::from_env();
+    #[cfg(feature = "notify")]
+    notify::init();
+
+    let mut max_concurrent = None;
+    let mut jitter_seed = None;
+    let mut compat = Compat::Default;
+    let mut control_socket = None;
+    #[cfg(feature = "metrics")]
+    let mut metrics_addr = None;
+    #[cfg(feature = "api")]
+    let mut api_addr = None;
+    #[cfg(feature = "api")]
+    let mut api_token_file = None;
+    let mut watch_crontabs = false;
+    let mut env_jobs = false;
+    let mut init = false;
+    let mut state_file = None;
+    #[cfg(feature = "history")]
+    let mut history_file = None;
+    let mut cgroup_root = None;
+    #[cfg(feature = "history")]
+    let mut history_limit = history::DEFAULT_LIMIT;
+    let mut state_format = state::Format::Json;
+    let mut lease_file = None;
+    let mut lease_time = Duration::seconds(30);
+    let mut shutdown_timeout = Duration::zero();
+    let mut until = None;
+    let mut default_timeout = None;
+    let mut shell = None;
+    let mut env_files = Vec::new();
+    let mut shard = None;
+    let mut forward_target = None;
+    #[cfg(feature = "publish")]
+    let mut events_broker = None;
+    #[cfg(feature = "otel")]
+    let mut otel_endpoint = None;
+    let mut forward_backlog = None;
+    let mut run_user = None;
+    let mut run_group = None;
+    #[cfg(feature = "agent")]
+    let mut agent_url = None;
+    #[cfg(feature = "agent")]
+    let mut agent_key = None;
+    #[cfg(feature = "agent")]
+    let mut agent_token_file = None;
+    #[cfg(feature = "agent")]
+    let mut agent_interval = Duration::minutes(1);
+    let mut verbosity = 0;
+    let mut lenient = false;
+    let mut run_on_start = false;
+    let mut spool = None;
+    let mut only_failures = false;
+    let mut crontabs = Vec::new();
+    let mut args = ::std::env::args_os().skip(if tick { 2 } else { 1 });
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--noop") => NOOP.store(true, Ordering::Relaxed),
+            Some("-q") => verbosity -= 1,
+            Some("-v") => verbosity += 1,
+            Some("-vv") => verbosity += 2,
+            Some("--watch-crontabs") => watch_crontabs = true,
+            Some("--stdin") => crontabs.push(STDIN.into()),
+            Some("--config") => {
+                let Some(value) = args.next() else {
+                    eprintln!("--config requires a path");
+                    exit(1);
+                };
+                if !config::is_config(Path::new(&value)) {
+                    eprintln!("--config requires a .toml file");
+                    exit(1);
+                }
+                crontabs.push(value);
+            }
+            Some("--spool") => {
+                let Some(value) = args.next() else {
+                    eprintln!("--spool requires a path");
+                    exit(1);
+                };
+                spool = Some(PathBuf::from(value));
+            }
+            Some("--env-jobs") => env_jobs = true,
+            Some("--lenient") => lenient = true,
+            Some("--run-on-start") => run_on_start = true,
+            Some("--only-failures") => only_failures = true,
+            Some("--init") => init = true,
+            Some("--inhibit-sleep") if inhibit::SUPPORTED => inhibit::enable(),
+            Some("--inhibit-sleep") => {
+                eprintln!("--inhibit-sleep is not supported on this platform");
+                exit(1);
             }
-            Ok(mut proc) => match proc.wait() {
-                Err(err) => {
-                    eprintln!("[{}] wait failed: {}", id, err);
+            Some("--max-concurrent") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(str::parse::<usize>) {
+                    Some(Ok(value)) if value > 0 => max_concurrent = Some(value),
+                    _ => {
+                        eprintln!("--max-concurrent requires a positive number");
+                        exit(1);
+                    }
                 }
-                Ok(status) if !status.success() => {
-                    eprintln!("[{}] {}", id, status);
+            }
+            Some("--jitter-seed") => {
+                let Some(value) = args.next().and_then(|value| value.into_string().ok()) else {
+                    eprintln!("--jitter-seed requires a value");
+                    exit(1);
+                };
+                jitter_seed = Some(value);
+            }
+            Some("--compat") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(str::parse) {
+                    Some(Ok(value)) => compat = value,
+                    Some(Err(err)) => {
+                        eprintln!("{}", err);
+                        exit(1);
+                    }
+                    None => {
+                        eprintln!("--compat requires a value");
+                        exit(1);
+                    }
                 }
-                _ => {}
-            },
+            }
+            Some("--events-fd") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(str::parse::<i32>) {
+                    // Safety: `fcntl` only checks that the descriptor is open.
+                    Some(Ok(fd)) if unsafe { libc::fcntl(fd, libc::F_GETFD) } != -1 => {
+                        // Safety: the descriptor is open, and passed to us for events only.
+                        unsafe { events::open(fd) };
+                    }
+                    _ => {
+                        eprintln!("--events-fd requires an open file descriptor");
+                        exit(1);
+                    }
+                }
+            }
+            Some("--control-socket") => {
+                let Some(value) = args.next() else {
+                    eprintln!("--control-socket requires a path");
+                    exit(1);
+                };
+                control_socket = Some(PathBuf::from(value));
+            }
+            Some("--state-file") => {
+                let Some(value) = args.next() else {
+                    eprintln!("--state-file requires a path");
+                    exit(1);
+                };
+                state_file = Some(PathBuf::from(value));
+            }
+            Some("--cgroup-root") if cgroup::SUPPORTED => {
+                let Some(value) = args.next() else {
+                    eprintln!("--cgroup-root requires a path");
+                    exit(1);
+                };
+                cgroup_root = Some(PathBuf::from(value));
+            }
+            Some("--cgroup-root") => {
+                eprintln!("--cgroup-root is not supported on this platform");
+                exit(1);
+            }
+            #[cfg(feature = "history")]
+            Some("--history-file") => {
+                let Some(value) = args.next() else {
+                    eprintln!("--history-file requires a path");
+                    exit(1);
+                };
+                history_file = Some(PathBuf::from(value));
+            }
+            #[cfg(feature = "history")]
+            Some("--history-limit") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(str::parse) {
+                    Some(Ok(value)) if value > 0 => history_limit = value,
+                    _ => {
+                        eprintln!("--history-limit requires a number of runs");
+                        exit(1);
+                    }
+                }
+            }
+            Some("--log-target") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(str::parse) {
+                    Some(Ok(value)) => {
+                        if let Err(err) = log::set_target(value) {
+                            eprintln!("--log-target failed: {}", err);
+                            exit(1);
+                        }
+                    }
+                    Some(Err(err)) => {
+                        eprintln!("{}", err);
+                        exit(1);
+                    }
+                    None => {
+                        eprintln!("--log-target requires a value");
+                        exit(1);
+                    }
+                }
+            }
+            Some("--prefix-output") => log::set_prefix_output(),
+            Some("--log-format") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(str::parse) {
+                    Some(Ok(value)) => log::set_format(value),
+                    Some(Err(err)) => {
+                        eprintln!("{}", err);
+                        exit(1);
+                    }
+                    None => {
+                        eprintln!("--log-format requires a value");
+                        exit(1);
+                    }
+                }
+            }
+            #[cfg(not(feature = "history"))]
+            Some(flag @ ("--history-file" | "--history-limit")) => {
+                eprintln!("{}: requires a build with the `history` feature", flag);
+                exit(1);
+            }
+            #[cfg(feature = "metrics")]
+            Some("--metrics-addr") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(str::parse::<SocketAddr>) {
+                    Some(Ok(value)) => metrics_addr = Some(value),
+                    Some(Err(err)) => {
+                        eprintln!("invalid metrics address: {}", err);
+                        exit(1);
+                    }
+                    None => {
+                        eprintln!("--metrics-addr requires a value");
+                        exit(1);
+                    }
+                }
+            }
+            #[cfg(not(feature = "metrics"))]
+            Some(flag @ "--metrics-addr") => {
+                eprintln!("{}: requires a build with the `metrics` feature", flag);
+                exit(1);
+            }
+            #[cfg(feature = "api")]
+            Some("--api-addr") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(str::parse::<SocketAddr>) {
+                    Some(Ok(value)) => api_addr = Some(value),
+                    Some(Err(err)) => {
+                        eprintln!("invalid API address: {}", err);
+                        exit(1);
+                    }
+                    None => {
+                        eprintln!("--api-addr requires a value");
+                        exit(1);
+                    }
+                }
+            }
+            #[cfg(feature = "api")]
+            Some("--api-token-file") => {
+                let Some(value) = args.next() else {
+                    eprintln!("--api-token-file requires a path");
+                    exit(1);
+                };
+                api_token_file = Some(PathBuf::from(value));
+            }
+            #[cfg(not(feature = "api"))]
+            Some(flag @ ("--api-addr" | "--api-token-file")) => {
+                eprintln!("{}: requires a build with the `api` feature", flag);
+                exit(1);
+            }
+            Some("--state-format") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(str::parse) {
+                    Some(Ok(value)) => state_format = value,
+                    Some(Err(err)) => {
+                        eprintln!("{}", err);
+                        exit(1);
+                    }
+                    None => {
+                        eprintln!("--state-format requires a value");
+                        exit(1);
+                    }
+                }
+            }
+            Some("--lease-file") => {
+                let Some(value) = args.next() else {
+                    eprintln!("--lease-file requires a path");
+                    exit(1);
+                };
+                lease_file = Some(PathBuf::from(value));
+            }
+            Some("--lease-time") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(parse_duration) {
+                    Some(Ok(value)) if value >= Duration::seconds(3) => lease_time = value,
+                    _ => {
+                        eprintln!("--lease-time requires a duration of at least 3s");
+                        exit(1);
+                    }
+                }
+            }
+            Some("--shutdown-timeout") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(parse_duration) {
+                    Some(Ok(value)) => shutdown_timeout = value,
+                    _ => {
+                        eprintln!("--shutdown-timeout requires a duration");
+                        exit(1);
+                    }
+                }
+            }
+            Some("--until") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(parse_time) {
+                    Some(Some(value)) => until = Some(value),
+                    _ => {
+                        eprintln!("--until requires a time, like 2024-06-01T00:00");
+                        exit(1);
+                    }
+                }
+            }
+            Some("--max-runs") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(str::parse) {
+                    Some(Ok(value)) if value > 0 => {
+                        let _ = MAX_RUNS.set(value);
+                    }
+                    _ => {
+                        eprintln!("--max-runs requires a number of runs");
+                        exit(1);
+                    }
+                }
+            }
+            Some("--default-timeout") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(parse_duration) {
+                    Some(Ok(value)) => default_timeout = Some(value),
+                    _ => {
+                        eprintln!("--default-timeout requires a duration");
+                        exit(1);
+                    }
+                }
+            }
+            Some("--max-output") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(parse_size) {
+                    Some(Ok(value)) => output::set_max_collected(value as usize),
+                    _ => {
+                        eprintln!("--max-output requires a size");
+                        exit(1);
+                    }
+                }
+            }
+            Some("--shell") => {
+                let Some(value) = args
+                    .next()
+                    .and_then(|value| value.into_string().ok())
+                    .filter(|value| !value.trim().is_empty())
+                else {
+                    eprintln!("--shell requires a command");
+                    exit(1);
+                };
+                shell = Some(value);
+            }
+            Some("--env-file") => {
+                let Some(value) = args.next() else {
+                    eprintln!("--env-file requires a path");
+                    exit(1);
+                };
+                env_files.push(PathBuf::from(value));
+            }
+            Some("--shard") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(str::parse) {
+                    Some(Ok(value)) => shard = Some(value),
+                    Some(Err(err)) => {
+                        eprintln!("{}", err);
+                        exit(1);
+                    }
+                    None => {
+                        eprintln!("--shard requires a value");
+                        exit(1);
+                    }
+                }
+            }
+            Some("--forward") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(str::parse::<forward::Target>) {
+                    Some(Ok(value)) => forward_target = Some(value),
+                    Some(Err(err)) => {
+                        eprintln!("{}", err);
+                        exit(1);
+                    }
+                    None => {
+                        eprintln!("--forward requires a URL");
+                        exit(1);
+                    }
+                }
+            }
+            #[cfg(feature = "publish")]
+            Some("--events") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(str::parse::<publish::Broker>) {
+                    Some(Ok(value)) => events_broker = Some(value),
+                    Some(Err(err)) => {
+                        eprintln!("{}", err);
+                        exit(1);
+                    }
+                    None => {
+                        eprintln!("--events requires a URL");
+                        exit(1);
+                    }
+                }
+            }
+            Some("--user") => {
+                let Some(value) = args.next().and_then(|value| value.into_string().ok()) else {
+                    eprintln!("--user requires a name");
+                    exit(1);
+                };
+                run_user = Some(value);
+            }
+            Some("--group") => {
+                let Some(value) = args.next().and_then(|value| value.into_string().ok()) else {
+                    eprintln!("--group requires a name");
+                    exit(1);
+                };
+                run_group = Some(value);
+            }
+            Some("--forward-backlog") => {
+                let Some(value) = args.next() else {
+                    eprintln!("--forward-backlog requires a path");
+                    exit(1);
+                };
+                forward_backlog = Some(PathBuf::from(value));
+            }
+            #[cfg(not(feature = "publish"))]
+            Some(flag @ "--events") => {
+                eprintln!("{}: requires a build with the `publish` feature", flag);
+                exit(1);
+            }
+            #[cfg(feature = "otel")]
+            Some("--otel-endpoint") => {
+                let Some(value) = args.next().and_then(|value| value.into_string().ok()) else {
+                    eprintln!("--otel-endpoint requires a URL");
+                    exit(1);
+                };
+                if !value.starts_with("http://") && !value.starts_with("https://") {
+                    eprintln!("invalid OTLP endpoint: {}", value);
+                    exit(1);
+                }
+                if !cfg!(feature = "http") {
+                    eprintln!("{}: requires a build with the `http` feature", value);
+                    exit(1);
+                }
+                otel_endpoint = Some(value);
+            }
+            #[cfg(not(feature = "otel"))]
+            Some(flag @ "--otel-endpoint") => {
+                eprintln!("{}: requires a build with the `otel` feature", flag);
+                exit(1);
+            }
+            #[cfg(feature = "agent")]
+            Some("--agent") => {
+                let Some(value) = args.next().and_then(|value| value.into_string().ok()) else {
+                    eprintln!("--agent requires a URL");
+                    exit(1);
+                };
+                agent_url = Some(value);
+            }
+            #[cfg(feature = "agent")]
+            Some("--agent-key") => {
+                let Some(value) = args.next() else {
+                    eprintln!("--agent-key requires a path");
+                    exit(1);
+                };
+                agent_key = Some(PathBuf::from(value));
+            }
+            #[cfg(feature = "agent")]
+            Some("--agent-token-file") => {
+                let Some(value) = args.next() else {
+                    eprintln!("--agent-token-file requires a path");
+                    exit(1);
+                };
+                agent_token_file = Some(PathBuf::from(value));
+            }
+            #[cfg(feature = "agent")]
+            Some("--agent-interval") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(parse_duration) {
+                    Some(Ok(value)) if value >= Duration::seconds(1) => agent_interval = value,
+                    _ => {
+                        eprintln!("--agent-interval requires a duration of at least 1s");
+                        exit(1);
+                    }
+                }
+            }
+            #[cfg(not(feature = "agent"))]
+            Some(flag) if flag.starts_with("--agent") => {
+                eprintln!("{}: requires a build with the `agent` feature", flag);
+                exit(1);
+            }
+            Some(flag) if flag.starts_with("--") => {
+                eprintln!("unknown option: {}", flag);
+                eprintln!("{}", USAGE);
+                exit(1);
+            }
+            _ => crontabs.push(arg),
+        }
+    }
+    log::set_max_level(match verbosity {
+        ..=-2 => log::Level::Error,
+        -1 => log::Level::Warning,
+        0 => log::Level::Info,
+        1 => log::Level::Debug,
+        _ => log::Level::Trace,
+    });
+    // Drop privileges before reading any files, so that those are read as the user too. Sockets
+    // passed in stay open.
+    match (&run_user, &run_group) {
+        (Some(name), group) => {
+            if let Err(err) = user::drop_privileges(name, group.as_deref()) {
+                eprintln!("{}", err);
+                exit(1);
+            }
+        }
+        (None, Some(_)) => {
+            eprintln!("--group requires --user");
+            exit(1);
+        }
+        (None, None) => {}
+    }
+    for path in &env_files {
+        if let Err(err) = dotenv::load(path) {
+            error!("{}", err);
+            exit(1);
+        }
+    }
+    if init {
+        init::start();
+    }
+    #[cfg(feature = "api")]
+    let api_token = api_addr.map(|_| {
+        let Some(path) = &api_token_file else {
+            eprintln!("--api-addr requires --api-token-file");
+            exit(1);
+        };
+        match fs::read_to_string(path) {
+            Ok(contents) if !contents.trim().is_empty() => contents.trim().to_owned(),
+            Ok(_) => {
+                eprintln!("{}: empty token", path.display());
+                exit(1);
+            }
+            Err(err) => {
+                eprintln!("{}: read failed: {}", path.display(), err);
+                exit(1);
+            }
+        }
+    });
+    #[cfg(feature = "agent")]
+    let agent = agent_url.map(|url| {
+        let Some(key_path) = agent_key else {
+            eprintln!("--agent requires --agent-key");
+            exit(1);
+        };
+        let read = |path: &PathBuf| match fs::read_to_string(path) {
+            Ok(contents) => contents.trim().to_owned(),
+            Err(err) => {
+                eprintln!("{}: read failed: {}", path.display(), err);
+                exit(1);
+            }
         };
+        let token = agent_token_file.as_ref().map(read);
+        let interval = agent_interval.to_std().unwrap_or_default();
+        Agent::new(&url, &read(&key_path), token, interval).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            exit(1);
+        })
+    });
+    #[cfg(not(feature = "agent"))]
+    let agent: Option<std::convert::Infallible> = None;
+    if crontabs.is_empty() && !env_jobs && spool.is_none() && agent.is_none() {
+        eprintln!("{}", USAGE);
+        exit(1);
+    }
+    if tick && state_file.is_none() {
+        eprintln!("tick requires --state-file");
+        exit(1);
+    }
 
-        let mut job = job_handle.lock().unwrap();
-        job.is_running = false;
+    // In agent mode, run results are reported to the server, unless they go elsewhere.
+    #[cfg(feature = "agent")]
+    let forward_target = forward_target.or_else(|| {
+        let agent = agent.as_ref()?;
+        Some(forward::Target::Http {
+            url: agent.results_url(),
+            token: agent.token().map(str::to_owned),
+        })
+    });
+
+    // By default, hosts in a fleet each get a different random delay.
+    let options = Arc::new(LoadOptions {
+        jitter_seed: jitter_seed.unwrap_or_else(hostname),
+        compat,
+        shard,
+        default_timeout,
+        shell,
+        env_jobs,
+        lenient,
+        spool,
+        only_failures,
     });
+
+    if let Some(path) = &state_file {
+        if let Err(err) = state::open(path, state_format) {
+            error!("{}: state file load failed: {}", path.display(), err);
+            exit(1);
+        }
+    }
+    #[cfg(feature = "history")]
+    if let Some(path) = &history_file {
+        if let Err(err) = history::open(path, history_limit) {
+            error!("{}: history file load failed: {}", path.display(), err);
+            exit(1);
+        }
+    }
+
+    if let Some(path) = &cgroup_root {
+        if let Err(err) = cgroup::enable(path) {
+            error!("{}: cgroup setup failed: {}", path.display(), err);
+            exit(1);
+        }
+    }
+
+    // A tick loads the jobs as of the last tick, to find those that were due since.
+    let last_tick = state::get(|state| state.last_tick)
+        .flatten()
+        .filter(|_| tick);
+    let mut jobs = Vec::new();
+    load_jobs(
+        &mut jobs,
+        &crontabs,
+        &options,
+        last_tick.unwrap_or_else(Local::now),
+    );
+
+    // Jobs from the agent server follow those from local crontabs. If the server can't be reached
+    // at startup, start with just the local jobs.
+    #[cfg(feature = "agent")]
+    let mut agent_crontab = None;
+    #[cfg(feature = "agent")]
+    if let Some(agent) = &agent {
+        match agent
+            .fetch()
+            .and_then(|crontab| agent::apply(&mut jobs, &crontab, &options).map(|_| crontab))
+        {
+            Ok(crontab) => agent_crontab = Some(crontab),
+            Err(err) => error!("initial agent crontab failed: {}", err),
+        }
+    }
+    Event::new("reload")
+        .num("crontabs", crontabs.len() as i64)
+        .num("jobs", jobs.len() as i64)
+        .emit();
+    for job_handle in &jobs {
+        job_handle.lock().unwrap().emit_scheduled();
+    }
+
+    let signals: Vec<i32> = jobs
+        .iter()
+        .filter_map(
+            |job_handle| match job_handle.lock().unwrap().trigger.as_deref() {
+                Some(Trigger::Signal(signal)) => Some(*signal),
+                _ => None,
+            },
+        )
+        .collect();
+    check_after(&jobs);
+    let jobs: JobList = Arc::new(Mutex::new(jobs));
+    let dispatcher = Dispatcher::new(max_concurrent);
+    let _ = CHAINED.set((jobs.clone(), dispatcher.clone()));
+
+    // Signals must be set up before any other threads are started. Jobs run in process groups of
+    // their own, so they don't get signals meant for pocketcron, and are stopped on shutdown.
+    {
+        let mut handled = signals;
+        handled.extend([libc::SIGTERM, libc::SIGINT, libc::SIGHUP]);
+        let jobs = jobs.clone();
+        let dispatcher = dispatcher.clone();
+        let crontabs = crontabs.clone();
+        let options = options.clone();
+        signals::handle(&handled, move |signal| match signal {
+            libc::SIGTERM | libc::SIGINT => stop::shutdown(
+                &dispatcher,
+                &format!("received signal {}", signal),
+                shutdown_timeout,
+            ),
+            libc::SIGHUP => reload(&jobs, &dispatcher, &crontabs, &options),
+            _ => {
+                fire(&jobs, &dispatcher, &Trigger::Signal(signal));
+            }
+        });
+    }
+    #[cfg(feature = "otel")]
+    if let Some(endpoint) = &otel_endpoint {
+        otel::start(endpoint);
+    }
+    #[cfg(feature = "publish")]
+    if let Some(broker) = events_broker {
+        publish::start(broker);
+    }
+    if let Some(target) = forward_target {
+        forward::start(target, forward_backlog);
+    }
+    let mut watches: Vec<Watch> = Vec::new();
+    for job_handle in jobs.lock().unwrap().iter() {
+        if let Some(Trigger::Watch(watch)) = job_handle.lock().unwrap().trigger.as_deref() {
+            if !watches.contains(watch) {
+                watches.push(watch.clone());
+            }
+        }
+    }
+    for watch in watches {
+        let path = watch.path.clone();
+        let trigger = Trigger::Watch(watch.clone());
+        let jobs = jobs.clone();
+        let dispatcher = dispatcher.clone();
+        let result = watch::watch(watch, move || {
+            fire(&jobs, &dispatcher, &trigger);
+        });
+        if let Err(err) = result {
+            error!("{}: watch failed: {}", path.display(), err);
+            exit(1);
+        }
+    }
+    if watch_crontabs {
+        if let Err((dir, err)) = watch_crontab_dirs(&jobs, &dispatcher, &crontabs, &options) {
+            error!("{}: watch failed: {}", dir.display(), err);
+            exit(1);
+        }
+    }
+    if options.spool.is_some() {
+        rescan_spool(&jobs, &dispatcher, &crontabs, &options);
+    }
+    let control_listener = match (listen_fds.take("control"), control_socket) {
+        // Safety: the descriptor was passed to us by the service manager, for this socket.
+        (Some(fd), _) => Some(unsafe { UnixListener::from_raw_fd(fd) }),
+        (None, Some(path)) => match control::bind(&path) {
+            Ok(listener) => Some(listener),
+            Err(err) => {
+                error!("{}: listen failed: {}", path.display(), err);
+                exit(1);
+            }
+        },
+        (None, None) => None,
+    };
+    if let Some(listener) = control_listener {
+        control::listen(listener, jobs.clone(), dispatcher.clone());
+    }
+    #[cfg(feature = "metrics")]
+    if let Some(addr) = metrics_addr {
+        match TcpListener::bind(addr) {
+            Ok(listener) => metrics::listen(listener, jobs.clone(), dispatcher.clone()),
+            Err(err) => {
+                error!("{}: metrics listen failed: {}", addr, err);
+                exit(1);
+            }
+        }
+    }
+    #[cfg(feature = "api")]
+    if let (Some(addr), Some(token)) = (api_addr, api_token) {
+        match TcpListener::bind(addr) {
+            Ok(listener) => api::listen(listener, token, jobs.clone(), dispatcher.clone()),
+            Err(err) => {
+                error!("{}: API listen failed: {}", addr, err);
+                exit(1);
+            }
+        }
+    }
+
+    #[cfg(feature = "agent")]
+    if let Some(agent) = agent {
+        let jobs = jobs.clone();
+        let dispatcher = dispatcher.clone();
+        let crontabs = crontabs.len() as i64 + 1;
+        agent.watch(agent_crontab, move |crontab| {
+            let mut jobs = jobs.lock().unwrap();
+            let count = agent::apply(&mut jobs, crontab, &options)?;
+            info!("applied new agent crontab with {} job(s)", count);
+            Event::new("reload")
+                .num("crontabs", crontabs)
+                .num("jobs", jobs.len() as i64)
+                .emit();
+            dispatcher.wake();
+            Ok(())
+        });
+    }
+
+    // Standby instances start doing the same as the leader, except running jobs. An observer
+    // doesn't run jobs either way, and must not take the lease from a real instance.
+    let noop = NOOP.load(Ordering::Relaxed);
+    if let Some(path) = lease_file.filter(|_| !noop) {
+        lease::start(path, lease_time);
+    }
+
+    // With a state file, `@reboot` jobs run once per boot of the machine, rather than every time
+    // pocketcron starts.
+    let boot_id = state::boot_id();
+    let last_boot_id = state::get(|state| state.boot_id.clone()).flatten();
+    if boot_id.is_none() || last_boot_id != boot_id {
+        fire(&jobs, &dispatcher, &Trigger::Reboot);
+    }
+    if !noop {
+        state::update(|state| state.boot_id = boot_id);
+    }
+    if tick {
+        tick::run(&jobs, &dispatcher, last_tick);
+    }
+
+    // Jobs with `RUN_ON_START`, or all jobs with a time schedule with `--run-on-start`, also run
+    // once now. Jobs that are due already, like to catch up, don't run twice.
+    let now = Local::now();
+    let due: Vec<JobHandle> = (jobs.lock().unwrap().iter())
+        .filter(|job_handle| {
+            let job = job_handle.lock().unwrap();
+            (job.run_on_start || (run_on_start && job.trigger.is_none()))
+                && job.next.is_none_or(|next| next > now)
+        })
+        .cloned()
+        .collect();
+    if !due.is_empty() {
+        info!("running {} job(s) on start", due.len());
+        dispatch_all(&dispatcher, due);
+    }
+
+    #[cfg(feature = "notify")]
+    notify::ready();
+    let mut zone = Zone::new(Local::now());
+    let mut clock = Clock::new(Local::now());
+    loop {
+        let now = Local::now();
+        #[cfg(feature = "notify")]
+        notify::watchdog();
+
+        // Bounded instances exit like on SIGTERM, letting runs in progress finish.
+        if until.is_some_and(|until| now >= until) {
+            stop::shutdown(&dispatcher, "reached --until", shutdown_timeout);
+        }
+        if let Some(max) = MAX_RUNS.get() {
+            let finished = FINISHED_RUNS.load(Ordering::Relaxed);
+            if finished >= *max {
+                let reason = format!("finished {} run(s)", finished);
+                stop::shutdown(&dispatcher, &reason, shutdown_timeout);
+            }
+        }
+
+        if let Some((before, after)) = zone.check(now) {
+            info!(
+                "time zone changed from {} to {}, rescheduling",
+                before, after
+            );
+            rezone(&jobs, now);
+        }
+
+        // Occurrences skipped by a jump forward run once, like after a pause. After a jump back,
+        // wall-clock times wait for the clock to catch up, rather than running twice.
+        if let Some(step) = clock.check(now) {
+            let secs = step.num_seconds();
+            if clock::is_jump(step) {
+                match secs {
+                    0.. => warning!("clock jumped forward by {}s, missed runs run once", secs),
+                    _ => warning!("clock jumped back by {}s, rescheduling intervals", -secs),
+                }
+                Event::new("clock_jump")
+                    .num("jump_ms", step.num_milliseconds())
+                    .emit();
+                if secs < 0 {
+                    rewind(&jobs, now);
+                }
+            }
+            shift(&jobs, step);
+        }
+
+        check_overdue(&jobs);
+
+        // Find the minimum of all jobs' `next` time.
+        // Max sleep is 1 minute, to account for any clock jumps.
+        let mut next_min = until.map_or(now + Duration::minutes(1), |until| {
+            until.min(now + Duration::minutes(1))
+        });
+        let mut due = Vec::new();
+        let mut expired = Vec::new();
+        let mut jobs = jobs.lock().unwrap();
+        for job_handle in jobs.iter() {
+            let mut job = job_handle.lock().unwrap();
+
+            // It's possible a job may not ever run again.
+            let Some(next) = job.next else {
+                if job.upcoming.expired() {
+                    info!([job.id] "schedule expired, retiring job");
+                    expired.push(job.id);
+                } else {
+                    trace!([job.id] "no next run");
+                }
+                continue;
+            };
+
+            // If in the future, aggregate in `next_min` .
+            if now < next {
+                trace!([job.id] "not due until {}", next.format("%Y-%m-%d %H:%M:%S%.3f %:z"));
+                next_min = next.min(next_min);
+                continue;
+            }
+
+            // Otherwise, the job needs to run. Dispatch after releasing the lock.
+            due.push(job_handle.clone());
+            job.occurrence = job.upcoming.scheduled();
+
+            // Iterate the schedule until we find the next time in the future.
+            while job.next.filter(|next| now >= *next).is_some() {
+                job.next = job.upcoming.next();
+            }
+            match job.next {
+                Some(after) => debug!(
+                    [job.id] "due at {}, next run at {}",
+                    next.format("%Y-%m-%d %H:%M:%S%.3f %:z"),
+                    after.format("%Y-%m-%d %H:%M:%S%.3f %:z")
+                ),
+                None => debug!(
+                    [job.id] "due at {}, no next run",
+                    next.format("%Y-%m-%d %H:%M:%S%.3f %:z")
+                ),
+            }
+            if let Some(next) = job.next {
+                next_min = next.min(next_min);
+            }
+            job.emit_scheduled();
+        }
+
+        if !expired.is_empty() {
+            jobs.retain(|job_handle| !expired.contains(&job_handle.lock().unwrap().id));
+        }
+        drop(jobs);
+
+        dispatch_all(&dispatcher, due);
+
+        // Delay until the aggregate `next_min` time. Dispatch may have taken a while, so this can
+        // already be in the past.
+        let delay = (next_min - Local::now()).to_std().unwrap_or_default();
+        #[cfg(feature = "notify")]
+        let delay = notify::watchdog_interval().map_or(delay, |interval| delay.min(interval));
+        debug!("sleeping for {:.3}s", delay.as_secs_f64());
+        dispatcher.sleep(delay);
+    }
+}
+
+/// Report jobs with `EXPECT_WITHIN` that didn't succeed in time, once until they do, and run their
+/// `ON_FAILURE` hook in the background.
+fn check_overdue(jobs: &JobList) {
+    for job_handle in jobs.lock().unwrap().iter() {
+        let mut job = job_handle.lock().unwrap();
+        let Some(within) = job.expect_within.filter(|_| !job.overdue && !job.retired) else {
+            continue;
+        };
+        if within
+            .to_std()
+            .is_ok_and(|within| job.expected_since.elapsed() < within)
+        {
+            continue;
+        }
+        job.overdue = true;
+        let message = format!("no successful run in {}s", within.num_seconds());
+        warning!([job.id] "overdue: {}", message);
+        Event::new("job_overdue")
+            .num("job", job.id as i64)
+            .num("expect_within_ms", within.num_milliseconds())
+            .emit();
+        let Some(hook) = job.on_failure.as_deref() else {
+            continue;
+        };
+        let account = match job.user.as_deref().map(Account::lookup).transpose() {
+            Ok(account) => account,
+            Err(err) => {
+                error!([job.id] "hook failed: {}", err);
+                continue;
+            }
+        };
+        let mut command = match job.hook_command(hook, account.as_ref()) {
+            Ok(command) => command,
+            Err(err) => {
+                error!([job.id] "hook failed: {}", err);
+                continue;
+            }
+        };
+        command
+            .env("POCKETCRON_ERROR", &message)
+            .env("POCKETCRON_OVERDUE", within.num_seconds().to_string());
+        let (label, hook) = (job.id.into(), hook.to_owned());
+        let timeout = job.timeout;
+        thread::spawn(move || run_hook(label, &hook, command, timeout));
+    }
+}
+
+/// Resolve the next run of jobs with wall-clock schedules again, after the time zone changed.
+fn rezone(jobs: &JobList, now: DateTime<Local>) {
+    for job_handle in jobs.lock().unwrap().iter() {
+        let mut job = job_handle.lock().unwrap();
+        // Runs that are due already aren't moved.
+        let Some(next) = job.next.filter(|next| *next > now) else {
+            continue;
+        };
+        if !job.upcoming.rezone(now) {
+            continue;
+        }
+        job.next = job.upcoming.next();
+        if job.next != Some(next) {
+            match job.next {
+                Some(new) => info!(
+                    [job.id] "next run moved from {} to {}",
+                    next.format("%Y-%m-%d %H:%M:%S %:z"),
+                    new.format("%Y-%m-%d %H:%M:%S %:z")
+                ),
+                None => info!([job.id] "no next run anymore"),
+            }
+            job.emit_scheduled();
+        }
+    }
+}
+
+/// Bring the next runs of interval jobs back, after the wall clock was set back.
+fn rewind(jobs: &JobList, now: DateTime<Local>) {
+    for job_handle in jobs.lock().unwrap().iter() {
+        let mut job = job_handle.lock().unwrap();
+        let Some(next) = job.next.filter(|_| !job.monotonic) else {
+            continue;
+        };
+        if let Some(new) = job.upcoming.rewind(now) {
+            info!(
+                [job.id] "next run moved from {} to {}",
+                next.format("%Y-%m-%d %H:%M:%S %:z"),
+                new.format("%Y-%m-%d %H:%M:%S %:z")
+            );
+            job.next = Some(new);
+            job.emit_scheduled();
+        }
+    }
+}
+
+/// Move the next run of jobs with `CLOCK=monotonic` by how far the wall clock was stepped, so
+/// their intervals keep their pace.
+fn shift(jobs: &JobList, step: Duration) {
+    for job_handle in jobs.lock().unwrap().iter() {
+        let mut job = job_handle.lock().unwrap();
+        let Some(next) = job.next.filter(|_| job.monotonic) else {
+            continue;
+        };
+        if let Some(new) = job.upcoming.shift(step) {
+            if clock::is_jump(step) {
+                info!(
+                    [job.id] "next run moved from {} to {}",
+                    next.format("%Y-%m-%d %H:%M:%S %:z"),
+                    new.format("%Y-%m-%d %H:%M:%S %:z")
+                );
+            }
+            job.next = Some(new);
+            job.emit_scheduled();
+        }
+    }
+}
+
+/// Dispatch jobs that are due at the same time.
+fn dispatch_all(dispatcher: &Arc<Dispatcher>, mut due: Vec<JobHandle>) {
+    // Higher priority jobs go first. The sort is stable, so file order is kept otherwise.
+    due.sort_by_key(|job_handle| Reverse(job_handle.lock().unwrap().priority));
+    for job_handle in due {
+        dispatcher.dispatch(job_handle);
+    }
+}
+
+/// Dispatch all jobs with a trigger, and return the number of jobs.
+fn fire(jobs: &JobList, dispatcher: &Arc<Dispatcher>, trigger: &Trigger) -> usize {
+    let due: Vec<JobHandle> = jobs
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|job_handle| job_handle.lock().unwrap().trigger.as_deref() == Some(trigger))
+        .cloned()
+        .collect();
+    let count = due.len();
+    if count == 0 {
+        return 0;
+    }
+    info!("trigger {} fired, dispatching {} job(s)", trigger, count);
+    Event::new("triggered")
+        .str("trigger", &trigger.to_string())
+        .num("jobs", count as i64)
+        .emit();
+    dispatch_all(dispatcher, due);
+    count
+}
+
+/// Dispatch the jobs that run `@after` a job, once a run of it succeeded.
+fn fire_after(jobs: &JobList, dispatcher: &Arc<Dispatcher>, job_handle: &JobHandle) {
+    // Jobs are locked one at a time, so that runs finishing at the same time can't deadlock.
+    let after: Vec<(JobHandle, String)> = jobs
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|other| match other.lock().unwrap().trigger.as_deref() {
+            Some(Trigger::After(name)) => Some((other.clone(), name.clone())),
+            _ => None,
+        })
+        .collect();
+    if after.is_empty() {
+        return;
+    }
+    let (trigger, due) = {
+        let job = job_handle.lock().unwrap();
+        let due: Vec<JobHandle> = after
+            .into_iter()
+            .filter(|(_, name)| job.is_named(name))
+            .map(|(other, _)| other)
+            .collect();
+        (Trigger::After(job.name()), due)
+    };
+    if due.is_empty() {
+        return;
+    }
+    info!(
+        "trigger {} fired, dispatching {} job(s)",
+        trigger,
+        due.len()
+    );
+    Event::new("triggered")
+        .str("trigger", &trigger.to_string())
+        .num("jobs", due.len() as i64)
+        .emit();
+    dispatch_all(dispatcher, due);
+}
+
+/// Warn about `@after` jobs that refer to no job, or that would trigger themselves through a
+/// chain of jobs, which then never ends.
+fn check_after(jobs: &[JobHandle]) {
+    let target = |name: &str| {
+        jobs.iter()
+            .position(|job_handle| job_handle.lock().unwrap().is_named(name))
+    };
+    let after = |index: usize| match jobs[index].lock().unwrap().trigger.as_deref() {
+        Some(Trigger::After(name)) => Some(name.clone()),
+        _ => None,
+    };
+    for start in 0..jobs.len() {
+        let Some(name) = after(start) else {
+            continue;
+        };
+        let id = jobs[start].lock().unwrap().id;
+        let Some(mut index) = target(&name) else {
+            warning!([id] "@after {}: no such job", name);
+            continue;
+        };
+        // A chain longer than the number of jobs must come around.
+        for _ in 0..jobs.len() {
+            if index == start {
+                warning!([id] "@after {}: runs after itself", name);
+                break;
+            }
+            match after(index).and_then(|name| target(&name)) {
+                Some(next) => index = next,
+                None => break,
+            }
+        }
+    }
+}
+
+/// Entry point of the `check` command, which parses crontabs and reports all errors in them,
+/// without running anything.
+fn check_main() -> ! {
+    let mut compat = Compat::Default;
+    let mut env_jobs = false;
+    let mut crontabs = Vec::new();
+    let mut args = ::std::env::args_os().skip(2);
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--compat") => {
+                let value = args.next().and_then(|value| value.into_string().ok());
+                match value.as_deref().map(str::parse) {
+                    Some(Ok(value)) => compat = value,
+                    Some(Err(err)) => {
+                        eprintln!("{}", err);
+                        exit(1);
+                    }
+                    None => {
+                        eprintln!("--compat requires a value");
+                        exit(1);
+                    }
+                }
+            }
+            Some("--env-jobs") => env_jobs = true,
+            Some(arg) if arg.starts_with('-') && arg != STDIN => {
+                eprintln!("{}", USAGE);
+                exit(1);
+            }
+            _ => crontabs.push(arg),
+        }
+    }
+    if crontabs.is_empty() && !env_jobs {
+        eprintln!("{}", USAGE);
+        exit(1);
+    }
+    let options = LoadOptions {
+        jitter_seed: String::new(),
+        compat,
+        shard: None,
+        default_timeout: None,
+        shell: None,
+        env_jobs,
+        lenient: false,
+        spool: None,
+        only_failures: false,
+    };
+    let Some(specs) = read_crontabs(&crontabs, &options) else {
+        exit(1);
+    };
+    println!(
+        "checked {} crontab(s) with {} job(s)",
+        crontabs.len(),
+        specs.len()
+    );
+    exit(0);
+}
+
+/// Entry point of commands that are sent to a running pocketcron through the control socket.
+fn control_main(command: &str) -> ! {
+    let mut control_socket = ::std::env::var_os(CONTROL_SOCKET_ENV).map(PathBuf::from);
+    let mut name = None;
+    let mut pause = false;
+    let mut signal = None;
+    let mut count = None;
+    let mut args = ::std::env::args().skip(2);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--control-socket" => control_socket = args.next().map(PathBuf::from),
+            "--pause" if command == "abort" => pause = true,
+            "--job" if command == "tail" => match args.next() {
+                Some(value) => name = Some(value),
+                None => {
+                    eprintln!("--job requires a name");
+                    exit(1);
+                }
+            },
+            "-s" if command == "kill" => match args.next() {
+                Some(value) => signal = Some(value),
+                None => {
+                    eprintln!("-s requires a signal");
+                    exit(1);
+                }
+            },
+            "-n" if command == "next" => match args.next() {
+                Some(value) => count = Some(value),
+                None => {
+                    eprintln!("-n requires a count");
+                    exit(1);
+                }
+            },
+            _ if matches!(
+                command,
+                "trigger" | "kill" | "restart" | "resume" | "pause" | "run" | "history"
+            ) && name.is_none()
+                && !arg.starts_with('-') =>
+            {
+                name = Some(arg)
+            }
+            _ => {
+                eprintln!("{}", USAGE);
+                exit(1);
+            }
+        }
+    }
+    let request = match (command, name) {
+        ("trigger", Some(name)) => format!("trigger {}", name),
+        ("kill", Some(name)) => match signal {
+            Some(signal) => format!("kill {} {}", name, signal),
+            None => format!("kill {}", name),
+        },
+        ("abort", None) if pause => "abort pause".to_owned(),
+        ("next", None) => match count {
+            Some(count) => format!("next {}", count),
+            None => "next".to_owned(),
+        },
+        ("abort" | "resume" | "list" | "status" | "stats" | "tail", None) => command.to_owned(),
+        ("tail" | "restart" | "resume" | "pause" | "run" | "history", Some(name)) => {
+            format!("{} {}", command, name)
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(1);
+        }
+    };
+    let Some(path) = control_socket else {
+        eprintln!("{}", USAGE);
+        exit(1);
+    };
+    if command == "tail" {
+        let result = control::follow(&path, &request, |line| {
+            if let Some(line) = describe_event(line) {
+                println!("{}", line);
+            }
+        });
+        match result {
+            Ok(()) => {
+                eprintln!("connection closed");
+                exit(0);
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                exit(1);
+            }
+        }
+    }
+    match control::request(&path, &request) {
+        Ok(message) => {
+            println!("{}", message);
+            exit(0);
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    }
+}
+
+/// Describe an event for `tail`, if it is about a run.
+fn describe_event(line: &str) -> Option<String> {
+    let event: serde_json::Value = serde_json::from_str(line).ok()?;
+    let time = DateTime::parse_from_rfc3339(event["time"].as_str()?).ok()?;
+    let job = event["job"].as_u64()?;
+    let message = match event["event"].as_str()? {
+        "job_started" => format!("started, pid {}", event["pid"]),
+        "job_output" => format!("{}: {}", event["stream"].as_str()?, event["line"].as_str()?),
+        "job_finished" => {
+            let duration = event["duration_ms"].as_f64().unwrap_or(0.0) / 1000.0;
+            if let Some(code) = event["code"].as_i64() {
+                format!("finished with code {} after {:.1}s", code, duration)
+            } else if let Some(signal) = event["signal"].as_i64() {
+                format!("killed by signal {} after {:.1}s", signal, duration)
+            } else {
+                format!(
+                    "failed: {}",
+                    event["error"].as_str().unwrap_or("unknown error")
+                )
+            }
+        }
+        "job_skipped" => format!("skipped: {}", event["reason"].as_str()?),
+        "job_timeout" => format!(
+            "timed out after {:.1}s",
+            event["timeout_ms"].as_f64()? / 1000.0
+        ),
+        _ => return None,
+    };
+    Some(format!("{} [{}] {}", time.format("%H:%M:%S"), job, message))
+}
+
+/// Get the hostname, or an empty string if it can't be determined.
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // Safety: the length passed is that of the buffer.
+    let res = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+    if res != 0 {
+        return String::new();
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// Load the jobs in crontabs at startup, scheduled after `now`.
+fn load_jobs(
+    jobs: &mut Vec<JobHandle>,
+    crontabs: &[OsString],
+    options: &LoadOptions,
+    now: DateTime<Local>,
+) {
+    let Some(specs) = read_crontabs(crontabs, options) else {
+        exit(1);
+    };
+    // Jobs that can't run as their user fail every run. After a reload, that is only logged.
+    let mut users: Vec<&str> = specs
+        .iter()
+        .filter_map(|spec| spec.user.as_deref())
+        .collect();
+    users.sort_unstable();
+    users.dedup();
+    let mut ok = true;
+    for user in users {
+        if let Err(err) = Account::lookup(user) {
+            error!("{}", err);
+            ok = false;
+        }
+    }
+    if !ok && !options.lenient {
+        exit(1);
+    }
+    let capped = |spec: &JobSpec| spec.limits.memory_max.is_some() || spec.limits.cpu_max.is_some();
+    if !cgroup::is_enabled() && specs.iter().any(capped) {
+        warning!("MEMORY_MAX and CPU_MAX have no effect without --cgroup-root");
+    }
+    for spec in specs {
+        if options.includes(&spec) {
+            let id = jobs.len() + 1;
+            jobs.push(new_job(spec, id, false, options, now));
+        }
+    }
+    STARTED.store(true, Ordering::Relaxed);
+}
+
+/// Read and parse crontabs, logging errors. Returns the jobs in all crontabs, or `None` if there
+/// were errors in any, unless they are skipped with `--lenient`.
+fn read_crontabs(crontabs: &[OsString], options: &LoadOptions) -> Option<Vec<JobSpec>> {
+    let mut specs = Vec::new();
+    let mut ok = true;
+    let mut files = read_crontab_files(crontabs);
+    if options.env_jobs {
+        files.extend(env_crontabs());
+    }
+    let spool = options.spool.as_deref().map(spool_crontabs);
+    let files = (files.into_iter().map(|(path, input)| (path, input, None)))
+        .chain(spool.into_iter().flatten());
+    for (path, input, user) in files {
+        let input = match input {
+            Ok(input) => input,
+            Err(err) => {
+                error!("{}: read failed: {}", path.display(), err);
+                match &user {
+                    Some(user) if !options.lenient => skipped_spool(&path, user),
+                    _ => ok = false,
+                }
+                continue;
+            }
+        };
+        let (expanded, errors) = match user {
+            // Users can't include files of others.
+            Some(_) => {
+                let (input, errors) = check_spool_crontab(&path, &input);
+                (
+                    vec![(Expanded::plain(&path, &input), Compat::Default)],
+                    errors,
+                )
+            }
+            None => expand_crontab(&path, &input, options.compat),
+        };
+        for err in &errors {
+            error!("{}", err);
+        }
+        let mut file_ok = errors.is_empty();
+        let mut file_specs = Vec::new();
+        for (expanded, compat) in expanded {
+            let (mut crontab, errors) = parse_crontab_lenient(&expanded.input, compat);
+            for err in &errors {
+                error!(
+                    "{}: error: {}",
+                    expanded.location(err.line, err.column),
+                    err.message
+                );
+            }
+            file_ok &= errors.is_empty();
+            if let Some(user) = &user {
+                for spec in &mut crontab {
+                    spool_job(spec, user);
+                }
+            }
+            file_specs.extend(crontab);
+        }
+        // Errors in the crontab of one user don't keep the jobs of others from loading.
+        match &user {
+            Some(user) if !file_ok && !options.lenient => skipped_spool(&path, user),
+            _ => {
+                ok &= file_ok;
+                specs.extend(file_specs);
+            }
+        }
+    }
+    if !ok && options.lenient {
+        warning!("skipped crontabs and lines with errors, with --lenient");
+        return Some(specs);
+    }
+    ok.then_some(specs)
+}
+
+/// The crontabs to parse from a file, with their includes, and how to parse them. A config file
+/// has a crontab for every job, and jobs with a user are parsed like system crontabs, and others
+/// like user crontabs.
+fn expand_crontab(
+    path: &Path,
+    input: &str,
+    compat: Compat,
+) -> (Vec<(Expanded, Compat)>, Vec<String>) {
+    if !config::is_config(path) {
+        let (expanded, errors) = include::expand(path, input);
+        return (vec![(expanded, compat)], errors);
+    }
+    let (jobs, errors) = config::translate(path, input);
+    let crontabs = jobs
+        .into_iter()
+        .map(|job| match (job.has_user, compat) {
+            (true, _) => (job.crontab, Compat::System),
+            (false, Compat::System) => (job.crontab, Compat::Default),
+            (false, compat) => (job.crontab, compat),
+        })
+        .collect();
+    (crontabs, errors)
+}
+
+/// Read the contents of crontabs. A directory stands for the crontabs in it, in order of their
+/// names, like `/etc/cron.d`. Hidden files and editor backups are skipped, and `-` stands for
+/// standard input.
+fn read_crontab_files(crontabs: &[OsString]) -> Vec<(PathBuf, io::Result<String>)> {
+    let mut files = Vec::new();
+    for path in crontabs {
+        if path == STDIN {
+            files.push((PathBuf::from("<stdin>"), read_stdin()));
+            continue;
+        }
+        let path = PathBuf::from(path);
+        if !path.is_dir() {
+            let input = fs::read_to_string(&path);
+            files.push((path, input));
+            continue;
+        }
+        match dir_crontabs(&path) {
+            Ok(entries) => {
+                for entry in entries {
+                    let input = fs::read_to_string(&entry);
+                    files.push((entry, input));
+                }
+            }
+            Err(err) => files.push((path, Err(err))),
+        }
+    }
+    files
+}
+
+/// The crontabs in a directory, in order of their names. Hidden files, editor backups and
+/// subdirectories are skipped.
+fn dir_crontabs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.retain(|entry| {
+        let name = entry.file_name().unwrap_or_default().to_string_lossy();
+        !name.starts_with('.') && !name.ends_with('~') && !name.ends_with(".swp")
+    });
+    entries.sort();
+    // Subdirectories aren't crontabs. Symlinks to files are.
+    entries.retain(|entry| entry.is_file());
+    Ok(entries)
+}
+
+/// The crontabs of users in a spool directory, like `/var/spool/cron/crontabs`, with `--spool`,
+/// and the users they are named after.
+fn spool_crontabs(dir: &Path) -> Vec<(PathBuf, io::Result<String>, Option<String>)> {
+    let entries = match dir_crontabs(dir) {
+        Ok(entries) => entries,
+        Err(err) => return vec![(dir.to_owned(), Err(err), None)],
+    };
+    entries
+        .into_iter()
+        .map(|path| {
+            let user = path.file_name().unwrap_or_default().to_string_lossy();
+            let user = user.into_owned();
+            let input = read_spool_crontab(&path, &user);
+            (path, input, Some(user))
+        })
+        .collect()
+}
+
+/// Read the crontab of a user in the spool. It must be owned by the user, so that nobody can
+/// add jobs for others.
+fn read_spool_crontab(path: &Path, user: &str) -> io::Result<String> {
+    let account = Account::lookup(user).map_err(io::Error::other)?;
+    let file = fs::File::open(path)?;
+    let owner = file.metadata()?.uid();
+    if owner != account.uid {
+        return Err(io::Error::other(format!(
+            "owned by uid {} instead of {}",
+            owner, user
+        )));
+    }
+    io::read_to_string(file)
+}
+
+/// Settings that pocketcron applies itself, as root, which users can't have in their crontabs in
+/// the spool: files it opens or locks for runs, limits it sets before switching users, and the
+/// variables of its own environment it passes on.
+const SPOOL_DENIED: [&str; 7] = [
+    "LOG_FILE",
+    "FLOCK",
+    "LOCK",
+    "NICE",
+    "RLIMIT_CPU",
+    "RLIMIT_AS",
+    "ENV_KEEP",
+];
+
+/// Check that the crontab of a user in the spool only has settings that users may change. Lines
+/// with other settings are emptied, so they have no effect with `--lenient`.
+fn check_spool_crontab(path: &Path, input: &str) -> (String, Vec<String>) {
+    let mut checked = String::with_capacity(input.len());
+    let mut errors = Vec::new();
+    for (index, raw) in input.lines().enumerate() {
+        let line = raw.trim();
+        let denied = (!line.starts_with('#'))
+            .then(|| parse_variable(line))
+            .flatten()
+            .filter(|(name, _)| SPOOL_DENIED.contains(name));
+        match denied {
+            Some((name, _)) => errors.push(format!(
+                "{}:{}:{}: error: {} is not allowed in crontabs in the spool",
+                path.display(),
+                index + 1,
+                name.as_ptr() as usize - raw.as_ptr() as usize + 1,
+                name
+            )),
+            None => checked.push_str(raw),
+        }
+        checked.push('\n');
+    }
+    (checked, errors)
+}
+
+/// Environment of the jobs of users in the spool, like cron sets it, with `HOME`, `USER` and
+/// `LOGNAME` of the user. That of pocketcron isn't passed on, since it may hold secrets.
+const SPOOL_ENV: [(&str, &str); 2] = [("SHELL", "/bin/sh"), ("PATH", "/usr/bin:/bin")];
+
+/// Make a job of the crontab of a user in the spool run as that user, in the environment of the
+/// user.
+fn spool_job(spec: &mut JobSpec, user: &str) {
+    spec.user = Some(user.to_owned());
+    spec.env_keep = Some(Vec::new());
+    for (name, value) in SPOOL_ENV.into_iter().rev() {
+        if !spec.env.iter().any(|(other, _)| other == name) {
+            spec.env.insert(0, (name.to_owned(), value.to_owned()));
+        }
+    }
+}
+
+/// Log that the crontab of a user in the spool was skipped, because of errors in it.
+fn skipped_spool(path: &Path, user: &str) {
+    warning!(
+        "{}: skipped the crontab of {} with errors, use --lenient to skip only those lines",
+        path.display(),
+        user
+    );
+}
+
+/// Interval of checking the spool for changes, with `--spool`.
+const SPOOL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Prefix of the environment variables that hold jobs, with `--env-jobs`.
+const ENV_JOB_PREFIX: &str = "POCKETCRON_JOB_";
+
+/// The `POCKETCRON_JOB_*` variables of pocketcron, as crontabs of their own, in order of their
+/// suffixes, with numbers in numeric order, like `POCKETCRON_JOB_2` before `POCKETCRON_JOB_10`.
+/// A value may hold variables before the job, on lines of their own.
+fn env_crontabs() -> Vec<(PathBuf, io::Result<String>)> {
+    let mut vars: Vec<(String, String)> = ::std::env::vars()
+        .filter(|(name, _)| name.len() > ENV_JOB_PREFIX.len() && name.starts_with(ENV_JOB_PREFIX))
+        .collect();
+    vars.sort_by_cached_key(|(name, _)| {
+        let suffix = name[ENV_JOB_PREFIX.len()..].to_owned();
+        (suffix.parse::<u64>().unwrap_or(u64::MAX), suffix)
+    });
+    vars.into_iter()
+        .map(|(name, value)| (PathBuf::from(name), Ok(value)))
+        .collect()
+}
+
+/// Read standard input as a crontab. It's only read once, so reloads keep the jobs from it.
+fn read_stdin() -> io::Result<String> {
+    static INPUT: OnceLock<Result<String, (io::ErrorKind, String)>> = OnceLock::new();
+    INPUT
+        .get_or_init(|| {
+            io::read_to_string(io::stdin()).map_err(|err| (err.kind(), err.to_string()))
+        })
+        .clone()
+        .map_err(|(kind, message)| io::Error::new(kind, message))
+}
+
+/// Reload the local crontabs when their contents change, with `--watch-crontabs`.
+///
+/// The directories of the crontabs are watched, rather than the files themselves, so that files
+/// that are replaced are still followed, like a Kubernetes ConfigMap that swaps a symlink.
+fn watch_crontab_dirs(
+    jobs: &JobList,
+    dispatcher: &Arc<Dispatcher>,
+    crontabs: &[OsString],
+    options: &Arc<LoadOptions>,
+) -> Result<(), (PathBuf, io::Error)> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    for path in crontabs.iter().filter(|path| *path != STDIN) {
+        let path = Path::new(path);
+        let dir = if path.is_dir() {
+            path
+        } else {
+            path.parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(Path::new("."))
+        };
+        if !dirs.iter().any(|other| other == dir) {
+            dirs.push(dir.to_owned());
+        }
+    }
+    // Other files in the directories change too, so only reload when the crontabs did.
+    let last = Arc::new(Mutex::new(crontabs_hash(crontabs, options)));
+    for dir in dirs {
+        let watch = Watch {
+            spec: dir.display().to_string(),
+            path: dir.clone(),
+            pattern: None,
+            debounce: Duration::seconds(1),
+        };
+        let jobs = jobs.clone();
+        let dispatcher = dispatcher.clone();
+        let crontabs = crontabs.to_vec();
+        let options = options.clone();
+        let last = last.clone();
+        watch::watch(watch, move || {
+            let hash = crontabs_hash(&crontabs, &options);
+            if mem::replace(&mut *last.lock().unwrap(), hash) != hash {
+                info!("crontabs changed");
+                reload(&jobs, &dispatcher, &crontabs, &options);
+            }
+        })
+        .map_err(|err| (dir, err))?;
+    }
+    Ok(())
+}
+
+/// Reload the crontabs when those in the spool change, checking every minute like cron does,
+/// with `--spool`.
+fn rescan_spool(
+    jobs: &JobList,
+    dispatcher: &Arc<Dispatcher>,
+    crontabs: &[OsString],
+    options: &Arc<LoadOptions>,
+) {
+    let jobs = jobs.clone();
+    let dispatcher = dispatcher.clone();
+    let crontabs = crontabs.to_vec();
+    let options = options.clone();
+    thread::spawn(move || {
+        let mut last = crontabs_hash(&crontabs, &options);
+        loop {
+            thread::sleep(SPOOL_INTERVAL);
+            let hash = crontabs_hash(&crontabs, &options);
+            if mem::replace(&mut last, hash) != hash {
+                info!("spool changed");
+                reload(&jobs, &dispatcher, &crontabs, &options);
+            }
+        }
+    });
+}
+
+/// Hash of the contents of crontabs, and those in the spool, or of the errors reading them.
+fn crontabs_hash(crontabs: &[OsString], options: &LoadOptions) -> u64 {
+    let mut hasher = Fnv1a::default();
+    let files = (read_crontab_files(crontabs).into_iter()).map(|(path, input)| (path, input, None));
+    let spool = options.spool.as_deref().map(spool_crontabs);
+    for (path, input, user) in files.chain(spool.into_iter().flatten()) {
+        hasher.write(path.as_os_str().as_bytes());
+        hasher.write_u8(0);
+        // Included crontabs count too.
+        let expand = user.is_none() && !config::is_config(&path);
+        let input = input.map(|input| match expand {
+            true => {
+                let (expanded, errors) = include::expand(&path, &input);
+                expanded.input + &errors.join("\n")
+            }
+            false => input,
+        });
+        match input {
+            Ok(data) => {
+                hasher.write_u8(1);
+                hasher.write_usize(data.len());
+                hasher.write(data.as_bytes());
+            }
+            Err(err) => {
+                hasher.write_u8(0);
+                hasher.write(err.to_string().as_bytes());
+            }
+        }
+    }
+    hasher.finish()
+}
+
+/// Read the local crontabs again, and replace the jobs from them, on SIGHUP. If any crontab has
+/// errors, the current jobs stay in place.
+fn reload(jobs: &JobList, dispatcher: &Dispatcher, crontabs: &[OsString], options: &LoadOptions) {
+    info!("reloading crontabs");
+    let Some(specs) = read_crontabs(crontabs, options) else {
+        error!("reload failed, keeping the current jobs");
+        return;
+    };
+    let mut jobs = jobs.lock().unwrap();
+    let count = replace_jobs(&mut jobs, specs, false, options);
+    info!(
+        "reloaded {} crontab(s) with {} job(s)",
+        crontabs.len(),
+        count
+    );
+    Event::new("reload")
+        .num("crontabs", crontabs.len() as i64)
+        .num("jobs", jobs.len() as i64)
+        .emit();
+    dispatcher.wake();
+}
+
+/// Create a job from its description.
+fn new_job(
+    spec: JobSpec,
+    id: usize,
+    remote: bool,
+    options: &LoadOptions,
+    now: DateTime<Local>,
+) -> JobHandle {
+    Arc::new(Mutex::new(Job::new(spec, id, remote, options, now)))
+}
+
+/// Replace the jobs from a source, local crontabs or the agent server, with those in a reloaded
+/// crontab. Returns the number of jobs that are now loaded from it.
+///
+/// Jobs with the same line as before are kept as they are, so that their schedule carries over.
+/// Changed jobs are updated in place, so they keep their number, and a run in progress still
+/// prevents overlap. Removed jobs finish a run in progress, but don't run again.
+fn replace_jobs(
+    jobs: &mut Vec<JobHandle>,
+    specs: Vec<JobSpec>,
+    remote: bool,
+    options: &LoadOptions,
+) -> usize {
+    let specs: Vec<JobSpec> = specs
+        .into_iter()
+        .filter(|spec| options.includes(spec))
+        .collect();
+    let mut next_id = jobs
+        .iter()
+        .map(|job_handle| job_handle.lock().unwrap().id)
+        .max()
+        .unwrap_or(0)
+        + 1;
+    let (old, mut rest): (Vec<JobHandle>, Vec<JobHandle>) = jobs
+        .drain(..)
+        .partition(|job_handle| job_handle.lock().unwrap().remote == remote);
+    // Local jobs go first, like at startup.
+    if remote {
+        jobs.append(&mut rest);
+    }
+
+    let matches = {
+        let old: Vec<_> = old
+            .iter()
+            .map(|job_handle| job_handle.lock().unwrap())
+            .collect();
+        let keys: Vec<JobKey> = old.iter().map(|job| job.key()).collect();
+        match_jobs(&keys, &specs)
+    };
+    let mut kept = vec![false; old.len()];
+    let now = Local::now();
+    let count = specs.len();
+    for (spec, result) in specs.into_iter().zip(matches) {
+        match result {
+            Match::Same(index) => {
+                kept[index] = true;
+                // The name is in a comment, so it may have changed on its own.
+                let mut job = old[index].lock().unwrap();
+                if job.given_name.as_deref() != spec.name.as_deref() {
+                    log::set_name(job.id, spec.name.as_deref());
+                    job.given_name = spec.name.map(String::into_boxed_str);
+                }
+                drop(job);
+                jobs.push(old[index].clone());
+            }
+            Match::Changed(index) => {
+                kept[index] = true;
+                let job_handle = old[index].clone();
+                let mut job = job_handle.lock().unwrap();
+                let key = spec.state_key();
+                if let Some((old_key, key)) = job.state_key.as_deref().zip(key) {
+                    if old_key != key {
+                        state::update(|state| {
+                            if let Some(job_state) = state.jobs.remove(old_key) {
+                                state.jobs.entry(key.to_owned()).or_insert(job_state);
+                            }
+                        });
+                    }
+                }
+                let mut new = Job::new(spec, job.id, remote, options, now);
+                new.runs = mem::take(&mut job.runs);
+                new.run_count = job.run_count;
+                new.rerun = job.rerun;
+                new.occurrence = job.occurrence;
+                new.last_start = job.last_start;
+                #[cfg(feature = "api")]
+                {
+                    new.last_result = job.last_result.take();
+                }
+                new.failures = job.failures;
+                new.expected_since = job.expected_since;
+                new.overdue = job.overdue;
+                new.enabled = job.enabled;
+                *job = new;
+                info!([job.id] "changed: {}", job.source);
+                job.emit_scheduled();
+                drop(job);
+                jobs.push(job_handle);
+            }
+            Match::New => {
+                let job_handle = new_job(spec, next_id, remote, options, now);
+                next_id += 1;
+                job_handle.lock().unwrap().emit_scheduled();
+                jobs.push(job_handle);
+            }
+        }
+    }
+    jobs.append(&mut rest);
+    for (job_handle, _) in old.iter().zip(kept).filter(|(_, kept)| !kept) {
+        let mut job = job_handle.lock().unwrap();
+        job.retired = true;
+        if job.is_running() {
+            info!([job.id] "removed, retiring after the current run");
+        } else {
+            info!([job.id] "removed");
+        }
+    }
+    check_after(jobs);
+    count
+}
+
+/// How a run ended.
+enum Outcome {
+    Code(i32),
+    Signal(i32),
+    Error(String),
+}
+
+/// Run a job to completion on the current thread.
+fn run_job(job_handle: &JobHandle) {
+    let (id, run, run_id, label, lock, flock) = {
+        let mut job = job_handle.lock().unwrap();
+
+        // A removed job may still be queued.
+        if job.retired {
+            Event::new("job_skipped")
+                .num("job", job.id as i64)
+                .str("reason", "retired")
+                .emit();
+            return;
+        }
+
+        // Prevent overlap, unless it's allowed.
+        if job.is_running() && job.overlap != Overlap::Allow {
+            Event::new("job_skipped")
+                .num("job", job.id as i64)
+                .str("reason", "running")
+                .emit();
+            return;
+        }
+        let occurrence = job.occurrence.take();
+        if !lease::is_leader() {
+            Event::new("job_skipped")
+                .num("job", job.id as i64)
+                .str("reason", "standby")
+                .emit();
+            drop(job);
+            dispatch::reschedule(job_handle);
+            return;
+        }
+
+        if stop::is_paused() || !job.enabled {
+            warning!([job.id] "skipped: paused");
+            Event::new("job_skipped")
+                .num("job", job.id as i64)
+                .str("reason", "paused")
+                .emit();
+            drop(job);
+            dispatch::reschedule(job_handle);
+            return;
+        }
+
+        // Measured on the monotonic clock, so that a clock correction can't start the job twice.
+        if let Some(since) = job.too_soon() {
+            warning!([job.id] "skipped: last run started {}s ago", since.as_secs());
+            Event::new("job_skipped")
+                .num("job", job.id as i64)
+                .str("reason", "min_interval")
+                .emit();
+            drop(job);
+            dispatch::reschedule(job_handle);
+            return;
+        }
+
+        // An observer stops here, before taking locks or recording the run.
+        if NOOP.load(Ordering::Relaxed) {
+            info!([job.id] "WOULD RUN {}", job.command());
+            Event::new("job_skipped")
+                .num("job", job.id as i64)
+                .str("reason", "noop")
+                .emit();
+            drop(job);
+            dispatch::reschedule(job_handle);
+            return;
+        }
+        if !claim_run() {
+            Event::new("job_skipped")
+                .num("job", job.id as i64)
+                .str("reason", "max_runs")
+                .emit();
+            return;
+        }
+        let (run, run_id) = job.start_run();
+        (
+            job.id,
+            run,
+            run_id,
+            job.label(run),
+            job.lock.clone().zip(occurrence),
+            job.flock.clone(),
+        )
+    };
+
+    // With a lock, only one host runs each occurrence. Runs that aren't scheduled, like those of
+    // triggers, have no occurrence to lock.
+    let lock_result = lock.map(|(lock, occurrence)| lock.acquire(occurrence.timestamp()));
+    let mut skipped = match lock_result {
+        None => None,
+        Some(Ok(None)) => {
+            info!([label] "lock acquired by {}", lock::holder());
+            None
+        }
+        Some(Ok(Some(holder))) => {
+            warning!([label] "skipped: locked by {}", holder);
+            Some(("holder", holder))
+        }
+        // Fail closed, because running on every host is usually worse than missing a run.
+        Some(Err(err)) => {
+            error!([label] "skipped: lock failed: {}", err);
+            Some(("error", err.to_string()))
+        }
+    };
+
+    // With a lock file, only one process runs the job at a time, on any host that shares the
+    // file, and also next to other cron daemons that use `flock` on it. It is held until the run
+    // ends.
+    let mut _flock = None;
+    if let Some(path) = flock.filter(|_| skipped.is_none()) {
+        match lock::flock(&path) {
+            Ok(Some(file)) => _flock = Some(file),
+            Ok(None) => {
+                warning!([label] "skipped: {} is locked", path.display());
+                skipped = Some(("path", path.display().to_string()));
+            }
+            Err(err) => {
+                error!([label] "skipped: {}: lock failed: {}", path.display(), err);
+                skipped = Some(("error", err.to_string()));
+            }
+        }
+    }
+    if let Some((name, value)) = skipped {
+        Event::new("job_skipped")
+            .num("job", id as i64)
+            .str("reason", "locked")
+            .str(name, &value)
+            .emit();
+        // The run didn't happen, so another may take its place.
+        CLAIMED_RUNS.fetch_sub(1, Ordering::Relaxed);
+        job_handle.lock().unwrap().end_run(run);
+        dispatch::reschedule(job_handle);
+        return;
+    }
+
+    let (mut command, input, pipe_to, mut capture, log, ping, account, cgroup, sandbox, workdir) = {
+        let mut job = job_handle.lock().unwrap();
+        if stop::is_stopping() {
+            Event::new("job_skipped")
+                .num("job", job.id as i64)
+                .str("reason", "stopping")
+                .emit();
+            job.end_run(run);
+            return;
+        }
+        match &job.pipe_to {
+            Some(pipe_to) => info!([label] "CMD {} | {}", job.command(), pipe_to),
+            None => info!([label] "CMD {}", job.command()),
+        }
+
+        // Like anacron, record the run when it starts.
+        if let Some(key) = &job.state_key {
+            state::update(|state| {
+                state.jobs.entry(key.to_string()).or_default().last_run = Some(Local::now());
+            });
+        }
+
+        let account = job.user.as_deref().map(Account::lookup).transpose();
+        let user = account.as_ref().ok().and_then(Option::as_ref);
+        let (script, input) = split_input(job.command());
+        // Injected faults are shell commands, also for jobs that run without a shell.
+        let mut command = match faults::pick(&job.source) {
+            Some(fault) => {
+                info!([label] "injecting {}", fault);
+                let mut command = job.shell_command(&fault.apply(&job.expand_script(&script, run)));
+                job.apply_env(&mut command, user);
+                command
+            }
+            None => job.exec_command(&script, run, user),
+        };
+        // In a process group of its own, the run can be stopped as a whole.
+        command
+            .env("POCKETCRON_RUN_ID", run_id.to_string())
+            .stdin(if input.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .process_group(0);
+        job.apply_limits(&mut command);
+        let cgroup = cgroup::create(job.id, run_id, job.limits.as_deref()).transpose();
+        if let Ok(Some(cgroup)) = &cgroup {
+            cgroup.apply(&mut command);
+        }
+        if let Some(account) = user {
+            account.apply(&mut command);
+        }
+        let sandbox = job.sandbox.as_deref().map(sandbox::prepare).transpose();
+        if let Ok(Some(sandbox)) = &sandbox {
+            sandbox.apply(&mut command);
+        }
+        let log = job.log_file.as_deref().and_then(|template| {
+            output::open_log(
+                template,
+                job.id,
+                &job.name(),
+                Local::now(),
+                job.log_rotation,
+            )
+            .inspect_err(|err| error!([label] "log file failed: {}", err))
+            .ok()
+        });
+        // Output only passes through pocketcron while someone is watching it, to mail it or send
+        // it with a ping, to keep the log all JSON or prefixed, to rotate the log file, to add
+        // the end of stderr to the span of the run, or to only pass it on if the run fails.
+        let live = events::has_subscribers();
+        let collect = job.mail_to.is_some() || job.ping.is_some();
+        let reformat = (log::is_json() || log::is_prefixed()) && log.is_none();
+        let rotate = log.as_ref().is_some_and(LogFile::rotates);
+        #[cfg(feature = "otel")]
+        let trace = otel::is_enabled();
+        #[cfg(not(feature = "otel"))]
+        let trace = false;
+        let quiet = job.quiet_success;
+        let (capture, log) = if live || collect || reformat || rotate || trace || quiet {
+            let mut capture = Capture::new(label, live, collect, log);
+            #[cfg(feature = "otel")]
+            if trace {
+                capture.keep_tail(otel::STDERR_TAIL);
+            }
+            if quiet {
+                capture.hold_back();
+            }
+            (Some(capture), None)
+        } else {
+            (None, log)
+        };
+        if capture.is_some() {
+            command.stderr(Stdio::piped());
+        } else if let Some(log) = &log {
+            command.stderr(log.stdio());
+        }
+        if job.pipe_to.is_some() || capture.is_some() {
+            command.stdout(Stdio::piped());
+        } else if let Some(log) = &log {
+            command.stdout(log.stdio());
+        }
+        let ping = job.ping.as_deref().map(|url| Ping::start(job.id, url));
+        (
+            command,
+            input,
+            job.pipe_to.clone(),
+            capture,
+            log,
+            ping,
+            account,
+            cgroup,
+            sandbox,
+            job.workdir.clone(),
+        )
+    };
+
+    let _inhibit = inhibit::hold();
+    let start = Instant::now();
+    let start_time = Local::now();
+    let spawned = match (&account, &cgroup, &sandbox) {
+        (Err(err), _, _) => Err(io::Error::other(err.clone())),
+        (_, Err(err), _) => Err(io::Error::new(
+            err.kind(),
+            format!("cgroup setup failed: {}", err),
+        )),
+        (_, _, Err(err)) => Err(io::Error::new(
+            err.kind(),
+            format!("sandbox setup failed: {}", err),
+        )),
+        // A missing directory fails like a missing command, so it's told apart.
+        _ => match workdir.as_deref().map(fs::metadata) {
+            Some(Err(err)) => Err(io::Error::new(
+                err.kind(),
+                format!("working directory {}: {}", workdir.unwrap().display(), err),
+            )),
+            _ => command.spawn(),
+        },
+    };
+    let result = match spawned {
+        Err(err) => {
+            error!([label] "spawn failed: {}", err);
+            Err(err)
+        }
+        Ok(mut proc) => {
+            let timeout = {
+                let mut job = job_handle.lock().unwrap();
+                if let Some(run) = job.runs.iter_mut().find(|other| other.number == run) {
+                    run.pid = Some(proc.id());
+                    run.started = Some(start_time);
+                }
+                job.timeout
+            };
+            // Written on a thread of its own, so that a job that doesn't read all of it can't
+            // block pocketcron. It's closed at the end, or when the job exits.
+            if let (Some(input), Some(mut stdin)) = (input, proc.stdin.take()) {
+                thread::spawn(move || {
+                    let _ = stdin.write_all(input.as_bytes());
+                });
+            }
+            if let Some(capture) = &mut capture {
+                capture.stderr(proc.stderr.take().unwrap());
+                if pipe_to.is_none() {
+                    capture.stdout(proc.stdout.take().unwrap());
+                }
+            }
+            let consumer = pipe_to.map(|pipe_to| {
+                let stdout = proc.stdout.take().unwrap();
+                let account = account.as_ref().ok().and_then(Option::as_ref);
+                let mut consumer = job_handle
+                    .lock()
+                    .unwrap()
+                    .exec_command(&pipe_to, run, account);
+                consumer
+                    .env("POCKETCRON_RUN_ID", run_id.to_string())
+                    .stdin(stdout)
+                    .process_group(proc.id() as i32);
+                job_handle.lock().unwrap().apply_limits(&mut consumer);
+                if let Ok(Some(cgroup)) = &cgroup {
+                    cgroup.apply(&mut consumer);
+                }
+                if let Some(account) = account {
+                    account.apply(&mut consumer);
+                }
+                if let Ok(Some(sandbox)) = &sandbox {
+                    sandbox.apply(&mut consumer);
+                }
+                if capture.is_some() {
+                    consumer.stdout(Stdio::piped()).stderr(Stdio::piped());
+                } else if let Some(log) = &log {
+                    let log = || log.stdio();
+                    consumer.stdout(log()).stderr(log());
+                }
+                let mut consumer = consumer
+                    .spawn()
+                    .inspect_err(|err| error!([label] "consumer spawn failed: {}", err));
+                if let (Some(capture), Ok(consumer)) = (&mut capture, &mut consumer) {
+                    capture.stdout(consumer.stdout.take().unwrap());
+                    capture.stderr(consumer.stderr.take().unwrap());
+                }
+                consumer
+            });
+            let mut started = Event::new("job_started")
+                .num("job", id as i64)
+                .num("run", run as i64)
+                .str("run_id", &run_id.to_string())
+                .num("pid", proc.id().into());
+            if let Some(Ok(consumer)) = &consumer {
+                started = started.num("consumer_pid", consumer.id().into());
+            }
+            started.emit();
+            #[cfg(feature = "publish")]
+            if publish::is_enabled() {
+                publish::started(&job_handle.lock().unwrap(), run, run_id);
+            }
+            let watchdog = timeout.map(|timeout| stop::watchdog(job_handle, run, timeout));
+            let result = proc.wait().inspect_err(|err| {
+                error!([label] "wait failed: {}", err);
+            });
+            let result = match consumer {
+                None => result,
+                // The job can't write anymore, and should stop on its own.
+                Some(Err(err)) => Err(err),
+                Some(Ok(mut consumer)) => {
+                    let consumer_result = consumer.wait().inspect_err(|err| {
+                        error!([label] "consumer wait failed: {}", err);
+                    });
+                    // Like `set -o pipefail`, the run fails if either side does, with the status
+                    // of the consumer if both do.
+                    if let Some(status) = result.as_ref().ok().filter(|status| !status.success()) {
+                        warning!([label] "producer {}", status);
+                    }
+                    match consumer_result {
+                        Ok(status) if status.success() => result,
+                        consumer_result => consumer_result,
+                    }
+                }
+            };
+            drop(watchdog);
+            result
+        }
+    };
+    drop(cgroup);
+    drop(sandbox);
+    let failure = match &result {
+        Ok(status) if status.success() => None,
+        Ok(status) => Some(status.to_string()),
+        Err(err) => Some(err.to_string()),
+    };
+    let (output, stderr) = capture
+        .map(|capture| capture.finish(failure.is_some()))
+        .unwrap_or_default();
+    #[cfg(not(feature = "otel"))]
+    let _ = stderr;
+    if let Some(ping) = ping {
+        ping.finish(failure.is_none(), output.clone());
+    }
+    #[cfg(feature = "metrics")]
+    metrics::record(id, start_time, start.elapsed(), failure.as_deref());
+    let finished = Event::new("job_finished")
+        .num("job", id as i64)
+        .num("run", run as i64)
+        .str("run_id", &run_id.to_string())
+        .num("duration_ms", start.elapsed().as_millis() as i64);
+    let outcome = match result {
+        Err(err) => Outcome::Error(err.to_string()),
+        Ok(status) => {
+            if !status.success() {
+                warning!([label] "{}", status);
+            }
+            match (status.code(), status.signal()) {
+                (Some(code), _) => Outcome::Code(code),
+                (None, Some(signal)) => Outcome::Signal(signal),
+                (None, None) => Outcome::Error(status.to_string()),
+            }
+        }
+    };
+    let mut hook_env = vec![
+        ("POCKETCRON_RUN_ID", run_id.to_string()),
+        ("POCKETCRON_DURATION", start.elapsed().as_secs().to_string()),
+    ];
+    match &outcome {
+        Outcome::Code(code) => {
+            hook_env.push(("POCKETCRON_EXIT_CODE", code.to_string()));
+            finished.num("code", (*code).into())
+        }
+        Outcome::Signal(signal) => {
+            hook_env.push(("POCKETCRON_SIGNAL", signal.to_string()));
+            finished.num("signal", (*signal).into())
+        }
+        Outcome::Error(err) => {
+            hook_env.push(("POCKETCRON_ERROR", err.clone()));
+            finished.str("error", err)
+        }
+    }
+    .emit();
+
+    let mut job = job_handle.lock().unwrap();
+    #[cfg(any(feature = "api", feature = "history"))]
+    let duration_ms = start.elapsed().as_millis() as u64;
+    #[cfg(feature = "api")]
+    {
+        job.last_result = Some(Box::new(LastRun::new(start_time, duration_ms, &outcome)));
+    }
+    #[cfg(feature = "otel")]
+    otel::export(&job, run, run_id, start_time, &outcome, stderr);
+    #[cfg(feature = "publish")]
+    publish::finished(&job, run, run_id, start_time, &outcome);
+    #[cfg(feature = "history")]
+    history::record(&job.name(), start_time, duration_ms, outcome);
+    job.end_run(run);
+    let succeeded = failure.is_none();
+    if succeeded {
+        job.expected_since = Instant::now();
+        job.overdue = false;
+    }
+    let failures = match &failure {
+        None => mem::take(&mut job.failures),
+        Some(_) => {
+            job.failures += 1;
+            job.failures
+        }
+    };
+    // With `ALERT_AFTER`, failures only alert once enough of them were in a row, and the first
+    // success after that is a recovery.
+    let alert = !succeeded && job.alert_after.is_none_or(|after| failures >= after);
+    let recovered = succeeded && job.alert_after.is_some_and(|after| failures >= after);
+    if let Some(after) = job.alert_after.filter(|_| !succeeded && !alert) {
+        info!([label] "failure {} of {} before alerting", failures, after);
+    } else if recovered {
+        info!([label] "recovered after {} failures", failures);
+    }
+    let hook = match &failure {
+        None => job.on_success.as_deref(),
+        Some(_) if alert => job.on_failure.as_deref(),
+        Some(_) => None,
+    };
+    if recovered {
+        hook_env.push(("POCKETCRON_RECOVERED", failures.to_string()));
+    } else if !succeeded {
+        hook_env.push(("POCKETCRON_FAILURES", failures.to_string()));
+    }
+    // Runs stopped by a shutdown didn't really fail. Without the user of the job, the hook can't
+    // run either.
+    let hook = hook.filter(|_| !stop::is_stopping());
+    let timeout = job.timeout;
+    let hook = hook.zip(account.as_ref().ok()).map(|(hook, account)| {
+        let command = job.hook_command(hook, account.as_ref());
+        let command = command.map(|mut command| {
+            command.envs(hook_env);
+            command
+        });
+        (hook.to_owned(), command)
+    });
+    if let Some(to) = &job.mail_to {
+        let note = match failure {
+            None if recovered => Some(format!("recovered after {} failures", failures)),
+            note => note,
+        };
+        if (!output.is_empty() || note.is_some()) && (succeeded || alert) {
+            mail::send(id, to, job.command(), output, note);
+        }
+    }
+    if job.retired {
+        info!([label] "retired after its run finished");
+    } else if let Some(next) = job.upcoming.complete(Local::now()) {
+        job.next = Some(next);
+        job.emit_scheduled();
+    }
+    drop(job);
+
+    match hook {
+        Some((hook, Ok(command))) => run_hook(label, &hook, command, timeout),
+        Some((_, Err(err))) => error!([label] "hook failed: {}", err),
+        None => {}
+    }
+    if succeeded && !stop::is_stopping() {
+        if let Some((jobs, dispatcher)) = CHAINED.get() {
+            fire_after(jobs, dispatcher, job_handle);
+        }
+    }
+    // The scheduler exits once enough runs finished.
+    let finished = FINISHED_RUNS.fetch_add(1, Ordering::Relaxed) + 1;
+    if MAX_RUNS.get().is_some_and(|max| finished >= *max) {
+        if let Some((_, dispatcher)) = CHAINED.get() {
+            dispatcher.wake();
+        }
+    }
+}
+
+/// Take one of the runs left with `--max-runs`. Returns `false` if there are none left.
+fn claim_run() -> bool {
+    let Some(max) = MAX_RUNS.get() else {
+        return true;
+    };
+    CLAIMED_RUNS
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |claimed| {
+            (claimed < *max).then_some(claimed + 1)
+        })
+        .is_ok()
+}
+
+/// Run the `ON_SUCCESS` or `ON_FAILURE` hook of a run to completion, or until the `TIMEOUT` of the
+/// job, after which it's killed, so that a hook that hangs doesn't keep the job from running
+/// again. Its output goes to that of pocketcron.
+fn run_hook(label: Label, hook: &str, mut command: Command, timeout: Option<Duration>) {
+    info!([label] "HOOK {}", hook);
+    // Like the output of jobs, that of hooks is logged as JSON lines, or prefixed.
+    let mut capture =
+        (log::is_json() || log::is_prefixed()).then(|| Capture::new(label, false, false, None));
+    if capture.is_some() {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+    let result = command.spawn().and_then(|mut proc| {
+        if let Some(capture) = &mut capture {
+            capture.stdout(proc.stdout.take().unwrap());
+            capture.stderr(proc.stderr.take().unwrap());
+        }
+        wait_hook(label, &mut proc, timeout)
+    });
+    if let Some(capture) = capture {
+        capture.finish(!result.as_ref().is_ok_and(|status| status.success()));
+    }
+    match result {
+        Ok(status) if status.success() => {}
+        Ok(status) => warning!([label] "hook {}", status),
+        Err(err) => error!([label] "hook failed: {}", err),
+    }
+}
+
+/// Wait for a hook to exit, killing its process group once it has taken longer than `timeout`.
+fn wait_hook(label: Label, proc: &mut Child, timeout: Option<Duration>) -> io::Result<ExitStatus> {
+    let Some(timeout) = timeout.and_then(|timeout| timeout.to_std().ok()) else {
+        return proc.wait();
+    };
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if let Some(status) = proc.try_wait()? {
+            return Ok(status);
+        }
+        thread::sleep(std::time::Duration::from_millis(100));
+    }
+    warning!([label] "hook timed out after {}s", timeout.as_secs());
+    // Safety: this only sends a signal.
+    unsafe { libc::kill(-(proc.id() as libc::pid_t), libc::SIGKILL) };
+    proc.wait()
 }
@@ -0,0 +1,276 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use chrono::{offset::Local, DateTime};
+
+use crate::{dispatch::Dispatcher, status, JobList};
+
+/// Upper bounds of the buckets of the run duration histogram, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 600.0, 1800.0, 3600.0,
+];
+
+/// How long to wait for a request to be sent.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Most bytes of the request line and headers that are read, so that a connection can't make its
+/// thread hold on to ever more memory.
+const MAX_REQUEST: u64 = 16 * 1024;
+
+/// Runs of every job since pocketcron started, by job number.
+static REGISTRY: Mutex<BTreeMap<usize, Runs>> = Mutex::new(BTreeMap::new());
+
+#[derive(Default)]
+struct Runs {
+    total: u64,
+    failures: u64,
+    /// When the last run started, as a Unix timestamp.
+    last_run: f64,
+    /// Runs per bucket of `BUCKETS`, not cumulative, with those that took longer at the end.
+    buckets: [u64; BUCKETS.len() + 1],
+    /// Total duration of all runs, in seconds.
+    duration: f64,
+    /// Shortest and longest duration of a run, in seconds.
+    min: f64,
+    max: f64,
+    /// How the last run failed, or `None` if it succeeded.
+    last_failure: Option<String>,
+}
+
+/// Record a finished run of a job, and how it failed, if it did.
+pub fn record(id: usize, started: DateTime<Local>, duration: Duration, failure: Option<&str>) {
+    let duration = duration.as_secs_f64();
+    let mut registry = REGISTRY.lock().unwrap();
+    let runs = registry.entry(id).or_default();
+    runs.min = if runs.total == 0 {
+        duration
+    } else {
+        runs.min.min(duration)
+    };
+    runs.max = runs.max.max(duration);
+    runs.total += 1;
+    if failure.is_some() {
+        runs.failures += 1;
+    }
+    runs.last_failure = failure.map(str::to_owned);
+    runs.last_run = started.timestamp_millis() as f64 / 1000.0;
+    let bucket = BUCKETS.partition_point(|bound| *bound < duration);
+    runs.buckets[bucket] += 1;
+    runs.duration += duration;
+}
+
+/// Serve metrics at `/metrics` over HTTP, on a separate thread.
+pub fn listen(listener: TcpListener, jobs: JobList, dispatcher: Arc<Dispatcher>) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let jobs = jobs.clone();
+            let dispatcher = dispatcher.clone();
+            thread::spawn(move || serve(stream, &jobs, &dispatcher));
+        }
+    });
+}
+
+fn serve(stream: TcpStream, jobs: &JobList, dispatcher: &Dispatcher) {
+    let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+    let mut reader = BufReader::new((&stream).take(MAX_REQUEST));
+    let mut request = String::new();
+    if reader.read_line(&mut request).is_err() {
+        return;
+    }
+    // Skip the headers. The request has no body.
+    let mut header = String::new();
+    while reader.read_line(&mut header).is_ok_and(|len| len > 2) {
+        header.clear();
+    }
+    if reader.get_ref().limit() == 0 {
+        return;
+    }
+
+    let mut parts = request.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = render(jobs, dispatcher);
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        (Some("GET"), Some(_)) => {
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned()
+        }
+        _ => "HTTP/1.1 405 Method Not Allowed\r\nAllow: GET\r\nContent-Length: 0\r\n\
+              Connection: close\r\n\r\n"
+            .to_owned(),
+    };
+    let _ = (&stream).write_all(response.as_bytes());
+}
+
+/// Describe the runs of every job since pocketcron started, one per line after a summary, for
+/// the `stats` request.
+pub fn stats(jobs: &JobList) -> String {
+    let registry = REGISTRY.lock().unwrap();
+    let mut rows = Vec::new();
+    for job_handle in jobs.lock().unwrap().iter() {
+        let job = job_handle.lock().unwrap();
+        if job.retired {
+            continue;
+        }
+        let runs = registry.get(&job.id);
+        let seconds = |f: fn(&Runs) -> f64| {
+            runs.filter(|runs| runs.total > 0)
+                .map_or("-".to_owned(), |runs| format!("{:.1}s", f(runs)))
+        };
+        rows.push([
+            job.id.to_string(),
+            job.name(),
+            runs.map_or(0, |runs| runs.total).to_string(),
+            runs.map_or(0, |runs| runs.failures).to_string(),
+            seconds(|runs| runs.min),
+            seconds(|runs| runs.duration / runs.total as f64),
+            seconds(|runs| runs.max),
+            match runs.filter(|runs| runs.total > 0) {
+                None => "-".to_owned(),
+                Some(runs) => runs.last_failure.clone().unwrap_or_else(|| "ok".to_owned()),
+            },
+        ]);
+    }
+    let mut output = format!("{} job(s)", rows.len());
+    if !rows.is_empty() {
+        let header = ["JOB", "NAME", "RUNS", "FAILED", "MIN", "AVG", "MAX", "LAST"];
+        status::write_table(&mut output, header, &rows);
+    }
+    output
+}
+
+/// Metrics in the Prometheus text format, for the dispatcher and jobs that are in a crontab.
+fn render(jobs: &JobList, dispatcher: &Dispatcher) -> String {
+    let mut rows = Vec::new();
+    for job_handle in jobs.lock().unwrap().iter() {
+        let job = job_handle.lock().unwrap();
+        if !job.retired {
+            let labels = format!("job=\"{}\",name=\"{}\"", job.id, escape(&job.name()));
+            rows.push((job.id, labels, job.is_running()));
+        }
+    }
+    let registry = REGISTRY.lock().unwrap();
+    let empty = Runs::default();
+    let runs = |id| registry.get(id).unwrap_or(&empty);
+
+    let mut output = String::new();
+    let running = rows.iter().filter(|(_, _, running)| *running).count();
+    output.push_str("# HELP pocketcron_running_jobs Number of jobs running.\n");
+    output.push_str("# TYPE pocketcron_running_jobs gauge\n");
+    let _ = writeln!(output, "pocketcron_running_jobs {}", running);
+
+    output.push_str("# HELP pocketcron_queue_depth Number of jobs queued until a slot is free.\n");
+    output.push_str("# TYPE pocketcron_queue_depth gauge\n");
+    let _ = writeln!(
+        output,
+        "pocketcron_queue_depth {}",
+        dispatcher.queue_depth()
+    );
+
+    output.push_str(
+        "# HELP pocketcron_dropped_total Number of runs dropped because all slots were taken.\n",
+    );
+    output.push_str("# TYPE pocketcron_dropped_total counter\n");
+    let _ = writeln!(output, "pocketcron_dropped_total {}", dispatcher.drops());
+
+    output.push_str("# HELP pocketcron_job_running Whether the job is running.\n");
+    output.push_str("# TYPE pocketcron_job_running gauge\n");
+    for (_, labels, is_running) in &rows {
+        let _ = writeln!(
+            output,
+            "pocketcron_job_running{{{}}} {}",
+            labels, *is_running as u8
+        );
+    }
+
+    output.push_str("# HELP pocketcron_job_runs_total Number of finished runs.\n");
+    output.push_str("# TYPE pocketcron_job_runs_total counter\n");
+    for (id, labels, _) in &rows {
+        let _ = writeln!(
+            output,
+            "pocketcron_job_runs_total{{{}}} {}",
+            labels,
+            runs(id).total
+        );
+    }
+
+    output.push_str("# HELP pocketcron_job_failures_total Number of failed runs.\n");
+    output.push_str("# TYPE pocketcron_job_failures_total counter\n");
+    for (id, labels, _) in &rows {
+        let _ = writeln!(
+            output,
+            "pocketcron_job_failures_total{{{}}} {}",
+            labels,
+            runs(id).failures
+        );
+    }
+
+    output.push_str(
+        "# HELP pocketcron_job_last_run_timestamp_seconds When the last finished run started.\n",
+    );
+    output.push_str("# TYPE pocketcron_job_last_run_timestamp_seconds gauge\n");
+    for (id, labels, _) in &rows {
+        let runs = runs(id);
+        if runs.total > 0 {
+            let _ = writeln!(
+                output,
+                "pocketcron_job_last_run_timestamp_seconds{{{}}} {}",
+                labels, runs.last_run
+            );
+        }
+    }
+
+    output.push_str("# HELP pocketcron_job_duration_seconds Duration of finished runs.\n");
+    output.push_str("# TYPE pocketcron_job_duration_seconds histogram\n");
+    for (id, labels, _) in &rows {
+        let runs = runs(id);
+        let mut count = 0;
+        for (bound, runs) in BUCKETS.iter().zip(&runs.buckets) {
+            count += runs;
+            let _ = writeln!(
+                output,
+                "pocketcron_job_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                labels, bound, count
+            );
+        }
+        let _ = writeln!(
+            output,
+            "pocketcron_job_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+            labels, runs.total
+        );
+        let _ = writeln!(
+            output,
+            "pocketcron_job_duration_seconds_sum{{{}}} {}",
+            labels, runs.duration
+        );
+        let _ = writeln!(
+            output,
+            "pocketcron_job_duration_seconds_count{{{}}} {}",
+            labels, runs.total
+        );
+    }
+    output
+}
+
+/// Escape a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,125 @@
+use std::{
+    env,
+    os::unix::net::UnixDatagram,
+    process,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use crate::log::warning;
+
+/// Connection to the service manager, with the systemd `NOTIFY_SOCKET` protocol.
+struct Notifier {
+    socket: UnixDatagram,
+    /// Path of the socket, or its name, with a leading `@` for an abstract socket.
+    addr: String,
+    /// How often the watchdog must be pinged, if it's enabled.
+    watchdog: Option<Duration>,
+    /// When the watchdog was last pinged.
+    last_ping: Mutex<Option<Instant>>,
+}
+
+/// Set if pocketcron runs under a service manager that wants to be notified.
+static NOTIFIER: OnceLock<Notifier> = OnceLock::new();
+
+/// Take the notification socket and watchdog settings passed to this process, if any.
+///
+/// The environment variables are removed, so child processes don't see them. Must be called
+/// before other threads are started.
+pub fn init() {
+    let addr = env::var("NOTIFY_SOCKET").ok();
+    let usec = env::var("WATCHDOG_USEC").ok();
+    let pid = env::var("WATCHDOG_PID").ok();
+    for name in ["NOTIFY_SOCKET", "WATCHDOG_USEC", "WATCHDOG_PID"] {
+        env::remove_var(name);
+    }
+
+    let Some(addr) = addr.filter(|addr| !addr.is_empty()) else {
+        return;
+    };
+    let socket = match UnixDatagram::unbound() {
+        Ok(socket) => socket,
+        Err(err) => {
+            warning!("{}: notify socket failed: {}", addr, err);
+            return;
+        }
+    };
+    // Without a pid, the watchdog is meant for whichever process reads the variables.
+    let for_us = pid.is_none_or(|pid| pid.parse() == Ok(process::id()));
+    let watchdog = usec
+        .filter(|_| for_us)
+        .and_then(|usec| usec.parse().ok())
+        .filter(|usec| *usec > 0)
+        .map(Duration::from_micros);
+    let _ = NOTIFIER.set(Notifier {
+        socket,
+        addr,
+        watchdog,
+        last_ping: Mutex::new(None),
+    });
+}
+
+/// Tell the service manager that pocketcron is up, with its jobs loaded.
+pub fn ready() {
+    send("READY=1");
+}
+
+/// Tell the service manager that pocketcron is shutting down.
+pub fn stopping() {
+    send("STOPPING=1");
+}
+
+/// How long the scheduler may sleep at most, to ping the watchdog in time.
+pub fn watchdog_interval() -> Option<Duration> {
+    // Pinging at half the timeout leaves room for delays, like systemd recommends.
+    NOTIFIER.get()?.watchdog.map(|timeout| timeout / 2)
+}
+
+/// Ping the watchdog, if it's enabled and due.
+pub fn watchdog() {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    let notifier = NOTIFIER.get().unwrap();
+    let mut last_ping = notifier.last_ping.lock().unwrap();
+    let now = Instant::now();
+    // The scheduler may wake up often, but the watchdog only needs a ping now and then.
+    if last_ping.is_some_and(|last_ping| now - last_ping < interval / 2) {
+        return;
+    }
+    *last_ping = Some(now);
+    drop(last_ping);
+    send("WATCHDOG=1");
+}
+
+/// Send a state change to the service manager, if any.
+fn send(state: &str) {
+    let Some(notifier) = NOTIFIER.get() else {
+        return;
+    };
+    if let Err(err) = send_to(&notifier.socket, &notifier.addr, state) {
+        warning!("{}: notify failed: {}", notifier.addr, err);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn send_to(socket: &UnixDatagram, addr: &str, state: &str) -> std::io::Result<()> {
+    use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+
+    match addr.strip_prefix('@') {
+        Some(name) => {
+            let addr = SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        None => {
+            socket.send_to(state.as_bytes(), addr)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_to(socket: &UnixDatagram, addr: &str, state: &str) -> std::io::Result<()> {
+    socket.send_to(state.as_bytes(), addr)?;
+    Ok(())
+}
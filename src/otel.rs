@@ -0,0 +1,158 @@
+use std::{
+    sync::{
+        mpsc::{self, Sender},
+        OnceLock,
+    },
+    thread,
+};
+
+#[cfg(feature = "http")]
+use std::time::Duration;
+
+use chrono::{offset::Local, DateTime};
+use serde_json::{json, Value};
+
+use crate::{
+    log::error,
+    output::Tail,
+    run_id::{self, RunId},
+    Job, Outcome,
+};
+
+/// Timeout of connections and requests.
+#[cfg(feature = "http")]
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of lines at the end of the stderr of a run that are added to its span.
+pub const STDERR_TAIL: usize = 20;
+
+/// Spans waiting to be exported, if enabled.
+static EXPORTER: OnceLock<Sender<Value>> = OnceLock::new();
+
+/// Export a span for every run to an OTLP collector from now on, like `http://localhost:4318`,
+/// with the HTTP protocol and JSON encoding, on a separate thread.
+pub fn start(endpoint: &str) {
+    let endpoint = endpoint.trim_end_matches('/');
+    let url = match endpoint.ends_with("/v1/traces") {
+        true => endpoint.to_owned(),
+        false => format!("{}/v1/traces", endpoint),
+    };
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for span in receiver {
+            let body = json!({
+                "resourceSpans": [{
+                    "resource": {
+                        "attributes": [
+                            string("service.name", "pocketcron"),
+                            string("host.name", &crate::hostname()),
+                        ],
+                    },
+                    "scopeSpans": [{
+                        "scope": { "name": "pocketcron", "version": env!("CARGO_PKG_VERSION") },
+                        "spans": [span],
+                    }],
+                }],
+            });
+            if let Err(err) = send(&url, body.to_string()) {
+                error!("otel export to {} failed: {}", url, err);
+            }
+        }
+    });
+    let _ = EXPORTER.set(sender);
+}
+
+/// Whether spans are exported, in which case the stderr of runs is captured for them.
+pub fn is_enabled() -> bool {
+    EXPORTER.get().is_some()
+}
+
+/// Export the span of a run that ended, with the lines at the end of its stderr as events. The
+/// trace id is the unique id of the run.
+pub fn export(
+    job: &Job,
+    run: u64,
+    run_id: RunId,
+    started: DateTime<Local>,
+    outcome: &Outcome,
+    stderr: Tail,
+) {
+    let Some(exporter) = EXPORTER.get() else {
+        return;
+    };
+    let mut attributes = vec![
+        int("pocketcron.job.id", job.id as i64),
+        string("pocketcron.job.name", &job.name()),
+        string("pocketcron.job.schedule", job.schedule()),
+        string("pocketcron.job.command", job.command()),
+        int("pocketcron.run", run as i64),
+        string("pocketcron.run.id", &run_id.to_string()),
+    ];
+    let failure = match outcome {
+        Outcome::Code(code) => {
+            attributes.push(int("process.exit.code", (*code).into()));
+            Some(format!("exit code {}", code)).filter(|_| *code != 0)
+        }
+        Outcome::Signal(signal) => {
+            attributes.push(int("pocketcron.signal", (*signal).into()));
+            Some(format!("killed by signal {}", signal))
+        }
+        Outcome::Error(err) => Some(err.clone()),
+    };
+    let events: Vec<Value> = stderr
+        .into_iter()
+        .map(|(time, line)| {
+            json!({
+                "timeUnixNano": nanos(time),
+                "name": "stderr",
+                "attributes": [string("line", &line)],
+            })
+        })
+        .collect();
+    let status = match failure {
+        None => json!({ "code": 1 }),
+        Some(message) => json!({ "code": 2, "message": message }),
+    };
+    let span = json!({
+        "traceId": format!("{:032x}", run_id.as_u128()),
+        "spanId": format!("{:016x}", run_id::random()),
+        "name": job.name(),
+        // Internal, because nothing called the job.
+        "kind": 1,
+        "startTimeUnixNano": nanos(started),
+        "endTimeUnixNano": nanos(Local::now()),
+        "attributes": attributes,
+        "events": events,
+        "status": status,
+    });
+    let _ = exporter.send(span);
+}
+
+fn string(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn int(key: &str, value: i64) -> Value {
+    // Protobuf JSON has 64-bit integers as strings.
+    json!({ "key": key, "value": { "intValue": value.to_string() } })
+}
+
+fn nanos(time: DateTime<Local>) -> String {
+    time.timestamp_nanos_opt().unwrap_or_default().to_string()
+}
+
+#[cfg(feature = "http")]
+fn send(url: &str, body: String) -> Result<(), String> {
+    ureq::post(url)
+        .timeout(TIMEOUT)
+        .set("Content-Type", "application/json")
+        .send_string(&body)
+        .map(drop)
+        .map_err(|err| err.to_string())
+}
+
+/// `--otel-endpoint` is rejected in builds without HTTP.
+#[cfg(not(feature = "http"))]
+fn send(_url: &str, _body: String) -> Result<(), String> {
+    Err("requires a build with the `http` feature".to_owned())
+}
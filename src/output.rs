@@ -0,0 +1,400 @@
+use std::{
+    collections::VecDeque,
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use chrono::{offset::Local, DateTime};
+use pocketcron::crontab::LogRotation;
+
+use crate::{
+    events::Event,
+    log::{self, warning, Label},
+};
+
+/// How long to wait for remaining output after a run has ended.
+const FINISH_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Maximum amount of output collected for a run by default, in bytes. The rest is dropped.
+const DEFAULT_MAX_COLLECTED: usize = 1 << 20;
+
+/// Maximum amount of output collected for a run, from `--max-output`.
+static MAX_COLLECTED: OnceLock<usize> = OnceLock::new();
+
+/// Longest piece of a line that is read at once, in bytes. Longer lines are split, so that a line
+/// never has to be held in memory as a whole.
+const MAX_LINE: u64 = 64 << 10;
+
+/// A line of output, with the time it was written.
+type Line = (DateTime<Local>, String);
+
+/// Lines at the end of the stderr of a run.
+pub type Tail = Vec<Line>;
+
+/// Collect at most this much output of every run, in bytes, instead of 1 MiB.
+pub fn set_max_collected(size: usize) {
+    let _ = MAX_COLLECTED.set(size);
+}
+
+/// Output collected for the end of a run.
+#[derive(Default)]
+struct Collected {
+    data: Vec<u8>,
+    /// Size of all output of the run, including what was dropped.
+    total: u64,
+}
+
+/// Output of a run that is held back until it ends, to only write it if the run failed.
+#[derive(Default)]
+struct Held {
+    lines: Vec<(&'static str, Vec<u8>)>,
+    size: usize,
+    /// Size of all output of the run, including what was dropped.
+    total: u64,
+}
+
+/// Output of a run, captured while clients follow events, to mail it or send it with a ping, to
+/// log it as JSON or prefixed with the job, to keep the end of its stderr, or to hold it back
+/// until the run ends.
+///
+/// Lines are sent to clients as `job_output` events, if any. They are written to the log file of
+/// the job, if any, like output is when it isn't captured. Collected output is kept for the end
+/// of the run, and otherwise lines are still written to the stdout or stderr of pocketcron.
+pub struct Capture {
+    label: Label,
+    live: bool,
+    collected: Option<Arc<Mutex<Collected>>>,
+    /// The last lines of stderr, if they're kept.
+    tail: Option<Arc<Mutex<VecDeque<Line>>>>,
+    /// How many lines of stderr are kept.
+    tail_lines: usize,
+    /// Output held back until the end of the run, if it is.
+    held: Option<Arc<Mutex<Held>>>,
+    log: Option<Arc<Mutex<LogFile>>>,
+    done: (Sender<()>, Receiver<()>),
+    streams: usize,
+}
+
+impl Capture {
+    /// Capture output, sending it to clients following events if `live`, and collecting it if
+    /// `collect`.
+    pub fn new(label: Label, live: bool, collect: bool, log: Option<LogFile>) -> Self {
+        Capture {
+            label,
+            live,
+            collected: collect.then(Default::default),
+            tail: None,
+            tail_lines: 0,
+            held: None,
+            log: log.map(|log| Arc::new(Mutex::new(log))),
+            done: mpsc::channel(),
+            streams: 0,
+        }
+    }
+
+    /// Keep the last lines of stderr, for the end of the run. Must come before the streams are
+    /// added.
+    #[cfg(feature = "otel")]
+    pub fn keep_tail(&mut self, lines: usize) {
+        self.tail = Some(Default::default());
+        self.tail_lines = lines;
+    }
+
+    /// Hold back output that would be written, and collected output, until the end of the run,
+    /// to only pass it on if the run failed. Must come before the streams are added.
+    pub fn hold_back(&mut self) {
+        self.held = Some(Default::default());
+    }
+
+    /// Copy the stdout of a process to that of pocketcron, on a separate thread.
+    pub fn stdout(&mut self, reader: impl Read + Send + 'static) {
+        self.copy(reader, "stdout", io::stdout);
+    }
+
+    /// Copy the stderr of a process to that of pocketcron, on a separate thread.
+    pub fn stderr(&mut self, reader: impl Read + Send + 'static) {
+        self.copy(reader, "stderr", io::stderr);
+    }
+
+    fn copy<W: Write + 'static>(
+        &mut self,
+        reader: impl Read + Send + 'static,
+        stream: &'static str,
+        writer: fn() -> W,
+    ) {
+        let label = self.label;
+        let live = self.live;
+        let collected = self.collected.clone();
+        let tail = self.tail.clone().filter(|_| stream == "stderr");
+        let tail_lines = self.tail_lines;
+        let held = self.held.clone();
+        let log = self.log.clone();
+        let done = self.done.0.clone();
+        self.streams += 1;
+        let max_collected = *MAX_COLLECTED.get().unwrap_or(&DEFAULT_MAX_COLLECTED);
+        thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            let mut line = Vec::new();
+            while (reader.by_ref().take(MAX_LINE))
+                .read_until(b'\n', &mut line)
+                .is_ok_and(|len| len > 0)
+            {
+                if let Some(collected) = &collected {
+                    let mut collected = collected.lock().unwrap();
+                    let len = line.len().min(max_collected - collected.data.len());
+                    collected.data.extend_from_slice(&line[..len]);
+                    collected.total += line.len() as u64;
+                }
+                if let Some(tail) = &tail {
+                    let text = String::from_utf8_lossy(&line);
+                    let mut tail = tail.lock().unwrap();
+                    if tail.len() == tail_lines {
+                        tail.pop_front();
+                    }
+                    let text = text.strip_suffix('\n').unwrap_or(&text);
+                    tail.push_back((Local::now(), text.to_owned()));
+                }
+                // Collected output is only written to the log file.
+                let written = log.is_some() || collected.is_none();
+                match &held {
+                    Some(held) if written => {
+                        let mut held = held.lock().unwrap();
+                        if held.size + line.len() <= max_collected {
+                            held.size += line.len();
+                            held.lines.push((stream, line.clone()));
+                        }
+                        held.total += line.len() as u64;
+                    }
+                    _ if written => write(writer(), label, stream, &line, log.as_deref()),
+                    _ => {}
+                }
+                if live {
+                    let text = String::from_utf8_lossy(&line);
+                    Event::new("job_output")
+                        .num("job", label.job() as i64)
+                        .str("stream", stream)
+                        .str("line", text.strip_suffix('\n').unwrap_or(&text))
+                        .emit_live();
+                }
+                line.clear();
+            }
+            let _ = done.send(());
+        });
+    }
+
+    /// Wait for copying to finish, after the run has ended, and return the collected output and
+    /// the end of stderr. Processes left behind by the run may keep writing, so this only waits
+    /// briefly, and later output follows the end of the run, or is lost if it was being collected.
+    ///
+    /// Collected output that was cut off at `--max-output` ends with a line that says so. Output
+    /// that was held back is written if the run `failed`, and dropped otherwise, along with the
+    /// collected output.
+    pub fn finish(self, failed: bool) -> (Vec<u8>, Tail) {
+        let deadline = Instant::now() + FINISH_TIMEOUT;
+        for _ in 0..self.streams {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if self.done.1.recv_timeout(timeout).is_err() {
+                break;
+            }
+        }
+        let quiet = self.held.is_some() && !failed;
+        if let Some(held) = self.held.filter(|_| failed) {
+            let held = held.lock().unwrap();
+            for (stream, line) in &held.lines {
+                match *stream {
+                    "stdout" => write(io::stdout(), self.label, stream, line, self.log.as_deref()),
+                    _ => write(io::stderr(), self.label, stream, line, self.log.as_deref()),
+                }
+            }
+            if held.total > held.size as u64 {
+                let message = format!(
+                    "held back output truncated after {} of {} bytes",
+                    held.size, held.total
+                );
+                warning!([self.label] "{}", message);
+            }
+        }
+        let collected = self.collected.filter(|_| !quiet).map(|collected| {
+            let collected = collected.lock().unwrap();
+            let mut data = collected.data.clone();
+            let kept = data.len() as u64;
+            if collected.total > kept {
+                let message = format!(
+                    "output truncated after {} of {} bytes",
+                    kept, collected.total
+                );
+                warning!([self.label] "{}", message);
+                if !data.is_empty() && !data.ends_with(b"\n") {
+                    data.push(b'\n');
+                }
+                data.extend_from_slice(format!("[{}]\n", message).as_bytes());
+            }
+            data
+        });
+        let collected = collected.unwrap_or_default();
+        let tail = self
+            .tail
+            .map(|tail| tail.lock().unwrap().drain(..).collect())
+            .unwrap_or_default();
+        (collected, tail)
+    }
+}
+
+/// Write a line of output of a run to the log file of the job, if any, or to the stdout or stderr
+/// of pocketcron.
+fn write(
+    mut writer: impl Write,
+    label: Label,
+    stream: &str,
+    line: &[u8],
+    log: Option<&Mutex<LogFile>>,
+) {
+    match log {
+        Some(log) => log.lock().unwrap().write(label, line),
+        None if log::is_json() => log::write_output(writer, label, stream, line),
+        None if log::is_prefixed() => log::write_prefixed(writer, label, line),
+        None => {
+            let _ = writer.write_all(line);
+        }
+    }
+}
+
+/// The log file of a run, which is rotated once it grows too large, if the job has
+/// `LOG_MAX_SIZE`.
+pub struct LogFile {
+    file: File,
+    path: PathBuf,
+    /// Size of the file, as far as this run knows.
+    size: u64,
+    rotation: Option<LogRotation>,
+}
+
+/// Open the log file of a run, from a `LOG_FILE` template, creating missing directories.
+pub fn open_log(
+    template: &str,
+    id: usize,
+    name: &str,
+    now: DateTime<Local>,
+    rotation: Option<LogRotation>,
+) -> io::Result<LogFile> {
+    let path = template
+        .replace("{id}", &id.to_string())
+        .replace("{name}", &name.replace('/', "_"))
+        .replace("{date}", &now.format("%Y-%m-%d").to_string());
+    let path = PathBuf::from(path);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = append(&path)?;
+    let size = file.metadata()?.len();
+    Ok(LogFile {
+        file,
+        path,
+        size,
+        rotation,
+    })
+}
+
+impl LogFile {
+    /// Whether the file is rotated, which requires output to be captured.
+    pub fn rotates(&self) -> bool {
+        self.rotation.is_some()
+    }
+
+    /// The file as stdout or stderr of a process, or those of pocketcron if it can't be shared.
+    pub fn stdio(&self) -> Stdio {
+        self.file.try_clone().map_or(Stdio::inherit(), Stdio::from)
+    }
+
+    /// Append output, and rotate the file if it grew too large.
+    fn write(&mut self, label: Label, data: &[u8]) {
+        let _ = self.file.write_all(data);
+        self.size += data.len() as u64;
+        let Some(rotation) = self
+            .rotation
+            .filter(|rotation| self.size >= rotation.max_size)
+        else {
+            return;
+        };
+        if let Err(err) = self.rotate(rotation) {
+            warning!([label] "{}: log rotation failed: {}", self.path.display(), err);
+            // Keep writing to the file as it is, rather than failing on every line.
+            self.rotation = None;
+        }
+    }
+
+    /// Move the file to `<path>.1`, and earlier rotated files up by one, dropping the oldest. The
+    /// file is then started over.
+    fn rotate(&mut self, rotation: LogRotation) -> io::Result<()> {
+        // Another run that writes to the same file may have rotated it already.
+        let current = match fs::metadata(&self.path) {
+            Ok(current) => Some(current),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+        let ours = self.file.metadata()?;
+        if current.is_some_and(|current| current.ino() == ours.ino() && current.dev() == ours.dev())
+        {
+            let suffix = if rotation.compress { ".gz" } else { "" };
+            let rotated = |number: usize| suffixed(&self.path, &format!(".{}{}", number, suffix));
+            if rotation.keep == 0 {
+                fs::remove_file(&self.path)?;
+            } else {
+                for number in (1..rotation.keep).rev() {
+                    match fs::rename(rotated(number), rotated(number + 1)) {
+                        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                        _ => {}
+                    }
+                }
+                if rotation.compress {
+                    compress(&self.path, &rotated(1))?;
+                    fs::remove_file(&self.path)?;
+                } else {
+                    fs::rename(&self.path, rotated(1))?;
+                }
+            }
+        }
+        self.file = append(&self.path)?;
+        self.size = self.file.metadata()?.len();
+        Ok(())
+    }
+}
+
+/// A path with a suffix added to its file name, like `job.log.1`.
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Write a gzip-compressed copy of a file, first to a temporary file next to it, so that a
+/// partial copy never replaces an earlier one.
+#[cfg(feature = "gzip")]
+fn compress(from: &Path, to: &Path) -> io::Result<()> {
+    use flate2::{write::GzEncoder, Compression};
+
+    let temp = suffixed(to, ".tmp");
+    let mut encoder = GzEncoder::new(File::create(&temp)?, Compression::default());
+    io::copy(&mut File::open(from)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::rename(temp, to)
+}
+
+#[cfg(not(feature = "gzip"))]
+fn compress(_from: &Path, _to: &Path) -> io::Result<()> {
+    Err(io::Error::other("requires a build with the `gzip` feature"))
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
@@ -0,0 +1,59 @@
+use std::{
+    sync::mpsc::{self, Sender},
+    thread,
+};
+
+#[cfg(feature = "http")]
+use std::time::Duration;
+
+use crate::log::error;
+
+/// Timeout of connections and requests.
+#[cfg(feature = "http")]
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pings about a run to a `PING` URL, like Healthchecks.io expects them: `/start` when the run
+/// starts, and the URL itself or `/fail` when it ends, with the output.
+///
+/// Requests are made on a separate thread, in order, so a slow server doesn't hold up the run.
+pub struct Ping(Sender<(&'static str, Vec<u8>)>);
+
+impl Ping {
+    /// Ping that a run started.
+    pub fn start(id: usize, url: &str) -> Self {
+        let (sender, receiver) = mpsc::channel::<(&str, Vec<u8>)>();
+        let url = url.trim_end_matches('/').to_owned();
+        let _ = sender.send(("/start", Vec::new()));
+        thread::spawn(move || {
+            for (suffix, body) in receiver {
+                let url = format!("{}{}", url, suffix);
+                if let Err(err) = send(&url, body) {
+                    error!([id] "ping to {} failed: {}", url, err);
+                }
+            }
+        });
+        Ping(sender)
+    }
+
+    /// Ping that the run ended, with its output.
+    pub fn finish(self, success: bool, output: Vec<u8>) {
+        let suffix = if success { "" } else { "/fail" };
+        let _ = self.0.send((suffix, output));
+    }
+}
+
+#[cfg(feature = "http")]
+fn send(url: &str, body: Vec<u8>) -> Result<(), String> {
+    ureq::post(url)
+        .timeout(TIMEOUT)
+        .set("User-Agent", "pocketcron")
+        .send_bytes(&body)
+        .map(drop)
+        .map_err(|err| err.to_string())
+}
+
+/// Crontabs with `PING` are rejected in builds without HTTP.
+#[cfg(not(feature = "http"))]
+fn send(_url: &str, _body: Vec<u8>) -> Result<(), String> {
+    Err("requires a build with the `http` feature".to_owned())
+}
@@ -0,0 +1,107 @@
+use std::{ffi::OsString, process::exit};
+
+use chrono::offset::Local;
+use pocketcron::schedule::Compat;
+
+use crate::{hostname, new_job, read_crontabs, status, LoadOptions, STDIN, USAGE};
+
+/// Number of runs shown per job by default.
+const DEFAULT_COUNT: usize = 5;
+
+/// Entry point of the `preview` command, which shows the next runs of the jobs in crontabs
+/// without running anything.
+pub fn main() -> ! {
+    let mut count = DEFAULT_COUNT;
+    let mut compat = Compat::Default;
+    let mut jitter_seed = None;
+    let mut crontabs: Vec<OsString> = Vec::new();
+    let mut args = ::std::env::args_os().skip(2);
+    while let Some(arg) = args.next() {
+        let Some(flag) = arg
+            .to_str()
+            .filter(|arg| arg.starts_with('-') && *arg != STDIN)
+        else {
+            crontabs.push(arg);
+            continue;
+        };
+        let value = args.next().and_then(|value| value.into_string().ok());
+        match (flag, value) {
+            ("--count", Some(value)) => match value.parse() {
+                Ok(value) if value > 0 => count = value,
+                _ => {
+                    eprintln!("invalid count: {}", value);
+                    exit(1);
+                }
+            },
+            ("--compat", Some(value)) => match value.parse() {
+                Ok(value) => compat = value,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    exit(1);
+                }
+            },
+            ("--jitter-seed", Some(value)) => jitter_seed = Some(value),
+            _ => {
+                eprintln!("{}", USAGE);
+                exit(1);
+            }
+        }
+    }
+    if crontabs.is_empty() {
+        eprintln!("{}", USAGE);
+        exit(1);
+    }
+
+    let options = LoadOptions {
+        jitter_seed: jitter_seed.unwrap_or_else(hostname),
+        compat,
+        shard: None,
+        default_timeout: None,
+        shell: None,
+        env_jobs: false,
+        lenient: false,
+        spool: None,
+        only_failures: false,
+    };
+    let Some(specs) = read_crontabs(&crontabs, &options) else {
+        exit(1);
+    };
+    let now = Local::now();
+    let mut rows = Vec::new();
+    for (index, spec) in specs.into_iter().enumerate() {
+        let job_handle = new_job(spec, index + 1, false, &options, now);
+        let mut job = job_handle.lock().unwrap();
+        let next = job.next;
+        let mut times: Vec<String> = next
+            .into_iter()
+            .chain(job.upcoming.by_ref().take(count - 1))
+            .map(|time| time.format("%Y-%m-%d %H:%M:%S %:z").to_string())
+            .collect();
+        // Triggered jobs have no times. Of jobs that depend on completion, only the first run is
+        // known.
+        if times.is_empty() {
+            times.push("-".to_owned());
+        }
+        for (run, time) in times.into_iter().enumerate() {
+            let first = run == 0;
+            rows.push([
+                if first {
+                    job.id.to_string()
+                } else {
+                    String::new()
+                },
+                if first { job.name() } else { String::new() },
+                time,
+                if first {
+                    job.command().to_owned()
+                } else {
+                    String::new()
+                },
+            ]);
+        }
+    }
+    let mut output = format!("next runs as of {}", now.format("%Y-%m-%d %H:%M:%S %:z"));
+    status::write_table(&mut output, ["JOB", "NAME", "TIME", "COMMAND"], &rows);
+    println!("{}", output);
+    exit(0);
+}
@@ -0,0 +1,318 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    str::FromStr,
+    sync::{
+        mpsc::{self, Sender},
+        OnceLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use chrono::{offset::Local, DateTime};
+use serde_json::json;
+
+use crate::{
+    log::{error, info},
+    run_id::RunId,
+    Job, Outcome,
+};
+
+/// Maximum number of messages kept in memory while the broker can't be reached.
+const MAX_PENDING: usize = 1000;
+
+/// Retry delays start at the minimum, and double up to the maximum.
+const MIN_RETRY: Duration = Duration::from_secs(1);
+const MAX_RETRY: Duration = Duration::from_secs(60);
+
+/// Timeout of connections and replies.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Messages waiting to be published, if enabled.
+static PUBLISHER: OnceLock<Sender<Message>> = OnceLock::new();
+
+/// A message about a run, published to a topic of its own for every kind of event.
+struct Message {
+    event: &'static str,
+    payload: String,
+}
+
+/// Protocol of a broker.
+#[derive(Clone, Copy, Debug)]
+enum Protocol {
+    Mqtt,
+    Nats,
+}
+
+/// Where to publish messages, from `--events`.
+#[derive(Clone, Debug)]
+pub struct Broker {
+    protocol: Protocol,
+    /// Host and port.
+    addr: String,
+    /// User name and password, if any.
+    auth: Option<(String, String)>,
+    /// Topic, or subject, that event names are appended to.
+    topic: String,
+}
+
+impl FromStr for Broker {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid events URL: {}", s);
+        let (protocol, rest, port) = if let Some(rest) = s.strip_prefix("mqtt://") {
+            (Protocol::Mqtt, rest, 1883)
+        } else if let Some(rest) = s.strip_prefix("nats://") {
+            (Protocol::Nats, rest, 4222)
+        } else {
+            return Err(invalid());
+        };
+        let (authority, topic) = rest.split_once('/').unwrap_or((rest, ""));
+        let topic = topic.trim_end_matches('/');
+        let (auth, host) = match authority.rsplit_once('@') {
+            Some((auth, host)) => {
+                let (user, password) = auth.split_once(':').unwrap_or((auth, ""));
+                (Some((user.to_owned(), password.to_owned())), host)
+            }
+            None => (None, authority),
+        };
+        if host.is_empty() || host.starts_with(':') {
+            return Err(invalid());
+        }
+        let addr = match host.contains(':') {
+            true => host.to_owned(),
+            false => format!("{}:{}", host, port),
+        };
+        Ok(Broker {
+            protocol,
+            addr,
+            auth,
+            topic: match topic {
+                "" => "pocketcron".to_owned(),
+                topic => topic.to_owned(),
+            },
+        })
+    }
+}
+
+impl fmt::Display for Broker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Without the credentials, since this is logged.
+        let scheme = match self.protocol {
+            Protocol::Mqtt => "mqtt",
+            Protocol::Nats => "nats",
+        };
+        write!(f, "{}://{}/{}", scheme, self.addr, self.topic)
+    }
+}
+
+impl Broker {
+    /// Publish messages over a new connection.
+    fn send(&self, messages: &[Message]) -> io::Result<()> {
+        let stream = TcpStream::connect(&self.addr)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        match self.protocol {
+            Protocol::Mqtt => self.send_mqtt(stream, messages),
+            Protocol::Nats => self.send_nats(stream, messages),
+        }
+    }
+
+    /// MQTT 3.1.1, with QoS 0. The topic of a message is like `pocketcron/failed`.
+    fn send_mqtt(&self, mut stream: TcpStream, messages: &[Message]) -> io::Result<()> {
+        let client_id = format!("pocketcron-{}-{}", crate::hostname(), std::process::id());
+        let mut connect = Vec::new();
+        connect.extend_from_slice(b"\x00\x04MQTT\x04");
+        // A clean session, without a keep-alive, since the connection only lasts for a batch.
+        let mut flags = 0x02;
+        if self.auth.is_some() {
+            flags |= 0xc0;
+        }
+        connect.push(flags);
+        connect.extend_from_slice(&[0, 0]);
+        mqtt_string(&mut connect, &client_id);
+        if let Some((user, password)) = &self.auth {
+            mqtt_string(&mut connect, user);
+            mqtt_string(&mut connect, password);
+        }
+        stream.write_all(&mqtt_packet(0x10, &connect))?;
+        let mut connack = [0; 4];
+        stream.read_exact(&mut connack)?;
+        if connack[0] != 0x20 || connack[3] != 0 {
+            return Err(io::Error::other(format!(
+                "connection refused, with code {}",
+                connack[3]
+            )));
+        }
+        let mut packets = Vec::new();
+        for message in messages {
+            let mut publish = Vec::new();
+            mqtt_string(&mut publish, &format!("{}/{}", self.topic, message.event));
+            publish.extend_from_slice(message.payload.as_bytes());
+            packets.extend(mqtt_packet(0x30, &publish));
+        }
+        // Disconnect cleanly, so the broker doesn't log an error.
+        packets.extend_from_slice(&[0xe0, 0]);
+        stream.write_all(&packets)
+    }
+
+    /// The NATS text protocol. The subject of a message is like `pocketcron.failed`.
+    fn send_nats(&self, mut stream: TcpStream, messages: &[Message]) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if !line.starts_with("INFO ") {
+            return Err(io::Error::other(format!(
+                "unexpected reply: {}",
+                line.trim()
+            )));
+        }
+        let mut options = json!({ "verbose": false, "pedantic": false, "name": "pocketcron" });
+        if let Some((user, password)) = &self.auth {
+            options["user"] = user.as_str().into();
+            options["pass"] = password.as_str().into();
+        }
+        let mut commands = format!("CONNECT {}\r\n", options);
+        for message in messages {
+            commands.push_str(&format!(
+                "PUB {}.{} {}\r\n{}\r\n",
+                self.topic,
+                message.event,
+                message.payload.len(),
+                message.payload
+            ));
+        }
+        // The server replies to a ping once it processed everything before it.
+        commands.push_str("PING\r\n");
+        stream.write_all(commands.as_bytes())?;
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            match line.trim() {
+                "PONG" => return Ok(()),
+                reply if reply.starts_with("-ERR") => {
+                    return Err(io::Error::other(reply.to_owned()));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A string in MQTT, with its length before it.
+fn mqtt_string(buf: &mut Vec<u8>, value: &str) {
+    buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// An MQTT packet, with its remaining length in the variable-length encoding.
+fn mqtt_packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![kind];
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        if len == 0 {
+            packet.push(byte);
+            break;
+        }
+        packet.push(byte | 0x80);
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// Publish a message when runs start and end from now on, with `--events`, on a separate thread.
+///
+/// Messages that can't be published are retried with a delay that doubles up to a minute. The
+/// oldest are dropped when too many are waiting.
+pub fn start(broker: Broker) {
+    let (sender, receiver) = mpsc::channel::<Message>();
+    thread::spawn(move || {
+        let mut pending = VecDeque::new();
+        let mut retry = MIN_RETRY;
+        let mut failing = false;
+        loop {
+            if pending.is_empty() {
+                let Ok(message) = receiver.recv() else {
+                    return;
+                };
+                pending.push_back(message);
+            }
+            pending.extend(receiver.try_iter());
+            if pending.len() > MAX_PENDING {
+                let dropped = pending.len() - MAX_PENDING;
+                pending.drain(..dropped);
+                error!("dropped {} event message(s) for {}", dropped, broker);
+            }
+            match broker.send(pending.make_contiguous()) {
+                Ok(()) => {
+                    if failing {
+                        info!("publishing events to {} again", broker);
+                    }
+                    pending.clear();
+                    retry = MIN_RETRY;
+                    failing = false;
+                }
+                Err(err) => {
+                    error!("events publish to {} failed: {}", broker, err);
+                    failing = true;
+                    thread::sleep(retry);
+                    retry = (retry * 2).min(MAX_RETRY);
+                }
+            }
+        }
+    });
+    let _ = PUBLISHER.set(sender);
+}
+
+/// Whether messages are published.
+pub fn is_enabled() -> bool {
+    PUBLISHER.get().is_some()
+}
+
+/// Publish that a run started.
+pub fn started(job: &Job, run: u64, run_id: RunId) {
+    publish("started", job, run, run_id, json!({}));
+}
+
+/// Publish that a run ended, as `succeeded` or `failed`, with how it ended.
+pub fn finished(job: &Job, run: u64, run_id: RunId, started: DateTime<Local>, outcome: &Outcome) {
+    let duration_ms = (Local::now() - started).num_milliseconds();
+    let (event, mut fields) = match outcome {
+        Outcome::Code(0) => ("succeeded", json!({ "code": 0 })),
+        Outcome::Code(code) => ("failed", json!({ "code": code })),
+        Outcome::Signal(signal) => ("failed", json!({ "signal": signal })),
+        Outcome::Error(err) => ("failed", json!({ "error": err })),
+    };
+    fields["duration_ms"] = duration_ms.into();
+    publish(event, job, run, run_id, fields);
+}
+
+fn publish(event: &'static str, job: &Job, run: u64, run_id: RunId, fields: serde_json::Value) {
+    let Some(publisher) = PUBLISHER.get() else {
+        return;
+    };
+    let mut payload = json!({
+        "event": event,
+        "time": Local::now().to_rfc3339(),
+        "host": crate::hostname(),
+        "job": job.id,
+        "name": job.name(),
+        "run": run,
+        "run_id": run_id.to_string(),
+    });
+    if let (Some(payload), serde_json::Value::Object(fields)) = (payload.as_object_mut(), fields) {
+        payload.extend(fields);
+    }
+    let _ = publisher.send(Message {
+        event,
+        payload: payload.to_string(),
+    });
+}
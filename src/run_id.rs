@@ -0,0 +1,53 @@
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Alphabet of ULIDs, Crockford's base32, which leaves out letters that look like digits.
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Unique id of a run, which tells it apart from runs of other jobs, instances and hosts, as a
+/// ULID like `01J9Z3K4QW8N6T2V5X7Y0B1C3D`: the time in milliseconds, followed by 80 random bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RunId(u128);
+
+impl RunId {
+    pub fn new() -> Self {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        let random = (random() as u128) << 64 | random() as u128;
+        RunId((millis as u128 & 0xFFFF_FFFF_FFFF) << 80 | random & ((1 << 80) - 1))
+    }
+
+    /// The 128 bits of the id, like for the trace id of a span.
+    #[cfg(feature = "otel")]
+    pub fn as_u128(self) -> u128 {
+        self.0
+    }
+}
+
+impl fmt::Display for RunId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // 26 characters of 5 bits, of which the first has only 3.
+        let mut text = [0; 26];
+        for (index, c) in text.iter_mut().enumerate() {
+            *c = ALPHABET[(self.0 >> (5 * (25 - index)) & 31) as usize];
+        }
+        f.write_str(std::str::from_utf8(&text).unwrap())
+    }
+}
+
+/// A random number, which is never zero.
+pub fn random() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    // The keys of `RandomState` are random, and the counter keeps numbers apart within a process.
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(process::id());
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.finish().max(1)
+}
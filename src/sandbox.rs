@@ -0,0 +1,314 @@
+use std::{io, process::Command};
+
+use pocketcron::crontab::Sandbox;
+
+/// The sandbox of a run, ready to be entered by its processes.
+pub struct Prepared {
+    /// Landlock ruleset that keeps files from being changed, if the file system is read-only.
+    #[cfg(all(target_os = "linux", feature = "sandbox"))]
+    ruleset: Option<std::sync::Arc<std::os::fd::OwnedFd>>,
+    /// Seccomp filter that denies network sockets, if there's no network.
+    #[cfg(all(target_os = "linux", feature = "sandbox"))]
+    filter: Option<Vec<libc::sock_filter>>,
+}
+
+/// Paths that can always be written, because commands redirect output there all the time.
+#[cfg(all(target_os = "linux", feature = "sandbox"))]
+const ALWAYS_ALLOWED: [&str; 1] = ["/dev/null"];
+
+/// Prepare the sandbox of a run, before its processes are started.
+#[cfg(all(target_os = "linux", feature = "sandbox"))]
+pub fn prepare(sandbox: &Sandbox) -> io::Result<Prepared> {
+    let ruleset = match sandbox.read_only {
+        true => {
+            let paths = (ALWAYS_ALLOWED.iter().map(std::path::Path::new))
+                .chain(sandbox.allow_paths.iter().map(|path| path.as_path()));
+            Some(std::sync::Arc::new(landlock::ruleset(paths)?))
+        }
+        false => None,
+    };
+    let filter = match sandbox.no_network {
+        true => Some(seccomp::no_network()?),
+        false => None,
+    };
+    Ok(Prepared { ruleset, filter })
+}
+
+/// Sandboxes are rejected on other platforms and in builds without the `sandbox` feature, so that
+/// jobs don't run with more access than intended.
+#[cfg(not(all(target_os = "linux", feature = "sandbox")))]
+pub fn prepare(_sandbox: &Sandbox) -> io::Result<Prepared> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        match cfg!(target_os = "linux") {
+            true => "sandboxes require a build with the `sandbox` feature",
+            false => "sandboxes require Linux",
+        },
+    ))
+}
+
+impl Prepared {
+    /// Make a command enter the sandbox just before it starts. Must come after switching users
+    /// and moving to a cgroup, which the sandbox may not allow.
+    #[cfg(all(target_os = "linux", feature = "sandbox"))]
+    pub fn apply(&self, command: &mut Command) {
+        use std::os::{fd::AsRawFd, unix::process::CommandExt};
+
+        let ruleset = self.ruleset.clone();
+        let filter = self.filter.clone();
+        // Safety: `prctl` and `syscall` are async-signal-safe, the filter was allocated before
+        // forking, and the command keeps the ruleset open.
+        unsafe {
+            command.pre_exec(move || {
+                let check = |res| match res {
+                    0 => Ok(()),
+                    _ => Err(io::Error::last_os_error()),
+                };
+                // Neither can be entered by unprivileged processes otherwise, and setuid binaries
+                // can't get out.
+                check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+                if let Some(ruleset) = &ruleset {
+                    let ruleset = ruleset.as_raw_fd();
+                    check(libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0) as i32)?;
+                }
+                if let Some(filter) = &filter {
+                    let program = libc::sock_fprog {
+                        len: filter.len() as u16,
+                        filter: filter.as_ptr().cast_mut(),
+                    };
+                    check(libc::prctl(
+                        libc::PR_SET_SECCOMP,
+                        libc::SECCOMP_MODE_FILTER,
+                        &program as *const libc::sock_fprog,
+                    ))?;
+                }
+                Ok(())
+            });
+        }
+    }
+
+    #[cfg(not(all(target_os = "linux", feature = "sandbox")))]
+    pub fn apply(&self, _command: &mut Command) {}
+}
+
+#[cfg(all(target_os = "linux", feature = "sandbox"))]
+mod landlock {
+    use std::{
+        ffi::CString,
+        io,
+        os::{
+            fd::{FromRawFd, OwnedFd},
+            unix::ffi::OsStrExt,
+        },
+        path::Path,
+    };
+
+    const CREATE_RULESET_VERSION: u32 = 1 << 0;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+    const ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+    const ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+    const ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+    const ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+    const ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+    const ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+    const ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+    const ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+    /// Since version 2 of the ABI, in Linux 5.19.
+    const ACCESS_FS_REFER: u64 = 1 << 13;
+    /// Since version 3 of the ABI, in Linux 6.2.
+    const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+    /// Access to files, rather than directories.
+    const ACCESS_FILE: u64 = ACCESS_FS_WRITE_FILE | ACCESS_FS_TRUNCATE;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: libc::c_int,
+    }
+
+    /// A ruleset that keeps all files from being changed, except those below the paths.
+    pub fn ruleset<'a>(paths: impl Iterator<Item = &'a Path>) -> io::Result<OwnedFd> {
+        // Safety: asking for the version takes no attributes.
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0,
+                CREATE_RULESET_VERSION,
+            )
+        };
+        if abi < 0 {
+            let err = io::Error::last_os_error();
+            return Err(io::Error::new(
+                err.kind(),
+                format!("Landlock is not available: {}", err),
+            ));
+        }
+        let mut handled = ACCESS_FS_WRITE_FILE
+            | ACCESS_FS_REMOVE_DIR
+            | ACCESS_FS_REMOVE_FILE
+            | ACCESS_FS_MAKE_CHAR
+            | ACCESS_FS_MAKE_DIR
+            | ACCESS_FS_MAKE_REG
+            | ACCESS_FS_MAKE_SOCK
+            | ACCESS_FS_MAKE_FIFO
+            | ACCESS_FS_MAKE_BLOCK
+            | ACCESS_FS_MAKE_SYM;
+        if abi >= 2 {
+            handled |= ACCESS_FS_REFER;
+        }
+        if abi >= 3 {
+            handled |= ACCESS_FS_TRUNCATE;
+        }
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        // Safety: the attributes are valid for the duration of the call, with their size.
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Safety: the descriptor was just created, and nothing else owns it.
+        let ruleset = unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) };
+        for path in paths {
+            let error =
+                |err: io::Error| io::Error::new(err.kind(), format!("{}: {}", path.display(), err));
+            let c_path =
+                CString::new(path.as_os_str().as_bytes()).map_err(|err| error(err.into()))?;
+            // Safety: the path is a valid C string.
+            let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+            if fd < 0 {
+                return Err(error(io::Error::last_os_error()));
+            }
+            // Safety: the descriptor was just opened, and nothing else owns it.
+            let parent = unsafe { OwnedFd::from_raw_fd(fd) };
+            let allowed = match path.is_dir() {
+                true => handled,
+                false => handled & ACCESS_FILE,
+            };
+            let rule = PathBeneathAttr {
+                allowed_access: allowed,
+                parent_fd: fd,
+            };
+            // Safety: the rule is valid for the duration of the call.
+            let res = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    std::os::fd::AsRawFd::as_raw_fd(&ruleset),
+                    RULE_PATH_BENEATH,
+                    &rule as *const PathBeneathAttr,
+                    0,
+                )
+            };
+            drop(parent);
+            if res < 0 {
+                return Err(error(io::Error::last_os_error()));
+            }
+        }
+        Ok(ruleset)
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "sandbox"))]
+mod seccomp {
+    use std::io;
+
+    use libc::sock_filter;
+
+    /// Architecture of system calls that the filter is made for, from `linux/audit.h`.
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+    /// Offsets in `struct seccomp_data`.
+    const NR: u32 = 0;
+    const ARCH: u32 = 4;
+    /// The low half of the first argument, on little-endian architectures.
+    const ARG0: u32 = 16;
+
+    const KILL_PROCESS: u32 = 0x8000_0000;
+
+    fn stmt(code: u32, k: u32) -> sock_filter {
+        sock_filter {
+            code: code as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    fn jump(k: u32, jt: u8, jf: u8) -> sock_filter {
+        sock_filter {
+            code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+            jt,
+            jf,
+            k,
+        }
+    }
+
+    /// A filter that denies opening sockets of any family but local and netlink sockets, and
+    /// io_uring, which can open sockets too. Netlink is how the C library finds out about the
+    /// addresses of the host, for one, and can't change anything without privileges.
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub fn no_network() -> io::Result<Vec<sock_filter>> {
+        let load = libc::BPF_LD | libc::BPF_W | libc::BPF_ABS;
+        let ret = libc::BPF_RET | libc::BPF_K;
+        let deny = libc::SECCOMP_RET_ERRNO | libc::EACCES as u32;
+        let allow = libc::SECCOMP_RET_ALLOW;
+        let mut filter = vec![
+            // System calls of other architectures have other numbers, and aren't expected.
+            stmt(load, ARCH),
+            jump(AUDIT_ARCH, 1, 0),
+            stmt(ret, KILL_PROCESS),
+            stmt(load, NR),
+        ];
+        // Nor are those of the x32 ABI.
+        #[cfg(target_arch = "x86_64")]
+        filter.extend([
+            sock_filter {
+                code: (libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K) as u16,
+                jt: 0,
+                jf: 1,
+                k: 0x4000_0000,
+            },
+            stmt(ret, KILL_PROCESS),
+        ]);
+        filter.extend([
+            jump(libc::SYS_io_uring_setup as u32, 0, 1),
+            stmt(ret, deny),
+            jump(libc::SYS_socket as u32, 1, 0),
+            stmt(ret, allow),
+            stmt(load, ARG0),
+            jump(libc::AF_UNIX as u32, 1, 0),
+            jump(libc::AF_NETLINK as u32, 0, 1),
+            stmt(ret, allow),
+            stmt(ret, deny),
+        ]);
+        Ok(filter)
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub fn no_network() -> io::Result<Vec<sock_filter>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "NO_NETWORK is not supported on this architecture",
+        ))
+    }
+}
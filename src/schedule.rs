@@ -0,0 +1,1018 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    hash::Hasher,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use chrono::{
+    offset::{Local, LocalResult, Offset, TimeZone},
+    DateTime, Duration, NaiveDateTime,
+};
+
+use crate::{
+    cron::{Dialect, Expression},
+    duration::parse_duration,
+    glob::glob_match,
+    hash::Fnv1a,
+    tz::Tz,
+};
+
+/// Longest interval of `@every`, which keeps the times it is stepped by within what chrono can
+/// represent.
+const MAX_INTERVAL: Duration = Duration::days(366);
+
+/// A parsed job schedule.
+#[derive(Clone, Debug)]
+pub enum Schedule {
+    /// Cron expression, or one of the `@` shorthands, in a time zone other than the local one if
+    /// given. Jobs with the same expression may share it.
+    Cron(Arc<Expression>, Dst, Option<Arc<Tz>>),
+    /// Fixed interval, from `@every`. The time zone only matters for `ALIGN=boundary`.
+    Every(Duration, Align, Dst, Option<Arc<Tz>>),
+    /// No time schedule, only runs when triggered. From `@trigger`, `@signal`, `@watch`,
+    /// `@after` and `@reboot`.
+    Trigger(Trigger),
+    /// Runs once every number of days, like in anacron. From `@period`.
+    Period(Period),
+}
+
+/// Schedule of an anacron-style job. The job runs when the period has passed since the day of
+/// the last run, and pocketcron has been running for the delay.
+#[derive(Clone, Debug)]
+pub struct Period {
+    pub days: u32,
+    pub delay: Duration,
+    /// Identifies the job in the state file.
+    pub id: String,
+}
+
+/// External event that runs a job.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    /// Named trigger, fired through the control socket.
+    Named(String),
+    /// Realtime signal number.
+    Signal(i32),
+    /// Changes to a file or directory.
+    Watch(Watch),
+    /// Successful run of another job, by its name.
+    After(String),
+    /// Startup of the machine, from `@reboot`.
+    Reboot,
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trigger::Named(name) => f.write_str(name),
+            Trigger::Signal(signal) => write!(f, "signal {}", signal),
+            Trigger::Watch(watch) => write!(f, "watch {}", watch.spec),
+            Trigger::After(name) => write!(f, "after {}", name),
+            Trigger::Reboot => f.write_str("reboot"),
+        }
+    }
+}
+
+/// A watched path, from `@watch`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watch {
+    /// The path as written in the crontab.
+    pub spec: String,
+    /// File or directory to watch.
+    pub path: PathBuf,
+    /// Pattern that names of changed files in the directory must match.
+    pub pattern: Option<String>,
+    /// How long to wait for changes to settle before running.
+    pub debounce: Duration,
+}
+
+impl Watch {
+    /// Whether a change to a file in the watched directory is relevant.
+    pub fn matches(&self, name: &str) -> bool {
+        self.pattern
+            .as_deref()
+            .is_none_or(|pattern| glob_match(pattern, name))
+    }
+}
+
+/// How wall-clock times that are skipped or repeated due to DST transitions are handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dst {
+    /// Repeated times run once. Skipped times run at the end of the gap.
+    Once,
+    /// Repeated times run twice. Skipped times run at the end of the gap.
+    Twice,
+    /// Repeated times run once. Skipped times don't run.
+    SkipGap,
+    /// Repeated times run once. Skipped times run just before the gap.
+    RunEarly,
+}
+
+impl FromStr for Dst {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "once" => Ok(Dst::Once),
+            "twice" => Ok(Dst::Twice),
+            "skip-gap" => Ok(Dst::SkipGap),
+            "run-early" => Ok(Dst::RunEarly),
+            _ => Err(format!("invalid DST policy: {}", s)),
+        }
+    }
+}
+
+/// Where `@every` intervals are counted from.
+#[derive(Clone, Copy, Debug)]
+pub enum Align {
+    /// Daemon start.
+    Start,
+    /// Clean boundaries in local time, i.e. multiples of the interval since the Unix epoch.
+    Boundary,
+    /// A fixed point in time.
+    Anchor(DateTime<Local>),
+    /// Completion of the previous run.
+    Completion,
+}
+
+impl FromStr for Align {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "start" => return Ok(Align::Start),
+            "boundary" => return Ok(Align::Boundary),
+            "completion" => return Ok(Align::Completion),
+            _ => {}
+        }
+        parse_time(s)
+            .map(Align::Anchor)
+            .ok_or_else(|| format!("invalid alignment: {}", s))
+    }
+}
+
+/// Parse a point in time, like `2024-06-01T00:00` in the local time zone, or with an offset in
+/// RFC 3339 format.
+pub fn parse_time(s: &str) -> Option<DateTime<Local>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Some(time.with_timezone(&Local));
+    }
+    ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+        .and_then(|time| resolve_wall(None, time).earliest())
+}
+
+/// Crontab dialect.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compat {
+    /// Pocketcron's own format.
+    Default,
+    /// BusyBox crond.
+    Busybox,
+    /// System crontabs, like `/etc/crontab`, with a user to run as before the command.
+    System,
+}
+
+impl FromStr for Compat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "busybox" => Ok(Compat::Busybox),
+            "system" => Ok(Compat::System),
+            _ => Err(format!("invalid compatibility mode: {}", s)),
+        }
+    }
+}
+
+/// Options that affect how a schedule is parsed.
+#[derive(Clone)]
+pub struct ParseOptions {
+    pub align: Align,
+    pub dst: Dst,
+    /// Time zone of wall-clock times, if not the local one.
+    pub zone: Option<Arc<Tz>>,
+    /// Whether days of the month past the end of a month are clamped to the last day.
+    pub clamp: bool,
+    pub compat: Compat,
+    /// Debounce of `@watch` schedules.
+    pub debounce: Duration,
+    /// Whether cron expressions start with seconds.
+    pub seconds: bool,
+    /// Hash of the job, for `H` in cron expressions.
+    pub hash: u64,
+}
+
+/// Error in a schedule.
+#[derive(Debug)]
+pub struct ScheduleError {
+    /// Index of the whitespace-separated element the error is about, if any.
+    pub field: Option<usize>,
+    pub message: String,
+}
+
+impl From<String> for ScheduleError {
+    fn from(message: String) -> Self {
+        ScheduleError {
+            field: None,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Schedule {
+    /// Parse the schedule part of a crontab line.
+    pub fn parse(schedule: &str, options: &ParseOptions) -> Result<Self, ScheduleError> {
+        if let Some(interval) = schedule.strip_prefix("@every") {
+            let interval = parse_duration(interval.trim()).and_then(|interval| {
+                if interval <= Duration::zero() {
+                    Err("interval must be positive".to_owned())
+                } else if interval > MAX_INTERVAL {
+                    Err("interval must be at most 366d".to_owned())
+                } else {
+                    Ok(interval)
+                }
+            });
+            let interval = interval.map_err(|message| ScheduleError {
+                field: Some(1),
+                message,
+            })?;
+            return Ok(Schedule::Every(
+                interval,
+                options.align,
+                options.dst,
+                options.zone.clone(),
+            ));
+        }
+        if let Some(name) = schedule.strip_prefix("@trigger") {
+            let name = name.trim();
+            if name.is_empty() {
+                return Err("missing trigger name".to_owned().into());
+            }
+            return Ok(Schedule::Trigger(Trigger::Named(name.to_owned())));
+        }
+        if let Some(name) = schedule.strip_prefix("@after") {
+            let name = name.trim();
+            if name.is_empty() {
+                return Err("missing job name".to_owned().into());
+            }
+            return Ok(Schedule::Trigger(Trigger::After(name.to_owned())));
+        }
+        if let Some(signal) = schedule.strip_prefix("@signal") {
+            let signal = parse_signal(signal.trim()).map_err(|message| ScheduleError {
+                field: Some(1),
+                message,
+            })?;
+            return Ok(Schedule::Trigger(Trigger::Signal(signal)));
+        }
+        if let Some(spec) = schedule.strip_prefix("@watch") {
+            let spec = spec.trim();
+            if spec.is_empty() {
+                return Err("missing watch path".to_owned().into());
+            }
+            // A pattern in the last component filters the files in the parent directory.
+            let path = Path::new(spec);
+            let pattern = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .filter(|name| name.contains(['*', '?', '[']));
+            let watch = Watch {
+                spec: spec.to_owned(),
+                path: match pattern {
+                    Some(_) => path.parent().unwrap_or(Path::new(".")).to_owned(),
+                    None => path.to_owned(),
+                },
+                pattern: pattern.map(str::to_owned),
+                debounce: options.debounce,
+            };
+            return Ok(Schedule::Trigger(Trigger::Watch(watch)));
+        }
+
+        if let Some(spec) = schedule.strip_prefix("@period") {
+            return parse_period(spec).map(Schedule::Period);
+        }
+        if schedule == "@reboot" {
+            return Ok(Schedule::Trigger(Trigger::Reboot));
+        }
+        // Shorthands never have seconds.
+        let (schedule, seconds) = match schedule {
+            "@yearly" | "@annually" => ("0 0 1 1 *", false),
+            "@monthly" => ("0 0 1 * *", false),
+            "@weekly" => ("0 0 * * 0", false),
+            "@daily" | "@midnight" => ("0 0 * * *", false),
+            "@hourly" => ("0 * * * *", false),
+            _ if schedule.starts_with('@') => {
+                return Err(format!("unknown shorthand: {}", schedule).into());
+            }
+            _ => (schedule, options.seconds),
+        };
+        let fields: Vec<&str> = schedule.split_whitespace().collect();
+        let dialect = if options.compat == Compat::Busybox {
+            Dialect::Busybox
+        } else if is_quartz(&fields) {
+            Dialect::Quartz
+        } else {
+            Dialect::Vixie
+        };
+        let expression = Expression::parse(&fields, dialect, seconds, options.hash, options.clamp)?;
+        Ok(Schedule::Cron(
+            Arc::new(expression),
+            options.dst,
+            options.zone.clone(),
+        ))
+    }
+
+    /// Key of the job in the state file, if its schedule depends on the last run.
+    pub fn state_key(&self) -> Option<&str> {
+        match self {
+            Schedule::Period(period) => Some(&period.id),
+            _ => None,
+        }
+    }
+
+    /// The trigger of a job without a time schedule.
+    pub fn trigger(&self) -> Option<&Trigger> {
+        match self {
+            Schedule::Trigger(trigger) => Some(trigger),
+            _ => None,
+        }
+    }
+
+    /// Number of whitespace-separated elements of the schedule at the start of a line.
+    ///
+    /// Cron expressions have 5 fields, optionally followed by a year. With a year, they may also
+    /// be preceded by seconds, for a total of 7 fields. Quartz expressions always start with
+    /// seconds, and are recognized by a `?` in the day of month or day of week field.
+    ///
+    /// BusyBox only supports 5 fields. With `seconds`, expressions start with seconds like in
+    /// Quartz.
+    pub fn len(tokens: &[&str], compat: Compat, seconds: bool) -> usize {
+        match tokens.first().copied().unwrap_or_default() {
+            "@every" | "@trigger" | "@signal" | "@watch" | "@after" => 2,
+            "@period" => 4,
+            first if first.starts_with('@') => 1,
+            _ if compat == Compat::Busybox => 5,
+            _ if seconds || is_quartz(tokens) => {
+                if tokens.len() > 6 && is_year_field(tokens[6]) {
+                    7
+                } else {
+                    6
+                }
+            }
+            _ if tokens.len() > 6
+                && is_day_of_week_field(tokens[5])
+                && is_year_field(tokens[6]) =>
+            {
+                7
+            }
+            _ if tokens.len() > 5 && is_year_field(tokens[5]) => 6,
+            _ => 5,
+        }
+    }
+}
+
+/// Parse the elements of `@period`: the period in days, the delay in minutes, and the job id.
+fn parse_period(spec: &str) -> Result<Period, ScheduleError> {
+    let at = |index: usize| {
+        move |message| ScheduleError {
+            field: Some(index),
+            message,
+        }
+    };
+    let mut elements = spec.split_whitespace();
+    let (Some(days), Some(delay), Some(id)) = (elements.next(), elements.next(), elements.next())
+    else {
+        return Err("expected a period, delay and job id".to_owned().into());
+    };
+    let days = days
+        .parse()
+        .ok()
+        .filter(|days| *days > 0)
+        .ok_or_else(|| format!("invalid period: {}", days))
+        .map_err(at(1))?;
+    let delay = delay
+        .parse()
+        .ok()
+        .and_then(Duration::try_minutes)
+        .filter(|delay| *delay >= Duration::zero())
+        .ok_or_else(|| format!("invalid delay: {}", delay))
+        .map_err(at(2))?;
+    Ok(Period {
+        days,
+        delay,
+        id: id.to_owned(),
+    })
+}
+
+/// Start of the day a period after the day of `last`.
+fn period_end(last: DateTime<Local>, days: u32) -> DateTime<Local> {
+    let midnight = (last.date_naive() + Duration::days(days.into())).and_time(Default::default());
+    resolve_wall(None, midnight)
+        .earliest()
+        .or_else(|| gap_end(None, midnight))
+        .unwrap_or(last)
+}
+
+/// Parse a realtime signal, like `RTMIN+3`.
+fn parse_signal(name: &str) -> Result<i32, String> {
+    let invalid = || format!("invalid realtime signal: {}", name);
+    let Some((min, max)) = realtime_signals() else {
+        return Err("realtime signals are not supported on this platform".to_owned());
+    };
+    let offset = |value: &str| value.parse::<i32>().map_err(|_| invalid());
+    let signal = match name.strip_prefix("SIG").unwrap_or(name) {
+        "RTMIN" => min,
+        "RTMAX" => max,
+        name if name.starts_with("RTMIN+") => min + offset(&name[6..])?,
+        name if name.starts_with("RTMAX-") => max - offset(&name[6..])?,
+        _ => return Err(invalid()),
+    };
+    if signal < min || signal > max {
+        return Err(invalid());
+    }
+    Ok(signal)
+}
+
+#[cfg(target_os = "linux")]
+fn realtime_signals() -> Option<(i32, i32)> {
+    Some((libc::SIGRTMIN(), libc::SIGRTMAX()))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn realtime_signals() -> Option<(i32, i32)> {
+    None
+}
+
+/// Whether tokens look like a Quartz expression, with `?` for "no specific value" in the day of
+/// month or day of week field.
+fn is_quartz<S: AsRef<str>>(tokens: &[S]) -> bool {
+    let is_any = |index: usize| tokens.get(index).is_some_and(|token| token.as_ref() == "?");
+    tokens.len() >= 6 && (is_any(3) || is_any(5))
+}
+
+/// Whether a token looks like a day of week field, by value or by name.
+fn is_day_of_week_field(token: &str) -> bool {
+    const NAMES: [&str; 14] = [
+        "sun",
+        "mon",
+        "tue",
+        "wed",
+        "thu",
+        "fri",
+        "sat",
+        "sunday",
+        "monday",
+        "tuesday",
+        "wednesday",
+        "thursday",
+        "friday",
+        "saturday",
+    ];
+    token.split([',', '-', '/']).all(|part| {
+        part == "*"
+            || part == "?"
+            || (!part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+            || NAMES.contains(&part.to_ascii_lowercase().as_str())
+    })
+}
+
+/// Whether a token looks like a year field, i.e. only contains years and the usual operators.
+fn is_year_field(token: &str) -> bool {
+    token.split(',').all(|item| {
+        let (range, step) = item.split_once('/').unwrap_or((item, "1"));
+        let is_year = |part: &str| part.len() == 4 && part.chars().all(|c| c.is_ascii_digit());
+        let range_ok = range == "*"
+            || match range.split_once('-') {
+                Some((start, end)) => is_year(start) && is_year(end),
+                None => is_year(range),
+            };
+        range_ok && !step.is_empty() && step.chars().all(|c| c.is_ascii_digit())
+    })
+}
+
+/// Iterator over the scheduled times, without offset or delay.
+enum Times {
+    /// Wall-clock times, resolved according to the DST policy.
+    Local(LocalTimes),
+    /// Fixed interval in absolute time.
+    Every {
+        interval: Duration,
+        next: DateTime<Local>,
+    },
+    /// No times, the job only runs when triggered.
+    Never,
+    /// Anacron-style period. There is no next time while the job is running.
+    Period {
+        days: u32,
+        delay: Duration,
+        next: Option<DateTime<Local>>,
+    },
+    /// Fixed interval after completion of the previous run. There is no next time while the job
+    /// is running.
+    AfterCompletion {
+        interval: Duration,
+        next: Option<DateTime<Local>>,
+    },
+}
+
+impl Times {
+    /// Create an iterator over scheduled times after `after`. `now` is the daemon start time, and
+    /// `last_run` the time of the last run by a previous instance, if known.
+    fn new(
+        schedule: Schedule,
+        now: DateTime<Local>,
+        after: DateTime<Local>,
+        last_run: Option<DateTime<Local>>,
+    ) -> Self {
+        match schedule {
+            Schedule::Cron(expression, dst, zone) => {
+                let inner = NaiveTimes::Cron {
+                    expression,
+                    last: Some(wall_clock(zone.as_deref(), after)),
+                };
+                Times::Local(LocalTimes::new(inner, dst, zone))
+            }
+            Schedule::Every(interval, Align::Start, ..) => Times::Every {
+                interval,
+                next: first_tick(now, after, interval),
+            },
+            Schedule::Every(interval, Align::Anchor(anchor), ..) => Times::Every {
+                interval,
+                next: first_tick(anchor, after, interval),
+            },
+            Schedule::Every(interval, Align::Boundary, dst, zone) => {
+                let next = first_tick(
+                    DateTime::UNIX_EPOCH.naive_utc(),
+                    wall_clock(zone.as_deref(), after),
+                    interval,
+                );
+                let inner = NaiveTimes::Every { interval, next };
+                Times::Local(LocalTimes::new(inner, dst, zone))
+            }
+            Schedule::Trigger(_) => Times::Never,
+            Schedule::Period(Period { days, delay, .. }) => {
+                // A job that never ran is due right away.
+                let due = last_run.map_or(now, |last| period_end(last, days));
+                Times::Period {
+                    days,
+                    delay,
+                    next: Some(due.max(now) + delay),
+                }
+            }
+            Schedule::Every(interval, Align::Completion, ..) => Times::AfterCompletion {
+                interval,
+                next: Some(now + interval),
+            },
+        }
+    }
+}
+
+/// Find the first `anchor + n * interval` that is after `after`.
+fn first_tick<T>(anchor: T, after: T, interval: Duration) -> T
+where
+    T: Copy + std::ops::Sub<Output = Duration> + std::ops::Add<Duration, Output = T>,
+{
+    let elapsed = (after - anchor).num_seconds();
+    let interval_secs = interval.num_seconds();
+    let ticks = elapsed.div_euclid(interval_secs) + 1;
+    anchor + Duration::seconds(ticks * interval_secs)
+}
+
+impl Iterator for Times {
+    type Item = DateTime<Local>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Times::Local(inner) => inner.next(),
+            Times::Every { interval, next } => {
+                let time = *next;
+                // There are no more times where chrono ends, thousands of years from now.
+                *next = time.checked_add_signed(*interval)?;
+                Some(time)
+            }
+            Times::Never => None,
+            Times::Period { next, .. } => next.take(),
+            Times::AfterCompletion { next, .. } => next.take(),
+        }
+    }
+}
+
+/// Iterator over wall-clock times.
+enum NaiveTimes {
+    Cron {
+        expression: Arc<Expression>,
+        /// Last returned time, or `None` if there are no more times.
+        last: Option<NaiveDateTime>,
+    },
+    Every {
+        interval: Duration,
+        next: NaiveDateTime,
+    },
+}
+
+impl Iterator for NaiveTimes {
+    type Item = NaiveDateTime;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            NaiveTimes::Cron { expression, last } => {
+                *last = expression.next_after((*last)?);
+                *last
+            }
+            NaiveTimes::Every { interval, next } => {
+                let time = *next;
+                *next = time.checked_add_signed(*interval)?;
+                Some(time)
+            }
+        }
+    }
+}
+
+/// Iterator that resolves wall-clock times to local time according to a DST policy.
+struct LocalTimes {
+    inner: NaiveTimes,
+    dst: Dst,
+    /// Time zone of the wall-clock times, if not the local one.
+    zone: Option<Arc<Tz>>,
+    /// Second occurrences of repeated times. These come after all first occurrences.
+    pending: VecDeque<DateTime<Local>>,
+    /// Time after the repeated period, held back until `pending` is drained.
+    held: Option<DateTime<Local>>,
+    /// Last returned time, used to not run multiple times at the end of a gap.
+    last: Option<DateTime<Local>>,
+}
+
+impl LocalTimes {
+    fn new(inner: NaiveTimes, dst: Dst, zone: Option<Arc<Tz>>) -> Self {
+        LocalTimes {
+            inner,
+            dst,
+            zone,
+            pending: VecDeque::new(),
+            held: None,
+            last: None,
+        }
+    }
+
+    /// Continue with the wall-clock times after `after`, forgetting times that were resolved
+    /// already.
+    fn restart(&mut self, after: NaiveDateTime) {
+        match &mut self.inner {
+            NaiveTimes::Cron { last, .. } => *last = Some(after),
+            NaiveTimes::Every { interval, next } => {
+                *next = first_tick(DateTime::UNIX_EPOCH.naive_utc(), after, *interval);
+            }
+        }
+        self.pending.clear();
+        self.held = None;
+        self.last = None;
+    }
+
+    /// Resolve the next wall-clock time, returning `None` if it doesn't run.
+    fn resolve(&mut self, time: NaiveDateTime) -> Option<DateTime<Local>> {
+        match resolve_wall(self.zone.as_deref(), time) {
+            LocalResult::Single(time) => Some(time),
+            LocalResult::Ambiguous(first, second) => {
+                if self.dst == Dst::Twice {
+                    self.pending.push_back(second);
+                }
+                return Some(first);
+            }
+            LocalResult::None => {
+                let end = gap_end(self.zone.as_deref(), time)?;
+                match self.dst {
+                    Dst::Once | Dst::Twice => Some(end),
+                    Dst::SkipGap => None,
+                    Dst::RunEarly => Some(end - Duration::seconds(1)),
+                }
+            }
+        }
+        .and_then(|time| {
+            // Hold back times after a repeated period until all second occurrences are done.
+            if self.pending.is_empty() {
+                Some(time)
+            } else {
+                self.held = Some(time);
+                None
+            }
+        })
+    }
+}
+
+/// UTC offset in seconds at an instant, in a time zone or the local one.
+fn offset_at(zone: Option<&Tz>, utc: NaiveDateTime) -> i32 {
+    match zone {
+        Some(zone) => zone.offset_at(utc.and_utc().timestamp()),
+        None => Local.offset_from_utc_datetime(&utc).fix().local_minus_utc(),
+    }
+}
+
+/// Wall-clock time of an instant, in a time zone or the local one.
+fn wall_clock(zone: Option<&Tz>, time: DateTime<Local>) -> NaiveDateTime {
+    let utc = time.naive_utc();
+    utc + Duration::seconds(offset_at(zone, utc).into())
+}
+
+/// Resolve a wall-clock time in a time zone, or the local one, to local time.
+///
+/// Like `Local::from_local_datetime`, but correct around transitions. Chrono treats the ends of
+/// gaps and overlaps inconsistently, and doesn't order ambiguous results.
+fn resolve_wall(zone: Option<&Tz>, time: NaiveDateTime) -> LocalResult<DateTime<Local>> {
+    // Try the offsets in effect a day before and after. This covers any transition near `time`.
+    let mut candidates = [time - Duration::days(1), time + Duration::days(1)]
+        .into_iter()
+        .filter_map(|probe| {
+            let offset = offset_at(zone, probe);
+            let utc = time - Duration::seconds(offset.into());
+            // Only valid if the offset is actually in effect at that instant.
+            (offset_at(zone, utc) == offset).then(|| Local.from_utc_datetime(&utc))
+        });
+    match (candidates.next(), candidates.next()) {
+        (Some(a), Some(b)) if a < b => LocalResult::Ambiguous(a, b),
+        (Some(a), Some(b)) if a > b => LocalResult::Ambiguous(b, a),
+        (Some(a), _) | (None, Some(a)) => LocalResult::Single(a),
+        (None, None) => LocalResult::None,
+    }
+}
+
+/// Find the moment a DST gap containing the wall-clock time ends, which is the transition that
+/// causes it.
+fn gap_end(zone: Option<&Tz>, time: NaiveDateTime) -> Option<DateTime<Local>> {
+    // Gaps are never longer than a day, so the offsets a day before and after are those around
+    // the transition. It's between the instants of the time with the offset after, and with the
+    // offset before.
+    let before = offset_at(zone, time - Duration::days(1));
+    let after = offset_at(zone, time + Duration::days(1));
+    let mut low = time - Duration::seconds(after.into());
+    let mut high = time - Duration::seconds(before.into());
+    if low >= high || offset_at(zone, low) != before || offset_at(zone, high) == before {
+        return None;
+    }
+    // Transitions are at whole seconds, which bisecting finds exactly.
+    while high - low > Duration::seconds(1) {
+        let middle = low + Duration::seconds((high - low).num_seconds() / 2);
+        if offset_at(zone, middle) == before {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    Some(Local.from_utc_datetime(&high))
+}
+
+impl Iterator for LocalTimes {
+    type Item = DateTime<Local>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let time = if self.held.is_some() {
+                self.pending.pop_front().or_else(|| self.held.take())
+            } else if let Some(time) = self.inner.next() {
+                self.resolve(time)
+            } else {
+                // Out of times, but there may still be second occurrences.
+                Some(self.pending.pop_front()?)
+            };
+
+            // Multiple times in a gap may resolve to the same time.
+            let Some(time) = time.filter(|time| self.last.filter(|last| time <= last).is_none())
+            else {
+                continue;
+            };
+            self.last = Some(time);
+            return Some(time);
+        }
+    }
+}
+
+/// Iterator over the times a job fires.
+///
+/// Wraps the schedule iterator and shifts every occurrence by a fixed offset plus a random delay.
+/// The random delay is derived from a seed, the job key and the scheduled time, so it is the same
+/// every time the schedule is computed, but differs between jobs and between hosts with different
+/// seeds.
+pub struct Upcoming {
+    inner: Times,
+    /// Fixed offset.
+    offset: Duration,
+    /// Maximum random delay.
+    jitter: Duration,
+    /// Hash of the seed and the job key.
+    key_hash: u64,
+    /// First fire time, found while skipping past occurrences.
+    first: Option<DateTime<Local>>,
+    /// Scheduled time of the last fire time returned, before the offset and random delay.
+    scheduled: Option<DateTime<Local>>,
+    /// Scheduled time of an occurrence missed since the last run, which fires before `first`.
+    missed: Option<DateTime<Local>>,
+    /// When the missed occurrence fires.
+    now: DateTime<Local>,
+    /// Whether the schedule has no more occurrences.
+    expired: bool,
+}
+
+impl Upcoming {
+    /// Create an iterator over fire times after `now`.
+    ///
+    /// `last_run` is the time of the last run by a previous instance, if known. If an occurrence
+    /// was missed since then, the first fire time is `now`, for that occurrence.
+    pub fn new(
+        schedule: Schedule,
+        now: DateTime<Local>,
+        last_run: Option<DateTime<Local>>,
+        offset: Duration,
+        jitter: Duration,
+        seed: &str,
+        key: &str,
+    ) -> Self {
+        let mut hasher = Fnv1a::default();
+        hasher.write(seed.as_bytes());
+        hasher.write(&[0]);
+        hasher.write(key.as_bytes());
+
+        // Periods use the last run for their next time instead.
+        let catch_up = last_run.filter(|_| !matches!(schedule, Schedule::Period(_)));
+        let mut missed = catch_up.map(|last| Times::new(schedule.clone(), now, last, None));
+
+        // Start early, so that delayed occurrences that are still in the future are not missed.
+        // Delays too large to subtract are too large to add too, so there are no times then.
+        let after = now.checked_sub_signed(offset);
+        let inner = match after.and_then(|after| after.checked_sub_signed(jitter)) {
+            Some(after) => Times::new(schedule, now, after, last_run),
+            None => Times::Never,
+        };
+        let mut upcoming = Upcoming {
+            inner,
+            offset,
+            jitter,
+            key_hash: hasher.finish(),
+            first: None,
+            scheduled: None,
+            missed: None,
+            now,
+            expired: false,
+        };
+        if let (Some(times), Some(last)) = (&mut missed, catch_up) {
+            // Only the first missed occurrence matters, because they all run once.
+            upcoming.missed = times
+                .find(|scheduled| upcoming.fire_time(*scheduled) > last)
+                .filter(|scheduled| upcoming.fire_time(*scheduled) <= now);
+        }
+        while let Some(scheduled) = upcoming.inner.next() {
+            let fire_time = upcoming.fire_time(scheduled);
+            // Overdue periods aren't skipped, they run right away.
+            if fire_time > now || matches!(upcoming.inner, Times::Period { .. }) {
+                upcoming.first = Some(fire_time);
+                upcoming.scheduled = Some(scheduled);
+                break;
+            }
+        }
+        upcoming
+    }
+
+    /// Resolve wall-clock times again after the local time zone changed, continuing with the fire
+    /// times after `now`. Returns whether the schedule depends on the local time zone at all.
+    pub fn rezone(&mut self, now: DateTime<Local>) -> bool {
+        // Schedules in a named time zone don't depend on the local one.
+        let Times::Local(inner) = &mut self.inner else {
+            return false;
+        };
+        if inner.zone.is_some() {
+            return false;
+        }
+        inner.restart((now - self.offset - self.jitter).naive_local());
+        self.first = None;
+        self.scheduled = None;
+        while let Some(scheduled) = self.inner.next() {
+            let fire_time = self.fire_time(scheduled);
+            if fire_time > now {
+                self.first = Some(fire_time);
+                self.scheduled = Some(scheduled);
+                break;
+            }
+        }
+        true
+    }
+
+    /// Bring the next fire time of an interval back after the wall clock was set back, so that it
+    /// is at most one interval after `now`, and continue from there. Returns the new next fire
+    /// time, or `None` if it didn't move. Wall-clock times don't move, so occurrences that already
+    /// ran don't run again.
+    pub fn rewind(&mut self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        let scheduled = self
+            .scheduled
+            .filter(|_| self.first.is_none() && self.missed.is_none())?;
+        let back = match &mut self.inner {
+            Times::Every { interval, next } => {
+                // Whole intervals keep the ticks aligned to their anchor.
+                let ticks =
+                    ((scheduled - now).num_seconds() - 1).div_euclid(interval.num_seconds());
+                let back = Duration::seconds(ticks.max(0) * interval.num_seconds());
+                *next -= back;
+                back
+            }
+            Times::AfterCompletion { interval, .. } => {
+                (scheduled - now - *interval).max(Duration::zero())
+            }
+            _ => return None,
+        };
+        if back <= Duration::zero() {
+            return None;
+        }
+        self.scheduled = Some(scheduled - back);
+        Some(self.fire_time(scheduled - back))
+    }
+
+    /// Move an interval by how far the wall clock jumped, so that it keeps its pace on the
+    /// monotonic clock. Returns the new next fire time, or `None` if there is none to move.
+    pub fn shift(&mut self, jump: Duration) -> Option<DateTime<Local>> {
+        let scheduled = self
+            .scheduled
+            .filter(|_| self.first.is_none() && self.missed.is_none())?;
+        match &mut self.inner {
+            Times::Every { next, .. } => *next += jump,
+            Times::AfterCompletion { next, .. } => {
+                if let Some(next) = next {
+                    *next += jump;
+                }
+            }
+            _ => return None,
+        }
+        self.scheduled = Some(scheduled + jump);
+        Some(self.fire_time(scheduled + jump))
+    }
+
+    /// Whether the schedule has no more occurrences, and the job will never run again.
+    pub fn expired(&self) -> bool {
+        self.expired
+    }
+
+    /// Scheduled time of the last fire time returned, before the offset and random delay.
+    ///
+    /// This identifies the occurrence, and is the same on every host.
+    pub fn scheduled(&self) -> Option<DateTime<Local>> {
+        self.scheduled
+    }
+
+    /// Notify the schedule that a run completed, or was dropped, at `at`.
+    ///
+    /// Returns the new next fire time if the schedule depends on completion.
+    pub fn complete(&mut self, at: DateTime<Local>) -> Option<DateTime<Local>> {
+        let scheduled = match self.inner {
+            Times::AfterCompletion { interval, .. } => at + interval,
+            Times::Period { days, delay, .. } => period_end(at, days) + delay,
+            _ => return None,
+        };
+        self.scheduled = Some(scheduled);
+        Some(self.fire_time(scheduled))
+    }
+
+    /// Apply the offset and delay to a scheduled time.
+    fn fire_time(&self, scheduled: DateTime<Local>) -> DateTime<Local> {
+        let max = self.jitter.num_seconds();
+        if max <= 0 {
+            return scheduled + self.offset;
+        }
+        let mut hasher = Fnv1a::default();
+        hasher.write_u64(self.key_hash);
+        hasher.write_i64(scheduled.timestamp());
+        let delay = hasher.finish() % (max as u64 + 1);
+        scheduled + self.offset + Duration::seconds(delay as i64)
+    }
+}
+
+impl Iterator for Upcoming {
+    type Item = DateTime<Local>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(missed) = self.missed.take() {
+            self.scheduled = Some(missed);
+            return Some(self.now);
+        }
+        if let Some(first) = self.first.take() {
+            return Some(first);
+        }
+        self.scheduled = None;
+        let Some(scheduled) = self.inner.next() else {
+            // Schedules that depend on completion just don't know the next time yet, and triggered
+            // jobs never have one.
+            self.expired = !matches!(
+                self.inner,
+                Times::AfterCompletion { .. } | Times::Never | Times::Period { .. }
+            );
+            return None;
+        };
+        self.scheduled = Some(scheduled);
+        Some(self.fire_time(scheduled))
+    }
+}
@@ -0,0 +1,327 @@
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+use std::{
+    cmp::Reverse,
+    fmt,
+    io::Write,
+    path::Path,
+    process::{Command, ExitStatus, Stdio},
+    thread,
+};
+
+use chrono::{offset::Local, DateTime, Duration};
+
+use crate::{
+    crontab::{
+        parse_crontab, split_assignment, split_input, split_words, JobSpec, ParseError,
+        ParseErrorKind,
+    },
+    schedule::{Trigger, Upcoming},
+};
+
+/// Number of a job in a scheduler, in the order jobs were added, starting at 1.
+pub type JobId = usize;
+
+/// How a run of a job ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// The run succeeded.
+    Success,
+    /// The command exited with a status other than zero.
+    Exit(i32),
+    /// The command was killed by a signal.
+    Signal(i32),
+    /// The run failed otherwise, like when the command couldn't be started.
+    Error(String),
+}
+
+impl RunOutcome {
+    pub fn is_success(&self) -> bool {
+        *self == RunOutcome::Success
+    }
+}
+
+impl fmt::Display for RunOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunOutcome::Success => write!(f, "success"),
+            RunOutcome::Exit(code) => write!(f, "exit code {}", code),
+            RunOutcome::Signal(signal) => write!(f, "killed by signal {}", signal),
+            RunOutcome::Error(err) => write!(f, "failed: {}", err),
+        }
+    }
+}
+
+/// What a job does when it runs.
+type Action = Box<dyn FnMut(&JobSpec) -> RunOutcome + Send>;
+
+struct Entry {
+    id: JobId,
+    spec: JobSpec,
+    upcoming: Upcoming,
+    next: Option<DateTime<Local>>,
+    /// Closure to call, or `None` to run the command of the job.
+    action: Option<Action>,
+}
+
+/// Runs jobs from crontabs, or closures on a crontab schedule, for programs that embed pocketcron.
+///
+/// Jobs run one after another, on the thread that drives the scheduler. Schedules work like in
+/// pocketcron, with offsets, random delays and `@period`, but none of the features of the binary
+/// around runs are here, like locks, timeouts, concurrency limits and the state file.
+pub struct Scheduler {
+    jobs: Vec<Entry>,
+    /// Seed for random delays.
+    seed: String,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scheduler {
+    /// Create a scheduler without jobs, with an empty seed for random delays.
+    pub fn new() -> Self {
+        Scheduler {
+            jobs: Vec::new(),
+            seed: String::new(),
+        }
+    }
+
+    /// Set the seed for random delays of jobs added after this, like `--jitter-seed`.
+    pub fn with_seed(mut self, seed: &str) -> Self {
+        self.seed = seed.to_owned();
+        self
+    }
+
+    /// Add the jobs in a crontab, which run their commands. Returns their numbers, or all errors
+    /// in the crontab, in which case no jobs are added.
+    pub fn add_crontab(&mut self, input: &str) -> Result<Vec<JobId>, Vec<ParseError>> {
+        let specs = parse_crontab(input)?;
+        Ok(specs
+            .into_iter()
+            .map(|spec| self.add_spec(spec, None))
+            .collect())
+    }
+
+    /// Add a job that calls a closure, from a crontab line. The command on the line only
+    /// describes the job, like `@daily cleanup`, and variables like in a crontab may come before
+    /// it.
+    pub fn add_fn(
+        &mut self,
+        line: &str,
+        f: impl FnMut(&JobSpec) -> RunOutcome + Send + 'static,
+    ) -> Result<JobId, Vec<ParseError>> {
+        let mut specs = parse_crontab(line)?;
+        let Some(spec) = specs.pop().filter(|_| specs.is_empty()) else {
+            return Err(vec![ParseError {
+                line: 1,
+                column: 1,
+                kind: ParseErrorKind::MissingCommand,
+                message: "expected a single job".to_owned(),
+            }]);
+        };
+        Ok(self.add_spec(spec, Some(Box::new(f))))
+    }
+
+    fn add_spec(&mut self, spec: JobSpec, action: Option<Action>) -> JobId {
+        let id = self.jobs.len() + 1;
+        let mut upcoming = Upcoming::new(
+            spec.schedule.clone(),
+            Local::now(),
+            None,
+            spec.offset,
+            spec.random_delay,
+            &self.seed,
+            &spec.source,
+        );
+        let next = upcoming.next();
+        self.jobs.push(Entry {
+            id,
+            spec,
+            upcoming,
+            next,
+            action,
+        });
+        id
+    }
+
+    /// The job with a number, if any.
+    pub fn job(&self, id: JobId) -> Option<&JobSpec> {
+        let entry = self.jobs.iter().find(|entry| entry.id == id)?;
+        Some(&entry.spec)
+    }
+
+    /// When the next job is due, if any job has a time schedule.
+    pub fn next_run(&self) -> Option<DateTime<Local>> {
+        self.jobs.iter().filter_map(|entry| entry.next).min()
+    }
+
+    /// Run the jobs that are due at `now`, in order of their `PRIORITY`, and return how their runs
+    /// ended. Occurrences that were missed in between run only once.
+    pub fn run_pending(&mut self, now: DateTime<Local>) -> Vec<(JobId, RunOutcome)> {
+        let mut due: Vec<usize> = Vec::new();
+        for (index, entry) in self.jobs.iter_mut().enumerate() {
+            if entry.next.is_none_or(|next| next > now) {
+                continue;
+            }
+            due.push(index);
+            while entry.next.is_some_and(|next| next <= now) {
+                entry.next = entry.upcoming.next();
+            }
+        }
+        // The sort is stable, so the order of the crontab is kept otherwise.
+        due.sort_by_key(|index| Reverse(self.jobs[*index].spec.priority));
+        self.run_all(due)
+    }
+
+    /// Run the jobs with a trigger, like `Trigger::Named` for `@trigger` jobs or `Trigger::Reboot`
+    /// at startup, and return how their runs ended.
+    pub fn fire(&mut self, trigger: &Trigger) -> Vec<(JobId, RunOutcome)> {
+        let due = (0..self.jobs.len())
+            .filter(|index| self.jobs[*index].spec.schedule.trigger() == Some(trigger))
+            .collect();
+        self.run_all(due)
+    }
+
+    /// Run jobs as they are due, and pass how their runs ended to a function. Returns once no job
+    /// has a next run anymore.
+    pub fn run(&mut self, mut on_outcome: impl FnMut(JobId, &RunOutcome)) {
+        loop {
+            let Some(next) = self.next_run() else {
+                return;
+            };
+            // Sleep at most a minute, to account for clock jumps.
+            let delay = (next - Local::now()).min(Duration::minutes(1));
+            thread::sleep(delay.to_std().unwrap_or_default());
+            for (id, outcome) in self.run_pending(Local::now()) {
+                on_outcome(id, &outcome);
+            }
+        }
+    }
+
+    fn run_all(&mut self, due: Vec<usize>) -> Vec<(JobId, RunOutcome)> {
+        due.into_iter()
+            .map(|index| {
+                let entry = &mut self.jobs[index];
+                let outcome = match &mut entry.action {
+                    Some(f) => f(&entry.spec),
+                    None => run_command(&entry.spec),
+                };
+                // Schedules that depend on completion only know their next time now.
+                if let Some(next) = entry.upcoming.complete(Local::now()) {
+                    entry.next = Some(next);
+                }
+                (entry.id, outcome)
+            })
+            .collect()
+    }
+}
+
+/// Shell of jobs without a `SHELL`.
+#[cfg(unix)]
+const DEFAULT_SHELL: &str = "sh";
+#[cfg(not(unix))]
+const DEFAULT_SHELL: &str = "cmd";
+
+/// Run the command of a job to completion, in its shell or directly with `SHELL=none`, with the
+/// environment of the crontab and, with `SHELL=none`, the variables before the command.
+fn run_command(spec: &JobSpec) -> RunOutcome {
+    let (script, input) = split_input(&spec.command);
+    let mut vars = Vec::new();
+    let mut command = if spec.exec {
+        let words = match split_words(&script) {
+            Ok(words) => words,
+            Err(err) => return RunOutcome::Error(err),
+        };
+        let mut words = words.iter();
+        let program = loop {
+            match words.next() {
+                Some(word) => match split_assignment(word) {
+                    Some((name, value)) => vars.push((name.to_owned(), value.to_owned())),
+                    None => break word,
+                },
+                None => return RunOutcome::Error("missing command after variables".to_owned()),
+            }
+        };
+        let mut command = Command::new(program);
+        command.args(words);
+        command
+    } else {
+        let shell = spec.shell.as_deref().unwrap_or(DEFAULT_SHELL);
+        let mut args = shell.split_whitespace();
+        let program = args.next().unwrap_or(DEFAULT_SHELL);
+        let mut command = Command::new(program);
+        command.args(args);
+        add_script(&mut command, program, script);
+        command
+    };
+    if let Some(dir) = &spec.workdir {
+        command.current_dir(dir);
+    }
+    command
+        .envs(spec.env.iter().map(|(name, value)| (name, value)))
+        .envs(vars)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        });
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) => return RunOutcome::Error(err.to_string()),
+    };
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        thread::spawn(move || {
+            let _ = stdin.write_all(input.as_bytes());
+        });
+    }
+    match child.wait() {
+        Ok(status) if status.success() => RunOutcome::Success,
+        Ok(status) => match (status.code(), signal(status)) {
+            (Some(code), _) => RunOutcome::Exit(code),
+            (None, Some(signal)) => RunOutcome::Signal(signal),
+            (None, None) => RunOutcome::Error(status.to_string()),
+        },
+        Err(err) => RunOutcome::Error(err.to_string()),
+    }
+}
+
+/// Pass a script to a shell, with the flag the shell expects: `/C` for `cmd`, `-Command` for
+/// PowerShell, and `-c` for others.
+fn add_script(command: &mut Command, program: &str, script: String) {
+    let name = Path::new(program)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_lowercase());
+    match name.as_deref() {
+        Some("cmd") => {
+            command.arg("/C");
+            // `cmd` doesn't follow the quoting rules of other programs, so the script is passed
+            // as it is.
+            #[cfg(windows)]
+            command.raw_arg(script);
+            #[cfg(not(windows))]
+            command.arg(script);
+        }
+        Some("powershell" | "pwsh") => {
+            command.arg("-Command").arg(script);
+        }
+        _ => {
+            command.arg("-c").arg(script);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn signal(status: ExitStatus) -> Option<i32> {
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal(_status: ExitStatus) -> Option<i32> {
+    None
+}
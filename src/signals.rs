@@ -0,0 +1,26 @@
+use std::{mem, ptr, thread};
+
+/// Handle signals on a dedicated thread, instead of with signal handlers.
+///
+/// The signals are blocked in the calling thread, so this must be called before other threads
+/// are spawned, for them to inherit the signal mask. Child processes start with no blocked signals
+/// regardless, because `Command` clears the mask when spawning them.
+pub fn handle(signals: &[i32], handler: impl Fn(i32) + Send + 'static) {
+    // Safety: the set is initialized by `sigemptyset` before use.
+    let set = unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for signal in signals {
+            libc::sigaddset(&mut set, *signal);
+        }
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut());
+        set
+    };
+    thread::spawn(move || loop {
+        let mut signal = 0;
+        // Safety: both pointers are valid for the duration of the call.
+        if unsafe { libc::sigwait(&set, &mut signal) } == 0 {
+            handler(signal);
+        }
+    });
+}
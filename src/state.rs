@@ -0,0 +1,215 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+};
+
+use chrono::{offset::Local, DateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::log::{error, warning};
+
+/// State that is kept across restarts, in the state file.
+///
+/// Fields are never skipped, because the binary format isn't self-describing.
+#[derive(Default, Serialize, Deserialize)]
+pub struct State {
+    /// Boot id of the machine when `@reboot` jobs last ran.
+    #[serde(default)]
+    pub boot_id: Option<String>,
+    /// State of jobs, by key.
+    #[serde(default)]
+    pub jobs: BTreeMap<String, JobState>,
+    /// When `pocketcron tick` last ran.
+    #[serde(default)]
+    pub last_tick: Option<DateTime<Local>>,
+}
+
+/// State in version 1 of the binary format, before `last_tick`.
+#[derive(Deserialize)]
+struct StateV1 {
+    boot_id: Option<String>,
+    jobs: BTreeMap<String, JobState>,
+}
+
+impl From<StateV1> for State {
+    fn from(state: StateV1) -> Self {
+        State {
+            boot_id: state.boot_id,
+            jobs: state.jobs,
+            last_tick: None,
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct JobState {
+    /// When the job last started.
+    #[serde(default)]
+    pub last_run: Option<DateTime<Local>>,
+}
+
+/// Serialization format of the state file.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Human-readable JSON.
+    Json,
+    /// Compact binary, to write less to flash storage.
+    Binary,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "binary" => Ok(Format::Binary),
+            _ => Err(format!("invalid state format: {}", s)),
+        }
+    }
+}
+
+/// Start of state files in the binary format, followed by a version number.
+const BINARY_MAGIC: &[u8] = b"pocketcron-state";
+const BINARY_VERSION: u8 = 2;
+
+impl Format {
+    /// Detect the format of state file contents.
+    fn detect(data: &[u8]) -> Self {
+        if data.starts_with(BINARY_MAGIC) {
+            Format::Binary
+        } else {
+            Format::Json
+        }
+    }
+
+    fn decode(self, data: &[u8]) -> Result<State, String> {
+        match self {
+            Format::Json => serde_json::from_slice(data).map_err(|err| err.to_string()),
+            Format::Binary => match &data[BINARY_MAGIC.len()..] {
+                [BINARY_VERSION, rest @ ..] => {
+                    postcard::from_bytes(rest).map_err(|err| err.to_string())
+                }
+                [1, rest @ ..] => postcard::from_bytes::<StateV1>(rest)
+                    .map(State::from)
+                    .map_err(|err| err.to_string()),
+                _ => Err("unsupported binary version".to_owned()),
+            },
+        }
+    }
+
+    fn encode(self, state: &State) -> io::Result<Vec<u8>> {
+        match self {
+            Format::Json => {
+                let mut data = serde_json::to_vec_pretty(state)?;
+                data.push(b'\n');
+                Ok(data)
+            }
+            Format::Binary => {
+                let mut data = BINARY_MAGIC.to_vec();
+                data.push(BINARY_VERSION);
+                postcard::to_extend(state, data)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+            }
+        }
+    }
+}
+
+struct Store {
+    path: PathBuf,
+    /// Format to write, which may differ from the format the file was read in.
+    format: Format,
+    state: Mutex<State>,
+}
+
+/// The state file, if enabled.
+static STORE: OnceLock<Store> = OnceLock::new();
+
+/// Load the state file, and keep it up-to-date from now on. If it doesn't exist yet, starts with
+/// empty state.
+///
+/// Files in either format are read, and written back in `format`.
+///
+/// A state file that can't be parsed, for example after a power loss on a filesystem without
+/// ordering guarantees, is moved aside with a warning, and also starts with empty state.
+pub fn open(path: &Path, format: Format) -> io::Result<()> {
+    let state = match fs::read(path) {
+        Ok(data) => match Format::detect(&data).decode(&data) {
+            Ok(state) => state,
+            Err(err) => {
+                let aside = with_suffix(path, ".corrupt");
+                warning!(
+                    "{}: state file is corrupt, moved to {} and starting over: {}",
+                    path.display(),
+                    aside.display(),
+                    err
+                );
+                fs::rename(path, &aside)?;
+                State::default()
+            }
+        },
+        Err(err) if err.kind() == io::ErrorKind::NotFound => State::default(),
+        Err(err) => return Err(err),
+    };
+    let _ = STORE.set(Store {
+        path: path.to_owned(),
+        format,
+        state: Mutex::new(state),
+    });
+    Ok(())
+}
+
+/// Read from the state, if enabled.
+pub fn get<T>(f: impl FnOnce(&State) -> T) -> Option<T> {
+    STORE.get().map(|store| f(&store.state.lock().unwrap()))
+}
+
+/// Modify the state, and write it to the state file, if enabled.
+pub fn update(f: impl FnOnce(&mut State)) {
+    let Some(store) = STORE.get() else {
+        return;
+    };
+    let mut state = store.state.lock().unwrap();
+    f(&mut state);
+    let result = store
+        .format
+        .encode(&state)
+        .and_then(|data| write_atomic(&store.path, &data));
+    if let Err(err) = result {
+        error!("{}: state file save failed: {}", store.path.display(), err);
+    }
+}
+
+/// Replace a file, such that it has either the old or the new contents after a crash.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let temp = with_suffix(path, ".tmp");
+    let mut file = File::create(&temp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&temp, path)?;
+
+    // Also make the rename itself durable.
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+/// Append a suffix to the file name of a path.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// The id of the current boot of the machine, if known.
+pub fn boot_id() -> Option<String> {
+    let id = fs::read_to_string("/proc/sys/kernel/random/boot_id").ok()?;
+    Some(id.trim().to_owned())
+}
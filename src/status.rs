@@ -0,0 +1,170 @@
+use std::{fmt::Write, fs};
+
+use chrono::offset::Local;
+
+use crate::{Job, JobHandle};
+
+/// Resource usage of a process group.
+struct Usage {
+    /// Resident memory in bytes.
+    rss: u64,
+    /// CPU time used so far, in seconds.
+    cpu: f64,
+}
+
+/// Describe runs in progress, one per line after a summary, for the `status` request.
+pub fn render(active: &[JobHandle]) -> String {
+    let now = Local::now();
+    let mut rows = Vec::new();
+    for job_handle in active {
+        let job = job_handle.lock().unwrap();
+        for run in &job.runs {
+            let (Some(pid), Some(started)) = (run.pid, run.started) else {
+                continue;
+            };
+            let elapsed = (now - started).num_milliseconds().max(0) as f64 / 1000.0;
+            let usage = usage(pid);
+            rows.push([
+                job.id.to_string(),
+                pid.to_string(),
+                started.format("%Y-%m-%d %H:%M:%S").to_string(),
+                format!("{:.0}s", elapsed),
+                usage.as_ref().map_or("-".to_owned(), |usage| {
+                    format!("{:.1} MiB", usage.rss as f64 / 1048576.0)
+                }),
+                usage.as_ref().map_or("-".to_owned(), |usage| {
+                    let percent = if elapsed > 0.0 {
+                        usage.cpu / elapsed * 100.0
+                    } else {
+                        0.0
+                    };
+                    format!("{:.1}s ({:.0}%)", usage.cpu, percent)
+                }),
+                job.command().to_owned(),
+            ]);
+        }
+    }
+
+    let mut output = format!("{} running job(s)", rows.len());
+    if !rows.is_empty() {
+        let header = ["JOB", "PID", "STARTED", "ELAPSED", "RSS", "CPU", "COMMAND"];
+        write_table(&mut output, header, &rows);
+    }
+    output
+}
+
+/// Describe jobs, one per line after a summary, for the `list` and `next` requests.
+///
+/// With a limit, only that many jobs are listed that are scheduled and not paused, in the order
+/// they run next.
+pub fn list(jobs: &[JobHandle], limit: Option<usize>) -> String {
+    let mut rows = Vec::new();
+    for job_handle in jobs {
+        let job = job_handle.lock().unwrap();
+        if job.retired || limit.is_some() && (job.next.is_none() || !job.enabled) {
+            continue;
+        }
+        rows.push((
+            job.next,
+            [
+                job.id.to_string(),
+                job.name(),
+                job.next.map_or("-".to_owned(), |next| {
+                    next.format("%Y-%m-%d %H:%M:%S").to_string()
+                }),
+                state(&job).to_owned(),
+                job.command().to_owned(),
+            ],
+        ));
+    }
+    if let Some(limit) = limit {
+        rows.sort_by_key(|(next, _)| *next);
+        rows.truncate(limit);
+    }
+    let rows: Vec<_> = rows.into_iter().map(|(_, row)| row).collect();
+
+    let mut output = format!("{} job(s)", rows.len());
+    if !rows.is_empty() {
+        let header = ["JOB", "NAME", "NEXT", "STATE", "COMMAND"];
+        write_table(&mut output, header, &rows);
+    }
+    output
+}
+
+/// Whether a job is `running`, `paused` or `idle`.
+pub fn state(job: &Job) -> &'static str {
+    if job.is_running() {
+        "running"
+    } else if !job.enabled {
+        "paused"
+    } else {
+        "idle"
+    }
+}
+
+/// Write rows aligned in columns below a header, each on a new line. There is no padding at the
+/// end of lines.
+pub fn write_table<const N: usize>(output: &mut String, header: [&str; N], rows: &[[String; N]]) {
+    let mut widths = header.map(str::len);
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for row in [header.map(str::to_owned)].iter().chain(rows) {
+        output.push('\n');
+        for (index, cell) in row.iter().enumerate() {
+            if index + 1 == row.len() {
+                output.push_str(cell);
+            } else {
+                let _ = write!(output, "{:width$}  ", cell, width = widths[index]);
+            }
+        }
+        output.truncate(output.trim_end_matches(' ').len());
+    }
+}
+
+/// Total resource usage of the processes in a process group, if known.
+#[cfg(target_os = "linux")]
+fn usage(pgid: u32) -> Option<Usage> {
+    // Safety: `sysconf` has no preconditions.
+    let (page_size, ticks) = unsafe {
+        (
+            libc::sysconf(libc::_SC_PAGESIZE),
+            libc::sysconf(libc::_SC_CLK_TCK),
+        )
+    };
+    if page_size <= 0 || ticks <= 0 {
+        return None;
+    }
+    let mut usage = Usage { rss: 0, cpu: 0.0 };
+    for entry in fs::read_dir("/proc").ok()?.flatten() {
+        let Ok(stat) = fs::read_to_string(entry.path().join("stat")) else {
+            continue;
+        };
+        // The command name is in parentheses, and may contain spaces itself.
+        let Some((_, fields)) = stat.rsplit_once(')') else {
+            continue;
+        };
+        let fields: Vec<&str> = fields.split_whitespace().collect();
+        let field = |index: usize| {
+            fields
+                .get(index)
+                .and_then(|field| field.parse::<u64>().ok())
+        };
+        if field(2) != Some(pgid.into()) {
+            continue;
+        }
+        let (Some(utime), Some(stime), Some(rss)) = (field(11), field(12), field(21)) else {
+            continue;
+        };
+        usage.cpu += (utime + stime) as f64 / ticks as f64;
+        usage.rss += rss * page_size as u64;
+    }
+    Some(usage)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn usage(_pgid: u32) -> Option<Usage> {
+    None
+}
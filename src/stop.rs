@@ -0,0 +1,274 @@
+use std::{
+    process::exit,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Condvar, Mutex, Once,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use pocketcron::crontab::Signal;
+
+use crate::{
+    dispatch::Dispatcher,
+    events::Event,
+    log::{info, warning},
+    JobHandle,
+};
+
+/// How often to check whether a job has ended.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Set when shutting down, after which no new runs start.
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// Set while scheduling is paused, after which due jobs are skipped.
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Whether pocketcron is shutting down.
+pub fn is_stopping() -> bool {
+    STOPPING.load(Ordering::Relaxed)
+}
+
+/// Whether scheduling is paused.
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::Relaxed)
+}
+
+/// Pause or resume scheduling. Runs in progress are unaffected.
+pub fn set_paused(paused: bool) {
+    if PAUSED.swap(paused, Ordering::Relaxed) != paused {
+        info!("scheduling {}", if paused { "paused" } else { "resumed" });
+    }
+}
+
+/// Stop the runs in progress of a job, if any, and wait for them to end. Runs that start in the
+/// meantime are left alone.
+///
+/// The process group of every run gets the kill signal of the job first, SIGTERM by default, so
+/// that it can clean up, and SIGKILL once the grace period of the job has passed.
+pub fn terminate(job_handle: &JobHandle) {
+    let last = job_handle.lock().unwrap().run_count;
+    stop_runs(job_handle, |number| number <= last);
+}
+
+/// Stop the runs of a job for which `stop` returns true, given their number, and wait for them to
+/// end.
+fn stop_runs(job_handle: &JobHandle, stop: impl Fn(u64) -> bool) {
+    let mut deadline = None;
+    let mut signalled = Vec::new();
+    let mut killed = false;
+    loop {
+        {
+            let job = job_handle.lock().unwrap();
+            let runs: Vec<_> = job.runs.iter().filter(|run| stop(run.number)).collect();
+            if runs.is_empty() {
+                match (deadline, killed) {
+                    (Some(_), false) => info!([job.id] "ended after {}", job.kill_signal),
+                    (Some(_), true) => warning!([job.id] "ended by SIGKILL"),
+                    (None, _) => {}
+                }
+                return;
+            }
+            let now = Instant::now();
+            for run in &runs {
+                // The process may not have been spawned yet.
+                let Some(pid) = run.pid else {
+                    continue;
+                };
+                if signalled.contains(&run.number) {
+                    continue;
+                }
+                if deadline.is_none() {
+                    info!(
+                        [job.id] "sending {}, killing after {}s",
+                        job.kill_signal,
+                        job.grace.num_seconds()
+                    );
+                    deadline = Some(now + job.grace.to_std().unwrap_or_default());
+                }
+                signal(pid, job.kill_signal.0);
+                signalled.push(run.number);
+            }
+            if deadline.is_some_and(|deadline| now >= deadline) && !killed {
+                warning!([job.id] "grace period expired, sending SIGKILL");
+                for pid in runs.iter().filter_map(|run| run.pid) {
+                    signal(pid, libc::SIGKILL);
+                }
+                killed = true;
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// A run that is stopped once its deadline passes.
+struct Deadline {
+    id: u64,
+    at: Instant,
+    job_handle: JobHandle,
+    run: u64,
+    timeout: chrono::Duration,
+}
+
+/// Deadlines of runs with a timeout, watched by a single timer thread.
+static DEADLINES: Mutex<Vec<Deadline>> = Mutex::new(Vec::new());
+/// Signalled when a deadline is added.
+static DEADLINE_ADDED: Condvar = Condvar::new();
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static TIMER: Once = Once::new();
+
+/// Stops a run of a job once it has taken too long, unless dropped first.
+pub struct Watchdog {
+    id: u64,
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        DEADLINES
+            .lock()
+            .unwrap()
+            .retain(|deadline| deadline.id != self.id);
+    }
+}
+
+/// Stop a run of a job like `terminate` once `timeout` has passed, unless the returned watchdog is
+/// dropped before then.
+pub fn watchdog(job_handle: &JobHandle, run: u64, timeout: chrono::Duration) -> Watchdog {
+    TIMER.call_once(|| {
+        thread::spawn(watch_deadlines);
+    });
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    DEADLINES.lock().unwrap().push(Deadline {
+        id,
+        at: Instant::now() + timeout.to_std().unwrap_or_default(),
+        job_handle: job_handle.clone(),
+        run,
+        timeout,
+    });
+    DEADLINE_ADDED.notify_one();
+    Watchdog { id }
+}
+
+/// Sleep until the earliest deadline, and stop the runs that are past theirs.
+fn watch_deadlines() {
+    let mut deadlines = DEADLINES.lock().unwrap();
+    loop {
+        let now = Instant::now();
+        // Stopping a run waits for it to end, so that happens on a thread of its own.
+        while let Some(index) = deadlines.iter().position(|deadline| deadline.at <= now) {
+            let deadline = deadlines.swap_remove(index);
+            thread::spawn(move || expire(deadline));
+        }
+        deadlines = match deadlines.iter().map(|deadline| deadline.at).min() {
+            Some(at) => DEADLINE_ADDED.wait_timeout(deadlines, at - now).unwrap().0,
+            None => DEADLINE_ADDED.wait(deadlines).unwrap(),
+        };
+    }
+}
+
+fn expire(deadline: Deadline) {
+    let id = deadline.job_handle.lock().unwrap().id;
+    warning!([id] "timed out after {}s", deadline.timeout.num_seconds());
+    Event::new("job_timeout")
+        .num("job", id as i64)
+        .num("run", deadline.run as i64)
+        .num("timeout_ms", deadline.timeout.num_milliseconds())
+        .emit();
+    stop_runs(&deadline.job_handle, |number| number == deadline.run);
+}
+
+/// Stop all runs in progress, each with its own grace period, and wait for them to end. Returns
+/// the number of runs.
+pub fn abort(dispatcher: &Dispatcher) -> usize {
+    // Jobs that are dispatched may not have started a run, like when it's skipped.
+    let active: Vec<_> = dispatcher
+        .active()
+        .into_iter()
+        .filter(|job_handle| job_handle.lock().unwrap().is_running())
+        .collect();
+    let count = active.len();
+    let threads: Vec<_> = active
+        .into_iter()
+        .map(|job_handle| thread::spawn(move || terminate(&job_handle)))
+        .collect();
+    for thread in threads {
+        let _ = thread.join();
+    }
+    count
+}
+
+/// Let runs in progress finish for at most `timeout`, stop the rest, and exit, logging why, like
+/// `received signal 15`. The exit status is 1 if any runs had to be stopped.
+pub fn shutdown(dispatcher: &Dispatcher, reason: &str, timeout: chrono::Duration) -> ! {
+    STOPPING.store(true, Ordering::Relaxed);
+    #[cfg(feature = "notify")]
+    crate::notify::stopping();
+    let running = dispatcher.active().len();
+    if running > 0 && timeout > chrono::Duration::zero() {
+        info!(
+            "{}, waiting up to {}s for {} running job(s)",
+            reason,
+            timeout.num_seconds(),
+            running
+        );
+        if dispatcher.wait_idle_timeout(timeout.to_std().unwrap_or_default()) {
+            info!("all jobs finished, exiting");
+            exit(0);
+        }
+        warning!("shutdown timeout expired, stopping running jobs");
+    } else {
+        info!("{}, stopping running jobs", reason);
+    }
+    let stopped = abort(dispatcher);
+    exit(if stopped > 0 { 1 } else { 0 });
+}
+
+/// Send a signal to the runs in progress of the jobs with a name, or their kill signal by default.
+pub fn kill(dispatcher: &Dispatcher, name: &str, signal: Option<Signal>) -> Result<String, String> {
+    let mut sent = Vec::new();
+    for job_handle in dispatcher.active() {
+        let job = job_handle.lock().unwrap();
+        if !job.is_named(name) {
+            continue;
+        }
+        let signal = signal.unwrap_or(job.kill_signal);
+        for pid in job.runs.iter().filter_map(|run| run.pid) {
+            info!([job.id] "sending {} on request", signal);
+            self::signal(pid, signal.0);
+            sent.push(format!("{} to job {} (pid {})", signal, job.id, pid));
+        }
+    }
+    if sent.is_empty() {
+        return Err(format!("no running job: {}", name));
+    }
+    Ok(format!("sent {}", sent.join(", ")))
+}
+
+/// Stop the runs in progress of jobs with a name, like `terminate`, and run them again once they
+/// ended. Returns once they did.
+pub fn restart(name: &str, jobs: &[JobHandle]) -> Result<String, String> {
+    let running: Vec<&JobHandle> = (jobs.iter())
+        .filter(|job_handle| job_handle.lock().unwrap().is_running())
+        .collect();
+    if running.is_empty() {
+        return Err(format!("no running job: {}", name));
+    }
+    // The worker of the run that ends runs the job again.
+    for job_handle in &running {
+        let mut job = job_handle.lock().unwrap();
+        info!([job.id] "restarting on request");
+        job.rerun = true;
+    }
+    for job_handle in &running {
+        terminate(job_handle);
+    }
+    Ok(format!("restarted {} job(s)", running.len()))
+}
+
+/// Send a signal to a process group.
+fn signal(pgid: u32, signal: i32) {
+    // Safety: this only sends a signal.
+    unsafe { libc::kill(-(pgid as libc::pid_t), signal) };
+}
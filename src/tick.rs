@@ -0,0 +1,49 @@
+use std::{
+    process::exit,
+    sync::{atomic::Ordering, Arc},
+};
+
+use chrono::{offset::Local, DateTime};
+
+use crate::{dispatch::Dispatcher, dispatch_all, log::info, state, JobHandle, JobList, NOOP};
+
+/// Run the jobs that were due since the last tick once, wait for them to finish, and exit. The
+/// jobs were loaded as of the last tick, so their next fire time is the first after it.
+///
+/// The first tick only records the time. Exits with 1 if any run failed.
+pub fn run(jobs: &JobList, dispatcher: &Arc<Dispatcher>, since: Option<DateTime<Local>>) -> ! {
+    let now = Local::now();
+    let mut due: Vec<JobHandle> = Vec::new();
+    if since.is_some() {
+        for job_handle in jobs.lock().unwrap().iter() {
+            let mut job = job_handle.lock().unwrap();
+            if job.next.is_some_and(|next| next <= now) {
+                job.occurrence = job.upcoming.scheduled();
+                due.push(job_handle.clone());
+            }
+        }
+    }
+    match since {
+        Some(since) => info!(
+            "{} job(s) due since the last tick at {}",
+            due.len(),
+            since.format("%Y-%m-%d %H:%M:%S %:z")
+        ),
+        None => info!("first tick, only recording the time"),
+    }
+
+    // Record the tick first, so that runs don't start again if this one is killed.
+    if !NOOP.load(Ordering::Relaxed) {
+        state::update(|state| state.last_tick = Some(now));
+    }
+    dispatch_all(dispatcher, due.clone());
+    dispatcher.wait_idle();
+    let failed = (due.iter())
+        .filter(|job_handle| job_handle.lock().unwrap().failures > 0)
+        .count();
+    if failed > 0 {
+        info!("{} of {} job(s) failed", failed, due.len());
+        exit(1);
+    }
+    exit(0);
+}
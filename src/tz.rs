@@ -0,0 +1,418 @@
+//! Named time zones, read from the system time zone database.
+
+use std::{env, fs, path::PathBuf};
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate};
+
+/// Where the time zone database is, unless `TZDIR` is set.
+const DEFAULT_TZDIR: &str = "/usr/share/zoneinfo";
+
+/// A time zone from the database, like `Europe/Amsterdam`.
+#[derive(Debug)]
+pub struct Tz {
+    name: String,
+    /// Times of transitions, in seconds since the epoch, with the UTC offset from then on.
+    transitions: Vec<(i64, i32)>,
+    /// UTC offset before the first transition.
+    initial: i32,
+    /// Rule for times after the last transition, if any.
+    rule: Option<Rule>,
+}
+
+impl Tz {
+    /// Load a time zone by name.
+    pub fn load(name: &str) -> Result<Self, String> {
+        let unknown = || format!("unknown time zone: {}", name);
+        if name.is_empty() || name.starts_with('/') || name.split('/').any(|part| part == "..") {
+            return Err(unknown());
+        }
+        let dir = env::var_os("TZDIR").map_or_else(|| PathBuf::from(DEFAULT_TZDIR), PathBuf::from);
+        let data = fs::read(dir.join(name)).map_err(|_| unknown())?;
+        let mut tz =
+            parse_tzif(&data).ok_or_else(|| format!("invalid time zone file: {}", name))?;
+        tz.name = name.to_owned();
+        Ok(tz)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// UTC offset in seconds at a time, in seconds since the epoch.
+    pub fn offset_at(&self, time: i64) -> i32 {
+        match self.transitions.partition_point(|(at, _)| *at <= time) {
+            0 => self.initial,
+            index if index == self.transitions.len() => match &self.rule {
+                Some(rule) => rule.offset_at(time),
+                None => self.transitions[index - 1].1,
+            },
+            index => self.transitions[index - 1].1,
+        }
+    }
+}
+
+/// Parse a TZif file, as described in RFC 8536.
+fn parse_tzif(data: &[u8]) -> Option<Tz> {
+    let mut reader = Reader(data);
+    let (version, counts) = reader.header()?;
+    if version == 0 {
+        return reader.block(counts, 4);
+    }
+    // Version 2 and later repeat the data with 64-bit times, followed by a rule.
+    reader.skip(block_len(counts, 4))?;
+    let (_, counts) = reader.header()?;
+    let mut tz = reader.block(counts, 8)?;
+    let footer = std::str::from_utf8(reader.0).ok()?;
+    let footer = footer.strip_prefix('\n')?.split('\n').next()?;
+    if !footer.is_empty() {
+        tz.rule = Some(Rule::parse(footer)?);
+    }
+    Some(tz)
+}
+
+/// Counts in a TZif header: UT/local indicators, standard/wall indicators, leap seconds,
+/// transitions, local time types and bytes of abbreviations.
+type Counts = [usize; 6];
+
+fn block_len([isut, isstd, leap, time, types, chars]: Counts, time_size: usize) -> usize {
+    time * time_size + time + types * 6 + chars + leap * (time_size + 4) + isstd + isut
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.take(len).map(drop)
+    }
+
+    fn int(&mut self, size: usize) -> Option<i64> {
+        let bytes = self.take(size)?;
+        Some(match size {
+            4 => i32::from_be_bytes(bytes.try_into().ok()?).into(),
+            _ => i64::from_be_bytes(bytes.try_into().ok()?),
+        })
+    }
+
+    /// Read a header, returning the version and counts.
+    fn header(&mut self) -> Option<(u8, Counts)> {
+        if self.take(4)? != b"TZif" {
+            return None;
+        }
+        let version = match self.take(1)?[0] {
+            0 => 0,
+            version => version.checked_sub(b'0')?,
+        };
+        self.skip(15)?;
+        let mut counts = [0; 6];
+        for count in &mut counts {
+            *count = usize::try_from(self.int(4)?).ok()?;
+        }
+        Some((version, counts))
+    }
+
+    /// Read the transitions and local time types of a data block.
+    fn block(&mut self, counts: Counts, time_size: usize) -> Option<Tz> {
+        let [isut, isstd, leap, time, types, chars] = counts;
+        let times = (0..time)
+            .map(|_| self.int(time_size))
+            .collect::<Option<Vec<_>>>()?;
+        let indices = self.take(time)?.to_vec();
+        let offsets = (0..types)
+            .map(|_| {
+                let offset = self.int(4)? as i32;
+                self.skip(2)?;
+                Some(offset)
+            })
+            .collect::<Option<Vec<_>>>()?;
+        self.skip(chars + leap * (time_size + 4) + isstd + isut)?;
+        let transitions = times
+            .into_iter()
+            .zip(indices)
+            .map(|(at, index)| Some((at, *offsets.get(usize::from(index))?)))
+            .collect::<Option<_>>()?;
+        Some(Tz {
+            name: String::new(),
+            transitions,
+            initial: *offsets.first()?,
+            rule: None,
+        })
+    }
+}
+
+/// A POSIX TZ rule, like `CET-1CEST,M3.5.0,M10.5.0/3`.
+#[derive(Debug)]
+struct Rule {
+    /// UTC offset of standard time, in seconds.
+    std: i32,
+    /// UTC offset of daylight saving time, and when it starts and ends, if observed.
+    dst: Option<(i32, Transition, Transition)>,
+}
+
+/// Day and time of a transition in a POSIX TZ rule.
+#[derive(Debug)]
+struct Transition {
+    date: RuleDate,
+    /// Local time of the transition, in seconds after midnight.
+    time: i64,
+}
+
+#[derive(Debug)]
+enum RuleDate {
+    /// Day of the year from 1 to 365, not counting February 29.
+    Julian(u16),
+    /// Day of the year from 0 to 365, counting February 29.
+    ZeroBased(u16),
+    /// Day of the week (0 is Sunday) of a week (5 is the last) of a month.
+    MonthWeekDay(u8, u8, u8),
+}
+
+impl Rule {
+    fn parse(spec: &str) -> Option<Self> {
+        let mut rest = spec;
+        parse_name(&mut rest)?;
+        // POSIX offsets are positive west of Greenwich.
+        let std = -parse_time(&mut rest)? as i32;
+        if rest.is_empty() {
+            return Some(Rule { std, dst: None });
+        }
+        parse_name(&mut rest)?;
+        let dst = if rest.is_empty() || rest.starts_with(',') {
+            std + 3600
+        } else {
+            -parse_time(&mut rest)? as i32
+        };
+        // Without rules, like in `EST5EDT`, the default of POSIX implementations applies, which is
+        // the rule of the United States since 2007.
+        let rest = match rest {
+            "" => "M3.2.0,M11.1.0",
+            _ => rest.strip_prefix(',')?,
+        };
+        let (start, end) = rest.split_once(',')?;
+        Some(Rule {
+            std,
+            dst: Some((dst, Transition::parse(start)?, Transition::parse(end)?)),
+        })
+    }
+
+    fn offset_at(&self, time: i64) -> i32 {
+        let Some((dst, start, end)) = &self.dst else {
+            return self.std;
+        };
+        let Some(local) = DateTime::from_timestamp(time + i64::from(self.std), 0) else {
+            return self.std;
+        };
+        let year = local.year();
+        // The start is in standard time, and the end in daylight saving time.
+        let (Some(start), Some(end)) = (
+            start.at(year).map(|at| at - i64::from(self.std)),
+            end.at(year).map(|at| at - i64::from(*dst)),
+        ) else {
+            return self.std;
+        };
+        let in_dst = if start < end {
+            start <= time && time < end
+        } else {
+            // Daylight saving time across the new year, like in the southern hemisphere.
+            time < end || start <= time
+        };
+        if in_dst {
+            *dst
+        } else {
+            self.std
+        }
+    }
+}
+
+impl Transition {
+    fn parse(spec: &str) -> Option<Self> {
+        let (date, time) = match spec.split_once('/') {
+            Some((date, time)) => {
+                let mut time = time;
+                let seconds = parse_time(&mut time)?;
+                if !time.is_empty() {
+                    return None;
+                }
+                (date, seconds)
+            }
+            None => (spec, 2 * 3600),
+        };
+        let date = if let Some(date) = date.strip_prefix('M') {
+            let mut fields = date.split('.').map(str::parse::<u8>);
+            let (Some(Ok(month)), Some(Ok(week)), Some(Ok(day)), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return None;
+            };
+            if !(1..=12).contains(&month) || !(1..=5).contains(&week) || day > 6 {
+                return None;
+            }
+            RuleDate::MonthWeekDay(month, week, day)
+        } else if let Some(day) = date.strip_prefix('J') {
+            RuleDate::Julian(day.parse().ok().filter(|day| (1..=365).contains(day))?)
+        } else {
+            RuleDate::ZeroBased(date.parse().ok().filter(|day| *day <= 365)?)
+        };
+        Some(Transition { date, time })
+    }
+
+    /// Local time of the transition in a year, in seconds since the epoch.
+    fn at(&self, year: i32) -> Option<i64> {
+        let date = match self.date {
+            RuleDate::Julian(day) => {
+                let date = NaiveDate::from_yo_opt(year, day.into())?;
+                // Skip February 29, so March 1 is always day 60.
+                if date.leap_year() && day >= 60 {
+                    date.succ_opt()?
+                } else {
+                    date
+                }
+            }
+            RuleDate::ZeroBased(day) => NaiveDate::from_yo_opt(year, u32::from(day) + 1)?,
+            RuleDate::MonthWeekDay(month, week, day) => {
+                let first = NaiveDate::from_ymd_opt(year, month.into(), 1)?;
+                let first_day = first.weekday().num_days_from_sunday() as u8;
+                let mut date = first
+                    + Duration::days(i64::from((day + 7 - first_day) % 7))
+                    + Duration::weeks(i64::from(week - 1));
+                // Week 5 is the last week the day occurs in the month.
+                while date.month() != u32::from(month) {
+                    date -= Duration::weeks(1);
+                }
+                date
+            }
+        };
+        let midnight = date.and_hms_opt(0, 0, 0)?.and_utc().timestamp();
+        Some(midnight + self.time)
+    }
+}
+
+/// Skip a zone abbreviation, like `CET` or `<+03>`.
+fn parse_name(rest: &mut &str) -> Option<()> {
+    let len = if let Some(quoted) = rest.strip_prefix('<') {
+        quoted.find('>')? + 2
+    } else {
+        rest.find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len())
+    };
+    if len < 3 {
+        return None;
+    }
+    *rest = &rest[len..];
+    Some(())
+}
+
+/// Parse a time like `-1`, `5:30` or `25:00:00`, in seconds.
+fn parse_time(rest: &mut &str) -> Option<i64> {
+    let (sign, unsigned) = match rest.as_bytes().first() {
+        Some(b'-') => (-1, &rest[1..]),
+        Some(b'+') => (1, &rest[1..]),
+        _ => (1, *rest),
+    };
+    let len = unsigned
+        .find(|c: char| !c.is_ascii_digit() && c != ':')
+        .unwrap_or(unsigned.len());
+    let mut seconds = 0;
+    let mut parts = 0;
+    for (index, part) in unsigned[..len].split(':').enumerate() {
+        let value: i64 = part.parse().ok()?;
+        if index > 0 && value >= 60 || index > 2 || index == 0 && value > 167 {
+            return None;
+        }
+        seconds += value * [3600, 60, 1][index];
+        parts += 1;
+    }
+    if parts == 0 {
+        return None;
+    }
+    *rest = &unsigned[len..];
+    Some(sign * seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Load a zone from the system database, if it's there.
+    fn system_zone(name: &str) -> Option<Tz> {
+        let Ok(data) = fs::read(PathBuf::from(DEFAULT_TZDIR).join(name)) else {
+            eprintln!("skipped, {} is not in {}", name, DEFAULT_TZDIR);
+            return None;
+        };
+        Some(parse_tzif(&data).expect(name))
+    }
+
+    fn offsets(rule: &Rule, times: &[i64]) -> Vec<i32> {
+        times.iter().map(|time| rule.offset_at(*time)).collect()
+    }
+
+    #[test]
+    fn system_zones_have_their_offsets() {
+        if let Some(tz) = system_zone("Europe/Amsterdam") {
+            // Local mean time, before the first transition.
+            assert_eq!(tz.offset_at(-5_364_662_400), 1172);
+            assert_eq!(tz.offset_at(1_711_846_799), 3600);
+            assert_eq!(tz.offset_at(1_711_846_800), 7200);
+            // After the last transition, from the rule of the footer.
+            assert_eq!(tz.offset_at(4_102_444_800), 3600);
+            assert_eq!(tz.offset_at(4_118_083_200), 7200);
+        }
+        if let Some(tz) = system_zone("Australia/Sydney") {
+            assert_eq!(tz.offset_at(4_102_444_800), 39600);
+            assert_eq!(tz.offset_at(4_118_083_200), 36000);
+        }
+        if let Some(tz) = system_zone("America/New_York") {
+            assert_eq!(tz.offset_at(4_108_690_799), -18000);
+            assert_eq!(tz.offset_at(4_108_690_800), -14400);
+            assert_eq!(tz.offset_at(4_129_250_399), -14400);
+            assert_eq!(tz.offset_at(4_129_250_400), -18000);
+        }
+        if let Some(tz) = system_zone("UTC") {
+            assert_eq!(tz.offset_at(0), 0);
+            assert_eq!(tz.offset_at(4_118_083_200), 0);
+        }
+    }
+
+    #[test]
+    fn rules_without_transitions_use_the_default() {
+        // Around the start and end of daylight saving time in New York in 2100.
+        let times = [4_108_690_799, 4_108_690_800, 4_129_250_399, 4_129_250_400];
+        let expected = [-18000, -14400, -14400, -18000];
+        for spec in ["EST5EDT", "EST5EDT4", "EST5EDT,M3.2.0,M11.1.0"] {
+            let rule = Rule::parse(spec).expect(spec);
+            assert_eq!(offsets(&rule, &times), expected, "{}", spec);
+        }
+    }
+
+    #[test]
+    fn rules_are_parsed() {
+        let rule = Rule::parse("<+03>-3").unwrap();
+        assert_eq!(offsets(&rule, &[0, 4_118_083_200]), [10800, 10800]);
+        // Daylight saving time across the new year, ending at 3:00 local time.
+        let rule = Rule::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(
+            offsets(&rule, &[4_102_444_800, 4_118_083_200]),
+            [39600, 36000]
+        );
+        for spec in ["", "E5", "EST", "EST5EDT,M3.2.0", "EST5EDT,M13.1.0,M11.1.0"] {
+            assert!(Rule::parse(spec).is_none(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        assert!(parse_tzif(b"").is_none());
+        assert!(parse_tzif(b"TZif2").is_none());
+        if let Ok(data) = fs::read(PathBuf::from(DEFAULT_TZDIR).join("Europe/Amsterdam")) {
+            assert!(parse_tzif(&data[..data.len() / 2]).is_none());
+        }
+    }
+}
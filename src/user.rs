@@ -0,0 +1,196 @@
+use std::{
+    ffi::{CStr, CString},
+    io,
+    os::unix::process::CommandExt,
+    process::Command,
+    ptr,
+};
+
+/// A user account to run as, from the user database.
+pub struct Account {
+    pub name: String,
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+    /// Supplementary groups of the user, including the primary group.
+    pub groups: Vec<libc::gid_t>,
+    pub home: String,
+}
+
+impl Account {
+    /// Look up a user by name.
+    pub fn lookup(name: &str) -> Result<Self, String> {
+        let unknown = || format!("unknown user: {}", name);
+        let c_name = CString::new(name).map_err(|_| unknown())?;
+        // Safety: `passwd` is plain data, and only read after it was filled in.
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut buf = vec![0 as libc::c_char; 16384];
+        let mut result = ptr::null_mut();
+        // Safety: all pointers are valid for the duration of the call, with the buffer length.
+        let err = unsafe {
+            libc::getpwnam_r(
+                c_name.as_ptr(),
+                &mut passwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if err != 0 {
+            return Err(format!(
+                "user lookup failed: {}: {}",
+                name,
+                io::Error::from_raw_os_error(err)
+            ));
+        }
+        if result.is_null() {
+            return Err(unknown());
+        }
+        // Safety: on success, the strings point into the buffer, which is still alive.
+        let (user_name, home) = unsafe {
+            (
+                CStr::from_ptr(passwd.pw_name),
+                CStr::from_ptr(passwd.pw_dir).to_string_lossy().into_owned(),
+            )
+        };
+        // Safety: `geteuid` has no preconditions.
+        if !is_root() && passwd.pw_uid != unsafe { libc::geteuid() } {
+            return Err(format!(
+                "can't run as {}: pocketcron isn't running as root",
+                name
+            ));
+        }
+        let groups = group_list(user_name, passwd.pw_gid);
+        Ok(Account {
+            name: user_name.to_string_lossy().into_owned(),
+            uid: passwd.pw_uid,
+            gid: passwd.pw_gid,
+            groups,
+            home,
+        })
+    }
+
+    /// Environment variables that describe the user, like cron sets them.
+    pub fn env(&self) -> [(&str, &str); 3] {
+        [
+            ("HOME", &self.home),
+            ("USER", &self.name),
+            ("LOGNAME", &self.name),
+        ]
+    }
+
+    /// Make a command run as this user, with its groups.
+    pub fn apply(&self, command: &mut Command) {
+        let (uid, gid, groups) = (self.uid, self.gid, self.groups.clone());
+        // Safety: `setgroups`, `setgid` and `setuid` are async-signal-safe, and the group list
+        // was allocated before forking.
+        unsafe {
+            command.pre_exec(move || switch(uid, gid, &groups));
+        }
+    }
+}
+
+/// Look up a group by name.
+pub fn lookup_group(name: &str) -> Result<libc::gid_t, String> {
+    let unknown = || format!("unknown group: {}", name);
+    let c_name = CString::new(name).map_err(|_| unknown())?;
+    // Safety: `group` is plain data, and only read after it was filled in.
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16384];
+    let mut result = ptr::null_mut();
+    // Safety: all pointers are valid for the duration of the call, with the buffer length.
+    let err = unsafe {
+        libc::getgrnam_r(
+            c_name.as_ptr(),
+            &mut group,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if err != 0 {
+        return Err(format!(
+            "group lookup failed: {}: {}",
+            name,
+            io::Error::from_raw_os_error(err)
+        ));
+    }
+    if result.is_null() {
+        return Err(unknown());
+    }
+    Ok(group.gr_gid)
+}
+
+/// Switch pocketcron itself to a user, with `--user` and `--group`. With a group, that is the
+/// only group, and otherwise those of the user. `HOME`, `USER` and `LOGNAME` are set for the user,
+/// and passed on to jobs.
+///
+/// Must be called before any threads are started.
+pub fn drop_privileges(user: &str, group: Option<&str>) -> Result<(), String> {
+    let account = Account::lookup(user)?;
+    let (gid, groups) = match group {
+        Some(group) => {
+            let gid = lookup_group(group)?;
+            (gid, vec![gid])
+        }
+        None => (account.gid, account.groups.clone()),
+    };
+    if !is_root() && gid != account.gid {
+        return Err(format!(
+            "can't switch to group {}: pocketcron isn't running as root",
+            group.unwrap_or_default()
+        ));
+    }
+    // Only root may set groups, and other users already have theirs.
+    if is_root() {
+        switch(account.uid, gid, &groups)
+            .map_err(|err| format!("switching to user {} failed: {}", user, err))?;
+    }
+    for (name, value) in account.env() {
+        std::env::set_var(name, value);
+    }
+    Ok(())
+}
+
+/// Supplementary groups of a user, like `initgroups` would set them.
+fn group_list(name: &CStr, gid: libc::gid_t) -> Vec<libc::gid_t> {
+    let mut len: libc::c_int = 32;
+    loop {
+        let mut groups: Vec<libc::gid_t> = vec![0; len as usize];
+        let capacity = len;
+        // Safety: the name is a valid C string, and the list has room for `len` groups.
+        let res = unsafe {
+            libc::getgrouplist(
+                name.as_ptr(),
+                gid as _,
+                groups.as_mut_ptr().cast(),
+                &mut len,
+            )
+        };
+        if res >= 0 {
+            groups.truncate(len as usize);
+            return groups;
+        }
+        // The list was too short, and `len` is now the number of groups, on most platforms.
+        len = len.max(capacity * 2);
+    }
+}
+
+/// Switch the current process to a user and its groups, for good.
+pub fn switch(uid: libc::uid_t, gid: libc::gid_t, groups: &[libc::gid_t]) -> io::Result<()> {
+    // Safety: the group list is valid for its length.
+    unsafe {
+        if libc::setgroups(groups.len() as _, groups.as_ptr()) != 0
+            || libc::setgid(gid) != 0
+            || libc::setuid(uid) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Whether pocketcron can run commands as other users.
+pub fn is_root() -> bool {
+    // Safety: `geteuid` has no preconditions.
+    unsafe { libc::geteuid() == 0 }
+}
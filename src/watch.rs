@@ -0,0 +1,115 @@
+use std::io;
+
+use pocketcron::schedule::Watch;
+
+/// Watch a path on a separate thread, and call `on_change` when changes have settled.
+#[cfg(target_os = "linux")]
+pub fn watch(watch: Watch, on_change: impl Fn() + Send + 'static) -> io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt, thread};
+
+    use crate::log::error;
+
+    let path = CString::new(watch.path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    // Safety: `inotify_init1` has no preconditions.
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mask = libc::IN_CLOSE_WRITE
+        | libc::IN_MODIFY
+        | libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO;
+    // Safety: the path is a valid C string.
+    if unsafe { libc::inotify_add_watch(fd, path.as_ptr(), mask) } < 0 {
+        let err = io::Error::last_os_error();
+        // Safety: the descriptor was opened above, and isn't used elsewhere.
+        unsafe { libc::close(fd) };
+        return Err(err);
+    }
+
+    thread::spawn(move || {
+        // Errors other than interruptions would only happen again, so the watch stops.
+        if let Err(err) = wait_changes(fd, &watch, on_change) {
+            error!("{}: watch stopped: {}", watch.path.display(), err);
+        }
+        // Safety: the descriptor was opened above, and isn't used anymore.
+        unsafe { libc::close(fd) };
+    });
+    Ok(())
+}
+
+/// Call `on_change` whenever changes have settled. Only returns when reading them fails.
+#[cfg(target_os = "linux")]
+fn wait_changes(fd: libc::c_int, watch: &Watch, on_change: impl Fn()) -> io::Result<()> {
+    use std::time::Instant;
+
+    let debounce = watch.debounce.to_std().unwrap_or_default();
+    loop {
+        // Wait for a relevant change, then until there are none for the debounce duration.
+        while !read_changes(fd, watch)? {}
+        let mut deadline = Instant::now() + debounce;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let mut pollfd = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // Safety: the pointer is valid for one `pollfd`.
+            let ready = unsafe { libc::poll(&mut pollfd, 1, timeout.as_millis() as libc::c_int) };
+            if ready == 0 {
+                break;
+            }
+            if ready < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            } else if read_changes(fd, watch)? {
+                deadline = Instant::now() + debounce;
+            }
+        }
+        on_change();
+    }
+}
+
+/// Read pending inotify events, and return whether any are relevant.
+#[cfg(target_os = "linux")]
+fn read_changes(fd: libc::c_int, watch: &Watch) -> io::Result<bool> {
+    const HEADER: usize = std::mem::size_of::<libc::inotify_event>();
+
+    let mut buf = [0u8; 4096];
+    // Safety: the pointer and length are those of the buffer.
+    let len = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+    let Ok(len) = usize::try_from(len) else {
+        let err = io::Error::last_os_error();
+        return match err.kind() {
+            io::ErrorKind::Interrupted => Ok(false),
+            _ => Err(err),
+        };
+    };
+
+    // Every event is a header, followed by a nul-padded name if about a file in a directory.
+    let mut relevant = false;
+    let mut offset = 0;
+    while offset + HEADER <= len {
+        let name_len = u32::from_ne_bytes(buf[offset + 12..offset + 16].try_into().unwrap());
+        let name_end = (offset + HEADER + name_len as usize).min(len);
+        let name = &buf[offset + HEADER..name_end];
+        let name = name.split(|b| *b == 0).next().unwrap_or_default();
+        relevant |= name.is_empty() || watch.matches(&String::from_utf8_lossy(name));
+        offset = name_end;
+    }
+    Ok(relevant)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn watch(_watch: Watch, _on_change: impl Fn() + Send + 'static) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "file watches are not supported on this platform",
+    ))
+}
@@ -0,0 +1,56 @@
+use chrono::{offset::Local, DateTime, Duration, Offset, TimeZone};
+
+/// How far ahead to compare offsets. This covers the next transitions in both directions.
+const HORIZON_DAYS: i64 = 400;
+
+/// Watches the rules of the local time zone.
+///
+/// Chrono picks up a new `/etc/localtime` or `TZ` by itself, but times that were resolved before
+/// keep the old offsets. Comparing the offsets once a day over the coming year tells whether they
+/// are stale, including when only future transitions moved.
+pub struct Zone {
+    start: DateTime<Local>,
+    /// UTC offsets in seconds, once a day from `start`.
+    offsets: Vec<i32>,
+}
+
+impl Zone {
+    pub fn new(now: DateTime<Local>) -> Self {
+        Zone {
+            start: now,
+            offsets: offsets(now),
+        }
+    }
+
+    /// Check whether the rules changed since the last check. Returns the current offsets before
+    /// and after, which are the same if only other times of the year changed.
+    pub fn check(&mut self, now: DateTime<Local>) -> Option<(String, String)> {
+        let changed = offsets(self.start) != self.offsets;
+        let before = describe(self.offsets[0]);
+        // Move the window along, so it keeps covering the coming year.
+        if changed || now - self.start > Duration::hours(1) {
+            *self = Zone::new(now);
+        }
+        changed.then(|| (before, describe(self.offsets[0])))
+    }
+}
+
+fn offsets(start: DateTime<Local>) -> Vec<i32> {
+    let start = start.naive_utc();
+    (0..HORIZON_DAYS)
+        .map(|day| {
+            let time = start + Duration::days(day);
+            Local
+                .offset_from_utc_datetime(&time)
+                .fix()
+                .local_minus_utc()
+        })
+        .collect()
+}
+
+/// Describe an offset, like `UTC+02:00`.
+fn describe(offset: i32) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let minutes = offset.abs() / 60;
+    format!("UTC{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+}
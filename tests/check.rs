@@ -0,0 +1,50 @@
+mod common;
+
+use std::fs;
+
+use common::{pocketcron, run, temp_dir};
+
+/// Check a crontab, and return the errors that were reported.
+fn check(name: &str, crontab: &str) -> String {
+    let dir = temp_dir(name);
+    fs::write(dir.join("jobs.cron"), crontab).unwrap();
+    let output = run(pocketcron().arg("check").arg(dir.join("jobs.cron")));
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let prefix = format!("{}:", dir.join("jobs.cron").display());
+    stderr.replace(&prefix, "")
+}
+
+#[test]
+fn offsets_longer_than_a_year_are_errors() {
+    assert_eq!(
+        check("check-offset", "OFFSET=99999999d\n* * * * * echo x\n"),
+        "1:8: error: offset must be at most 366d: 99999999d\n\
+         2:1: error: skipped, because the value of OFFSET above is invalid\n"
+    );
+    assert_eq!(
+        check("check-offset-token", "0 3 * * * +367d echo x\n"),
+        "1:11: error: offset must be at most 366d: 367d\n"
+    );
+}
+
+#[test]
+fn random_delays_longer_than_a_year_are_errors() {
+    assert_eq!(
+        check("check-delay", "RANDOM_DELAY=99999999d\n* * * * * echo x\n"),
+        "1:14: error: random delay must be at most 366d: 99999999d\n\
+         2:1: error: skipped, because the value of RANDOM_DELAY above is invalid\n"
+    );
+    assert_eq!(
+        check("check-delay-token", "0 3 * * * ~367d echo x\n"),
+        "1:11: error: random delay must be at most 366d: 367d\n"
+    );
+}
+
+#[test]
+fn intervals_longer_than_a_year_are_errors() {
+    assert_eq!(
+        check("check-every", "@every 99999999d echo x\n"),
+        "1:8: error: interval must be at most 366d\n"
+    );
+}
@@ -0,0 +1,58 @@
+// Helpers shared by tests, which not all of them use.
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long pocketcron may take before a test gives up on it.
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+/// An empty directory of its own for a test.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pocketcron-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The pocketcron binary, to run with arguments.
+pub fn pocketcron() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_pocketcron"));
+    command.stdin(Stdio::null());
+    command
+}
+
+/// Run pocketcron until it exits, which should be soon, like with `--max-runs`.
+pub fn run(command: &mut Command) -> Output {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let start = Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        if start.elapsed() > TIMEOUT {
+            child.kill().unwrap();
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    child.wait_with_output().unwrap()
+}
+
+/// Name of the user that runs the tests.
+pub fn user_name() -> String {
+    // Safety: the entry is only read right after the call, on this thread.
+    unsafe {
+        let passwd = libc::getpwuid(libc::geteuid());
+        assert!(!passwd.is_null());
+        std::ffi::CStr::from_ptr((*passwd).pw_name)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
@@ -0,0 +1,88 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use pocketcron::cron::{Dialect, Expression};
+
+fn parse(schedule: &str, dialect: Dialect, clamp: bool) -> Result<Expression, String> {
+    let fields: Vec<&str> = schedule.split_whitespace().collect();
+    Expression::parse(&fields, dialect, false, 0, clamp).map_err(|err| err.message)
+}
+
+fn time(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(year, month, day)
+        .unwrap()
+        .and_hms_opt(hour, minute, 0)
+        .unwrap()
+}
+
+fn next(schedule: &str, after: NaiveDateTime) -> Option<NaiveDateTime> {
+    parse(schedule, Dialect::Vixie, false)
+        .unwrap()
+        .next_after(after)
+}
+
+#[test]
+fn times_of_day_follow_each_other() {
+    let start = time(2025, 1, 1, 10, 7);
+    assert_eq!(next("*/5 * * * *", start), Some(time(2025, 1, 1, 10, 10)));
+    assert_eq!(next("0 9 * * *", start), Some(time(2025, 1, 2, 9, 0)));
+    assert_eq!(next("30 23 * * 1-5", start), Some(time(2025, 1, 1, 23, 30)));
+}
+
+#[test]
+fn months_and_years_without_the_day_are_skipped() {
+    let start = time(2025, 1, 31, 12, 0);
+    assert_eq!(next("0 0 31 * *", start), Some(time(2025, 3, 31, 0, 0)));
+    assert_eq!(
+        next("0 0 29 2 *", time(2025, 3, 1, 0, 0)),
+        Some(time(2028, 2, 29, 0, 0))
+    );
+    assert_eq!(next("0 0 1 1 * 2030", start), Some(time(2030, 1, 1, 0, 0)));
+    assert_eq!(next("0 0 1 1 * 2030", time(2030, 1, 1, 0, 0)), None);
+}
+
+#[test]
+fn day_fields_combine_by_dialect() {
+    let start = time(2025, 1, 1, 0, 0);
+    // Either the 13th or a Friday.
+    assert_eq!(next("0 0 13 * 5", start), Some(time(2025, 1, 3, 0, 0)));
+    // Both the 13th and a Friday.
+    let quartz = parse("0 0 0 13 * 6", Dialect::Quartz, false).unwrap();
+    assert_eq!(quartz.next_after(start), Some(time(2025, 6, 13, 0, 0)));
+    assert_eq!(next("0 0 * * 5L", start), Some(time(2025, 1, 31, 0, 0)));
+    assert_eq!(next("0 0 L * *", start), Some(time(2025, 1, 31, 0, 0)));
+    assert_eq!(
+        next("0 0 15W * *", time(2025, 2, 1, 0, 0)),
+        Some(time(2025, 2, 14, 0, 0))
+    );
+}
+
+#[test]
+fn impossible_dates_are_rejected() {
+    for schedule in ["0 0 30 2 *", "0 0 31 4,6,9,11 *", "0 0 29 2 * 2025-2027"] {
+        let err = parse(schedule, Dialect::Vixie, false).unwrap_err();
+        assert!(err.contains("no such day"), "{}: {}", schedule, err);
+    }
+    // Unless they run on the last day of the month instead.
+    let clamped = parse("0 0 30 2 *", Dialect::Vixie, true).unwrap();
+    assert_eq!(
+        clamped.next_after(time(2025, 1, 1, 0, 0)),
+        Some(time(2025, 2, 28, 0, 0))
+    );
+    // Or on a day of the week.
+    assert!(parse("0 0 30 2 1", Dialect::Vixie, false).is_ok());
+}
+
+#[test]
+fn invalid_fields_are_explained() {
+    let err = parse("0 0 * * L", Dialect::Vixie, false).unwrap_err();
+    assert!(
+        err.contains("expected a day of the week before 'L'"),
+        "{}",
+        err
+    );
+    let err = parse("60 * * * *", Dialect::Vixie, false).unwrap_err();
+    assert!(err.contains("60 is out of range 0-59"), "{}", err);
+    let err = parse("0 0 * foo *", Dialect::Vixie, false).unwrap_err();
+    assert!(err.contains("unknown value 'foo'"), "{}", err);
+    assert!(parse("0 0 * * L", Dialect::Busybox, false).is_err());
+    assert!(parse("0 0 * *", Dialect::Vixie, false).is_err());
+}
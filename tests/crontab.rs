@@ -0,0 +1,130 @@
+use chrono::{offset::Local, Duration, TimeZone};
+use pocketcron::{
+    crontab::{expand, expand_shell, parse_crontab, parse_crontab_lenient, JobSpec},
+    schedule::Compat,
+};
+
+fn parse_one(input: &str) -> JobSpec {
+    let mut jobs = parse_crontab(input).unwrap();
+    assert_eq!(jobs.len(), 1);
+    jobs.pop().unwrap()
+}
+
+#[test]
+fn random_delay_after_the_schedule() {
+    let job = parse_one("0 * * * * ~15m fetch-updates\n");
+    assert_eq!(job.random_delay, Duration::minutes(15));
+    assert_eq!(job.command, "fetch-updates");
+}
+
+#[test]
+fn command_in_the_home_directory_is_not_a_delay() {
+    let job = parse_one("0 3 * * * ~/bin/backup.sh\n");
+    assert_eq!(job.random_delay, Duration::zero());
+    assert_eq!(job.command, "~/bin/backup.sh");
+}
+
+#[test]
+fn command_in_the_home_directory_after_a_delay() {
+    let job = parse_one("0 3 * * * ~5 ~/bin/backup.sh --full\n");
+    assert_eq!(job.random_delay, Duration::minutes(5));
+    assert_eq!(job.command, "~/bin/backup.sh --full");
+}
+
+#[test]
+fn command_that_looks_like_a_delay() {
+    let job = parse_one("0 3 * * * ~15m\n");
+    assert_eq!(job.random_delay, Duration::zero());
+    assert_eq!(job.command, "~15m");
+}
+
+fn expand_job_name(command: &str, name: &str) -> String {
+    let now = Local.with_ymd_and_hms(2024, 5, 1, 3, 0, 0).unwrap();
+    expand_shell(command, now, |other| {
+        (other == "JOB_NAME").then(|| name.to_owned())
+    })
+}
+
+#[test]
+fn shell_values_are_quoted() {
+    assert_eq!(
+        expand_job_name("echo ${JOB_NAME}", "a; rm -rf ~; 'b'"),
+        r#"echo 'a; rm -rf ~; '\''b'\'''"#
+    );
+    assert_eq!(
+        expand_job_name(r#"echo "x ${JOB_NAME}""#, r#"$HOME `id` "\"#),
+        r#"echo "x \$HOME \`id\` \"\\""#
+    );
+}
+
+#[test]
+fn shell_single_quotes_are_not_expanded() {
+    assert_eq!(
+        expand_job_name("echo '${JOB_NAME}' ${DATE}", "backup"),
+        "echo '${JOB_NAME}' '2024-05-01'"
+    );
+    assert_eq!(
+        expand_job_name(r#"echo "it's ${JOB_NAME}""#, "backup"),
+        r#"echo "it's backup""#
+    );
+}
+
+#[test]
+fn shell_variables_are_left_for_the_shell() {
+    assert_eq!(
+        expand_job_name(r"echo ${HOME} ${FOO:-x} \${JOB_NAME}", "backup"),
+        r"echo ${HOME} ${FOO:-x} \${JOB_NAME}"
+    );
+}
+
+#[test]
+fn words_are_expanded_as_they_are() {
+    let now = Local.with_ymd_and_hms(2024, 5, 1, 3, 0, 0).unwrap();
+    let lookup = |name: &str| (name == "HOME").then(|| "/home/a b".to_owned());
+    assert_eq!(expand("${HOME}/${DATE:%Y}", now, lookup), "/home/a b/2024");
+}
+
+#[test]
+fn jobs_below_an_invalid_variable_are_skipped() {
+    let input = "UMASK=077\n\
+                 0 * * * * first\n\
+                 UMASK=999\n\
+                 0 * * * * second\n\
+                 # name: third\n\
+                 0 * * * * third\n\
+                 UMASK=027\n\
+                 0 * * * * fourth\n";
+    let (jobs, errors) = parse_crontab_lenient(input, Compat::Default);
+    let jobs: Vec<_> = jobs
+        .iter()
+        .map(|job| (job.command.as_str(), job.limits.umask))
+        .collect();
+    assert_eq!(jobs, [("first", Some(0o077)), ("fourth", Some(0o027))]);
+    let errors: Vec<_> = errors
+        .iter()
+        .map(|err| (err.line, err.message.as_str()))
+        .collect();
+    assert_eq!(
+        errors,
+        [
+            (3, "invalid umask: 999"),
+            (4, "skipped, because the value of UMASK above is invalid"),
+            (6, "skipped, because the value of UMASK above is invalid"),
+        ]
+    );
+}
+
+#[test]
+fn names_are_unique_among_many_jobs() {
+    let mut input: String = (0..10_000)
+        .map(|index| format!("# name: job-{}\n@daily true\n", index))
+        .collect();
+    input.push_str("# name: job-5000\n@daily false\n");
+    let (jobs, errors) = parse_crontab_lenient(&input, Compat::Default);
+    assert_eq!(jobs.len(), 10_001);
+    let errors: Vec<_> = errors
+        .iter()
+        .map(|err| (err.line, err.message.as_str()))
+        .collect();
+    assert_eq!(errors, [(20_001, "duplicate job name: job-5000")]);
+}
@@ -0,0 +1,106 @@
+use pocketcron::crontab::{match_jobs, parse_crontab, JobSpec, Match};
+
+fn parse(input: &str) -> Vec<JobSpec> {
+    parse_crontab(input).unwrap()
+}
+
+fn reload(old: &str, new: &str) -> Vec<Match> {
+    let old = parse(old);
+    let keys: Vec<_> = old.iter().map(JobSpec::key).collect();
+    match_jobs(&keys, &parse(new))
+}
+
+#[test]
+fn unchanged_lines_are_the_same() {
+    let crontab = "* * * * * a\n0 * * * * b\n";
+    assert_eq!(reload(crontab, crontab), [Match::Same(0), Match::Same(1)]);
+}
+
+#[test]
+fn moved_lines_are_the_same() {
+    assert_eq!(
+        reload(
+            "* * * * * a\n0 * * * * b\n",
+            "# b first\n0 * * * * b\n* * * * * a\n"
+        ),
+        [Match::Same(1), Match::Same(0)]
+    );
+}
+
+#[test]
+fn changed_schedule_keeps_the_job() {
+    assert_eq!(
+        reload("* * * * * a\n0 * * * * b\n", "* * * * * a\n30 * * * * b\n"),
+        [Match::Same(0), Match::Changed(1)]
+    );
+}
+
+#[test]
+fn changed_settings_keep_the_job() {
+    assert_eq!(
+        reload("0 * * * * b\n", "PRIORITY=5\n0 * * * * +10s b\n"),
+        [Match::Changed(0)]
+    );
+}
+
+#[test]
+fn changed_period_command_keeps_the_job() {
+    assert_eq!(
+        reload("@period 1 5 backup old\n", "@period 1 5 backup new\n"),
+        [Match::Changed(0)]
+    );
+}
+
+#[test]
+fn added_and_removed_jobs() {
+    assert_eq!(
+        reload("* * * * * a\n* * * * * b\n", "* * * * * b\n* * * * * c\n"),
+        [Match::Same(1), Match::New]
+    );
+}
+
+#[test]
+fn unchanged_line_is_not_taken_by_a_changed_job() {
+    assert_eq!(
+        reload("* * * * * a\n0 * * * * a\n", "5 * * * * a\n0 * * * * a\n"),
+        [Match::Changed(0), Match::Same(1)]
+    );
+}
+
+#[test]
+fn duplicate_lines_are_matched_one_by_one() {
+    assert_eq!(
+        reload("* * * * * a\n* * * * * a\n", "* * * * * a\n"),
+        [Match::Same(0)]
+    );
+    assert_eq!(
+        reload("* * * * * a\n", "* * * * * a\n* * * * * a\n"),
+        [Match::Same(0), Match::New]
+    );
+}
+
+#[test]
+fn changed_variables_above_a_line_change_the_job() {
+    assert_eq!(
+        reload(
+            "TIMEOUT=1h\n* * * * * a\n* * * * * b\n",
+            "TIMEOUT=1h\n* * * * * a\nNICE=10\n* * * * * b\n"
+        ),
+        [Match::Same(0), Match::Changed(1)]
+    );
+    assert_eq!(
+        reload("UMASK=022\n* * * * * a\n", "UMASK=077\n* * * * * a\n"),
+        [Match::Changed(0)]
+    );
+}
+
+#[test]
+fn variables_with_the_same_values_keep_the_job() {
+    assert_eq!(
+        reload(
+            "FOO=1\nBAR=2\n* * * * * a\n",
+            "# reordered\nBAR=2\nFOO=0\nFOO=1\n* * * * * a\n"
+        ),
+        [Match::Same(0)]
+    );
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use std::{
+    fs,
+    time::{Duration, Instant},
+};
+
+use common::{pocketcron, run, temp_dir};
+
+/// Run every job in a crontab once, and return what they printed.
+fn run_once(name: &str, crontab: &str, runs: u32) -> String {
+    let dir = temp_dir(name);
+    fs::write(dir.join("jobs.cron"), crontab).unwrap();
+    let output = run(pocketcron()
+        .args(["--run-on-start", "--max-runs", &runs.to_string()])
+        .arg(dir.join("jobs.cron")));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn variables_are_expanded_by_the_shell() {
+    let stdout = run_once(
+        "run-expand",
+        "VALUE=\"; echo injected; \"\n* * * * * echo ${VALUE} '${VALUE}'\n",
+        1,
+    );
+    assert_eq!(stdout, "; echo injected; ${VALUE}\n");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn jobs_start_with_no_blocked_signals() {
+    let stdout = run_once(
+        "run-signals",
+        "* * * * * grep SigBlk /proc/self/status\n",
+        1,
+    );
+    assert_eq!(stdout, "SigBlk:\t0000000000000000\n");
+}
+
+#[test]
+fn blocked_jobs_let_other_jobs_through() {
+    let dir = temp_dir("run-block");
+    fs::write(
+        dir.join("jobs.cron"),
+        "* * * * * sleep 1; echo long\n\
+         QUEUE=block\n\
+         * * * * * echo blocked\n\
+         QUEUE=drop\n\
+         * * * * * echo dropped\n",
+    )
+    .unwrap();
+    let output = run(pocketcron()
+        .args(["--run-on-start", "--max-runs", "2", "--max-concurrent", "1"])
+        .args(["--events-fd", "1"])
+        .arg(dir.join("jobs.cron")));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let position = |text| {
+        stdout
+            .find(text)
+            .unwrap_or_else(|| panic!("no {}: {}", text, stdout))
+    };
+    // The dropped job is dispatched while the blocked one waits for the long one, not after.
+    assert!(position("\"reason\":\"dropped\"") < position("long\n"));
+    assert!(position("long\n") < position("blocked\n"));
+    assert!(!stdout.lines().any(|line| line == "dropped"));
+}
+
+/// Whether the kernel supports Landlock, which sandboxes need.
+#[cfg(feature = "sandbox")]
+fn has_landlock() -> bool {
+    // Safety: asking for the version takes no attributes.
+    let abi = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<u8>(),
+            0,
+            1,
+        )
+    };
+    abi > 0
+}
+
+#[cfg(feature = "sandbox")]
+#[test]
+fn sandboxed_runs_and_hooks_only_write_allowed_paths() {
+    if !has_landlock() {
+        eprintln!("skipped, Landlock is not available");
+        return;
+    }
+    let dir = temp_dir("run-sandbox");
+    let allowed = dir.join("allowed");
+    fs::create_dir(&allowed).unwrap();
+    let crontab = format!(
+        "SANDBOX=readonly-fs\n\
+         ALLOW_PATHS={allowed}\n\
+         ON_SUCCESS=touch {dir}/hook 2>/dev/null || echo hook denied\n\
+         * * * * * touch {allowed}/file && (touch {dir}/denied 2>/dev/null || echo denied)\n",
+        dir = dir.display(),
+        allowed = allowed.display(),
+    );
+    fs::write(dir.join("jobs.cron"), crontab).unwrap();
+    let output = run(pocketcron()
+        .args(["--run-on-start", "--max-runs", "1"])
+        .arg(dir.join("jobs.cron")));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "denied\nhook denied\n"
+    );
+    assert!(allowed.join("file").exists());
+    assert!(!dir.join("denied").exists());
+    assert!(!dir.join("hook").exists());
+}
+
+#[test]
+fn hooks_are_killed_after_the_timeout() {
+    let dir = temp_dir("run-hook-timeout");
+    fs::write(
+        dir.join("jobs.cron"),
+        "TIMEOUT=1s\nON_SUCCESS=sleep 30; echo not killed\n* * * * * true\n",
+    )
+    .unwrap();
+    let start = Instant::now();
+    let output = run(pocketcron()
+        .args(["--run-on-start", "--max-runs", "1"])
+        .arg(dir.join("jobs.cron")));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(start.elapsed() < Duration::from_secs(10), "{}", stderr);
+    assert!(stderr.contains("hook timed out after 1s"), "{}", stderr);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+}
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use chrono::{offset::Local, Duration, TimeZone};
+use pocketcron::{
+    schedule::{Align, Compat, Dst, ParseOptions, Schedule, Upcoming},
+    tz::Tz,
+};
+
+/// The first fire times of a schedule in Amsterdam after an instant, as seconds since the epoch.
+fn fire_times(schedule: &str, dst: Dst, after: i64, count: usize) -> Option<Vec<i64>> {
+    let Ok(zone) = Tz::load("Europe/Amsterdam") else {
+        eprintln!("skipped, Europe/Amsterdam is not in the time zone database");
+        return None;
+    };
+    let options = ParseOptions {
+        align: Align::Start,
+        dst,
+        zone: Some(Arc::new(zone)),
+        clamp: false,
+        compat: Compat::Default,
+        debounce: Duration::zero(),
+        seconds: false,
+        hash: 0,
+    };
+    let schedule = Schedule::parse(schedule, &options).unwrap();
+    let now = Local.timestamp_opt(after, 0).unwrap();
+    let upcoming = Upcoming::new(
+        schedule,
+        now,
+        None,
+        Duration::zero(),
+        Duration::zero(),
+        "",
+        "",
+    );
+    Some(upcoming.take(count).map(|time| time.timestamp()).collect())
+}
+
+/// 2024-03-31, when clocks in Amsterdam go from 2:00 to 3:00, at 1:00 UTC.
+const SPRING_FORWARD: i64 = 1_711_846_800;
+/// 2024-10-27, when clocks in Amsterdam go from 3:00 back to 2:00, at 1:00 UTC.
+const FALL_BACK: i64 = 1_729_990_800;
+const HOUR: i64 = 3600;
+const DAY: i64 = 24 * HOUR;
+
+#[test]
+fn times_in_a_gap_follow_the_policy() {
+    let after = SPRING_FORWARD - 12 * HOUR;
+    // 2:30 the next day is 0:30 UTC.
+    let next_day = SPRING_FORWARD + DAY - HOUR / 2;
+    for (dst, expected) in [
+        (Dst::Once, [SPRING_FORWARD, next_day]),
+        (Dst::Twice, [SPRING_FORWARD, next_day]),
+        (Dst::RunEarly, [SPRING_FORWARD - 1, next_day]),
+        (Dst::SkipGap, [next_day, next_day + DAY]),
+    ] {
+        let Some(times) = fire_times("30 2 * * *", dst, after, 2) else {
+            return;
+        };
+        assert_eq!(times, expected, "{:?}", dst);
+    }
+}
+
+#[test]
+fn times_in_a_gap_run_once_at_its_end() {
+    let Some(times) = fire_times("*/20 2 * * *", Dst::Once, SPRING_FORWARD - HOUR, 2) else {
+        return;
+    };
+    assert_eq!(times, [SPRING_FORWARD, SPRING_FORWARD + DAY - HOUR]);
+}
+
+#[test]
+fn times_in_a_fold_follow_the_policy() {
+    let after = FALL_BACK - 12 * HOUR;
+    // 2:30 comes at 0:30 UTC in summer time, and at 1:30 UTC in winter time.
+    let (first, second) = (FALL_BACK - HOUR / 2, FALL_BACK + HOUR / 2);
+    let Some(once) = fire_times("30 2 * * *", Dst::Once, after, 2) else {
+        return;
+    };
+    assert_eq!(once, [first, second + DAY]);
+    let twice = fire_times("30 2 * * *", Dst::Twice, after, 3).unwrap();
+    assert_eq!(twice, [first, second, second + DAY]);
+}
+
+#[test]
+fn second_occurrences_come_after_the_first_ones() {
+    let after = FALL_BACK - 2 * HOUR;
+    let Some(twice) = fire_times("*/30 * * * *", Dst::Twice, after, 6) else {
+        return;
+    };
+    let once = fire_times("*/30 * * * *", Dst::Once, after, 4).unwrap();
+    let half = HOUR / 2;
+    // 2:00 and 2:30 in summer time, then in winter time, then 3:00.
+    let start = FALL_BACK - 2 * half;
+    assert_eq!(
+        twice,
+        [
+            start - half,
+            start,
+            start + half,
+            start + 2 * half,
+            start + 3 * half,
+            start + 4 * half
+        ]
+    );
+    assert_eq!(once, [start - half, start, start + half, start + 4 * half]);
+}
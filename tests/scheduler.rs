@@ -0,0 +1,142 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use chrono::{offset::Local, Duration};
+use pocketcron::{
+    schedule::Trigger,
+    scheduler::{RunOutcome, Scheduler},
+};
+
+#[test]
+fn due_closures_run_once() {
+    let count = Arc::new(AtomicUsize::new(0));
+    let mut scheduler = Scheduler::new();
+    let counter = count.clone();
+    let id = scheduler
+        .add_fn("@every 1s tick", move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            RunOutcome::Success
+        })
+        .unwrap();
+    assert!(scheduler
+        .run_pending(Local::now() - Duration::seconds(1))
+        .is_empty());
+    // Missed occurrences in between run only once.
+    let outcomes = scheduler.run_pending(Local::now() + Duration::seconds(10));
+    assert_eq!(outcomes, [(id, RunOutcome::Success)]);
+    assert_eq!(count.load(Ordering::Relaxed), 1);
+    assert!(scheduler.next_run().unwrap() > Local::now() + Duration::seconds(10));
+}
+
+#[cfg(unix)]
+#[test]
+fn commands_report_their_outcome() {
+    let mut scheduler = Scheduler::new();
+    let ids = scheduler
+        .add_crontab("@trigger go true\n@trigger go exit 3\n@trigger go kill -9 $$\n")
+        .unwrap();
+    let outcomes = scheduler.fire(&Trigger::Named("go".to_owned()));
+    assert_eq!(
+        outcomes,
+        [
+            (ids[0], RunOutcome::Success),
+            (ids[1], RunOutcome::Exit(3)),
+            (ids[2], RunOutcome::Signal(9)),
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn commands_run_without_shell() {
+    let mut scheduler = Scheduler::new();
+    let ids = scheduler
+        .add_crontab(
+            "SHELL=none\n@trigger go test 'a b' = \"a b\"\n@trigger go test $HOME = '$HOME'\n",
+        )
+        .unwrap();
+    // Without a shell, quotes are removed, but variables aren't expanded.
+    let outcomes = scheduler.fire(&Trigger::Named("go".to_owned()));
+    assert_eq!(
+        outcomes,
+        [(ids[0], RunOutcome::Success), (ids[1], RunOutcome::Success)]
+    );
+    assert!(scheduler
+        .add_crontab("SHELL=none\n@daily echo 'a\n")
+        .is_err());
+}
+
+#[cfg(windows)]
+#[test]
+fn commands_run_in_cmd_as_they_are() {
+    let dir = std::env::temp_dir().join(format!("pocketcron-cmd-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut scheduler = Scheduler::new();
+    let ids = scheduler
+        .add_crontab(&format!(
+            "WORKDIR={}\n@trigger go echo \"a b\">out.txt\n@trigger go exit 3\n",
+            dir.display()
+        ))
+        .unwrap();
+    let outcomes = scheduler.fire(&Trigger::Named("go".to_owned()));
+    assert_eq!(
+        outcomes,
+        [(ids[0], RunOutcome::Success), (ids[1], RunOutcome::Exit(3))]
+    );
+    // `cmd` gets the quotes as they are, rather than escaped like arguments of other programs.
+    let output = std::fs::read_to_string(dir.join("out.txt")).unwrap();
+    assert_eq!(output, "\"a b\"\r\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(windows)]
+#[test]
+fn commands_run_in_powershell() {
+    let mut scheduler = Scheduler::new();
+    let ids = scheduler
+        .add_crontab("SHELL=powershell\n@trigger go if ('a b' -eq \"a b\") { exit 4 }\n")
+        .unwrap();
+    let outcomes = scheduler.fire(&Trigger::Named("go".to_owned()));
+    assert_eq!(outcomes, [(ids[0], RunOutcome::Exit(4))]);
+}
+
+#[test]
+fn higher_priority_runs_first() {
+    let mut scheduler = Scheduler::new();
+    let low = scheduler
+        .add_fn("@every 1s low", |_| RunOutcome::Success)
+        .unwrap();
+    let high = scheduler
+        .add_fn("PRIORITY=5\n@every 1s high", |_| RunOutcome::Success)
+        .unwrap();
+    let outcomes = scheduler.run_pending(Local::now() + Duration::seconds(2));
+    let order: Vec<_> = outcomes.into_iter().map(|(id, _)| id).collect();
+    assert_eq!(order, [high, low]);
+}
+
+#[test]
+fn lowest_priority_runs_last() {
+    let mut scheduler = Scheduler::new();
+    let lowest = scheduler
+        .add_fn("PRIORITY=-2147483648\n@every 1s lowest", |_| {
+            RunOutcome::Success
+        })
+        .unwrap();
+    let default = scheduler
+        .add_fn("@every 1s default", |_| RunOutcome::Success)
+        .unwrap();
+    let outcomes = scheduler.run_pending(Local::now() + Duration::seconds(2));
+    let order: Vec<_> = outcomes.into_iter().map(|(id, _)| id).collect();
+    assert_eq!(order, [default, lowest]);
+}
+
+#[test]
+fn add_fn_takes_a_single_job() {
+    let mut scheduler = Scheduler::new();
+    assert!(scheduler
+        .add_fn("@daily a\n@daily b\n", |_| RunOutcome::Success)
+        .is_err());
+    assert!(scheduler.add_fn("@daily", |_| RunOutcome::Success).is_err());
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use std::{fs, path::Path};
+
+use common::{pocketcron, run, temp_dir, user_name};
+
+/// Run the jobs in a spool with the crontab of the user running the tests, and a system crontab.
+fn run_spool(dir: &Path, user_crontab: &str, args: &[&str], max_runs: u32) -> (String, String) {
+    let spool = dir.join("spool");
+    fs::create_dir_all(&spool).unwrap();
+    fs::write(spool.join(user_name()), user_crontab).unwrap();
+    fs::write(dir.join("system.cron"), "* * * * * echo system\n").unwrap();
+    let output = run(pocketcron()
+        .args(args)
+        .arg("--spool")
+        .arg(&spool)
+        .args(["--run-on-start", "--max-runs", &max_runs.to_string()])
+        .arg(dir.join("system.cron"))
+        .env("SECRET", "hunter2"));
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert!(output.status.success(), "{}", stderr);
+    (stdout, stderr)
+}
+
+#[test]
+fn crontab_with_errors_is_skipped() {
+    let dir = temp_dir("spool-skipped");
+    let (stdout, stderr) = run_spool(&dir, "* * * * * echo user\n60 * * * * echo typo\n", &[], 1);
+    assert_eq!(stdout, "system\n");
+    assert!(stderr.contains("skipped the crontab of"), "{}", stderr);
+}
+
+#[test]
+fn crontab_with_errors_is_partly_loaded_with_lenient() {
+    let dir = temp_dir("spool-lenient");
+    let (stdout, _) = run_spool(
+        &dir,
+        "* * * * * echo user\n60 * * * * echo typo\n",
+        &["--lenient"],
+        2,
+    );
+    let mut lines: Vec<_> = stdout.lines().collect();
+    lines.sort_unstable();
+    assert_eq!(lines, ["system", "user"]);
+}
+
+#[test]
+fn settings_applied_as_root_are_refused() {
+    let dir = temp_dir("spool-refused");
+    let log = dir.join("stolen.log");
+    let crontab = format!("LOG_FILE={}\n* * * * * echo user\n", log.display());
+    let (stdout, stderr) = run_spool(&dir, &crontab, &[], 1);
+    assert_eq!(stdout, "system\n");
+    assert!(stderr.contains("LOG_FILE is not allowed"), "{}", stderr);
+    let (stdout, _) = run_spool(&dir, &crontab, &["--lenient"], 2);
+    assert!(stdout.contains("user\n"), "{}", stdout);
+    assert!(!log.exists());
+}
+
+#[test]
+fn jobs_dont_get_the_environment_of_pocketcron() {
+    let dir = temp_dir("spool-env");
+    let (stdout, _) = run_spool(
+        &dir,
+        "* * * * * echo \"user secret=$SECRET path=$PATH shell=$SHELL\"\n",
+        &[],
+        2,
+    );
+    assert!(
+        stdout.contains("user secret= path=/usr/bin:/bin shell=/bin/sh\n"),
+        "{}",
+        stdout
+    );
+}